@@ -0,0 +1,203 @@
+use std::fmt;
+
+/// Canonical audio channel position, so ports naming the same position differently still pair
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Mono,
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    Lfe,
+    RearLeft,
+    RearRight,
+    RearCenter,
+    SideLeft,
+    SideRight,
+    /// Pro-audio auxiliary channel, e.g. `AUX3`
+    Aux(u32),
+    /// Any name without a known alias (e.g. `input_1`), compared verbatim
+    Other(String),
+}
+
+impl Channel {
+    /// Map a channel name onto its canonical position, ignoring case and `-`/`_`/space differences
+    pub fn parse(name: &str) -> Self {
+        let key: String = name
+            .trim()
+            .to_ascii_lowercase()
+            .chars()
+            .map(|c| if c == '_' || c == ' ' { '-' } else { c })
+            .collect();
+        match key.as_str() {
+            "mono" | "m" => Channel::Mono,
+            "fl" | "front-left" | "left" | "l" => Channel::FrontLeft,
+            "fr" | "front-right" | "right" | "r" => Channel::FrontRight,
+            "fc" | "front-center" | "front-centre" | "center" | "centre" | "c" => {
+                Channel::FrontCenter
+            }
+            "lfe" | "sub" | "subwoofer" | "low-frequency" => Channel::Lfe,
+            "rl" | "rear-left" | "bl" | "back-left" => Channel::RearLeft,
+            "rr" | "rear-right" | "br" | "back-right" => Channel::RearRight,
+            "rc" | "rear-center" | "rear-centre" | "bc" | "back-center" => Channel::RearCenter,
+            "sl" | "side-left" => Channel::SideLeft,
+            "sr" | "side-right" => Channel::SideRight,
+            _ => match key.strip_prefix("aux").and_then(|n| n.parse().ok()) {
+                Some(index) => Channel::Aux(index),
+                None => Channel::Other(name.to_string()),
+            },
+        }
+    }
+
+    /// Shift an AUX channel by `offset`, leaving named channels untouched
+    ///
+    /// Returns None if the shifted index would be negative
+    pub fn offset(&self, offset: i32) -> Option<Channel> {
+        match self {
+            Channel::Aux(index) => index.checked_add_signed(offset).map(Channel::Aux),
+            channel => Some(channel.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    /// PipeWire's short position names, or the original name for unknown channels
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Mono => f.write_str("MONO"),
+            Channel::FrontLeft => f.write_str("FL"),
+            Channel::FrontRight => f.write_str("FR"),
+            Channel::FrontCenter => f.write_str("FC"),
+            Channel::Lfe => f.write_str("LFE"),
+            Channel::RearLeft => f.write_str("RL"),
+            Channel::RearRight => f.write_str("RR"),
+            Channel::RearCenter => f.write_str("RC"),
+            Channel::SideLeft => f.write_str("SL"),
+            Channel::SideRight => f.write_str("SR"),
+            Channel::Aux(index) => write!(f, "AUX{}", index),
+            Channel::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Prefixes PipeWire, ALSA and JACK put in front of the channel in `port.name`
+const PORT_NAME_PREFIXES: [&str; 5] = ["playback_", "capture_", "monitor_", "input_", "output_"];
+
+/// Derive a channel name from `port.name` for ports without an `audio.channel` property
+///
+/// Strips a direction prefix (e.g. `playback_FL`) and a trailing index (e.g. `FL_1`), returning
+/// the canonical name if what remains is a known position, and the raw name otherwise
+pub fn channel_from_port_name(port_name: &str) -> String {
+    let mut name = port_name;
+    for prefix in PORT_NAME_PREFIXES {
+        if let Some(rest) = name
+            .get(..prefix.len())
+            .filter(|head| head.eq_ignore_ascii_case(prefix))
+            .and_then(|_| name.get(prefix.len()..))
+        {
+            name = rest;
+            break;
+        }
+    }
+    if let Some((stem, index)) = name.rsplit_once(['_', '-']) {
+        if !stem.is_empty() && !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()) {
+            name = stem;
+        }
+    }
+    match Channel::parse(name) {
+        Channel::Other(_) => port_name.to_string(),
+        channel => channel.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aliases() {
+        let table = [
+            (Channel::Mono, &["mono", "MONO", "m", "M"][..]),
+            (
+                Channel::FrontLeft,
+                &["FL", "front-left", "Front Left", "FRONT_LEFT", "left", "l"],
+            ),
+            (
+                Channel::FrontRight,
+                &["FR", "front-right", "front_right", "right", "R"],
+            ),
+            (
+                Channel::FrontCenter,
+                &[
+                    "FC",
+                    "front-center",
+                    "front-centre",
+                    "center",
+                    "centre",
+                    "c",
+                ],
+            ),
+            (
+                Channel::Lfe,
+                &["LFE", "sub", "subwoofer", "low-frequency", "Low Frequency"],
+            ),
+            (Channel::RearLeft, &["RL", "rear-left", "BL", "back-left"]),
+            (
+                Channel::RearRight,
+                &["RR", "rear-right", "BR", "back-right"],
+            ),
+            (
+                Channel::RearCenter,
+                &["RC", "rear-center", "rear-centre", "BC", "back-center"],
+            ),
+            (Channel::SideLeft, &["SL", "side-left", "Side Left"]),
+            (Channel::SideRight, &["SR", "side-right", " side_right "]),
+        ];
+        for (channel, aliases) in table {
+            for alias in aliases {
+                assert_eq!(Channel::parse(alias), channel, "{:?}", alias);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_aux_channels() {
+        assert_eq!(Channel::parse("AUX0"), Channel::Aux(0));
+        assert_eq!(Channel::parse("aux17"), Channel::Aux(17));
+        assert_eq!(Channel::Aux(3).to_string(), "AUX3");
+    }
+
+    #[test]
+    fn unknown_names_pass_through_verbatim() {
+        for name in ["input_1", "AUX", "AUXx", "Port 3", ""] {
+            assert_eq!(Channel::parse(name), Channel::Other(name.to_string()));
+            assert_eq!(Channel::parse(name).to_string(), name);
+        }
+    }
+
+    #[test]
+    fn channels_from_port_names() {
+        let table = [
+            ("playback_FL", "FL"),
+            ("capture_AUX3", "AUX3"),
+            ("FL_1", "FL"),
+            ("monitor_FR", "FR"),
+            ("output_front-left", "FL"),
+            ("Playback_RR-2", "RR"),
+            ("capture_MONO", "MONO"),
+            ("AUX12", "AUX12"),
+            // Kept raw when no known position remains
+            ("input_1", "input_1"),
+            ("capture_1", "capture_1"),
+            ("system:out", "system:out"),
+            ("playback_", "playback_"),
+        ];
+        for (port_name, channel) in table {
+            assert_eq!(
+                channel_from_port_name(port_name),
+                channel,
+                "{:?}",
+                port_name
+            );
+        }
+    }
+}
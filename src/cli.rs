@@ -0,0 +1,564 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use pie_mixer::channel::Channel;
+use pie_mixer::config::{Rule, RuleInput};
+use pie_mixer::expr::Expression;
+use pie_mixer::graph::Serial;
+use pie_mixer::matcher::{MatchField, MatchOptions};
+use pie_mixer::mixer::{ForeignLinks, NodeLatency, DEFAULT_LINK_FACTORY};
+use pie_mixer::plan::Pairing;
+
+/// Digital mixer for Raspberry Pi 4
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Serve the discovered graph and active links as JSON over HTTP (e.g. 127.0.0.1:8080)
+    #[arg(long, value_name = "ADDR")]
+    pub serve: Option<SocketAddr>,
+
+    /// Print an aligned table of the discovered nodes (colorized when writing to a terminal)
+    #[arg(long)]
+    pub pretty: bool,
+
+    /// Give up waiting for the initial discovery after this many milliseconds and continue
+    /// with whatever was found, instead of hanging on a stalled daemon
+    #[arg(long, value_name = "MS")]
+    pub discovery_timeout: Option<u64>,
+
+    /// Sync with the daemon this many times in a row before discovery is complete: the first
+    /// round collects the globals, the second the properties of the objects bound meanwhile
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(2..)
+    )]
+    pub discovery_rounds: u32,
+
+    /// Write the process id to this file, refusing to start while another instance holds it
+    #[arg(long, value_name = "PATH")]
+    pub pidfile: Option<PathBuf>,
+
+    /// Leave out outputs that are already playing something when the mixer starts, so a device
+    /// in use by another application is not taken over
+    #[arg(long)]
+    pub only_if_idle: bool,
+
+    /// Leave out rules whose input and output share no sample rate or format, instead of only
+    /// warning that PipeWire will resample or convert between them
+    #[arg(long)]
+    pub strict_format: bool,
+
+    /// Keep running when nothing matches, linking once matching nodes appear, e.g. for a mixer
+    /// started at boot before its devices are plugged in
+    #[arg(long)]
+    pub wait_forever: bool,
+
+    /// Stop the mixer and remove its links after this long, e.g. 90s, 30m, 2h or 1h30m
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub max_runtime: Option<Duration>,
+
+    /// Link the inputs matching this pattern but set their volume to zero, so they are ready
+    /// and silent until turned up elsewhere, e.g. in pavucontrol. Their previous volume is
+    /// restored when the mixer stops. Can be given several times
+    #[arg(long, value_name = "PATTERN")]
+    pub mute: Vec<String>,
+
+    /// Exit with an error when links requested at startup do not become active: code 7 if some
+    /// failed, 8 if all did
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Exit with code 7 when a stereo input ends up with only one of its channels linked at
+    /// startup, instead of only warning about the half-connection
+    #[arg(long)]
+    pub require_complete: bool,
+
+    /// Create the explicit links of a rule that resolve, warning about the others, instead of
+    /// linking nothing for it
+    #[arg(long)]
+    pub best_effort: bool,
+
+    /// Also destroy links that already existed and were adopted instead of duplicated, when
+    /// routes change or the mixer stops
+    #[arg(long)]
+    pub teardown_adopted: bool,
+
+    /// Link rules that close a feedback loop, e.g. A into B and the monitor of B back into A,
+    /// instead of refusing them
+    #[arg(long)]
+    pub allow_cycles: bool,
+
+    /// Add the mixer's links to outputs alongside the links other clients made into them,
+    /// e.g. a DAW sharing the output, never destroying one of those
+    #[arg(long)]
+    pub output_append_to_existing: bool,
+
+    /// Retries of a link the daemon refused to create, e.g. as a device was still settling,
+    /// waiting twice as long before each one (0 disables retrying)
+    #[arg(long, value_name = "COUNT", default_value_t = 5)]
+    pub link_retries: u32,
+
+    /// Leave links removed by another client removed, instead of recreating them (at most 3
+    /// times a minute per link)
+    #[arg(long)]
+    pub no_watchdog: bool,
+
+    /// Milliseconds between two link requests, so a device woken by many links at once does
+    /// not cause xruns for everything already playing (0 requests them all at once)
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub link_stagger_ms: u64,
+
+    /// Name of the PipeWire factory creating the links, for setups providing it under
+    /// another name
+    #[arg(long, value_name = "NAME", default_value = DEFAULT_LINK_FACTORY)]
+    pub link_factory: String,
+
+    /// Create passive links, which do not wake the devices or keep them running on their own
+    #[arg(long)]
+    pub passive: bool,
+
+    /// Request a latency for the links as QUANTUM/RATE, e.g. 256/48000 for low-latency monitoring
+    #[arg(long, value_name = "QUANTUM/RATE")]
+    pub node_latency: Option<NodeLatency>,
+
+    /// Milliseconds to wait for a Bluetooth node recreated by a profile switch to settle
+    /// before relinking it, as profiles often flap more than once
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    pub bluetooth_settle: u64,
+
+    /// Milliseconds to wait for new links to be established before reporting the ones that
+    /// are not, e.g. still negotiating a format
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    pub activation_timeout: u64,
+
+    /// How input ports are paired with output ports
+    #[arg(
+        long,
+        visible_alias = "pair-by",
+        value_enum,
+        value_name = "STRATEGY",
+        default_value_t
+    )]
+    pub pairing: Pairing,
+
+    /// What to do with the links a previous run left behind: keep them and add to them, or
+    /// remove them all before linking
+    #[arg(long, value_enum, value_name = "MODE", default_value_t)]
+    pub mode: LinkMode,
+
+    /// What to do where the session manager or another client already links an input to its
+    /// output: link anyway, leave that pair alone, or replace their links with the mixer's
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    pub foreign_links: ForeignLinks,
+
+    /// Log every node, port, link and other object as it is added to or removed from the
+    /// graph, without linking anything, until stopped
+    #[arg(long, conflicts_with = "dry_run")]
+    pub watch: bool,
+
+    /// Print the links that would be created and exit without linking
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Send a desktop notification saying how many links were created, or why linking failed
+    #[cfg(feature = "notify")]
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Also use the monitor ports of sinks as sources, e.g. to cascade one sink into another
+    #[arg(long, global = true)]
+    pub include_monitors: bool,
+
+    /// Only link these input channels, e.g. FL,FR to use the front pair of a multichannel device
+    #[arg(long, value_name = "CHANNELS", value_delimiter = ',', value_parser = parse_channel, global = true)]
+    pub input_channels: Vec<Channel>,
+
+    /// Only link into these output channels, leaving the others of the output alone
+    #[arg(long, value_name = "CHANNELS", value_delimiter = ',', value_parser = parse_channel, global = true)]
+    pub output_channels: Vec<Channel>,
+
+    /// Send input channels to other output channels, e.g. FL:FR,FR:FL for speakers wired the
+    /// wrong way round. A channel given twice goes to both; rules with a channel_map keep theirs
+    #[arg(long, value_name = "FROM:TO", value_delimiter = ',', value_parser = parse_channel_pair, global = true)]
+    pub channel_map: Vec<(Channel, Channel)>,
+
+    /// Also match nodes of unknown media class (e.g. loopbacks and other virtual devices),
+    /// treating them as inputs or outputs according to their ports
+    #[arg(long, global = true)]
+    pub include_virtual: bool,
+
+    /// Print every known property of the nodes matched by the rules or the input/output
+    /// selection, sorted by key, to help writing property-based rules
+    #[arg(long, global = true)]
+    pub verbose_props: bool,
+
+    /// With --verbose-props, also print the nodes nothing matched
+    #[arg(long = "all", requires = "verbose_props", global = true)]
+    pub all_nodes: bool,
+
+    /// With --verbose-props, print long property values in full instead of cutting them short
+    #[arg(long, requires = "verbose_props", global = true)]
+    pub no_truncate: bool,
+
+    /// Print how every rule matched every audio node and exit, optionally for one rule only
+    /// (by name, or "INPUT=>OUTPUT" for unnamed rules)
+    #[arg(long, value_name = "RULE", num_args = 0..=1, require_equals = true)]
+    pub explain: Option<Option<String>>,
+
+    /// Read routing rules from a TOML file
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Read a routing plan from a JSON file: `{"rules": [...]}` with the same fields as the
+    /// rules of the config file
+    #[arg(long, value_name = "FILE", global = true)]
+    pub plan: Option<PathBuf>,
+
+    /// Mix the inputs matching one description into the output matching another, as
+    /// "INPUT=>OUTPUT" (repeatable, case-insensitive substrings). Replaces the default of
+    /// mixing every selected input into a single output
+    #[arg(
+        long = "route",
+        value_name = "INPUT=>OUTPUT",
+        global = true,
+        conflicts_with_all = ["input_serials", "output_serials", "input_matches", "output_matches", "input_excludes", "input_apps", "match_expression"]
+    )]
+    pub routes: Vec<RouteSpec>,
+
+    /// Select inputs whose description contains PATTERN (repeatable, case-insensitive), or
+    /// every input for "*". A node matching any of the patterns is selected, unless
+    /// --input-match-all is given. "-" reads the patterns from stdin, one per line
+    #[arg(long = "input-match", value_name = "PATTERN", global = true)]
+    pub input_matches: Vec<String>,
+
+    /// Select the output whose description contains PATTERN (repeatable, case-insensitive)
+    /// instead of the built-in SPDIF match. "-" reads the patterns from stdin, one per line
+    #[arg(long = "output-match", value_name = "PATTERN", global = true)]
+    pub output_matches: Vec<String>,
+
+    /// Select inputs satisfying an expression, e.g.
+    /// 'class == "Audio/Source" && (desc ~ "Scarlett" || name =~ "^alsa_input") && !desc ~ "Monitor"'.
+    /// Fields: class, desc, nick, name, prop["key"]; operators: == (exact), ~ (substring,
+    /// case-insensitive), =~ (regex), &&, ||, ! and parentheses
+    #[arg(
+        long = "match",
+        value_name = "EXPR",
+        global = true,
+        conflicts_with = "input_matches"
+    )]
+    pub match_expression: Option<Expression>,
+
+    /// Leave out inputs whose description contains PATTERN (repeatable, case-insensitive),
+    /// e.g. with --input-match '*' to select everything but one device
+    #[arg(long = "input-exclude", value_name = "PATTERN", global = true)]
+    pub input_excludes: Vec<String>,
+
+    /// Select the streams of applications whose application.name contains NAME (repeatable,
+    /// case-insensitive), e.g. "Firefox". Combined with other input selections, only their
+    /// nodes belonging to one of the applications are kept
+    #[arg(long = "input-app", value_name = "NAME", global = true)]
+    pub input_apps: Vec<String>,
+
+    /// Refer to a node by a friendly name, e.g. "Deck=USB Audio CODEC 0x4a2b": a pattern of
+    /// --input-match, --output-match, --route or a rule that equals NAME (case-insensitive)
+    /// stands for PATTERN (repeatable)
+    #[arg(long = "alias", value_name = "NAME=PATTERN", global = true)]
+    pub aliases: Vec<Alias>,
+
+    /// Compare description patterns (--input-match, --route, rules) with the node description
+    /// or its shorter nick, where a device gives one
+    #[arg(long, value_enum, value_name = "FIELD", global = true, default_value_t)]
+    pub match_field: MatchField,
+
+    /// Link at most N of the inputs selected for each output, in node order, as a safety valve
+    /// against patterns matching far more than intended
+    #[arg(long, value_name = "N", global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_inputs: Option<u32>,
+
+    /// Only select inputs whose description contains every --input-match pattern
+    #[arg(long, requires = "input_matches", global = true)]
+    pub input_match_all: bool,
+
+    /// Use the first output when a pattern matches several, instead of refusing to choose.
+    /// Rules can decide for themselves with on_ambiguous
+    #[arg(long, global = true)]
+    pub allow_multiple: bool,
+
+    /// Mix the inputs into every matching output instead of a single one, pairing channels
+    /// separately for each. Rules can do the same with on_ambiguous = "all"
+    #[arg(long, global = true, conflicts_with = "allow_multiple")]
+    pub all_outputs: bool,
+
+    /// Minimum similarity (0 to 1) for a description to match a "fuzzy:" rule pattern
+    #[arg(long, value_name = "SCORE", default_value_t = 0.6, global = true)]
+    pub fuzzy_threshold: f64,
+
+    /// Select an input by its stable object.serial instead of by description (repeatable)
+    #[arg(long = "input-serial", value_name = "SERIAL", global = true)]
+    pub input_serials: Vec<Serial>,
+
+    /// Select an output by its stable object.serial instead of by description (repeatable)
+    #[arg(long = "output-serial", value_name = "SERIAL", global = true)]
+    pub output_serials: Vec<Serial>,
+}
+
+/// Without a subcommand, the mixer links the selected nodes and keeps running
+impl Cli {
+    /// Replace a "-" given to --input-match or --output-match with the patterns on stdin
+    ///
+    /// Stdin is read to the end, so only one of the two may ask for it
+    pub fn read_stdin_patterns(&mut self) -> Result<()> {
+        let is_stdin = |patterns: &Vec<String>| patterns.iter().any(|p| p == STDIN_PATTERN);
+        let patterns = match (
+            is_stdin(&self.input_matches),
+            is_stdin(&self.output_matches),
+        ) {
+            (false, false) => return Ok(()),
+            (true, true) => {
+                return Err(anyhow!(
+                    "Only one of --input-match and --output-match can read patterns from stdin"
+                ))
+            }
+            (true, false) => &mut self.input_matches,
+            (false, true) => &mut self.output_matches,
+        };
+        let mut read = Vec::new();
+        for line in io::stdin().lock().lines() {
+            let line = line.map_err(|error| anyhow!("Failed to read stdin: {:?}", error))?;
+            let line = line.trim();
+            if !line.is_empty() {
+                read.push(line.to_string());
+            }
+        }
+        if read.is_empty() {
+            return Err(anyhow!("No match patterns on stdin"));
+        }
+        patterns.retain(|p| p != STDIN_PATTERN);
+        patterns.extend(read);
+        Ok(())
+    }
+
+    /// Replace a pattern naming an --alias with the pattern it stands for
+    pub fn unalias(&self, pattern: &mut String) {
+        unalias(&self.aliases, pattern);
+    }
+
+    /// Resolve the aliases among the selection patterns given on the command line; those of
+    /// --route are resolved with the rules
+    pub fn apply_aliases(&mut self) {
+        let patterns = self
+            .input_matches
+            .iter_mut()
+            .chain(&mut self.output_matches)
+            .chain(&mut self.input_excludes)
+            .chain(&mut self.mute);
+        for pattern in patterns {
+            unalias(&self.aliases, pattern);
+        }
+    }
+
+    pub fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            include_monitors: self.include_monitors,
+            include_virtual: self.include_virtual,
+            fuzzy_threshold: self.fuzzy_threshold,
+            match_field: self.match_field,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the discovered nodes and exit
+    List {
+        /// Also query and show the formats, sample rates and channel counts of audio nodes
+        #[arg(long, short)]
+        verbose: bool,
+
+        /// Group the nodes under their device, followed by the streams of each application
+        #[arg(long)]
+        by_device: bool,
+    },
+    /// Show the nodes the mixer would select, with their formats, and exit without linking
+    Status,
+    /// Print the entries of every metadata object (defaults, settings, stream targets) and exit
+    Metadata {
+        /// Keep running, printing every change as it happens
+        #[arg(long)]
+        watch: bool,
+
+        /// Print JSON: one document, then one line per change while watching
+        #[arg(long)]
+        json: bool,
+    },
+    /// Destroy the links earlier runs left behind and exit; links without the mixer's stamp are
+    /// never touched. Pass --dry-run to only list them
+    Clean {
+        /// Only the links made for this rule
+        #[arg(long)]
+        rule: Option<String>,
+
+        /// Only the links made by this mixer instance, given by its process id
+        #[arg(long, value_name = "PID")]
+        instance: Option<String>,
+
+        /// Only the links created at least this long ago, e.g. 12h or 7d. Links from versions
+        /// without the creation stamp are left alone
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: Option<Duration>,
+    },
+}
+
+/// Pattern standing for the patterns read from stdin
+const STDIN_PATTERN: &str = "-";
+
+/// How a run treats the links stamped by earlier runs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LinkMode {
+    /// Leave existing links in place and create the missing ones
+    #[default]
+    Additive,
+    /// Remove every link a previous run created before linking
+    Exclusive,
+}
+
+/// Channel names take any alias [`Channel::parse`] knows, so they never fail to parse
+fn parse_channel(name: &str) -> Result<Channel, String> {
+    Ok(Channel::parse(name))
+}
+
+/// One `--channel-map` entry, e.g. `FL:FR`
+fn parse_channel_pair(pair: &str) -> Result<(Channel, Channel), String> {
+    let (from, to) = pair
+        .split_once(':')
+        .ok_or_else(|| format!("expected FROM:TO, got {:?}", pair))?;
+    let (from, to) = (from.trim(), to.trim());
+    if from.is_empty() || to.is_empty() {
+        return Err(format!("both sides of {:?} need a channel", pair));
+    }
+    Ok((Channel::parse(from), Channel::parse(to)))
+}
+
+/// A duration as a number of seconds, or as numbers followed by a unit (`s`, `m`, `h` or `d`),
+/// e.g. `90`, `30m` or `1h30m`
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid duration {:?}, expected e.g. 90s, 30m or 2h", value);
+    let mut total = 0u64;
+    let mut rest = value;
+    if let Ok(seconds) = value.parse::<u64>() {
+        total = seconds;
+        rest = "";
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total = amount
+            .checked_mul(seconds)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + unit.len_utf8()..];
+    }
+    // A zero timeout would disarm the timer rather than fire it at once
+    if total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// One `--alias`: a friendly name and the pattern it stands for
+#[derive(Clone, Debug)]
+pub struct Alias {
+    pub name: String,
+    pub pattern: String,
+}
+
+impl FromStr for Alias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, pattern) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=PATTERN, got {:?}", s))?;
+        let (name, pattern) = (name.trim(), pattern.trim());
+        if name.is_empty() || pattern.is_empty() {
+            return Err(format!("both sides of {:?} need a value", s));
+        }
+        Ok(Alias {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        })
+    }
+}
+
+fn unalias(aliases: &[Alias], pattern: &mut String) {
+    if let Some(alias) = aliases
+        .iter()
+        .find(|alias| alias.name.eq_ignore_ascii_case(pattern))
+    {
+        *pattern = alias.pattern.clone();
+    }
+}
+
+/// One `--route` entry of the routing matrix
+#[derive(Clone, Debug)]
+pub struct RouteSpec {
+    pub input: String,
+    pub output: String,
+}
+
+impl FromStr for RouteSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (input, output) = s
+            .split_once("=>")
+            .ok_or_else(|| format!("expected INPUT=>OUTPUT, got {:?}", s))?;
+        let (input, output) = (input.trim(), output.trim());
+        if input.is_empty() || output.is_empty() {
+            return Err(format!("both sides of {:?} need a pattern", s));
+        }
+        Ok(RouteSpec {
+            input: input.to_string(),
+            output: output.to_string(),
+        })
+    }
+}
+
+impl From<RouteSpec> for Rule {
+    fn from(route: RouteSpec) -> Self {
+        Rule {
+            input: RuleInput::Pattern(route.input),
+            output: route.output,
+            ..Rule::default()
+        }
+    }
+}
+
+impl fmt::Display for RouteSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=>{}", self.input, self.output)
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::MixerError;
+use crate::expr::Expression;
+use crate::gate::LinkWhile;
+use crate::graph::Media;
+use crate::matcher::OnAmbiguous;
+use crate::mixer::Mechanism;
+use crate::plan::{Downmix, ExplicitLink, OnChannelMismatch, Upmix};
+
+/// Contents of the `--config` TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, MixerError> {
+        let text = read(path, "config")?;
+        let config: Config = toml::from_str(&text).map_err(invalid(path, "config"))?;
+        config.validate().map_err(invalid(path, "config"))?;
+        Ok(config)
+    }
+
+    /// Load a version-controlled routing plan, the JSON counterpart of the config file
+    pub fn load_plan(path: &Path) -> Result<Config, MixerError> {
+        let text = read(path, "plan")?;
+        let plan: Plan = serde_json::from_str(&text).map_err(invalid(path, "plan"))?;
+        if plan.rules.is_empty() {
+            return Err(MixerError::InvalidConfig {
+                kind: "plan",
+                path: path.to_path_buf(),
+                reason: "no rules".to_string(),
+            });
+        }
+        let config = Config { rules: plan.rules };
+        config.validate().map_err(invalid(path, "plan"))?;
+        Ok(config)
+    }
+
+    /// Check what the format alone cannot, like a rule giving both `output` and `outputs`
+    fn validate(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            if !rule.outputs.is_empty() && !rule.output.is_empty() {
+                return Err(format!("rule {} gives both output and outputs", rule));
+            }
+            if rule.atomic && rule.outputs.is_empty() {
+                return Err(format!("rule {} is atomic but has no outputs", rule));
+            }
+            // Video and MIDI ports carry no channels to map
+            if rule.media != Media::Audio {
+                let audio_only = [
+                    ("downmix", rule.downmix.is_some()),
+                    ("upmix", rule.upmix.is_some()),
+                    ("channel_map", !rule.channel_map.is_empty()),
+                    ("swap_lr", rule.swap_lr),
+                    ("aux_offset", rule.aux_offset != 0),
+                ];
+                if let Some((option, _)) = audio_only.iter().find(|(_, set)| *set) {
+                    return Err(format!(
+                        "rule {} links {} but sets {}, which only applies to audio",
+                        rule, rule.media, option
+                    ));
+                }
+            }
+            // The session manager links a moved stream as a whole, to a node and not a port
+            if rule.mechanism == Mechanism::Metadata {
+                if rule.monitor || rule.input.is_monitor() {
+                    return Err(format!(
+                        "rule {} taps monitor ports, which mechanism = \"metadata\" cannot route as only application streams can be moved",
+                        rule
+                    ));
+                }
+                if !rule.links.is_empty() {
+                    return Err(format!(
+                        "rule {} gives links, which need mechanism = \"links\"",
+                        rule
+                    ));
+                }
+                if rule.link_while == LinkWhile::Running {
+                    return Err(format!(
+                        "rule {} sets link_while = \"running\", which needs mechanism = \"links\" as there are no links to drop otherwise",
+                        rule
+                    ));
+                }
+            }
+            if rule.idle_grace_ms.is_some() && rule.link_while != LinkWhile::Running {
+                return Err(format!(
+                    "rule {} sets idle_grace_ms, which only applies with link_while = \"running\"",
+                    rule
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read a config or plan file whole
+fn read(path: &Path, kind: &'static str) -> Result<String, MixerError> {
+    std::fs::read_to_string(path).map_err(|source| MixerError::ReadFailed {
+        kind,
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Wrap why a config or plan file is invalid, for use with `map_err`
+fn invalid<E: fmt::Display>(path: &Path, kind: &'static str) -> impl FnOnce(E) -> MixerError {
+    let path = path.to_path_buf();
+    move |reason| MixerError::InvalidConfig {
+        kind,
+        path,
+        reason: reason.to_string(),
+    }
+}
+
+/// Contents of the `--plan` JSON file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Plan {
+    rules: Vec<Rule>,
+}
+
+/// Mix the inputs whose description contains `input` into the output whose description
+/// contains `output` (both case-insensitive)
+///
+/// Identical devices share a description, so either side can also be pinned by properties
+/// that survive re-enumeration, like `device.bus-path`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: Option<String>,
+    /// May be left empty when the input is matched by its properties or an expression
+    #[serde(default)]
+    pub input: RuleInput,
+    /// May be left empty to consider every output, e.g. to pick one by form factor
+    #[serde(default)]
+    pub output: String,
+    /// Properties an input must have, compared exactly, e.g. `{ "device.serial" = "..." }`
+    #[serde(default)]
+    pub input_properties: BTreeMap<String, String>,
+    /// Properties an output must have, compared exactly
+    #[serde(default)]
+    pub output_properties: BTreeMap<String, String>,
+    /// Expression an input must satisfy, e.g. `class == "Audio/Source" && !desc ~ "Monitor"`
+    #[serde(default)]
+    pub input_match: Option<Expression>,
+    /// Expression an output must satisfy
+    #[serde(default)]
+    pub output_match: Option<Expression>,
+    /// `media.role` an input stream must have (case-insensitive), e.g. "Music"
+    #[serde(default)]
+    pub role: Option<String>,
+    /// Rank matching outputs by `device.form-factor`, e.g. `["headset", "headphone", "speaker"]`
+    #[serde(default)]
+    pub output_prefer_form_factor: Vec<String>,
+    /// Shift input AUXn onto output AUX(n + aux_offset), e.g. 8 to send AUX0/1 to AUX8/9
+    #[serde(default)]
+    pub aux_offset: i32,
+    /// Use the monitor ports of matching sinks as sources, which are skipped by default
+    #[serde(default)]
+    pub monitor: bool,
+    /// Explicit source channel -> destination channel(s) pairs, e.g. `{ "AUX0" = "FL" }` or
+    /// `{ "FC" = ["FL", "FR"] }`
+    #[serde(default)]
+    pub channel_map: BTreeMap<String, ChannelTargets>,
+    /// Fold channels the output lacks into the ones it has: "stereo", or "stereo-lfe" to keep LFE
+    #[serde(default)]
+    pub downmix: Option<Downmix>,
+    /// Copy input channels onto output channels nothing else feeds: "copy-rears" plays FL/FR
+    /// on RL/RR as well
+    #[serde(default)]
+    pub upmix: Option<Upmix>,
+    /// Cross FL and FR of the input, e.g. for a receiver wired the wrong way round. Explicit
+    /// `channel_map` entries are left as written
+    #[serde(default)]
+    pub swap_lr: bool,
+    /// Exact port pairs, e.g. `[{ from = "AUX3", to = "FL" }, { from_port = 57, to_port = 112 }]`,
+    /// used instead of any automatic pairing
+    #[serde(default)]
+    pub links: Vec<ExplicitLink>,
+    /// Only link these input channels, e.g. `["FL", "FR"]`, overriding --input-channels
+    #[serde(default)]
+    pub input_channels: Vec<String>,
+    /// Split the input across several outputs, each fed its own channels, e.g.
+    /// `[{ match = "DAC A", channels = ["FL"] }, { match = "DAC B", channels = ["FR"] }]`.
+    /// Replaces `output`
+    #[serde(default)]
+    pub outputs: Vec<OutputLeg>,
+    /// With `outputs`, link every leg or none: a leg that cannot be linked removes the others
+    #[serde(default)]
+    pub atomic: bool,
+    /// Set on the rules [`Rule::legs`] derives, to group them under the rule they came from
+    #[serde(skip)]
+    pub leg: Option<Leg>,
+    /// Destroy links into the output from anything the rules do not route to it, e.g. an
+    /// application stream the session manager linked straight to the sink
+    #[serde(default)]
+    pub exclusive: bool,
+    /// When the input and output channels differ: "truncate" links what pairs silently, "warn"
+    /// (the default) also logs the unrouted and silent channels, "error" links nothing
+    #[serde(default)]
+    pub on_channel_mismatch: OnChannelMismatch,
+    /// Link an input with a single port (e.g. a MONO microphone) to both FL and FR. On by
+    /// default for such inputs; false pairs their port like any other
+    #[serde(default)]
+    pub mono_to_stereo: Option<bool>,
+    /// Link every port of an input to an output with a single port (e.g. a MONO amplifier), so
+    /// they are summed. On by default for such outputs; false pairs the ports like any other
+    #[serde(default)]
+    pub stereo_to_mono: Option<bool>,
+    /// What to do when a side matches several nodes: "first", "all" or "error". By default
+    /// several outputs are an error and all matching inputs are mixed
+    #[serde(default)]
+    pub on_ambiguous: Option<OnAmbiguous>,
+    /// Milliseconds a node matching the rule must exist, unchanged by a reappearance, before it
+    /// is linked, e.g. 1000 for Bluetooth devices that rename themselves after connecting
+    #[serde(default)]
+    pub settle_ms: u64,
+    /// How inputs reach the output: "links" (the default) creates the links itself, "metadata"
+    /// sets `target.object` on application streams so the session manager moves them
+    #[serde(default)]
+    pub mechanism: Mechanism,
+    /// What the rule links: "audio" (the default), "video", e.g. a capture card into a virtual
+    /// camera, or "midi", e.g. a keyboard into a synth. Nodes not carrying it never match
+    #[serde(default)]
+    pub media: Media,
+    /// When inputs are linked: "always" (the default), or "running" to only link an input while
+    /// its node is running, e.g. a capture card that carries signal only when the console is on
+    #[serde(default)]
+    pub link_while: LinkWhile,
+    /// With link_while = "running", milliseconds an input may stay idle or suspended before it is
+    /// unlinked, 5000 by default
+    #[serde(default)]
+    pub idle_grace_ms: Option<u64>,
+}
+
+/// One output of a rule splitting its input, and the input channels sent to it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputLeg {
+    /// Pattern the output is matched with, like a rule's `output`
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Input channels sent to this output, all when empty
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+/// Where a rule derived from `outputs` came from
+#[derive(Clone, Debug)]
+pub struct Leg {
+    pub rule: String, // Display of the rule giving the outputs
+    pub index: usize,
+    pub count: usize,
+    pub atomic: bool,
+}
+
+impl Rule {
+    /// The rules to select with: one per entry of `outputs`, or the rule itself without any
+    pub fn legs(&self) -> Vec<Rule> {
+        if self.outputs.is_empty() {
+            return vec![self.clone()];
+        }
+        let name = self.to_string();
+        self.outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| Rule {
+                output: output.pattern.clone(),
+                input_channels: if output.channels.is_empty() {
+                    self.input_channels.clone()
+                } else {
+                    output.channels.clone()
+                },
+                outputs: Vec::new(),
+                leg: Some(Leg {
+                    rule: name.clone(),
+                    index,
+                    count: self.outputs.len(),
+                    atomic: self.atomic,
+                }),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Right-hand side of a `channel_map` entry: one destination channel or several
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ChannelTargets {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ChannelTargets {
+    pub fn names(&self) -> &[String] {
+        match self {
+            ChannelTargets::One(name) => std::slice::from_ref(name),
+            ChannelTargets::Many(names) => names,
+        }
+    }
+}
+
+/// The input side of a rule: a description pattern, or the sink whose monitor ports to use
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RuleInput {
+    Pattern(String),
+    /// `input.monitor_of = "Desktop"`: cascade what the matching sink plays into the output
+    Monitor {
+        monitor_of: String,
+    },
+}
+
+impl RuleInput {
+    /// The pattern the input description is compared with
+    pub fn pattern(&self) -> &str {
+        match self {
+            RuleInput::Pattern(pattern) => pattern,
+            RuleInput::Monitor { monitor_of } => monitor_of,
+        }
+    }
+
+    /// True if the input is a sink tapped through its monitor ports
+    pub fn is_monitor(&self) -> bool {
+        matches!(self, RuleInput::Monitor { .. })
+    }
+}
+
+impl Default for RuleInput {
+    fn default() -> Self {
+        RuleInput::Pattern(String::new())
+    }
+}
+
+impl fmt::Display for RuleInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleInput::Pattern(pattern) => f.write_str(pattern),
+            RuleInput::Monitor { monitor_of } => write!(f, "monitor of {}", monitor_of),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, &self.leg) {
+            (Some(name), Some(leg)) => write!(f, "{} (leg {})", name, leg.index + 1),
+            (Some(name), None) => f.write_str(name),
+            (None, _) if !self.outputs.is_empty() => {
+                let outputs: Vec<&str> = self.outputs.iter().map(|o| o.pattern.as_str()).collect();
+                write!(f, "{}=>[{}]", self.input, outputs.join(", "))
+            }
+            (None, _) => write!(f, "{}=>{}", self.input, self.output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `contents` to a file of its own under the temporary directory
+    fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("pie_mixer-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_file_fails_to_read() {
+        let path = Path::new("/nonexistent/pie_mixer.toml");
+        let error = Config::load(path).unwrap_err();
+        assert!(matches!(
+            error,
+            MixerError::ReadFailed { kind: "config", .. }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Failed to read config /nonexistent/pie_mixer.toml"
+        );
+    }
+
+    #[test]
+    fn contradicting_rules_are_invalid() {
+        let path = write_temp(
+            "both.toml",
+            "[[rule]]\ninput = \"Mic\"\noutput = \"Speakers\"\noutputs = [{ match = \"Headphones\" }]\n",
+        );
+        let error = Config::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        let MixerError::InvalidConfig { kind, reason, .. } = &error else {
+            panic!("expected an invalid config, got {:?}", error);
+        };
+        assert_eq!(*kind, "config");
+        assert!(
+            reason.contains("gives both output and outputs"),
+            "{}",
+            reason
+        );
+    }
+
+    #[test]
+    fn plan_without_rules_is_invalid() {
+        let path = write_temp("empty.json", "{\"rules\": []}");
+        let error = Config::load_plan(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("Invalid plan {}: no rules", path.display())
+        );
+    }
+}
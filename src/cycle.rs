@@ -0,0 +1,92 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::graph::Serial;
+
+/// Edge of the node graph, pointing the way audio flows: (producing node, consuming node)
+pub type Edge = (Serial, Serial);
+
+/// Find a feedback loop that adding the `planned` edges to the `existing` ones would close,
+/// returning the nodes along it, starting and ending with the same node
+///
+/// Only loops running through a planned edge are reported: a loop made of existing links alone
+/// is not undone by refusing the planned ones. A planned edge from a node to itself is the
+/// shortest loop there is
+pub fn find_cycle(existing: &[Edge], planned: &[Edge]) -> Option<Vec<Serial>> {
+    let mut next: HashMap<Serial, Vec<Serial>> = HashMap::new();
+    for (from, to) in existing.iter().chain(planned) {
+        next.entry(*from).or_default().push(*to);
+    }
+    planned.iter().find_map(|(from, to)| {
+        let mut path = vec![*from];
+        path.extend(shortest_path(&next, *to, *from)?);
+        Some(path)
+    })
+}
+
+/// Nodes along the shortest path from `start` to `goal`, both included
+fn shortest_path(
+    next: &HashMap<Serial, Vec<Serial>>,
+    start: Serial,
+    goal: Serial,
+) -> Option<Vec<Serial>> {
+    let mut previous: HashMap<Serial, Serial> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        if node == goal {
+            let mut path = vec![goal];
+            while let Some(before) = previous.get(path.last()?) {
+                path.push(*before);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for to in next.get(&node).into_iter().flatten() {
+            if *to != start && !previous.contains_key(to) {
+                previous.insert(*to, node);
+                queue.push_back(*to);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_link_is_a_loop() {
+        assert_eq!(find_cycle(&[], &[(7, 7)]), Some(vec![7, 7]));
+    }
+
+    #[test]
+    fn planned_edge_closing_a_two_node_loop() {
+        assert_eq!(find_cycle(&[(2, 1)], &[(1, 2)]), Some(vec![1, 2, 1]));
+    }
+
+    #[test]
+    fn loop_of_existing_edges_alone_is_not_reported() {
+        assert_eq!(find_cycle(&[(1, 2), (2, 1)], &[(3, 4)]), None);
+    }
+
+    #[test]
+    fn shortest_loop_is_returned() {
+        let existing = [(2, 3), (3, 4), (4, 1), (2, 1)];
+        assert_eq!(find_cycle(&existing, &[(1, 2)]), Some(vec![1, 2, 1]));
+        let existing = [(2, 3), (3, 4), (4, 1), (2, 5), (5, 1)];
+        assert_eq!(find_cycle(&existing, &[(1, 2)]), Some(vec![1, 2, 5, 1]));
+    }
+
+    #[test]
+    fn planned_edge_without_a_way_back() {
+        assert_eq!(find_cycle(&[(2, 3), (3, 4)], &[(1, 2)]), None);
+    }
+
+    #[test]
+    fn loop_through_several_planned_edges() {
+        assert_eq!(
+            find_cycle(&[(3, 1)], &[(1, 2), (2, 3)]),
+            Some(vec![1, 2, 3, 1])
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+
+use pipewire::context::ContextRc;
+use pipewire::core::CoreRc;
+use pipewire::main_loop::MainLoopRc;
+use pipewire::registry::{GlobalObject, Listener, RegistryRc};
+use pipewire::spa::utils::dict::DictRef;
+use pipewire::types::ObjectType;
+
+use crate::error::MixerError;
+use crate::graph::{Graph, NodeInfo, NodeKind, PortInfo, Removed, Serial, UNKNOWN_MEDIA_CLASS};
+
+/// A connection to the PipeWire daemon with its registry
+///
+/// Listeners and proxies only work while the whole connection is kept alive
+pub struct Connection {
+    pub main_loop: MainLoopRc,
+    pub context: ContextRc,
+    pub core: CoreRc,
+    pub registry: RegistryRc,
+}
+
+/// Connect to the PipeWire daemon and request the registry of its globals
+pub fn connect() -> Result<Connection, MixerError> {
+    pipewire::init();
+    let main_loop =
+        MainLoopRc::new(None).map_err(MixerError::connect("initialize PipeWire main loop"))?;
+    let context =
+        ContextRc::new(&main_loop, None).map_err(MixerError::connect("create PipeWire context"))?;
+    let core = context
+        .connect_rc(None)
+        .map_err(MixerError::connect("connect to PipeWire core"))?;
+    let registry = core.get_registry_rc().map_err(MixerError::RegistryFailed)?;
+    Ok(Connection {
+        main_loop,
+        context,
+        core,
+        registry,
+    })
+}
+
+/// What [`collect`] recorded for a registry global
+#[derive(Clone, Debug)]
+pub enum Collected {
+    Node(Serial),
+    /// A port added to the node with serial `node`
+    ///
+    /// `reclassified` is set when the port changed the direction of a node without media class
+    Port {
+        node: Serial,
+        port: PortInfo,
+        reclassified: bool,
+    },
+}
+
+/// Record a Node or Port global in the graph, ignoring every other type
+pub fn collect(graph: &mut Graph, global: &GlobalObject<&DictRef>) -> Option<Collected> {
+    let props = global.props?;
+    if global.type_ == ObjectType::Node {
+        let node = NodeInfo::from_props(global.id, props);
+        let serial = node.serial;
+        graph.add_node(node);
+        Some(Collected::Node(serial))
+    } else if global.type_ == ObjectType::Port {
+        let (node_id, port) = PortInfo::from_props(global.id, props)?;
+        let direction = |graph: &Graph| {
+            graph
+                .nodes()
+                .find(|node| node.global_id == node_id)
+                .map(|node| (node.classified, node.input))
+        };
+        let before = direction(graph);
+        let node = graph.add_port(node_id, port.clone())?;
+        let reclassified = graph
+            .node(node)
+            .is_some_and(|info| info.media_class == UNKNOWN_MEDIA_CLASS)
+            && direction(graph) != before;
+        Some(Collected::Port {
+            node,
+            port,
+            reclassified,
+        })
+    } else {
+        None
+    }
+}
+
+/// Keep `graph` in step with the registry, handing every evicted node or port to `removed`
+pub fn watch(
+    registry: &RegistryRc,
+    graph: Arc<Mutex<Graph>>,
+    removed: impl Fn(Removed) + 'static,
+) -> Listener {
+    let graph_removal = graph.clone();
+    registry
+        .add_listener_local()
+        .global(move |global| {
+            if let Ok(mut graph) = graph.lock() {
+                collect(&mut graph, global);
+            }
+        })
+        .global_remove(move |id| {
+            let evicted = graph_removal
+                .lock()
+                .ok()
+                .and_then(|mut graph| graph.remove_global(id));
+            if let Some(evicted) = evicted {
+                removed(evicted);
+            }
+        })
+        .register()
+}
+
+/// Audio devices playing to the speakers, like ALSA or Bluetooth sinks, in global id order
+///
+/// Connects to PipeWire, discovers the graph and disconnects again; application streams are
+/// left out
+pub fn list_sinks() -> Result<Vec<NodeInfo>, MixerError> {
+    Ok(discover()?
+        .into_iter()
+        .filter(|node| !node.input && node.kind() == NodeKind::AudioOutput && !node.is_stream())
+        .collect())
+}
+
+/// Audio devices capturing sound, like microphones and line inputs, in global id order
+///
+/// Like [`list_sinks`], application streams are left out
+pub fn list_sources() -> Result<Vec<NodeInfo>, MixerError> {
+    Ok(discover()?
+        .into_iter()
+        .filter(|node| node.input && node.kind() == NodeKind::AudioInput && !node.is_stream())
+        .collect())
+}
+
+/// Every node with its ports, in global id order, as found by a single discovery
+pub fn discover() -> Result<Vec<NodeInfo>, MixerError> {
+    let Connection {
+        main_loop,
+        context: _context,
+        core,
+        registry,
+    } = connect()?;
+
+    let graph = Arc::new(Mutex::new(Graph::default()));
+    let _registry_listener = watch(&registry, graph.clone(), |_| {});
+
+    // The globals are all announced once the daemon answers a sync sent after the registry
+    let pending = core.sync(0).map_err(MixerError::SyncFailed)?;
+    let main_loop_quit = main_loop.clone();
+    let _core_listener = core
+        .add_listener_local()
+        .done(move |_id, seq| {
+            if seq == pending {
+                main_loop_quit.quit();
+            }
+        })
+        .register();
+    main_loop.run();
+
+    let mut nodes: Vec<NodeInfo> = graph
+        .lock()
+        .map(|graph| graph.nodes().cloned().collect())
+        .unwrap_or_default();
+    nodes.sort_by_key(|node| node.global_id);
+    Ok(nodes)
+}
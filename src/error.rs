@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Failures of the mixer that callers may want to tell apart
+#[derive(Debug, Error)]
+pub enum MixerError {
+    /// Setting up the main loop, context or core connection failed
+    #[error("Failed to {step}")]
+    ConnectFailed {
+        step: &'static str, // e.g. "create PipeWire context"
+        #[source]
+        source: pipewire::Error,
+    },
+    #[error("Failed to retrieve PipeWire registry")]
+    RegistryFailed(#[source] pipewire::Error),
+    #[error("PipeWire sync failed")]
+    SyncFailed(#[source] pipewire::Error),
+    #[error("No matching input(s) found")]
+    NoInputs,
+    #[error("No matching output found")]
+    NoOutputs,
+    #[error("Failed to create link for channel {channel}")]
+    LinkFailed {
+        channel: String,
+        #[source]
+        source: pipewire::Error,
+    },
+    /// A port or node of the link was removed between planning and linking
+    #[error("Skipping channel {channel}: node or port no longer exists")]
+    PortVanished { channel: String },
+    #[error("Failed to spawn the mixer thread")]
+    SpawnFailed(#[source] std::io::Error),
+    /// The loop thread of a [`crate::service::MixerService`] is gone
+    #[error("Mixer thread stopped before answering")]
+    Stopped,
+    /// A config or plan file could not be read
+    #[error("Failed to read {kind} {}", path.display())]
+    ReadFailed {
+        kind: &'static str, // "config" or "plan"
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A config or plan file does not parse, or its rules contradict each other
+    #[error("Invalid {kind} {}: {reason}", path.display())]
+    InvalidConfig {
+        kind: &'static str, // "config" or "plan"
+        path: PathBuf,
+        reason: String,
+    },
+    /// The HTTP server could not bind its address or start its thread
+    #[error("Failed to start HTTP server on {addr}")]
+    ServeFailed {
+        addr: std::net::SocketAddr,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl MixerError {
+    /// Wrap the error of one connection step, for use with `map_err`
+    pub fn connect(step: &'static str) -> impl FnOnce(pipewire::Error) -> MixerError {
+        move |source| MixerError::ConnectFailed { step, source }
+    }
+}
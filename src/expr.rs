@@ -0,0 +1,567 @@
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::graph::NodeInfo;
+use crate::matcher::Check;
+
+/// Node attribute an expression compares against
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Class,            // media.class
+    Description,      // desc
+    Nick,             // node.nick
+    Name,             // node.name
+    Property(String), // prop["key"]
+}
+
+impl Field {
+    fn value<'a>(&self, node: &'a NodeInfo) -> Option<&'a str> {
+        match self {
+            Field::Class => Some(&node.media_class),
+            Field::Description => Some(&node.description),
+            Field::Nick => node.nick.as_deref(),
+            Field::Name => node.properties.get("node.name").map(String::as_str),
+            Field::Property(key) => node.properties.get(key).map(String::as_str),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Class => f.write_str("class"),
+            Field::Description => f.write_str("desc"),
+            Field::Nick => f.write_str("nick"),
+            Field::Name => f.write_str("name"),
+            Field::Property(key) => write!(f, "prop[{:?}]", key),
+        }
+    }
+}
+
+/// How a field is compared with a string
+#[derive(Clone, Debug)]
+pub enum Operator {
+    Equals,         // ==, exact
+    Contains,       // ~, case-insensitive substring like the description patterns
+    Matches(Regex), // =~
+}
+
+/// Condition tree evaluated against a node
+#[derive(Clone, Debug)]
+pub enum Condition {
+    Compare {
+        field: Field,
+        operator: Operator,
+        value: String,
+    },
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    /// Evaluate every comparison, without short-circuiting, so each one can be reported
+    fn evaluate(&self, node: &NodeInfo, details: &mut Vec<Check>) -> bool {
+        match self {
+            Condition::Compare {
+                field,
+                operator,
+                value,
+            } => {
+                let passed = field.value(node).is_some_and(|actual| match operator {
+                    Operator::Equals => actual == value,
+                    Operator::Contains => actual.to_uppercase().contains(&value.to_uppercase()),
+                    Operator::Matches(regex) => regex.is_match(actual),
+                });
+                details.push(Check {
+                    condition: self.to_string(),
+                    passed,
+                    details: Vec::new(),
+                });
+                passed
+            }
+            Condition::Not(inner) => !inner.evaluate(node, details),
+            Condition::And(left, right) => {
+                let left = left.evaluate(node, details);
+                right.evaluate(node, details) && left
+            }
+            Condition::Or(left, right) => {
+                let left = left.evaluate(node, details);
+                right.evaluate(node, details) || left
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Compare {
+                field,
+                operator,
+                value,
+            } => {
+                let operator = match operator {
+                    Operator::Equals => "==",
+                    Operator::Contains => "~",
+                    Operator::Matches(_) => "=~",
+                };
+                write!(f, "{} {} {:?}", field, operator, value)
+            }
+            Condition::Not(inner) => match **inner {
+                Condition::Compare { .. } | Condition::Not(_) => write!(f, "!{}", inner),
+                _ => write!(f, "!({})", inner),
+            },
+            Condition::And(left, right) => {
+                let group = |c: &Condition| match c {
+                    Condition::Or(..) => format!("({})", c),
+                    _ => c.to_string(),
+                };
+                write!(f, "{} && {}", group(left), group(right))
+            }
+            Condition::Or(left, right) => write!(f, "{} || {}", left, right),
+        }
+    }
+}
+
+/// A parsed `--match` expression, e.g.
+/// `class == "Audio/Source" && (desc ~ "Scarlett" || prop["node.name"] =~ "^alsa") && !desc ~ "Monitor"`
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    condition: Condition,
+}
+
+impl Expression {
+    /// Evaluate the expression as one check, with each comparison attached as a detail
+    pub fn check(&self, node: &NodeInfo) -> Check {
+        let mut details = Vec::new();
+        let passed = self.condition.evaluate(node, &mut details);
+        Check {
+            condition: self.source.clone(),
+            passed,
+            details,
+        }
+    }
+
+    pub fn matches(&self, node: &NodeInfo) -> bool {
+        self.check(node).passed
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            source,
+            tokens,
+            position: 0,
+        };
+        let condition = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(parser.error_at(token.column, "expected && or || or the end"));
+        }
+        Ok(Expression {
+            source: source.to_string(),
+            condition,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Syntax error in an expression, pointing at the offending column
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize, // 1-based, in characters
+    pub message: String,
+    source: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} at column {}", self.message, self.column)?;
+        writeln!(f, "  {}", self.source)?;
+        write!(f, "  {:>width$}", "^", width = self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Str(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    And,
+    Or,
+    Not,
+    Equals,
+    Tilde,
+    RegexMatch,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let error = |column: usize, message: &str| ParseError {
+        column,
+        message: message.to_string(),
+        source: source.to_string(),
+    };
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let next = chars.get(i + 1).copied();
+        let (kind, len) = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => (TokenKind::LParen, 1),
+            ')' => (TokenKind::RParen, 1),
+            '[' => (TokenKind::LBracket, 1),
+            ']' => (TokenKind::RBracket, 1),
+            '!' => (TokenKind::Not, 1),
+            '~' => (TokenKind::Tilde, 1),
+            '&' if next == Some('&') => (TokenKind::And, 2),
+            '|' if next == Some('|') => (TokenKind::Or, 2),
+            '=' if next == Some('=') => (TokenKind::Equals, 2),
+            '=' if next == Some('~') => (TokenKind::RegexMatch, 2),
+            '"' => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(error(column, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            match chars.get(j + 1) {
+                                Some(escaped @ ('"' | '\\')) => value.push(*escaped),
+                                _ => return Err(error(j + 1, "expected \\\" or \\\\")),
+                            }
+                            j += 2;
+                        }
+                        Some(c) => {
+                            value.push(*c);
+                            j += 1;
+                        }
+                    }
+                }
+                (TokenKind::Str(value), j + 1 - i)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                let ident: String = chars[i..i + len].iter().collect();
+                (TokenKind::Ident(ident), len)
+            }
+            _ => return Err(error(column, "unexpected character")),
+        };
+        tokens.push(Token { kind, column });
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, by increasing precedence:
+///
+/// ```text
+/// or      = and ("||" and)*
+/// and     = unary ("&&" unary)*
+/// unary   = "!" unary | primary
+/// primary = "(" or ")" | field ("==" | "~" | "=~") string
+/// field   = "class" | "desc" | "nick" | "name" | "prop" "[" string "]"
+/// ```
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.and()?;
+        while self.accept(&TokenKind::Or) {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.unary()?;
+        while self.accept(&TokenKind::And) {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self) -> Result<Condition, ParseError> {
+        if self.accept(&TokenKind::Not) {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition, ParseError> {
+        if self.accept(&TokenKind::LParen) {
+            let condition = self.or()?;
+            self.expect(&TokenKind::RParen, "expected )")?;
+            return Ok(condition);
+        }
+        let field = self.field()?;
+        let column = self.column();
+        let operator = self.next().map(|token| token.kind);
+        if !matches!(
+            operator,
+            Some(TokenKind::Equals | TokenKind::Tilde | TokenKind::RegexMatch)
+        ) {
+            return Err(self.error_at(column, "expected ==, ~ or =~"));
+        }
+        let value_column = self.column();
+        let value = self.string()?;
+        let operator = match operator {
+            Some(TokenKind::Equals) => Operator::Equals,
+            Some(TokenKind::Tilde) => Operator::Contains,
+            _ => Operator::Matches(Regex::new(&value).map_err(|error| {
+                // Syntax errors span several lines ending with the reason, e.g. "error: unclosed group"
+                let error = error.to_string();
+                let reason = error.lines().last().unwrap_or_default();
+                let reason = reason.trim_start_matches("error: ");
+                self.error_at(value_column, &format!("invalid regex ({})", reason))
+            })?),
+        };
+        Ok(Condition::Compare {
+            field,
+            operator,
+            value,
+        })
+    }
+
+    fn field(&mut self) -> Result<Field, ParseError> {
+        let column = self.column();
+        let Some(TokenKind::Ident(name)) = self.next().map(|token| token.kind) else {
+            return Err(self.error_at(column, "expected a field, ( or !"));
+        };
+        match name.as_str() {
+            "class" => Ok(Field::Class),
+            "desc" => Ok(Field::Description),
+            "nick" => Ok(Field::Nick),
+            "name" => Ok(Field::Name),
+            "prop" => {
+                self.expect(&TokenKind::LBracket, "expected [ after prop")?;
+                let key = self.string()?;
+                self.expect(&TokenKind::RBracket, "expected ]")?;
+                Ok(Field::Property(key))
+            }
+            _ => Err(self.error_at(
+                column,
+                "unknown field, expected class, desc, nick, name or prop[...]",
+            )),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let column = self.column();
+        match self.next().map(|token| token.kind) {
+            Some(TokenKind::Str(value)) => Ok(value),
+            _ => Err(self.error_at(column, "expected a quoted string")),
+        }
+    }
+
+    fn accept(&mut self, kind: &TokenKind) -> bool {
+        let matched = self
+            .tokens
+            .get(self.position)
+            .is_some_and(|token| token.kind == *kind);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, kind: &TokenKind, message: &str) -> Result<(), ParseError> {
+        let column = self.column();
+        if self.accept(kind) {
+            Ok(())
+        } else {
+            Err(self.error_at(column, message))
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    /// Column of the next token, or just past the end of the input
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.source.chars().count() + 1, |token| token.column)
+    }
+
+    fn error_at(&self, column: usize, message: &str) -> ParseError {
+        ParseError {
+            column,
+            message: message.to_string(),
+            source: self.source.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::node;
+
+    fn parse(source: &str) -> Condition {
+        source.parse::<Expression>().unwrap().condition
+    }
+
+    fn error(source: &str) -> ParseError {
+        source.parse::<Expression>().unwrap_err()
+    }
+
+    fn matches(source: &str, description: &str) -> bool {
+        let node = node(1, description, "Audio/Source", Vec::new());
+        source.parse::<Expression>().unwrap().matches(&node)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let condition = parse(r#"desc ~ "a" || desc ~ "b" && desc ~ "c""#);
+        let Condition::Or(_, right) = condition else {
+            panic!("expected || at the top, got {}", condition);
+        };
+        assert!(matches!(*right, Condition::And(..)));
+        assert!(matches(r#"desc ~ "a" || desc ~ "b" && desc ~ "c""#, "a"));
+        assert!(!matches(r#"desc ~ "a" || desc ~ "b" && desc ~ "c""#, "b"));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let condition = parse(r#"!desc ~ "x" && desc ~ "y""#);
+        let Condition::And(left, _) = condition else {
+            panic!("expected && at the top, got {}", condition);
+        };
+        assert!(matches!(*left, Condition::Not(_)));
+    }
+
+    #[test]
+    fn not_applies_to_the_whole_comparison() {
+        let Condition::Not(inner) = parse(r#"!desc ~ "x""#) else {
+            panic!("expected !");
+        };
+        assert!(matches!(
+            *inner,
+            Condition::Compare {
+                field: Field::Description,
+                operator: Operator::Contains,
+                ..
+            }
+        ));
+        assert!(!matches(r#"!desc ~ "x""#, "x card"));
+        assert!(matches(r#"!desc ~ "x""#, "card"));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let condition = parse(r#"(desc ~ "a" || desc ~ "b") && desc ~ "c""#);
+        let Condition::And(left, _) = condition else {
+            panic!("expected && at the top, got {}", condition);
+        };
+        assert!(matches!(*left, Condition::Or(..)));
+        assert!(!matches(r#"(desc ~ "a" || desc ~ "b") && desc ~ "c""#, "a"));
+        assert!(matches(
+            r#"(desc ~ "a" || desc ~ "b") && desc ~ "c""#,
+            "a c"
+        ));
+        assert!(matches(r#"!(desc ~ "a" || desc ~ "b")"#, "c"));
+    }
+
+    #[test]
+    fn strings_unescape_quotes_and_backslashes() {
+        let Condition::Compare { value, .. } = parse(r#"desc == "say \"hi\" \\ bye""#) else {
+            panic!("expected a comparison");
+        };
+        assert_eq!(value, r#"say "hi" \ bye"#);
+    }
+
+    #[test]
+    fn prop_reads_any_property() {
+        let Condition::Compare { field, .. } = parse(r#"prop["device.bus-path"] == "usb-1""#)
+        else {
+            panic!("expected a comparison");
+        };
+        assert_eq!(field, Field::Property("device.bus-path".to_string()));
+
+        let mut card = node(1, "Card", "Audio/Sink", Vec::new());
+        card.properties
+            .insert("device.bus-path".to_string(), "usb-1".to_string());
+        let expression: Expression = r#"prop["device.bus-path"] == "usb-1""#.parse().unwrap();
+        assert!(expression.matches(&card));
+        let missing: Expression = r#"prop["device.serial"] == "usb-1""#.parse().unwrap();
+        assert!(!missing.matches(&card));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_column() {
+        let cases = [
+            (r#"desc ~ "abc"#, 8, "unterminated string"),
+            (r#"desc ~ "a" && bogus == "x""#, 15, "unknown field"),
+            (r#"desc "x""#, 6, "expected ==, ~ or =~"),
+            (r#"desc ~ "a" desc"#, 12, "expected && or || or the end"),
+            (r#"name =~ "(""#, 9, "invalid regex"),
+        ];
+        for (source, column, message) in cases {
+            let error = error(source);
+            assert_eq!(error.column, column, "column for {}", source);
+            assert!(
+                error.message.starts_with(message),
+                "message for {}: {}",
+                source,
+                error.message
+            );
+        }
+    }
+
+    #[test]
+    fn display_puts_the_caret_under_the_column() {
+        let error = error(r#"desc "x""#);
+        let lines: Vec<String> = error.to_string().lines().map(str::to_string).collect();
+        assert_eq!(lines[0], "expected ==, ~ or =~ at column 6");
+        assert_eq!(lines[1], r#"  desc "x""#);
+        assert_eq!(lines[2], "       ^");
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::process::ExitCode;
+
+use pie_mixer::error::MixerError;
+
+/// Failures of the binary scripts can tell apart by the exit code, next to those of
+/// [`MixerError`]; any other error exits with 1
+///
+/// Attached to an error directly or as context, and found again with `downcast_ref`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Config,
+    AlreadyRunning,
+    PartialLinks,
+    Incomplete,
+    NoLinks,
+}
+
+impl Failure {
+    /// The exit code of the failure
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Config => 5,
+            Failure::AlreadyRunning => 6,
+            // Either way the mixer runs with fewer links than it was asked for
+            Failure::PartialLinks | Failure::Incomplete => 7,
+            Failure::NoLinks => 8,
+        }
+    }
+
+    /// Exit code for an error, 1 unless it carries a [`Failure`] or a [`MixerError`] with a code
+    pub fn exit_code(error: &anyhow::Error) -> ExitCode {
+        if let Some(failure) = error.downcast_ref::<Failure>() {
+            return ExitCode::from(failure.code());
+        }
+        let code = match error.downcast_ref::<MixerError>() {
+            Some(MixerError::NoInputs) => 2,
+            Some(MixerError::NoOutputs) => 3,
+            Some(
+                MixerError::ConnectFailed { .. }
+                | MixerError::RegistryFailed(_)
+                | MixerError::SyncFailed(_),
+            ) => 4,
+            _ => 1,
+        };
+        ExitCode::from(code)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::Config => "Invalid configuration",
+            Failure::AlreadyRunning => "Another instance is already running",
+            Failure::PartialLinks => "Some links did not become active",
+            Failure::Incomplete => "A stereo input is only half linked",
+            Failure::NoLinks => "No link became active",
+        })
+    }
+}
+
+impl std::error::Error for Failure {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn failures_carry_their_exit_code() {
+        let error = anyhow!("1 stereo input(s) not fully linked").context(Failure::Incomplete);
+        assert_eq!(Failure::exit_code(&error), ExitCode::from(7));
+        assert_eq!(error.to_string(), "A stereo input is only half linked");
+        let error = Err::<(), _>(anyhow!("bad rule"))
+            .context(Failure::Config)
+            .unwrap_err();
+        assert_eq!(Failure::exit_code(&error), ExitCode::from(5));
+        assert_eq!(Failure::exit_code(&anyhow!("other")), ExitCode::from(1));
+        assert_eq!(
+            Failure::exit_code(&anyhow::Error::new(MixerError::NoOutputs)),
+            ExitCode::from(3)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Hand-built nodes and ports for unit tests, as discovering them needs a PipeWire daemon
+
+use std::collections::BTreeMap;
+
+use crate::graph::{NodeInfo, PortInfo, Serial};
+
+/// An audio port; its serial doubles as its global id
+pub fn port(serial: Serial, channel: &str, direction: &str) -> PortInfo {
+    PortInfo {
+        global_id: serial as u32,
+        serial,
+        channel: channel.to_string(),
+        direction: direction.to_string(),
+        monitor: false,
+        midi: false,
+        index: None,
+    }
+}
+
+/// A node of `media_class` with the given ports; its serial doubles as its global id
+pub fn node(
+    serial: Serial,
+    description: &str,
+    media_class: &str,
+    ports: Vec<PortInfo>,
+) -> NodeInfo {
+    NodeInfo {
+        global_id: serial as u32,
+        serial,
+        description: description.to_string(),
+        media_class: media_class.to_string(),
+        input: media_class.contains("Source") || media_class.contains("Output"),
+        classified: true,
+        nick: None,
+        app_name: None,
+        form_factor: None,
+        device_id: None,
+        profile_device: None,
+        device_string: None,
+        bluetooth_profile: None,
+        properties: BTreeMap::new(),
+        ports,
+        caps: None,
+        state: None,
+    }
+}
+
+/// An `Audio/Source` with one output port per channel, numbered from `serial * 100`
+pub fn source(serial: Serial, channels: &[&str]) -> NodeInfo {
+    let ports = numbered_ports(serial, channels, "out");
+    node(serial, &format!("Source {}", serial), "Audio/Source", ports)
+}
+
+/// An `Audio/Sink` with one input port per channel, numbered from `serial * 100`
+pub fn sink(serial: Serial, channels: &[&str]) -> NodeInfo {
+    let ports = numbered_ports(serial, channels, "in");
+    node(serial, &format!("Sink {}", serial), "Audio/Sink", ports)
+}
+
+fn numbered_ports(serial: Serial, channels: &[&str], direction: &str) -> Vec<PortInfo> {
+    channels
+        .iter()
+        .enumerate()
+        .map(|(index, channel)| PortInfo {
+            index: Some(index as u32),
+            ..port(serial * 100 + index as Serial, channel, direction)
+        })
+        .collect()
+}
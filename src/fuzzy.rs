@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Prefix marking a pattern as fuzzy, e.g. `fuzzy:Built-in Audio Analog Stereo`
+const PREFIX: &str = "fuzzy:";
+
+/// Candidates scoring within this much of the best one make the choice ambiguous
+pub const AMBIGUITY_MARGIN: f64 = 0.05;
+
+/// The text of a `fuzzy:` pattern, or None for a plain substring pattern
+pub fn fuzzy_pattern(pattern: &str) -> Option<&str> {
+    pattern.strip_prefix(PREFIX).map(str::trim)
+}
+
+/// Similarity of two descriptions from 0 (nothing in common) to 1 (same words)
+///
+/// Dice coefficient over the character trigrams of each lowercased word, so it tolerates
+/// reordered words and inflections like "Analog Stereo" vs "Stereo analogico"
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = trigrams(a);
+    let b = trigrams(b);
+    let total: usize = a.values().chain(b.values()).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let common: usize = a
+        .iter()
+        .map(|(trigram, count)| (*count).min(b.get(trigram).copied().unwrap_or(0)))
+        .sum();
+    2.0 * common as f64 / total as f64
+}
+
+/// Count the trigrams of every word, padded so short words and word edges still count
+fn trigrams(text: &str) -> HashMap<[char; 3], usize> {
+    let mut counts = HashMap::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = format!("  {} ", word.to_lowercase()).chars().collect();
+        for window in padded.windows(3) {
+            *counts.entry([window[0], window[1], window[2]]).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// The candidate chosen by [`best_match`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FuzzyMatch {
+    pub index: usize,
+    pub score: f64,
+}
+
+/// Why [`best_match`] refused to choose
+#[derive(Clone, Debug, PartialEq)]
+pub enum FuzzyError {
+    /// No candidate reached the threshold; holds the best score seen, if any candidate
+    BelowThreshold(Option<f64>),
+    /// The two best candidates scored within [`AMBIGUITY_MARGIN`] of each other
+    Ambiguous {
+        best: FuzzyMatch,
+        runner_up: FuzzyMatch,
+    },
+}
+
+impl fmt::Display for FuzzyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuzzyError::BelowThreshold(None) => f.write_str("no candidates"),
+            FuzzyError::BelowThreshold(Some(best)) => {
+                write!(f, "best score {:.2} is below the threshold", best)
+            }
+            FuzzyError::Ambiguous { best, runner_up } => write!(
+                f,
+                "ambiguous, two candidates score {:.2} and {:.2}",
+                best.score, runner_up.score
+            ),
+        }
+    }
+}
+
+/// Pick the candidate most similar to `pattern`, if it scores at least `threshold` and no other
+/// candidate comes within `margin` of it
+pub fn best_match(
+    pattern: &str,
+    candidates: &[&str],
+    threshold: f64,
+    margin: f64,
+) -> Result<FuzzyMatch, FuzzyError> {
+    let mut scored: Vec<FuzzyMatch> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| FuzzyMatch {
+            index,
+            score: similarity(pattern, candidate),
+        })
+        .collect();
+    // Stable, so equal scores keep the candidates' order
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let Some(&best) = scored.first().filter(|best| best.score >= threshold) else {
+        return Err(FuzzyError::BelowThreshold(
+            scored.first().map(|best| best.score),
+        ));
+    };
+    match scored.get(1) {
+        Some(&runner_up) if best.score - runner_up.score < margin => {
+            Err(FuzzyError::Ambiguous { best, runner_up })
+        }
+        _ => Ok(best),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANDIDATES: [&str; 3] = [
+        "Built-in Audio Analog Stereo",
+        "USB Audio Digital Stereo (IEC958)",
+        "HDMI / DisplayPort 2 Output",
+    ];
+
+    #[test]
+    fn fuzzy_prefix() {
+        assert_eq!(
+            fuzzy_pattern("fuzzy: Analog Stereo "),
+            Some("Analog Stereo")
+        );
+        assert_eq!(fuzzy_pattern("Analog Stereo"), None);
+    }
+
+    #[test]
+    fn similarity_ignores_word_order_and_case() {
+        assert_eq!(similarity("Analog Stereo", "Analog Stereo"), 1.0);
+        assert_eq!(similarity("Analog Stereo", "stereo ANALOG"), 1.0);
+        assert_eq!(similarity("Analog-Stereo", "Stereo, Analog"), 1.0);
+        assert_eq!(similarity("abc", "xyz"), 0.0);
+        assert_eq!(similarity("", ""), 0.0);
+        let inflected = similarity("Analog Stereo", "Stereo analogico");
+        assert!(inflected > 0.5 && inflected < 1.0, "{}", inflected);
+    }
+
+    #[test]
+    fn best_match_picks_the_closest_candidate() {
+        let found = best_match("analog stereo built-in", &CANDIDATES, 0.5, AMBIGUITY_MARGIN);
+        assert_eq!(found.map(|found| found.index), Ok(0));
+    }
+
+    #[test]
+    fn best_match_without_candidates() {
+        let error = best_match("Analog Stereo", &[], 0.5, AMBIGUITY_MARGIN).unwrap_err();
+        assert_eq!(error, FuzzyError::BelowThreshold(None));
+        assert_eq!(error.to_string(), "no candidates");
+    }
+
+    #[test]
+    fn best_match_below_the_threshold() {
+        let error = best_match("Bluetooth Headset", &CANDIDATES, 0.5, AMBIGUITY_MARGIN);
+        let Err(FuzzyError::BelowThreshold(Some(best))) = error else {
+            panic!("expected a score below the threshold, got {:?}", error);
+        };
+        assert!(best < 0.5);
+        // The threshold is inclusive
+        let found = best_match("Bluetooth Headset", &CANDIDATES, best, 0.0);
+        assert_eq!(found.map(|found| found.score), Ok(best));
+    }
+
+    #[test]
+    fn best_match_refuses_close_runner_ups() {
+        let candidates = ["Analog Stereo 1", "Analog Stereo 2", "HDMI Output"];
+        let error = best_match("Analog Stereo", &candidates, 0.5, AMBIGUITY_MARGIN).unwrap_err();
+        let FuzzyError::Ambiguous { best, runner_up } = error else {
+            panic!("expected an ambiguous match, got {:?}", error);
+        };
+        // Equal scores keep the candidates' order
+        assert_eq!((best.index, runner_up.index), (0, 1));
+        assert_eq!(best.score, runner_up.score);
+
+        // Without a margin the first of the equals wins
+        let found = best_match("Analog Stereo", &candidates, 0.5, 0.0);
+        assert_eq!(found.map(|found| found.index), Ok(0));
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// How long an input may stop running before a `link_while = "running"` route unlinks it,
+/// unless the rule sets `idle_grace_ms`
+pub const DEFAULT_IDLE_GRACE: Duration = Duration::from_secs(5);
+
+/// When a route keeps its inputs linked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkWhile {
+    /// For as long as the route exists
+    #[default]
+    Always,
+    /// While the input node is running, so an idle input does not keep the output awake
+    Running,
+}
+
+impl fmt::Display for LinkWhile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkWhile::Always => f.write_str("always"),
+            LinkWhile::Running => f.write_str("running"),
+        }
+    }
+}
+
+/// Where an input of a `link_while = "running"` route stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GatePhase {
+    /// Not running, so not linked
+    Waiting,
+    /// Running and linked
+    Open,
+    /// Linked, but stopped running; unlinked at the deadline unless it runs again first
+    Closing(Instant),
+}
+
+/// What the mixer has to do after a gate changed phase
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GateAction {
+    Link,
+    Unlink,
+}
+
+/// Follows the state of one input, linking it as soon as it runs and unlinking it once it has
+/// stayed idle or suspended for the grace period
+///
+/// Knows nothing of nodes or links: the caller reports every state seen and the passing of
+/// time, then acts on the [`GateAction`] returned. An input flapping between states within the
+/// grace period stays linked throughout
+#[derive(Clone, Debug)]
+pub struct RunGate {
+    phase: GatePhase,
+    grace: Duration,
+}
+
+impl RunGate {
+    pub fn new(grace: Duration) -> Self {
+        RunGate {
+            phase: GatePhase::Waiting,
+            grace,
+        }
+    }
+
+    pub fn phase(&self) -> GatePhase {
+        self.phase
+    }
+
+    /// True while the input should be linked, including during the grace period
+    pub fn is_open(&self) -> bool {
+        self.phase != GatePhase::Waiting
+    }
+
+    /// Record whether the input is running at `now`
+    ///
+    /// Reporting the same state again changes nothing, so a grace period is not extended by
+    /// repeated idle reports
+    pub fn observe(&mut self, running: bool, now: Instant) -> Option<GateAction> {
+        match (self.phase, running) {
+            (GatePhase::Waiting, true) => {
+                self.phase = GatePhase::Open;
+                Some(GateAction::Link)
+            }
+            (GatePhase::Open, false) => {
+                self.phase = GatePhase::Closing(now + self.grace);
+                None
+            }
+            (GatePhase::Closing(_), true) => {
+                self.phase = GatePhase::Open;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Close the gate if its grace period has run out by `now`
+    pub fn expire(&mut self, now: Instant) -> Option<GateAction> {
+        match self.phase {
+            GatePhase::Closing(deadline) if deadline <= now => {
+                self.phase = GatePhase::Waiting;
+                Some(GateAction::Unlink)
+            }
+            _ => None,
+        }
+    }
+
+    /// When the grace period runs out, if the gate is closing
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.phase {
+            GatePhase::Closing(deadline) => Some(deadline),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(5);
+
+    /// Feed the gate a script of (seconds since start, running) states, returning the actions
+    fn replay(gate: &mut RunGate, start: Instant, script: &[(u64, bool)]) -> Vec<GateAction> {
+        script
+            .iter()
+            .filter_map(|(seconds, running)| {
+                let now = start + Duration::from_secs(*seconds);
+                gate.expire(now).or_else(|| gate.observe(*running, now))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_opens_the_gate() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        assert_eq!(gate.phase(), GatePhase::Waiting);
+        assert_eq!(gate.observe(false, start), None);
+        assert_eq!(gate.observe(true, start), Some(GateAction::Link));
+        assert_eq!(gate.phase(), GatePhase::Open);
+        assert!(gate.is_open());
+    }
+
+    #[test]
+    fn idle_starts_the_grace_period() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        gate.observe(true, start);
+        assert_eq!(gate.observe(false, start), None);
+        assert_eq!(gate.phase(), GatePhase::Closing(start + GRACE));
+        assert!(gate.is_open());
+        assert_eq!(gate.deadline(), Some(start + GRACE));
+    }
+
+    #[test]
+    fn flapping_within_the_grace_period_stays_linked() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        let actions = replay(
+            &mut gate,
+            start,
+            &[
+                (0, true),
+                (1, false),
+                (2, true),
+                (3, false),
+                (4, true),
+                (8, false),
+            ],
+        );
+        assert_eq!(actions, vec![GateAction::Link]);
+        assert_eq!(
+            gate.phase(),
+            GatePhase::Closing(start + Duration::from_secs(8) + GRACE)
+        );
+    }
+
+    #[test]
+    fn expire_only_closes_once_the_deadline_passes() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        gate.observe(true, start);
+        gate.observe(false, start);
+        assert_eq!(gate.expire(start + GRACE - Duration::from_millis(1)), None);
+        assert!(gate.is_open());
+        assert_eq!(gate.expire(start + GRACE), Some(GateAction::Unlink));
+        assert_eq!(gate.phase(), GatePhase::Waiting);
+        assert_eq!(gate.deadline(), None);
+        assert_eq!(gate.expire(start + GRACE * 2), None);
+    }
+
+    #[test]
+    fn expire_leaves_an_open_gate_alone() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        gate.observe(true, start);
+        assert_eq!(gate.expire(start + GRACE * 10), None);
+        assert_eq!(gate.phase(), GatePhase::Open);
+    }
+
+    #[test]
+    fn repeated_idle_reports_keep_the_deadline() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        let actions = replay(
+            &mut gate,
+            start,
+            &[(0, true), (1, false), (3, false), (5, false), (6, false)],
+        );
+        assert_eq!(actions, vec![GateAction::Link, GateAction::Unlink]);
+        assert_eq!(gate.phase(), GatePhase::Waiting);
+    }
+
+    #[test]
+    fn running_again_after_closing_links_again() {
+        let start = Instant::now();
+        let mut gate = RunGate::new(GRACE);
+        let actions = replay(
+            &mut gate,
+            start,
+            &[(0, true), (1, false), (7, false), (9, true)],
+        );
+        assert_eq!(
+            actions,
+            vec![GateAction::Link, GateAction::Unlink, GateAction::Link]
+        );
+    }
+}
@@ -0,0 +1,1418 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+use pipewire::link::LinkState;
+use pipewire::spa::utils::dict::DictRef;
+use serde::{Deserialize, Serialize};
+
+use crate::channel::{channel_from_port_name, Channel};
+use crate::pod::{AudioCaps, DeviceRoute};
+use crate::stamp::LinkStamp;
+
+/// PipeWire `object.serial`: unlike global ids, serials are never reused while the daemon runs
+pub type Serial = u64;
+
+/// Base for serials synthesized when a global does not report `object.serial` (PipeWire < 0.3.47)
+/// Kept out of the range used by the daemon so the two can never collide
+const FALLBACK_SERIAL_BASE: Serial = 1 << 63;
+
+/// Parse the `object.serial` property, falling back to a value derived from the global id
+pub fn parse_serial(global_id: u32, value: Option<&str>) -> Serial {
+    value
+        .and_then(|s| s.parse::<Serial>().ok())
+        .unwrap_or(FALLBACK_SERIAL_BASE | Serial::from(global_id))
+}
+
+/// Properties identifying the hardware behind a node, which stay the same when identical
+/// devices are enumerated in a different order. Most only exist on the owning Device global
+pub const IDENTITY_KEYS: [&str; 3] = ["device.bus-path", "device.serial", "api.alsa.card.longname"];
+
+/// Matchable property holding the name of the monitor connected to an HDMI/DisplayPort node,
+/// as read from its ELD
+pub const MONITOR_NAME_KEY: &str = "hdmi.monitor";
+
+/// Collect the identity properties present in a property dictionary
+pub fn identity_properties(props: &DictRef) -> BTreeMap<String, String> {
+    IDENTITY_KEYS
+        .iter()
+        .filter_map(|key| Some((key.to_string(), props.get(key)?.to_string())))
+        .collect()
+}
+
+/// Retain relevant metadata associated with a PipeWire port
+#[derive(Clone, Debug, Serialize)]
+pub struct PortInfo {
+    pub global_id: u32,
+    pub serial: Serial,
+    pub channel: String,
+    pub direction: String,
+    pub monitor: bool,      // Monitor port of a sink, carrying what it plays
+    pub midi: bool,         // Carries MIDI events rather than audio samples, from format.dsp
+    pub index: Option<u32>, // port.id, the port's position among the node's ports of its direction
+}
+
+impl PortInfo {
+    /// Read a Port global, returning the global id of its node along with it
+    pub fn from_props(global_id: u32, props: &DictRef) -> Option<(u32, PortInfo)> {
+        let node_id = props
+            .get(*pipewire::keys::NODE_ID)
+            .and_then(|s| s.parse::<u32>().ok())?;
+        let channel = match props.get(*pipewire::keys::AUDIO_CHANNEL) {
+            Some(channel) => channel.to_string(),
+            // e.g. "playback_FL" on devices without audio.channel
+            None => props
+                .get(*pipewire::keys::PORT_NAME)
+                .map(channel_from_port_name)
+                .unwrap_or_else(|| "unknown".to_string()),
+        };
+        let direction = props
+            .get(*pipewire::keys::PORT_DIRECTION)
+            .unwrap_or("unknown")
+            .to_string();
+        // Sinks expose monitor ports that carry what they are playing
+        let monitor = props.get(*pipewire::keys::PORT_MONITOR) == Some("true")
+            || props
+                .get(*pipewire::keys::PORT_NAME)
+                .is_some_and(|name| name.starts_with("monitor_"));
+        let midi = props
+            .get(*pipewire::keys::FORMAT_DSP)
+            .is_some_and(is_midi_format);
+        let port = PortInfo {
+            global_id,
+            serial: parse_serial(global_id, props.get(*pipewire::keys::OBJECT_SERIAL)),
+            channel,
+            direction,
+            monitor,
+            midi,
+            index: props
+                .get(*pipewire::keys::PORT_ID)
+                .and_then(|s| s.parse().ok()),
+        };
+        Some((node_id, port))
+    }
+
+    /// Channel position with aliases normalized, for pairing
+    pub fn position(&self) -> Channel {
+        Channel::parse(&self.channel)
+    }
+}
+
+/// True for the `format.dsp` of MIDI ports, e.g. "8 bit raw midi", or "32 bit raw UMP" for
+/// MIDI 2.0
+fn is_midi_format(format: &str) -> bool {
+    format.ends_with(" midi") || format.ends_with(" UMP")
+}
+
+impl fmt::Display for PortInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({}/{}, {:?}, {:?}{})",
+            self.global_id,
+            self.serial,
+            self.channel,
+            self.direction,
+            if self.monitor {
+                ", monitor"
+            } else if self.midi {
+                ", midi"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// Broad classification of a node by its media class, for display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    AudioInput,
+    AudioOutput,
+    VideoInput,
+    VideoOutput,
+    Midi, // Both directions at once, e.g. the Midi/Bridge node holding every ALSA MIDI port
+    Other,
+}
+
+impl NodeKind {
+    pub fn from_media_class(media_class: &str) -> Self {
+        match media_class {
+            "Audio/Sink" | "Stream/Input/Audio" => NodeKind::AudioOutput,
+            "Audio/Source" | "Audio/Source/Virtual" | "Stream/Output/Audio" => NodeKind::AudioInput,
+            "Video/Source" | "Stream/Output/Video" => NodeKind::VideoInput,
+            "Video/Sink" | "Stream/Input/Video" => NodeKind::VideoOutput,
+            class if class.starts_with("Midi/") => NodeKind::Midi,
+            _ => NodeKind::Other,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NodeKind::AudioInput => "Audio Input",
+            NodeKind::AudioOutput => "Audio Output",
+            NodeKind::VideoInput => "Video Input",
+            NodeKind::VideoOutput => "Video Output",
+            NodeKind::Midi => "MIDI",
+            NodeKind::Other => "Other/Virtual",
+        }
+    }
+}
+
+/// What a node carries; a route only links nodes of the same media
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Media {
+    #[default]
+    Audio,
+    Video,
+    Midi,
+}
+
+impl fmt::Display for Media {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Media::Audio => f.write_str("audio"),
+            Media::Video => f.write_str("video"),
+            Media::Midi => f.write_str("midi"),
+        }
+    }
+}
+
+/// Media class recorded for nodes that do not announce one
+pub const UNKNOWN_MEDIA_CLASS: &str = "Unknown";
+
+/// Retain relevant metadata associated with a PipeWire node
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeInfo {
+    pub global_id: u32, // Current global id, only valid for issuing requests right now
+    pub serial: Serial, // Stable identity of the node
+    pub description: String,
+    pub media_class: String,
+    pub input: bool, // True if the node is an input (like a mic), False if the node is an output (like a speaker)
+    pub classified: bool, // False while neither the media class nor the ports tell the direction
+    pub nick: Option<String>, // Short name from node.nick
+    pub app_name: Option<String>, // application.name, set on the streams of an application
+    pub form_factor: Option<String>, // device.form-factor, e.g. "headphone" or "speaker"
+    pub device_id: Option<u32>, // Global id of the owning Device, from device.id
+    pub profile_device: Option<i32>, // card.profile.device, only known once the node is bound
+    pub device_string: Option<String>, // Shared by the nodes a device recreates, e.g. a Bluetooth address
+    pub bluetooth_profile: Option<String>, // api.bluez5.profile, e.g. "a2dp-sink" or "headset-head-unit"
+    pub properties: BTreeMap<String, String>, // Every known property of the node, and its device's identity
+    pub ports: Vec<PortInfo>,
+    pub caps: Option<AudioCaps>, // Only queried for nodes that are listed verbosely or routed
+    pub state: Option<String>, // "running", "idle", "suspended", ...; only known once the node is bound
+}
+
+impl NodeInfo {
+    /// Read a Node global; what is only known once the node is bound is left empty
+    pub fn from_props(global_id: u32, props: &DictRef) -> NodeInfo {
+        let description = props
+            .get(*pipewire::keys::NODE_DESCRIPTION)
+            .or_else(|| props.get(*pipewire::keys::NODE_NAME))
+            .unwrap_or("Unknown");
+        let media_class = props
+            .get(*pipewire::keys::MEDIA_CLASS)
+            .unwrap_or(UNKNOWN_MEDIA_CLASS);
+        // Playback streams produce audio like sources, though their class says Output
+        let input = match NodeKind::from_media_class(media_class) {
+            NodeKind::AudioInput | NodeKind::VideoInput => true,
+            NodeKind::AudioOutput | NodeKind::VideoOutput => false,
+            NodeKind::Midi | NodeKind::Other => {
+                media_class.contains("Source") || media_class.contains("Input")
+            }
+        };
+        NodeInfo {
+            global_id,
+            serial: parse_serial(global_id, props.get(*pipewire::keys::OBJECT_SERIAL)),
+            description: description.to_string(),
+            media_class: media_class.to_string(),
+            input,
+            // Without a media class the direction is only known once ports are announced
+            classified: media_class != UNKNOWN_MEDIA_CLASS,
+            nick: props.get(*pipewire::keys::NODE_NICK).map(str::to_string),
+            app_name: props.get(*pipewire::keys::APP_NAME).map(str::to_string),
+            form_factor: props
+                .get(*pipewire::keys::DEVICE_FORM_FACTOR)
+                .map(str::to_string),
+            device_id: props
+                .get(*pipewire::keys::DEVICE_ID)
+                .and_then(|s| s.parse().ok()),
+            profile_device: None,
+            device_string: None,
+            bluetooth_profile: None,
+            properties: props
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ports: Vec::new(),
+            caps: None,
+            state: None,
+        }
+    }
+
+    /// True if the node belongs to an application whose name contains one of `names`,
+    /// compared case-insensitively
+    pub fn matches_app(&self, names: &[String]) -> bool {
+        let Some(app_name) = &self.app_name else {
+            return false;
+        };
+        let app_name = app_name.to_uppercase();
+        names
+            .iter()
+            .any(|name| app_name.contains(name.to_uppercase().as_str()))
+    }
+
+    /// True if the node is known to be processing, i.e. something is playing into or from it
+    pub fn is_busy(&self) -> bool {
+        self.state.as_deref() == Some("running")
+    }
+
+    /// Take the direction of a node without a media class from its ports: only output ports
+    /// make an input, only input ports an output
+    ///
+    /// Nodes with both or neither (e.g. duplex filters) stay unclassified
+    fn classify_by_ports(&mut self) {
+        let has = |direction: &str| {
+            self.ports
+                .iter()
+                .any(|port| port.direction == direction && !port.monitor)
+        };
+        match (has("out"), has("in")) {
+            (true, false) => {
+                self.input = true;
+                self.classified = true;
+            }
+            (false, true) => {
+                self.input = false;
+                self.classified = true;
+            }
+            _ => self.classified = false,
+        }
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        NodeKind::from_media_class(&self.media_class)
+    }
+
+    /// Video for the video classes (including virtual ones like a virtual camera), MIDI for the
+    /// MIDI classes, audio for everything else
+    pub fn media(&self) -> Media {
+        if self.media_class.contains("Video") {
+            Media::Video
+        } else if self.media_class.contains("Midi") {
+            Media::Midi
+        } else {
+            Media::Audio
+        }
+    }
+
+    /// True if the node can be linked as `media`: software synths are audio nodes that take
+    /// MIDI on a port of their own
+    pub fn carries(&self, media: Media) -> bool {
+        self.media() == media || media == Media::Midi && self.ports.iter().any(|port| port.midi)
+    }
+
+    /// True if the node is an application stream rather than a device or virtual node
+    pub fn is_stream(&self) -> bool {
+        self.media_class.starts_with("Stream/")
+    }
+
+    /// True if the node can be mixed as an input (`input`) or into as an output
+    ///
+    /// MIDI nodes act as whatever their MIDI ports allow. Nodes outside the known media classes,
+    /// like loopbacks and other virtual devices, are only considered with `include_virtual`,
+    /// taking their direction from their ports. Nodes without a media class are considered once
+    /// their ports tell the direction
+    pub fn acts_as(&self, input: bool, include_virtual: bool) -> bool {
+        let direction = if input { "out" } else { "in" };
+        match self.kind() {
+            // A bridge holds the ports of every MIDI device, so it plays both parts
+            NodeKind::Midi => {
+                return self
+                    .ports
+                    .iter()
+                    .any(|port| port.direction == direction && port.midi)
+            }
+            NodeKind::Other => {}
+            _ => return self.input == input,
+        }
+        if self.media_class == UNKNOWN_MEDIA_CLASS {
+            return self.classified && self.input == input;
+        }
+        include_virtual
+            && self
+                .ports
+                .iter()
+                .any(|port| port.direction == direction && !port.monitor)
+    }
+
+    /// Number of the node's ports carrying the same channel as `port` in the same direction,
+    /// more than one only for drivers announcing a channel twice
+    pub fn channel_count(&self, port: &PortInfo) -> usize {
+        let channel = port.position();
+        self.ports
+            .iter()
+            .filter(|other| {
+                other.direction == port.direction
+                    && other.monitor == port.monitor
+                    && other.position() == channel
+            })
+            .count()
+    }
+
+    /// True if the node has monitor ports, i.e. is a sink whose output can be tapped
+    pub fn has_monitor_ports(&self) -> bool {
+        self.ports.iter().any(|port| port.monitor)
+    }
+
+    /// Render the ports as a compact list of `(id/serial, channel, direction)` tuples
+    ///
+    /// Runs of consecutive AUX channels are collapsed per direction, e.g. `(AUX0–17, "in")`,
+    /// as pro-audio devices can expose dozens of them
+    pub fn ports_summary(&self) -> String {
+        let mut ports = Vec::new();
+        // (Direction, monitor) -> AUX indices
+        let mut aux: BTreeMap<(&str, bool), Vec<u32>> = BTreeMap::new();
+        for port in &self.ports {
+            match port.position() {
+                Channel::Aux(index) => aux
+                    .entry((&port.direction, port.monitor))
+                    .or_default()
+                    .push(index),
+                _ => ports.push(port.to_string()),
+            }
+        }
+        for ((direction, monitor), mut indices) in aux {
+            indices.sort_unstable();
+            indices.dedup();
+            let mut runs: Vec<(u32, u32)> = Vec::new();
+            for index in indices {
+                match runs.last_mut() {
+                    Some((_, last)) if *last + 1 == index => *last = index,
+                    _ => runs.push((index, index)),
+                }
+            }
+            for (first, last) in runs {
+                let channels = if first == last {
+                    format!("AUX{}", first)
+                } else {
+                    format!("AUX{}–{}", first, last)
+                };
+                ports.push(format!(
+                    "({}, {:?}{})",
+                    channels,
+                    direction,
+                    if monitor { ", monitor" } else { "" }
+                ));
+            }
+        }
+        format!("[{}]", ports.join(", "))
+    }
+}
+
+/// A link created by the mixer between an input port and an output port, identified by serials
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ActiveLink {
+    pub channel: String,
+    pub output_node: Serial, // Node producing audio (the mixer input)
+    pub output_port: Serial,
+    pub input_node: Serial, // Node consuming audio (the mixer output)
+    pub input_port: Serial,
+}
+
+impl ActiveLink {
+    /// True if either end of the link belongs to the given node or port
+    pub fn involves(&self, serial: Serial) -> bool {
+        [
+            self.output_node,
+            self.output_port,
+            self.input_node,
+            self.input_port,
+        ]
+        .contains(&serial)
+    }
+}
+
+/// Object evicted from the graph by [`Graph::remove_global`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Removed {
+    Node(Serial),
+    Port { node: Serial, port: Serial },
+}
+
+impl Removed {
+    /// Serial of the removed object itself
+    pub fn serial(&self) -> Serial {
+        match self {
+            Removed::Node(serial) => *serial,
+            Removed::Port { port, .. } => *port,
+        }
+    }
+}
+
+/// Cache of discovered nodes and ports, keyed on serial
+///
+/// Global ids are only used to translate registry events, since PipeWire recycles them aggressively
+#[derive(Debug, Default)]
+pub struct Graph {
+    nodes: HashMap<Serial, NodeInfo>,
+    serials: HashMap<u32, Serial>, // Current global id -> serial, for nodes and ports
+    port_nodes: HashMap<Serial, Serial>, // Port serial -> owning node serial
+    devices: HashMap<u32, DeviceEntry>, // Keyed on device global id
+    clients: HashMap<u32, String>, // Client global id -> application name
+    links: HashMap<u32, LinkEntry>, // Keyed on link global id
+    default_metadata: Option<u32>, // Global id of the "default" metadata object
+    defaults: Defaults,
+}
+
+/// Nodes the session manager chose as defaults, as announced in its "default" metadata
+#[derive(Clone, Debug, Default)]
+pub struct Defaults {
+    pub available: bool, // False without a session manager publishing defaults
+    pub audio_sink: Option<String>, // node.name of default.audio.sink
+}
+
+/// Metadata key holding the default sink
+pub const DEFAULT_AUDIO_SINK_KEY: &str = "default.audio.sink";
+
+/// Read the node name out of a default metadata value like `{ "name": "alsa_output.usb-..." }`
+fn parse_default_name(value: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(value).ok()?;
+    Some(value.get("name")?.as_str()?.to_string())
+}
+
+/// A Link global, with the global ids it was announced with, as links are only looked up while
+/// they exist
+#[derive(Clone, Debug, Serialize)]
+pub struct LinkEntry {
+    pub output_node: u32, // Node producing audio
+    pub output_port: u32,
+    pub input_node: u32, // Node consuming audio
+    pub input_port: u32,
+    pub client_id: Option<u32>, // Client that asked for the link, e.g. the session manager
+    pub stamp: Option<LinkStamp>, // Set if a mixer made the link, known once it is bound
+    pub state: Option<LinkPhase>, // Known once the link is bound
+}
+
+/// State of a link, from creation until it carries audio or fails
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkPhase {
+    Unlinked,
+    Init,
+    Negotiating,
+    Allocating,
+    Paused,
+    Active,
+    Error(String), // The daemon's message, e.g. a format mismatch
+}
+
+impl From<&LinkState<'_>> for LinkPhase {
+    fn from(state: &LinkState<'_>) -> Self {
+        match state {
+            LinkState::Error(message) => LinkPhase::Error(message.to_string()),
+            LinkState::Unlinked => LinkPhase::Unlinked,
+            LinkState::Init => LinkPhase::Init,
+            LinkState::Negotiating => LinkPhase::Negotiating,
+            LinkState::Allocating => LinkPhase::Allocating,
+            LinkState::Paused => LinkPhase::Paused,
+            LinkState::Active => LinkPhase::Active,
+        }
+    }
+}
+
+impl LinkPhase {
+    /// True once the link is set up, whether or not audio flows: links between nodes that are
+    /// not running stay paused
+    pub fn is_established(&self) -> bool {
+        matches!(self, LinkPhase::Active | LinkPhase::Paused)
+    }
+
+    /// True once the link is done changing on its own, set up or failed
+    pub fn is_settled(&self) -> bool {
+        self.is_established() || matches!(self, LinkPhase::Error(_) | LinkPhase::Unlinked)
+    }
+}
+
+impl fmt::Display for LinkPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LinkPhase::Unlinked => "unlinked",
+            LinkPhase::Init => "init",
+            LinkPhase::Negotiating => "negotiating",
+            LinkPhase::Allocating => "allocating",
+            LinkPhase::Paused => "paused",
+            LinkPhase::Active => "active",
+            LinkPhase::Error(message) => return write!(f, "error: {}", message),
+        })
+    }
+}
+
+impl LinkEntry {
+    /// Read the endpoints out of a Link global's properties
+    pub fn from_props(props: &DictRef) -> Option<Self> {
+        let id = |key: &str| props.get(key)?.parse().ok();
+        Some(LinkEntry {
+            output_node: id(*pipewire::keys::LINK_OUTPUT_NODE)?,
+            output_port: id(*pipewire::keys::LINK_OUTPUT_PORT)?,
+            input_node: id(*pipewire::keys::LINK_INPUT_NODE)?,
+            input_port: id(*pipewire::keys::LINK_INPUT_PORT)?,
+            client_id: id(*pipewire::keys::CLIENT_ID),
+            stamp: None,
+            state: None,
+        })
+    }
+}
+
+/// What is known about a Device global, to be copied onto its nodes
+#[derive(Debug, Default)]
+struct DeviceEntry {
+    description: Option<String>, // device.description, e.g. the card name
+    properties: BTreeMap<String, String>, // Identity properties
+    monitors: HashMap<i32, String>, // card.profile.device -> connected monitor name
+}
+
+impl DeviceEntry {
+    /// Copy the device's properties onto one of its nodes, returning true if the node's
+    /// monitor name changed
+    fn apply(&self, node: &mut NodeInfo) -> bool {
+        node.properties.extend(self.properties.clone());
+        let monitor = node
+            .profile_device
+            .and_then(|device| self.monitors.get(&device))
+            .cloned();
+        let previous = match monitor.clone() {
+            Some(name) => node.properties.insert(MONITOR_NAME_KEY.to_string(), name),
+            None => node.properties.remove(MONITOR_NAME_KEY),
+        };
+        previous != monitor
+    }
+}
+
+impl Graph {
+    /// Record a newly announced node, evicting whatever previously held its global id
+    pub fn add_node(&mut self, mut node: NodeInfo) {
+        self.evict_stale(node.global_id, node.serial);
+        if let Some(device) = node.device_id.and_then(|id| self.devices.get(&id)) {
+            device.apply(&mut node);
+        }
+        self.serials.insert(node.global_id, node.serial);
+        self.nodes.entry(node.serial).or_insert(node);
+    }
+
+    /// Record a newly announced port on the node currently holding `node_id`
+    ///
+    /// Returns the owning node's serial, or None if the node is unknown
+    pub fn add_port(&mut self, node_id: u32, port: PortInfo) -> Option<Serial> {
+        self.evict_stale(port.global_id, port.serial);
+        let node_serial = *self.serials.get(&node_id)?;
+        let node = self.nodes.get_mut(&node_serial)?;
+        self.serials.insert(port.global_id, port.serial);
+        self.port_nodes.insert(port.serial, node_serial);
+        if !node.ports.iter().any(|p| p.serial == port.serial) {
+            node.ports.push(port);
+        }
+        if node.media_class == UNKNOWN_MEDIA_CLASS {
+            node.classify_by_ports();
+        }
+        Some(node_serial)
+    }
+
+    /// Record the identity properties of a Device global, copying them onto its nodes
+    ///
+    /// Device info arrives after binding, so nodes may be announced before or after it
+    pub fn add_device(
+        &mut self,
+        device_id: u32,
+        description: Option<String>,
+        properties: BTreeMap<String, String>,
+    ) {
+        let device = self.devices.entry(device_id).or_default();
+        device.description = description;
+        device.properties = properties;
+        self.apply_device(device_id);
+    }
+
+    /// Human readable description of a Device global, if its info has arrived
+    pub fn device_description(&self, device_id: u32) -> Option<&str> {
+        self.devices.get(&device_id)?.description.as_deref()
+    }
+
+    /// Record the "default" metadata object, from which defaults will be announced
+    pub fn add_default_metadata(&mut self, metadata_id: u32) {
+        self.default_metadata = Some(metadata_id);
+        self.defaults.available = true;
+    }
+
+    /// Update one default from the "default" metadata; a missing key clears every default
+    ///
+    /// Returns true if the default sink changed
+    pub fn set_default(&mut self, key: Option<&str>, value: Option<&str>) -> bool {
+        let audio_sink = match key {
+            None => None,
+            Some(DEFAULT_AUDIO_SINK_KEY) => value.and_then(parse_default_name),
+            Some(_) => return false,
+        };
+        if self.defaults.audio_sink == audio_sink {
+            return false;
+        }
+        self.defaults.audio_sink = audio_sink;
+        true
+    }
+
+    /// The defaults known so far
+    pub fn defaults(&self) -> &Defaults {
+        &self.defaults
+    }
+
+    /// Record the application name of a Client global, to group the streams it owns
+    pub fn add_client(&mut self, client_id: u32, name: String) {
+        self.clients.insert(client_id, name);
+    }
+
+    /// Record a Link global; whether a mixer made it is only known once it is bound
+    pub fn add_link(&mut self, link_id: u32, link: LinkEntry) {
+        self.links.insert(link_id, link);
+    }
+
+    /// Record the mixer's stamp read from a bound link, whichever run created it
+    pub fn add_managed_link(&mut self, link_id: u32, stamp: LinkStamp) {
+        if let Some(link) = self.links.get_mut(&link_id) {
+            link.stamp = Some(stamp);
+        }
+    }
+
+    /// Record the state a bound link reported
+    pub fn set_link_state(&mut self, link_id: u32, state: LinkPhase) {
+        if let Some(link) = self.links.get_mut(&link_id) {
+            link.state = Some(state);
+        }
+    }
+
+    pub fn link(&self, link_id: u32) -> Option<&LinkEntry> {
+        self.links.get(&link_id)
+    }
+
+    /// Serials of the (output, input) ports of a link global, while both ports exist
+    pub fn link_ports(&self, link_id: u32) -> Option<(Serial, Serial)> {
+        let link = self.links.get(&link_id)?;
+        Some((
+            *self.serials.get(&link.output_port)?,
+            *self.serials.get(&link.input_port)?,
+        ))
+    }
+
+    /// Global id of a link already connecting two ports, whoever made it
+    pub fn find_link(&self, output_port: u32, input_port: u32) -> Option<u32> {
+        self.links
+            .iter()
+            .find(|(_, link)| link.output_port == output_port && link.input_port == input_port)
+            .map(|(id, _)| *id)
+    }
+
+    /// Node-to-node edges of every link, by serial, for finding feedback loops
+    pub fn link_edges(&self) -> Vec<(Serial, Serial)> {
+        self.links
+            .values()
+            .filter_map(|link| {
+                Some((
+                    *self.serials.get(&link.output_node)?,
+                    *self.serials.get(&link.input_node)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Global ids of the stamped links that still exist, in ascending order
+    pub fn managed_links(&self) -> Vec<u32> {
+        self.stamped_links().into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Links carrying the mixer's stamp, from any run or instance, in ascending id order
+    pub fn stamped_links(&self) -> Vec<(u32, &LinkEntry)> {
+        let mut links: Vec<(u32, &LinkEntry)> = self
+            .links
+            .iter()
+            .filter(|(_, link)| link.stamp.is_some())
+            .map(|(id, link)| (*id, link))
+            .collect();
+        links.sort_unstable_by_key(|(id, _)| *id);
+        links
+    }
+
+    /// Stamped links the `clean` filters select, in ascending id order: those of a rule, of a
+    /// mixer instance, and at least `older_than` old, each when given
+    ///
+    /// Links without the stamp are never selected, nor are links without a creation time once
+    /// `older_than` is given
+    pub fn stale_links(
+        &self,
+        rule: Option<&str>,
+        instance: Option<&str>,
+        older_than: Option<Duration>,
+    ) -> Vec<(u32, &LinkEntry)> {
+        self.stamped_links()
+            .into_iter()
+            .filter(|(_, link)| {
+                let Some(stamp) = &link.stamp else {
+                    return false;
+                };
+                rule.is_none_or(|rule| stamp.rule.as_deref() == Some(rule))
+                    && instance.is_none_or(|instance| stamp.instance.as_deref() == Some(instance))
+                    && older_than
+                        .is_none_or(|older_than| stamp.age().is_some_and(|age| age >= older_than))
+            })
+            .collect()
+    }
+
+    /// Links without the mixer's stamp, made by the session manager or another tool, in
+    /// ascending id order
+    pub fn foreign_links(&self) -> Vec<(u32, &LinkEntry)> {
+        let mut links: Vec<(u32, &LinkEntry)> = self
+            .links
+            .iter()
+            .filter(|(_, link)| link.stamp.is_none())
+            .map(|(id, link)| (*id, link))
+            .collect();
+        links.sort_unstable_by_key(|(id, _)| *id);
+        links
+    }
+
+    /// Links without the mixer's stamp from one node into another, in ascending id order
+    ///
+    /// Links between the port pairs in `own` (by serial) are left out, as the mixer's own links
+    /// are only known to be stamped once bound
+    pub fn foreign_links_between(
+        &self,
+        output_node: Serial,
+        input_node: Serial,
+        own: &HashSet<(Serial, Serial)>,
+    ) -> Vec<(u32, &LinkEntry)> {
+        let (Some(output_node), Some(input_node)) = (self.node(output_node), self.node(input_node))
+        else {
+            return Vec::new();
+        };
+        self.foreign_links()
+            .into_iter()
+            .filter(|(_, link)| {
+                link.output_node == output_node.global_id
+                    && link.input_node == input_node.global_id
+                    && !self.owns(link, own)
+            })
+            .collect()
+    }
+
+    /// True if a link connects one of the port pairs in `own`, given by serial
+    fn owns(&self, link: &LinkEntry, own: &HashSet<(Serial, Serial)>) -> bool {
+        match (
+            self.serials.get(&link.output_port),
+            self.serials.get(&link.input_port),
+        ) {
+            (Some(output_port), Some(input_port)) => own.contains(&(*output_port, *input_port)),
+            _ => false,
+        }
+    }
+
+    /// Links into a node from any node outside `sources`, in ascending id order
+    pub fn competing_links(
+        &self,
+        input_node: u32,
+        sources: &HashSet<u32>,
+    ) -> Vec<(u32, &LinkEntry)> {
+        let mut links: Vec<(u32, &LinkEntry)> = self
+            .links
+            .iter()
+            .filter(|(_, link)| {
+                link.input_node == input_node && !sources.contains(&link.output_node)
+            })
+            .map(|(id, link)| (*id, link))
+            .collect();
+        links.sort_unstable_by_key(|(id, _)| *id);
+        links
+    }
+
+    /// Links into an output from nodes outside `sources`, leaving out the port pairs in `own`,
+    /// all given by serial, in ascending id order
+    ///
+    /// These are what an exclusive route evicts: `own` holds the links the mixer created or
+    /// adopted, which stay whatever their endpoints turned into
+    pub fn competitors(
+        &self,
+        output: Serial,
+        sources: &[Serial],
+        own: &HashSet<(Serial, Serial)>,
+    ) -> Vec<(u32, &LinkEntry)> {
+        let Some(output) = self.node(output) else {
+            return Vec::new();
+        };
+        let sources: HashSet<u32> = sources
+            .iter()
+            .filter_map(|serial| Some(self.node(*serial)?.global_id))
+            .collect();
+        self.competing_links(output.global_id, &sources)
+            .into_iter()
+            .filter(|(_, link)| !self.owns(link, own))
+            .collect()
+    }
+
+    /// Label of a node by its global id, falling back to the id for nodes not (or no longer)
+    /// known
+    pub fn describe_global(&self, global_id: u32) -> String {
+        self.nodes
+            .values()
+            .find(|node| node.global_id == global_id)
+            .map_or_else(
+                || format!("[ID: {}]", global_id),
+                |node| format!("[Serial: {}, {}]", node.serial, node.description),
+            )
+    }
+
+    /// Endpoints and creator of a link, for logging it
+    pub fn describe_link(&self, link: &LinkEntry) -> String {
+        format!(
+            "{} => {}  (made by {})",
+            self.describe_global(link.output_node),
+            self.describe_global(link.input_node),
+            self.link_client(link)
+        )
+    }
+
+    /// Name of the client that created a link, for labeling it
+    pub fn link_client(&self, link: &LinkEntry) -> String {
+        match link.client_id {
+            Some(id) => self
+                .client_name(id)
+                .map_or_else(|| format!("client {}", id), str::to_string),
+            None => "unknown client".to_string(),
+        }
+    }
+
+    /// Application name of a Client global
+    pub fn client_name(&self, client_id: u32) -> Option<&str> {
+        self.clients.get(&client_id).map(String::as_str)
+    }
+
+    /// Record the monitor connected to a playback route of a device, or its disconnection
+    ///
+    /// Returns true if the monitor name of any node changed, so rules may match differently
+    pub fn set_route(&mut self, device_id: u32, route: &DeviceRoute) -> bool {
+        // Capture routes share profile device indices with nothing that has a monitor
+        if !route.output {
+            return false;
+        }
+        let device = self.devices.entry(device_id).or_default();
+        for profile_device in &route.devices {
+            match route.monitor_name() {
+                Some(name) => device.monitors.insert(*profile_device, name.to_string()),
+                None => device.monitors.remove(profile_device),
+            };
+        }
+        self.apply_device(device_id)
+    }
+
+    /// Record the state a bound node reports, e.g. "running"
+    pub fn set_node_state(&mut self, serial: Serial, state: &str) {
+        if let Some(node) = self.nodes.get_mut(&serial) {
+            node.state = Some(state.to_string());
+        }
+    }
+
+    /// Merge the properties only available once a node is bound
+    ///
+    /// Returns true if this revealed a monitor name for the node
+    pub fn add_node_info(&mut self, serial: Serial, props: &DictRef) -> bool {
+        let Some(node) = self.nodes.get_mut(&serial) else {
+            return false;
+        };
+        node.properties.extend(
+            props
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        if let Some(profile_device) = props
+            .get("card.profile.device")
+            .and_then(|s| s.parse().ok())
+        {
+            node.profile_device = Some(profile_device);
+        }
+        if props.iter().any(|(key, _)| key.starts_with("api.bluez5.")) {
+            node.bluetooth_profile = props.get("api.bluez5.profile").map(str::to_string);
+            node.device_string = props
+                .get("device.string")
+                .or_else(|| props.get("api.bluez5.address"))
+                .map(str::to_string);
+        }
+        match node.device_id.and_then(|id| self.devices.get(&id)) {
+            Some(device) => device.apply(node),
+            None => false,
+        }
+    }
+
+    /// Evict the node or port holding a global id that the registry reports as destroyed
+    pub fn remove_global(&mut self, global_id: u32) -> Option<Removed> {
+        // Nodes outliving their device keep the properties they already copied
+        if self.devices.remove(&global_id).is_some()
+            || self.clients.remove(&global_id).is_some()
+            || self.links.remove(&global_id).is_some()
+        {
+            return None;
+        }
+        if self.default_metadata == Some(global_id) {
+            self.default_metadata = None;
+            self.defaults = Defaults::default();
+            return None;
+        }
+        let serial = self.serials.remove(&global_id)?;
+        if let Some(node) = self.nodes.remove(&serial) {
+            for port in node.ports {
+                self.port_nodes.remove(&port.serial);
+                self.serials.remove(&port.global_id);
+            }
+            return Some(Removed::Node(serial));
+        }
+        let node_serial = self.port_nodes.remove(&serial)?;
+        if let Some(node) = self.nodes.get_mut(&node_serial) {
+            node.ports.retain(|p| p.serial != serial);
+        }
+        Some(Removed::Port {
+            node: node_serial,
+            port: serial,
+        })
+    }
+
+    /// Merge audio capabilities reported by one of the node's `EnumFormat` params
+    pub fn add_caps(&mut self, serial: Serial, caps: AudioCaps) {
+        if let Some(node) = self.nodes.get_mut(&serial) {
+            node.caps.get_or_insert_with(AudioCaps::default).merge(caps);
+        }
+    }
+
+    /// Look up a node by its serial
+    pub fn node(&self, serial: Serial) -> Option<&NodeInfo> {
+        self.nodes.get(&serial)
+    }
+
+    /// Look up a port by its serial
+    pub fn port(&self, serial: Serial) -> Option<&PortInfo> {
+        let node = self.nodes.get(self.port_nodes.get(&serial)?)?;
+        node.ports.iter().find(|p| p.serial == serial)
+    }
+
+    /// Translate a link between serials into the current global ids, in
+    /// `(output node, output port, input node, input port)` order
+    ///
+    /// Returns None if any of the four objects has disappeared
+    pub fn link_ids(&self, link: &ActiveLink) -> Option<(u32, u32, u32, u32)> {
+        Some((
+            self.node(link.output_node)?.global_id,
+            self.port(link.output_port)?.global_id,
+            self.node(link.input_node)?.global_id,
+            self.port(link.input_port)?.global_id,
+        ))
+    }
+
+    /// Iterate over all known nodes in arbitrary order
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeInfo> {
+        self.nodes.values()
+    }
+
+    /// How long to wait before relinking a node that gained or lost a port, or None if
+    /// `routed` says no route uses it
+    ///
+    /// The wait folds a burst of changes, like a JACK client registering eight ports, into one
+    /// relink. Bluetooth nodes gaining ports wait `bluetooth_settle`, as their profile may flap
+    pub fn port_relink_delay(
+        &self,
+        node: Serial,
+        gained: bool,
+        routed: impl Fn(Serial) -> bool,
+        settle: Duration,
+        bluetooth_settle: Duration,
+    ) -> Option<Duration> {
+        if !routed(node) {
+            return None;
+        }
+        let bluetooth = self
+            .node(node)
+            .is_some_and(|node| node.bluetooth_profile.is_some());
+        Some(if gained && bluetooth {
+            bluetooth_settle
+        } else {
+            settle
+        })
+    }
+
+    /// Copy a device's properties onto all of its nodes, returning true if any monitor changed
+    fn apply_device(&mut self, device_id: u32) -> bool {
+        let Some(device) = self.devices.get(&device_id) else {
+            return false;
+        };
+        let mut changed = false;
+        for node in self.nodes.values_mut() {
+            if node.device_id == Some(device_id) {
+                changed |= device.apply(node);
+            }
+        }
+        changed
+    }
+
+    /// A global id being announced with a different serial means the previous holder is gone,
+    /// even if its removal event was never seen
+    fn evict_stale(&mut self, global_id: u32, serial: Serial) {
+        if self
+            .serials
+            .get(&global_id)
+            .is_some_and(|&existing| existing != serial)
+        {
+            self.remove_global(global_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::fixtures::{node, port, sink, source};
+
+    /// A sink without ports, announced under `global_id`
+    fn announced_sink(global_id: u32, serial: Serial) -> NodeInfo {
+        NodeInfo {
+            global_id,
+            ..node(serial, "Sink", "Audio/Sink", Vec::new())
+        }
+    }
+
+    fn announced_port(global_id: u32, serial: Serial, channel: &str) -> PortInfo {
+        PortInfo {
+            global_id,
+            ..port(serial, channel, "in")
+        }
+    }
+
+    #[test]
+    fn reused_node_id_evicts_the_old_node_and_its_ports() {
+        let mut graph = Graph::default();
+        graph.add_node(announced_sink(10, 100));
+        assert_eq!(graph.add_port(10, announced_port(11, 101, "FL")), Some(100));
+        assert_eq!(graph.add_port(10, announced_port(12, 102, "FR")), Some(100));
+
+        // The removal of the old node was missed, and its id handed to a new one
+        graph.add_node(announced_sink(10, 200));
+        assert!(graph.node(100).is_none());
+        assert!(graph.port(101).is_none());
+        assert!(graph.port(102).is_none());
+        assert!(graph.node(200).unwrap().ports.is_empty());
+
+        // Its port ids are free for the new node's ports
+        assert_eq!(graph.add_port(10, announced_port(11, 201, "FL")), Some(200));
+        let ports: Vec<Serial> = graph
+            .node(200)
+            .unwrap()
+            .ports
+            .iter()
+            .map(|p| p.serial)
+            .collect();
+        assert_eq!(ports, [201]);
+        assert_eq!(graph.remove_global(10), Some(Removed::Node(200)));
+        assert!(graph.port(201).is_none());
+    }
+
+    #[test]
+    fn reused_port_id_evicts_the_old_port() {
+        let mut graph = Graph::default();
+        graph.add_node(announced_sink(10, 100));
+        graph.add_node(announced_sink(20, 200));
+        graph.add_port(10, announced_port(11, 101, "FL"));
+
+        graph.add_port(20, announced_port(11, 201, "FL"));
+        assert!(graph.port(101).is_none());
+        assert!(graph.node(100).unwrap().ports.is_empty());
+        assert_eq!(graph.port(201).map(|p| p.global_id), Some(11));
+        assert_eq!(
+            graph.remove_global(11),
+            Some(Removed::Port {
+                node: 200,
+                port: 201
+            })
+        );
+    }
+
+    #[test]
+    fn same_serial_announced_again_is_kept() {
+        let mut graph = Graph::default();
+        graph.add_node(announced_sink(10, 100));
+        graph.add_port(10, announced_port(11, 101, "FL"));
+        graph.add_node(announced_sink(10, 100));
+        graph.add_port(10, announced_port(11, 101, "FL"));
+        assert_eq!(graph.node(100).unwrap().ports.len(), 1);
+    }
+
+    fn hdmi_sink(serial: Serial, profile_device: i32) -> NodeInfo {
+        NodeInfo {
+            device_id: Some(40),
+            profile_device: Some(profile_device),
+            ..sink(serial, &["FL", "FR"])
+        }
+    }
+
+    fn hdmi_route(monitor: Option<&str>) -> DeviceRoute {
+        DeviceRoute {
+            output: true,
+            devices: vec![3],
+            info: monitor
+                .map(|name| ("device.product.name".to_string(), name.to_string()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn monitor_name(graph: &Graph, serial: Serial) -> Option<&str> {
+        let node = graph.node(serial).unwrap();
+        node.properties.get(MONITOR_NAME_KEY).map(String::as_str)
+    }
+
+    #[test]
+    fn monitor_name_follows_the_route() {
+        let mut graph = Graph::default();
+        graph.add_node(hdmi_sink(1, 3));
+        graph.add_node(hdmi_sink(2, 7));
+
+        assert!(graph.set_route(40, &hdmi_route(Some("DELL U2415"))));
+        assert_eq!(monitor_name(&graph, 1), Some("DELL U2415"));
+        assert_eq!(monitor_name(&graph, 2), None);
+        // Announcing the same monitor again changes nothing
+        assert!(!graph.set_route(40, &hdmi_route(Some("DELL U2415"))));
+
+        assert!(graph.set_route(40, &hdmi_route(None)));
+        assert_eq!(monitor_name(&graph, 1), None);
+        assert!(!graph.set_route(40, &hdmi_route(None)));
+    }
+
+    #[test]
+    fn monitor_name_reaches_nodes_added_later() {
+        let mut graph = Graph::default();
+        assert!(!graph.set_route(40, &hdmi_route(Some("LG TV"))));
+        graph.add_node(hdmi_sink(1, 3));
+        assert_eq!(monitor_name(&graph, 1), Some("LG TV"));
+    }
+
+    #[test]
+    fn capture_routes_are_ignored() {
+        let mut graph = Graph::default();
+        graph.add_node(hdmi_sink(1, 3));
+        let capture = DeviceRoute {
+            output: false,
+            ..hdmi_route(Some("Webcam"))
+        };
+        assert!(!graph.set_route(40, &capture));
+        assert_eq!(monitor_name(&graph, 1), None);
+    }
+
+    #[test]
+    fn late_ports_on_routed_nodes_relink_once_settled() {
+        let settle = Duration::from_millis(250);
+        let bluetooth_settle = Duration::from_secs(2);
+        let mut graph = Graph::default();
+        // A JACK client connects without ports and registers them afterwards
+        graph.add_node(node(1, "Client", "Stream/Output/Audio", Vec::new()));
+        graph.add_node(NodeInfo {
+            bluetooth_profile: Some("a2dp-sink".to_string()),
+            ..sink(2, &[])
+        });
+        graph.add_node(node(3, "Other", "Stream/Output/Audio", Vec::new()));
+        let routed = |serial: Serial| serial != 3;
+        let delay = |graph: &Graph, node: Serial, gained: bool| {
+            graph.port_relink_delay(node, gained, routed, settle, bluetooth_settle)
+        };
+        for (id, channel) in [(11, "FL"), (12, "FR")] {
+            assert_eq!(graph.add_port(1, port(id, channel, "out")), Some(1));
+            assert_eq!(delay(&graph, 1, true), Some(settle));
+        }
+        assert_eq!(graph.node(1).unwrap().ports.len(), 2);
+        assert_eq!(graph.add_port(2, port(21, "FL", "in")), Some(2));
+        assert_eq!(delay(&graph, 2, true), Some(bluetooth_settle));
+        assert_eq!(graph.add_port(3, port(31, "FL", "out")), Some(3));
+        assert_eq!(delay(&graph, 3, true), None);
+
+        // Losing a port relinks after the usual wait, Bluetooth or not
+        for id in [12, 21] {
+            let Some(Removed::Port { node, .. }) = graph.remove_global(id) else {
+                panic!("port {} was not removed", id);
+            };
+            assert_eq!(delay(&graph, node, false), Some(settle));
+        }
+        assert_eq!(graph.node(1).unwrap().ports.len(), 1);
+    }
+
+    /// A link from the first port of `output` to the first port of `input`, as announced
+    fn link_entry(output: &NodeInfo, input: &NodeInfo, stamped: bool) -> LinkEntry {
+        LinkEntry {
+            output_node: output.global_id,
+            output_port: output.ports[0].global_id,
+            input_node: input.global_id,
+            input_port: input.ports[0].global_id,
+            client_id: None,
+            stamp: stamped.then(|| LinkStamp::own(None)),
+            state: None,
+        }
+    }
+
+    /// A graph whose nodes and ports are all registered, so links resolve to serials
+    fn linked_graph(nodes: &[NodeInfo]) -> Graph {
+        let mut graph = Graph::default();
+        for node in nodes {
+            graph.add_node(NodeInfo {
+                ports: Vec::new(),
+                ..node.clone()
+            });
+            for port in &node.ports {
+                graph.add_port(node.global_id, port.clone());
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn foreign_links_leave_out_stamped_and_own_links() {
+        let music = source(1, &["FL", "FR"]);
+        let voice = source(3, &["MONO"]);
+        let speakers = sink(2, &["FL", "FR"]);
+        let mut graph = linked_graph(&[music.clone(), voice.clone(), speakers.clone()]);
+        graph.add_link(10, link_entry(&music, &speakers, false)); // The session manager's
+        graph.add_link(11, link_entry(&voice, &speakers, false)); // Another pair
+        graph.add_link(12, link_entry(&music, &speakers, true)); // A stamped one
+        let nothing = HashSet::new();
+        let ids = |links: Vec<(u32, &LinkEntry)>| -> Vec<u32> {
+            links.into_iter().map(|(id, _)| id).collect()
+        };
+        assert_eq!(ids(graph.foreign_links()), [10, 11]);
+        assert_eq!(ids(graph.foreign_links_between(1, 2, &nothing)), [10]);
+        assert_eq!(ids(graph.foreign_links_between(3, 2, &nothing)), [11]);
+        assert!(graph.foreign_links_between(2, 1, &nothing).is_empty());
+
+        // One of the mixer's own links, announced before its stamp is known
+        let own = HashSet::from([(100, 200)]);
+        assert!(graph.foreign_links_between(1, 2, &own).is_empty());
+        assert!(graph.foreign_links_between(1, 9, &nothing).is_empty());
+    }
+
+    #[test]
+    fn clean_selects_only_stamped_links_matching_every_filter() {
+        let music = source(1, &["FL", "FR"]);
+        let speakers = sink(2, &["FL", "FR"]);
+        let mut graph = linked_graph(&[music.clone(), speakers.clone()]);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let stamped = |rule: Option<&str>, instance: Option<&str>, age: Option<u64>| LinkEntry {
+            stamp: Some(LinkStamp {
+                rule: rule.map(str::to_string),
+                instance: instance.map(str::to_string),
+                created: age.map(|age| now - age),
+                recreated: 0,
+            }),
+            ..link_entry(&music, &speakers, false)
+        };
+        graph.add_link(10, stamped(Some("music"), Some("100"), Some(7200)));
+        graph.add_link(11, stamped(Some("music"), Some("200"), Some(60)));
+        graph.add_link(12, stamped(Some("voice"), Some("100"), None));
+        graph.add_link(13, stamped(None, None, Some(7200))); // Made before rules were stamped
+        graph.add_link(14, link_entry(&music, &speakers, false)); // Untagged
+        let hour = Some(Duration::from_secs(3600));
+        let ids = |links: Vec<(u32, &LinkEntry)>| -> Vec<u32> {
+            links.into_iter().map(|(id, _)| id).collect()
+        };
+        assert_eq!(ids(graph.stale_links(None, None, None)), [10, 11, 12, 13]);
+        assert_eq!(ids(graph.stale_links(Some("music"), None, None)), [10, 11]);
+        assert_eq!(ids(graph.stale_links(None, Some("100"), None)), [10, 12]);
+        assert_eq!(ids(graph.stale_links(None, None, hour)), [10, 13]);
+        assert_eq!(
+            ids(graph.stale_links(Some("music"), Some("100"), hour)),
+            [10]
+        );
+        assert!(graph
+            .stale_links(Some("music"), Some("200"), hour)
+            .is_empty());
+        assert!(graph.stale_links(Some("other"), None, None).is_empty());
+    }
+
+    #[test]
+    fn only_foreign_links_compete_for_an_exclusive_output() {
+        let music = source(1, &["FL", "FR"]);
+        let speakers = sink(2, &["FL", "FR"]);
+        let voice = source(3, &["MONO"]);
+        let browser = source(4, &["FL", "FR"]);
+        let rewired = source(5, &["FL", "FR"]);
+        let headphones = sink(6, &["FL", "FR"]);
+        let mut graph = linked_graph(&[
+            music.clone(),
+            speakers.clone(),
+            voice.clone(),
+            browser.clone(),
+            rewired.clone(),
+            headphones.clone(),
+        ]);
+        let second = |link: LinkEntry| LinkEntry {
+            output_port: link.output_port + 1,
+            input_port: link.input_port + 1,
+            ..link
+        };
+        // Routed inputs: this route's, and another route's into the same output
+        graph.add_link(10, link_entry(&music, &speakers, true));
+        graph.add_link(11, link_entry(&voice, &speakers, false));
+        // The mixer's own links from a node no route names any more: one proxied, one adopted
+        graph.add_link(20, link_entry(&rewired, &speakers, true));
+        graph.add_link(21, second(link_entry(&rewired, &speakers, false)));
+        // Foreign links, into the output and elsewhere
+        graph.add_link(30, link_entry(&browser, &speakers, false));
+        graph.add_link(31, second(link_entry(&browser, &speakers, true)));
+        graph.add_link(32, link_entry(&browser, &headphones, false));
+        let own = HashSet::from([(500, 200), (501, 201)]);
+        let ids = |links: Vec<(u32, &LinkEntry)>| -> Vec<u32> {
+            links.into_iter().map(|(id, _)| id).collect()
+        };
+        assert_eq!(ids(graph.competitors(2, &[1, 3], &own)), [30, 31]);
+        assert_eq!(
+            ids(graph.competitors(2, &[1, 3], &HashSet::new())),
+            [20, 21, 30, 31]
+        );
+        assert_eq!(ids(graph.competitors(2, &[1], &own)), [11, 30, 31]);
+        assert_eq!(ids(graph.competitors(6, &[1], &own)), [32]);
+        assert!(graph.competitors(9, &[1], &own).is_empty());
+    }
+
+    #[test]
+    fn midi_formats() {
+        assert!(is_midi_format("8 bit raw midi"));
+        assert!(is_midi_format("32 bit raw UMP"));
+        assert!(!is_midi_format("32 bit float mono audio"));
+        assert!(!is_midi_format("32 bit float RGBA video"));
+        assert!(!is_midi_format(""));
+    }
+
+    #[test]
+    fn ports_summary_collapses_aux_runs() {
+        let channels: Vec<String> = (0..18).map(|index| format!("AUX{}", index)).collect();
+        let channels: Vec<&str> = channels.iter().map(String::as_str).collect();
+        assert_eq!(sink(1, &channels).ports_summary(), "[(AUX0–17, \"in\")]");
+    }
+
+    #[test]
+    fn ports_summary_keeps_named_ports_and_aux_gaps() {
+        let ports = vec![
+            port(10, "FL", "in"),
+            port(11, "AUX0", "in"),
+            port(12, "AUX1", "in"),
+            port(13, "AUX3", "in"),
+            port(14, "AUX0", "out"),
+        ];
+        assert_eq!(
+            node(1, "Interface", "Audio/Duplex", ports).ports_summary(),
+            "[(10/10, \"FL\", \"in\"), (AUX0–1, \"in\"), (AUX3, \"in\"), (AUX0, \"out\")]"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use pie_mixer::graph::{Graph, NodeInfo, NodeKind};
+
+/// Nodes printed together, under a heading when grouped
+pub struct Section<'a> {
+    pub heading: Option<String>,
+    pub nodes: Vec<&'a NodeInfo>,
+}
+
+/// Heading of the section listing the MIDI nodes
+pub const MIDI_HEADING: &str = "MIDI";
+
+/// Group the nodes under the device owning them, in device id order, followed by the nodes
+/// without a device (application streams) grouped by the application that created them, and
+/// last the MIDI nodes
+///
+/// Nodes keep their order within a section
+pub fn by_device<'a>(nodes: &[&'a NodeInfo], graph: &Graph) -> Vec<Section<'a>> {
+    let mut devices: BTreeMap<u32, Vec<&NodeInfo>> = BTreeMap::new();
+    let mut streams: BTreeMap<String, Vec<&NodeInfo>> = BTreeMap::new();
+    let mut midi: Vec<&NodeInfo> = Vec::new();
+    for node in nodes {
+        if node.kind() == NodeKind::Midi {
+            midi.push(node);
+            continue;
+        }
+        match node.device_id {
+            Some(device_id) => devices.entry(device_id).or_default().push(node),
+            None => streams
+                .entry(client_name(node, graph))
+                .or_default()
+                .push(node),
+        }
+    }
+
+    let devices = devices.into_iter().map(|(device_id, nodes)| {
+        let heading = match graph.device_description(device_id) {
+            Some(description) => format!("{} [Device ID: {}]", description, device_id),
+            None => format!("Device [ID: {}]", device_id),
+        };
+        Section {
+            heading: Some(heading),
+            nodes,
+        }
+    });
+    let streams = streams.into_iter().map(|(client, nodes)| Section {
+        heading: Some(format!("Streams: {}", client)),
+        nodes,
+    });
+    let midi = (!midi.is_empty()).then(|| Section {
+        heading: Some(MIDI_HEADING.to_string()),
+        nodes: midi,
+    });
+    devices.chain(streams).chain(midi).collect()
+}
+
+/// Name of the application owning a stream, preferring what its Client global reports
+fn client_name(node: &NodeInfo, graph: &Graph) -> String {
+    let client_id = node.properties.get("client.id");
+    client_id
+        .and_then(|id| graph.client_name(id.parse().ok()?))
+        .or_else(|| node.properties.get("application.name").map(String::as_str))
+        .map(str::to_string)
+        .or_else(|| client_id.map(|id| format!("client {}", id)))
+        .unwrap_or_else(|| "no client".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pie_mixer::graph::Serial;
+
+    use super::*;
+
+    fn node(
+        serial: Serial,
+        media_class: &str,
+        device_id: Option<u32>,
+        props: &[(&str, &str)],
+    ) -> NodeInfo {
+        NodeInfo {
+            global_id: serial as u32,
+            serial,
+            description: format!("Node {}", serial),
+            media_class: media_class.to_string(),
+            input: media_class.contains("Source") || media_class.contains("Output"),
+            classified: true,
+            nick: None,
+            app_name: None,
+            form_factor: None,
+            device_id,
+            profile_device: None,
+            device_string: None,
+            bluetooth_profile: None,
+            properties: props
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ports: Vec::new(),
+            caps: None,
+            state: None,
+        }
+    }
+
+    /// One line per section: its heading and the serials of its nodes
+    fn render(sections: &[Section]) -> String {
+        sections
+            .iter()
+            .map(|section| {
+                let serials: Vec<String> = section
+                    .nodes
+                    .iter()
+                    .map(|node| node.serial.to_string())
+                    .collect();
+                format!(
+                    "{}: {}\n",
+                    section.heading.as_deref().unwrap_or("-"),
+                    serials.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nodes_grouped_by_device_then_client_then_midi() {
+        let mut graph = Graph::default();
+        graph.add_device(40, Some("Built-in Audio".to_string()), BTreeMap::new());
+        graph.add_client(7, "Firefox".to_string());
+        let nodes = [
+            node(3, "Audio/Sink", Some(40), &[]),
+            node(1, "Audio/Sink", Some(41), &[]),
+            node(2, "Audio/Source", Some(40), &[]),
+            node(4, "Stream/Output/Audio", None, &[("client.id", "7")]),
+            node(
+                5,
+                "Stream/Output/Audio",
+                None,
+                &[("application.name", "mpv")],
+            ),
+            node(6, "Stream/Input/Audio", None, &[]),
+            node(7, "Midi/Bridge", Some(50), &[]),
+            node(8, "Stream/Output/Audio", None, &[("client.id", "9")]),
+            node(
+                9,
+                "Stream/Output/Audio",
+                None,
+                &[("client.id", "7"), ("application.name", "Firefox Nightly")],
+            ),
+        ];
+        let nodes: Vec<&NodeInfo> = nodes.iter().collect();
+        assert_eq!(
+            render(&by_device(&nodes, &graph)),
+            "Built-in Audio [Device ID: 40]: 3, 2\n\
+             Device [ID: 41]: 1\n\
+             Streams: Firefox: 4, 9\n\
+             Streams: client 9: 8\n\
+             Streams: mpv: 5\n\
+             Streams: no client: 6\n\
+             MIDI: 7\n"
+        );
+    }
+
+    #[test]
+    fn no_midi_section_without_midi_nodes() {
+        let nodes = [node(1, "Audio/Sink", Some(40), &[])];
+        let nodes: Vec<&NodeInfo> = nodes.iter().collect();
+        assert_eq!(
+            render(&by_device(&nodes, &Graph::default())),
+            "Device [ID: 40]: 1\n"
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Digital mixer for Raspberry Pi 4
+//!
+//! Discovers PipeWire nodes and mixes inputs into outputs by linking their ports
+
+pub mod channel;
+pub mod config;
+pub mod cycle;
+pub mod discover;
+pub mod error;
+pub mod expr;
+#[cfg(test)]
+mod fixtures;
+pub mod fuzzy;
+pub mod gate;
+pub mod graph;
+pub mod links;
+pub mod matcher;
+pub mod mixer;
+pub mod plan;
+pub mod pod;
+pub mod retry;
+pub mod routing;
+pub mod serve;
+pub mod service;
+pub mod settle;
+pub mod stamp;
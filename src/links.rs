@@ -0,0 +1,380 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::graph::{ActiveLink, Graph, Serial};
+use crate::retry::RateLimit;
+
+/// What linking one planned channel takes, given the links already relied on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkStep {
+    /// The mixer created or adopted a link between the ports already
+    Kept,
+    /// Another client, or an earlier run, linked the ports: a second link would double the
+    /// signal, so the link global with this id is used instead
+    Adopt(u32),
+    /// Nothing links the ports yet
+    Create,
+}
+
+/// What forgetting a link removed from the graph leaves the mixer to do
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Forgotten {
+    /// An adopted link went: linking its channel is up to the mixer now
+    Adopted,
+    /// Not a link the mixer relied on, or one whose channel is no longer active
+    Untracked,
+    /// One of the mixer's own links went, which only another client does
+    Own(ActiveLink),
+}
+
+/// The links a mixer relies on: those it created, each kept alive by its proxy `P`, and those
+/// it adopted from whoever made them, keyed on their (output port, input port) serials
+///
+/// Knows nothing of PipeWire, so the proxies can be anything, e.g. `()` in tests
+#[derive(Debug)]
+pub struct LinkBook<P> {
+    own: HashMap<(Serial, Serial), P>,
+    adopted: HashMap<(Serial, Serial), (u32, ActiveLink)>, // Link global id, and the link
+}
+
+impl<P> Default for LinkBook<P> {
+    fn default() -> Self {
+        LinkBook {
+            own: HashMap::new(),
+            adopted: HashMap::new(),
+        }
+    }
+}
+
+impl<P> LinkBook<P> {
+    /// Decide how to link the ports of `link`, looking for a link between them in the graph
+    pub fn step(&self, link: &ActiveLink, graph: &Graph) -> LinkStep {
+        let ports = (link.output_port, link.input_port);
+        if self.holds(&ports) {
+            return LinkStep::Kept;
+        }
+        graph
+            .link_ids(link)
+            .and_then(|(_, output_port, _, input_port)| graph.find_link(output_port, input_port))
+            .map_or(LinkStep::Create, LinkStep::Adopt)
+    }
+
+    /// True if the mixer created or adopted a link between the ports
+    pub fn holds(&self, ports: &(Serial, Serial)) -> bool {
+        self.own.contains_key(ports) || self.adopted.contains_key(ports)
+    }
+
+    /// Keep the proxy of a link the mixer created
+    pub fn insert(&mut self, ports: (Serial, Serial), proxy: P) {
+        self.own.insert(ports, proxy);
+    }
+
+    pub fn proxy(&self, ports: &(Serial, Serial)) -> Option<&P> {
+        self.own.get(ports)
+    }
+
+    pub fn proxies(&self) -> impl Iterator<Item = &P> {
+        self.own.values()
+    }
+
+    /// Give up a link the mixer created, returning its proxy to be dropped
+    pub fn remove(&mut self, ports: &(Serial, Serial)) -> Option<P> {
+        self.own.remove(ports)
+    }
+
+    /// Rely on a link another client made rather than creating a second one
+    pub fn adopt(&mut self, link_id: u32, link: ActiveLink) {
+        self.adopted
+            .insert((link.output_port, link.input_port), (link_id, link));
+    }
+
+    pub fn adopted(&self) -> impl Iterator<Item = (&(Serial, Serial), &(u32, ActiveLink))> {
+        self.adopted.iter()
+    }
+
+    /// Stop relying on an adopted link, returning its global id and the link
+    pub fn release(&mut self, ports: &(Serial, Serial)) -> Option<(u32, ActiveLink)> {
+        self.adopted.remove(ports)
+    }
+
+    /// Forget the adopted link with the global id, returning true if there was one
+    pub fn forget_adopted(&mut self, link_id: u32) -> bool {
+        let before = self.adopted.len();
+        self.adopted.retain(|_, (adopted, _)| *adopted != link_id);
+        self.adopted.len() != before
+    }
+
+    /// Forget a link removed from the graph, along with its active channel
+    ///
+    /// `ports` are those of the link global, None once they are gone; the mixer drops a proxy
+    /// before its link goes, so an own link still held was removed by someone else
+    pub fn forget(
+        &mut self,
+        link_id: u32,
+        ports: Option<(Serial, Serial)>,
+        active: &mut Vec<ActiveLink>,
+    ) -> Forgotten {
+        if self.forget_adopted(link_id) {
+            return Forgotten::Adopted;
+        }
+        let Some(ports) = ports else {
+            return Forgotten::Untracked;
+        };
+        if self.own.remove(&ports).is_none() {
+            return Forgotten::Untracked;
+        }
+        match active
+            .iter()
+            .position(|link| (link.output_port, link.input_port) == ports)
+        {
+            Some(index) => Forgotten::Own(active.remove(index)),
+            None => Forgotten::Untracked,
+        }
+    }
+
+    /// Forget the adopted links `keep` rejects, leaving them to whoever made them
+    pub fn retain_adopted(&mut self, mut keep: impl FnMut(&ActiveLink) -> bool) {
+        self.adopted.retain(|_, (_, link)| keep(link));
+    }
+
+    /// The port pairs of every link relied on, created or adopted
+    pub fn port_pairs(&self) -> HashSet<(Serial, Serial)> {
+        self.own
+            .keys()
+            .chain(self.adopted.keys())
+            .copied()
+            .collect()
+    }
+
+    /// The port pairs of the links the mixer created
+    pub fn own_port_pairs(&self) -> HashSet<(Serial, Serial)> {
+        self.own.keys().copied().collect()
+    }
+
+    /// Drop every proxy, which destroys the links the mixer created
+    pub fn clear_own(&mut self) {
+        self.own.clear();
+    }
+}
+
+/// What the watchdog makes of one of the mixer's links being removed by another client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recreation {
+    /// The watchdog is off: the link stays gone
+    Off,
+    /// The link was recreated as often as the rate limit allows, so another client wants it gone
+    RateLimited,
+    /// Recreate the link, for the given time
+    Recreate(u32),
+}
+
+/// Recreates the links another client removes, as often as a rate limit allows, and counts
+/// the recreations of each (output port, input port) pair for the link stamp
+#[derive(Debug)]
+pub struct Watchdog {
+    enabled: bool,
+    limit: RateLimit<(Serial, Serial)>,
+    recreated: HashMap<(Serial, Serial), u32>,
+}
+
+impl Watchdog {
+    pub fn new(enabled: bool, limit: usize, window: Duration) -> Self {
+        Watchdog {
+            enabled,
+            limit: RateLimit::new(limit, window),
+            recreated: HashMap::new(),
+        }
+    }
+
+    /// Decide whether to recreate the removed link, counting the recreation if so
+    pub fn recreate(&mut self, link: &ActiveLink, now: Instant) -> Recreation {
+        let ports = (link.output_port, link.input_port);
+        if !self.enabled {
+            return Recreation::Off;
+        }
+        if !self.limit.allow(ports, now) {
+            return Recreation::RateLimited;
+        }
+        let recreated = self.recreated.entry(ports).or_default();
+        *recreated += 1;
+        Recreation::Recreate(*recreated)
+    }
+
+    /// True if the ports were relinked as often as allowed, so linking them is left out for now
+    pub fn exhausted(&self, ports: &(Serial, Serial), now: Instant) -> bool {
+        self.enabled && self.limit.exhausted(ports, now)
+    }
+
+    /// Times the link between the ports was recreated, None if never
+    pub fn recreated(&self, ports: &(Serial, Serial)) -> Option<u32> {
+        self.recreated.get(ports).copied()
+    }
+
+    /// Drop the recreation counts of the links involving a removed global
+    pub fn forget(&mut self, serial: Serial) {
+        self.recreated
+            .retain(|(output_port, input_port), _| *output_port != serial && *input_port != serial);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{sink, source};
+    use crate::graph::{LinkEntry, NodeInfo};
+    use crate::mixer::Route;
+    use crate::plan::{plan_links, Pairing};
+
+    /// A graph holding the link another client made between the first ports of two nodes
+    fn linked_once(output: Serial, input: Serial) -> Graph {
+        let mut graph = Graph::default();
+        for node in [source(output, &["FL", "FR"]), sink(input, &["FL", "FR"])] {
+            let (id, ports) = (node.global_id, node.ports.clone());
+            graph.add_node(NodeInfo {
+                ports: Vec::new(),
+                ..node
+            });
+            for port in ports {
+                graph.add_port(id, port);
+            }
+        }
+        graph.add_link(40, link_entry(100, 200));
+        graph
+    }
+
+    /// A link between two fixture ports, whose nodes number them from `serial * 100`
+    fn link_entry(output_port: u32, input_port: u32) -> LinkEntry {
+        LinkEntry {
+            output_node: output_port / 100,
+            output_port,
+            input_node: input_port / 100,
+            input_port,
+            client_id: None,
+            stamp: None,
+            state: None,
+        }
+    }
+
+    fn planned(graph: &Graph) -> Vec<ActiveLink> {
+        let route = Route {
+            inputs: vec![1],
+            output: 2,
+            ..Route::default()
+        };
+        plan_links(
+            graph.node(1).unwrap(),
+            graph.node(2).unwrap(),
+            &route,
+            Pairing::default(),
+        )
+        .links
+        .into_iter()
+        .map(|planned| planned.link)
+        .collect()
+    }
+
+    #[test]
+    fn existing_links_are_adopted_and_only_the_missing_channels_created() {
+        let mut graph = linked_once(1, 2);
+        let links = planned(&graph);
+        let mut book: LinkBook<()> = LinkBook::default();
+        let steps: Vec<LinkStep> = links.iter().map(|link| book.step(link, &graph)).collect();
+        assert_eq!(steps, [LinkStep::Adopt(40), LinkStep::Create]);
+
+        book.adopt(40, links[0].clone());
+        book.insert((links[1].output_port, links[1].input_port), ());
+        for link in &links {
+            assert_eq!(book.step(link, &graph), LinkStep::Kept);
+        }
+        assert_eq!(book.port_pairs(), HashSet::from([(100, 200), (101, 201)]));
+        assert_eq!(book.own_port_pairs(), HashSet::from([(101, 201)]));
+
+        // The adopted link going away leaves its channel to be linked again
+        assert!(book.forget_adopted(40));
+        assert!(!book.forget_adopted(40));
+        graph.remove_global(40);
+        assert_eq!(book.step(&links[0], &graph), LinkStep::Create);
+    }
+
+    #[test]
+    fn removed_links_are_relinked_only_when_adopted_or_watched() {
+        let graph = linked_once(1, 2);
+        let links = planned(&graph);
+        let mut book: LinkBook<()> = LinkBook::default();
+        book.adopt(40, links[0].clone());
+        book.insert((links[1].output_port, links[1].input_port), ());
+        let mut active = links.clone();
+
+        // Adopted: the channel is the mixer's to link, its active entry stays
+        assert_eq!(
+            book.forget(40, Some((100, 200)), &mut active),
+            Forgotten::Adopted
+        );
+        assert_eq!(active.len(), 2);
+        // Someone else's link, or one whose ports are already gone
+        assert_eq!(
+            book.forget(41, Some((100, 201)), &mut active),
+            Forgotten::Untracked
+        );
+        assert_eq!(book.forget(42, None, &mut active), Forgotten::Untracked);
+        // Own: the channel goes from the active links
+        assert_eq!(
+            book.forget(43, Some((101, 201)), &mut active),
+            Forgotten::Own(links[1].clone())
+        );
+        assert_eq!(active, [links[0].clone()]);
+        assert!(!book.holds(&(101, 201)));
+        // Its proxy is gone with it, and an own link without an active channel is left alone
+        assert_eq!(
+            book.forget(43, Some((101, 201)), &mut active),
+            Forgotten::Untracked
+        );
+        book.insert((101, 201), ());
+        assert_eq!(
+            book.forget(44, Some((101, 201)), &mut active),
+            Forgotten::Untracked
+        );
+        assert!(!book.holds(&(101, 201)));
+    }
+
+    #[test]
+    fn the_watchdog_recreates_links_within_its_rate_limit() {
+        let link = planned(&linked_once(1, 2))[0].clone();
+        let ports = (link.output_port, link.input_port);
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+
+        let mut off = Watchdog::new(false, 2, window);
+        assert_eq!(off.recreate(&link, start), Recreation::Off);
+        assert_eq!(off.recreate(&link, start), Recreation::Off);
+        assert!(!off.exhausted(&ports, start));
+        assert_eq!(off.recreated(&ports), None);
+
+        let mut watchdog = Watchdog::new(true, 2, window);
+        assert_eq!(watchdog.recreate(&link, start), Recreation::Recreate(1));
+        assert!(!watchdog.exhausted(&ports, start));
+        assert_eq!(watchdog.recreate(&link, start), Recreation::Recreate(2));
+        assert!(watchdog.exhausted(&ports, start));
+        assert_eq!(watchdog.recreate(&link, start), Recreation::RateLimited);
+        assert_eq!(watchdog.recreated(&ports), Some(2));
+
+        // Once the window passes the link is recreated again, still counting up
+        let later = start + window;
+        assert!(!watchdog.exhausted(&ports, later));
+        assert_eq!(watchdog.recreate(&link, later), Recreation::Recreate(3));
+        watchdog.forget(link.output_port);
+        assert_eq!(watchdog.recreated(&ports), None);
+    }
+
+    #[test]
+    fn fully_linked_pairs_are_adopted_whole() {
+        let mut graph = linked_once(1, 2);
+        graph.add_link(41, link_entry(101, 201));
+        let book: LinkBook<()> = LinkBook::default();
+        let steps: Vec<LinkStep> = planned(&graph)
+            .iter()
+            .map(|link| book.step(link, &graph))
+            .collect();
+        assert_eq!(steps, [LinkStep::Adopt(40), LinkStep::Adopt(41)]);
+    }
+}
@@ -1,128 +1,721 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::Mutex;
+mod cli;
+mod failure;
+mod layout;
+mod metadata;
+mod mute;
+#[cfg(feature = "notify")]
+mod notify;
+mod picker;
+mod pidfile;
+mod progress;
+mod table;
+
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::process::ExitCode;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Result};
-use pipewire;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 
-/// Retain relevant metadata associated with a PipeWire node
-struct NodeInfo {
-    global_id: u32,
-    description: String,
-    media_class: String,
-    input: bool, // True if the node is an input (like a mic), False if the node is an output (like a speaker)
-    ports: Vec<(u32, String, String)>, // Port ID, Channel Name, Direction
-}
+use pipewire::link::LinkChangeMask;
+use pipewire::node::{NodeChangeMask, NodeState};
+use pipewire::spa::param::ParamType;
+use pipewire::spa::utils::result::AsyncSeq;
+
+use pie_mixer::channel::Channel;
+use pie_mixer::config::{Config, Rule, RuleInput};
+use pie_mixer::discover::{self, Collected, Connection};
+use pie_mixer::error::MixerError;
+use pie_mixer::expr::Expression;
+use pie_mixer::fuzzy::{self, fuzzy_pattern, FuzzyError};
+use pie_mixer::gate::{LinkWhile, DEFAULT_IDLE_GRACE};
+use pie_mixer::graph::{
+    identity_properties, ActiveLink, Defaults, Graph, LinkEntry, LinkPhase, Media, NodeInfo,
+    PortInfo, Removed, Serial,
+};
+use pie_mixer::matcher::{
+    default_sinks, is_wildcard, match_expression, match_node, rank_by_form_factor,
+    refuse_self_cascade, resolve_ambiguity, Ambiguous, Check, MatchOptions, MatchTrace,
+    OnAmbiguous, Side, DEFAULT_TARGET, MEDIA_ROLE_KEY,
+};
+use pie_mixer::mixer::{ForeignLinks, LinkOptions, Mixer, Route, Verification};
+use pie_mixer::plan::{compare_formats, plan_links, LinkPlan};
+use pie_mixer::pod::{self, AudioCaps};
+use pie_mixer::serve;
+use pie_mixer::settle::Settling;
+use pie_mixer::stamp::LinkStamp;
+
+use crate::cli::{Cli, Command, LinkMode};
+use crate::failure::Failure;
+use crate::mute::Muter;
+use crate::pidfile::Pidfile;
+use crate::progress::Spinner;
+
+/// Quiet period after the last port change on a routed node before relinking,
+/// so a client announcing many ports in quick succession is linked once
+const PORT_SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// How long a new default sink must stay in place before the links follow it, so switching
+/// back and forth quickly moves them once
+const DEFAULT_SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Delay before relinking a channel whose link was removed while its ports remain
+const WATCHDOG_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the states of new links are checked while waiting for them to be established
+const LINK_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 /// Entrypoint
-fn main() -> Result<()> {
-    // Initialize log/tracing
-    tracing_subscriber::fmt()
-        // Control verbosity with RUST_LOG environment variable, falling back to INFO as the default
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
-    info!("pie_mixer init...");
+fn main() -> ExitCode {
+    // Usage errors are configuration errors, while --help and --version exit as usual
+    let cli = Cli::try_parse().unwrap_or_else(|error| {
+        if !error.use_stderr() {
+            error.exit();
+        }
+        let _ = error.print();
+        std::process::exit(Failure::Config.code().into());
+    });
+    #[cfg(feature = "notify")]
+    let notify = cli.notify;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            #[cfg(feature = "notify")]
+            if notify {
+                notify::failed(&error);
+            }
+            Failure::exit_code(&error)
+        }
+    }
+}
 
-    // Initialize PipeWire
-    pipewire::init();
+fn run(mut cli: Cli) -> Result<()> {
+    // Patterns piped in by another program are read once, before anything is discovered
+    cli.read_stdin_patterns().context(Failure::Config)?;
+    cli.apply_aliases();
+    // Shared with the callbacks that re-resolve the rules while running
+    let cli = Rc::new(cli);
 
-    // Use a reference-counted main loop to allow sharing with callbacks
-    let pipewire_main_loop = pipewire::main_loop::MainLoopRc::new(None)
-        .map_err(|error| anyhow!("Failed to initialize PipeWire main loop: {:?}", error))?;
+    // Initialize log/tracing
+    // Control verbosity with RUST_LOG environment variable, falling back to INFO as the default
+    let env_filter = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into());
+    // Parse strictly first, so a typo in RUST_LOG can be reported instead of silently dropped
+    let (env_filter, invalid_directives) = match env_filter.from_env() {
+        Ok(filter) => (filter, None),
+        Err(error) => (env_filter.from_env_lossy(), Some(error)),
+    };
+    tracing_subscriber::fmt().with_env_filter(env_filter).init();
+    if let Some(error) = invalid_directives {
+        warn!(
+            "Ignoring invalid parts of RUST_LOG, the log level may not be what you expect: {}",
+            error
+        );
+    }
+    info!("pie_mixer init...");
 
-    // Manage local resources and the PipeWire session configuration
-    let pipewire_context = pipewire::context::ContextBox::new(&pipewire_main_loop.loop_(), None)
-        .map_err(|error| anyhow!("Failed to create PipeWire context: {:?}", error))?;
+    let rules = load_rules(&cli).context(Failure::Config)?;
 
-    // Connect to the PipeWire daemon
-    let pipewire_core = pipewire_context
-        .connect(None)
-        .map_err(|error| anyhow!("Failed to connect to PipeWire core: {:?}", error))?;
+    // Connect to the PipeWire daemon; the registry provides access to global objects like nodes
+    // and devices
+    let Connection {
+        main_loop,
+        context: _pipewire_context,
+        core: pipewire_core,
+        registry: pipewire_registry,
+    } = discover::connect()?;
 
-    // The registry provides access to global objects like nodes and devices
-    let pipewire_registry = pipewire_core
-        .get_registry()
-        .map_err(|error| anyhow!("Failed to retrieve PipeWire registry: {:?}", error))?;
+    // The main loop lives for the whole process, so leak it to let timers armed from callbacks
+    // borrow it
+    let pipewire_main_loop: &'static pipewire::main_loop::MainLoopRc =
+        Box::leak(Box::new(main_loop));
 
-    // Shared storage between the main thread and local callbacks
-    let discovered_nodes = Rc::new(Mutex::new(HashMap::<u32, NodeInfo>::new()));
+    // Shared storage between the main thread, local callbacks, and the optional HTTP server thread
+    let discovered_nodes = Arc::new(Mutex::new(Graph::default()));
     let discovered_nodes_collection = discovered_nodes.clone();
     let discovered_nodes_removal = discovered_nodes.clone();
+    let active_links = Arc::new(Mutex::new(Vec::<ActiveLink>::new()));
+
+    // The mixer owns the link proxies; it is configured with a route once discovery completes.
+    // Registry and timer callbacks run whenever the loop iterates, so the mixer, the graph and
+    // the other shared state are only borrowed or locked for a single call, and never while
+    // the loop runs
+    let mixer = Rc::new(RefCell::new(Mixer::new(
+        pipewire_core.clone(),
+        pipewire_registry.clone(),
+        discovered_nodes.clone(),
+        active_links.clone(),
+        LinkOptions {
+            passive: cli.passive,
+            pairing: cli.pairing,
+            node_latency: cli.node_latency,
+            foreign_links: cli.foreign_links,
+            best_effort: cli.best_effort,
+            teardown_adopted: cli.teardown_adopted,
+            allow_cycles: cli.allow_cycles,
+            append_to_existing: cli.output_append_to_existing,
+            link_factory: Some(cli.link_factory.clone()),
+            link_retries: cli.link_retries,
+            watchdog: !cli.no_watchdog,
+            link_stagger: Duration::from_millis(cli.link_stagger_ms),
+        },
+    )));
+
+    // Links the daemon refused are retried once their backoff has passed. The timer only holds
+    // the mixer weakly, as the mixer holds the timer to arm it
+    let mixer_retry = Rc::downgrade(&mixer);
+    let retry_timer = pipewire_main_loop.loop_().add_timer(move |_| {
+        if let Some(mixer) = mixer_retry.upgrade() {
+            mixer.borrow_mut().retry_links();
+        }
+    });
+    mixer.borrow_mut().set_retry_scheduler(move |delay| {
+        // An overdue retry comes back as zero, which would disarm the timer instead
+        retry_timer.update_timer(Some(delay.max(Duration::from_millis(1))), None);
+    });
+    // Staged links are requested one per tick of their own timer, held the same way
+    let mixer_stagger = Rc::downgrade(&mixer);
+    let stagger_timer = pipewire_main_loop.loop_().add_timer(move |_| {
+        if let Some(mixer) = mixer_stagger.upgrade() {
+            mixer.borrow_mut().create_staged();
+        }
+    });
+    mixer.borrow_mut().set_stagger_scheduler(move |delay| {
+        stagger_timer.update_timer(Some(delay.max(Duration::from_millis(1))), None);
+    });
+    // Inputs linked only while running are unlinked once their grace period runs out
+    let mixer_gate = Rc::downgrade(&mixer);
+    let gate_timer = pipewire_main_loop.loop_().add_timer(move |_| {
+        if let Some(mixer) = mixer_gate.upgrade() {
+            mixer.borrow_mut().close_idle_inputs();
+        }
+    });
+    mixer.borrow_mut().set_gate_scheduler(move |delay| {
+        gate_timer.update_timer(Some(delay.max(Duration::from_millis(1))), None);
+    });
+    let mixer_ports = mixer.clone();
+    let mixer_removal = mixer.clone();
+
+    // Inputs matching --mute are linked at zero volume, whenever they are routed
+    let muter = Rc::new(RefCell::new(Muter::new(
+        pipewire_registry.clone(),
+        cli.mute.clone(),
+        cli.match_options(),
+    )));
+
+    // Relink the route once a burst of port changes has settled
+    let mixer_relink = mixer.clone();
+    let muter_relink = muter.clone();
+    let graph_relink = discovered_nodes.clone();
+    let relink_timer = Rc::new(pipewire_main_loop.loop_().add_timer(move |_| {
+        let created = mixer_relink.borrow_mut().link_all();
+        if created > 0 {
+            info!("Port changes settled, created {} missing link(s)", created);
+        }
+        // A replacement node adopted into a route is muted like the one it replaces
+        muter_relink
+            .borrow_mut()
+            .sync(&graph_relink, &mixer_relink.borrow().inputs());
+    }));
+    let relink_timer_removal = relink_timer.clone();
+    let relink_timer_adopt = relink_timer.clone();
+    // Bluetooth nodes often flap between profiles, so they are given longer to settle
+    let bluetooth_settle = Duration::from_millis(cli.bluetooth_settle);
+
+    // A device recreating a routed node (e.g. a Bluetooth profile switch) keeps its route, and
+    // an input linked only while running is linked as soon as it starts
+    let mixer_adopt = mixer.clone();
+    let graph_adopt = discovered_nodes.clone();
+    let node_updated: Rc<dyn Fn(Serial)> = Rc::new(move |serial| {
+        let Some(node) = graph_adopt
+            .lock()
+            .ok()
+            .and_then(|graph| graph.node(serial).cloned())
+        else {
+            return;
+        };
+        let mut mixer = mixer_adopt.borrow_mut();
+        if mixer.adopt_replacement(&node) {
+            relink_timer_adopt.update_timer(Some(bluetooth_settle), None);
+        } else if mixer.input_state_changed(serial, node.is_busy()) {
+            relink_timer_adopt.update_timer(Some(Duration::from_millis(1)), None);
+        }
+    });
+
+    // Expose the graph over HTTP, updating live as the registry changes
+    if let Some(addr) = cli.serve {
+        serve::spawn(addr, discovered_nodes.clone(), active_links.clone())?;
+    }
+
+    // The rules in effect, replaced when the configuration is reloaded
+    let active_rules = Rc::new(RefCell::new(rules.clone()));
+
+    // Re-resolve the rules once a monitor name has changed (e.g. an AV receiver was switched on),
+    // moving the links to whichever nodes match now
+    let reselect_rules = active_rules.clone();
+    let reselect_graph = discovered_nodes.clone();
+    let mixer_reselect = mixer.clone();
+    let cli_reselect = cli.clone();
+    // Nodes that appeared matching a rule with settle_ms, left out of selections until then
+    let settling: Rc<RefCell<Settling>> = Rc::default();
+    let settling_reselect = settling.clone();
+    let settling_reload = settling.clone();
+    let muter_reselect = muter.clone();
+    let reselect_timer = Rc::new(pipewire_main_loop.loop_().add_timer(move |_| {
+        match reapply_rules(
+            &reselect_graph,
+            &mixer_reselect,
+            &reselect_rules.borrow(),
+            &cli_reselect,
+            &settling_reselect.borrow(),
+        ) {
+            Ok(created) => info!("Rules re-evaluated, created {} link(s)", created),
+            Err(error) if cli_reselect.wait_forever && unmatched(&error) => {
+                debug!("Still waiting for matching nodes: {}", error)
+            }
+            Err(error) => warn!("Keeping the current links: {}", error),
+        }
+        // Inputs that appeared since are muted as they are routed
+        muter_reselect
+            .borrow_mut()
+            .sync(&reselect_graph, &mixer_reselect.borrow().inputs());
+    }));
+    let reselect_timer_default = reselect_timer.clone();
+    let reselect_timer_role = reselect_timer.clone();
+    let reselect_timer_waiting = reselect_timer.clone();
+    let reselect_timer_settled = reselect_timer.clone();
+    let mixer_monitors = mixer.clone();
+    let monitor_changed: Rc<dyn Fn()> = Rc::new(move || {
+        if mixer_monitors.borrow().has_routes() {
+            debug!("Monitor name changed, re-evaluating rules once it settles");
+            reselect_timer.update_timer(Some(PORT_SETTLE_DELAY), None);
+        }
+    });
+
+    // Follow the default sink once it stops changing, so flapping does not relink every time
+    let mixer_default = mixer.clone();
+    let default_rules = active_rules.clone();
+    let default_changed: Rc<dyn Fn()> = Rc::new(move || {
+        let follows_default = default_rules
+            .borrow()
+            .iter()
+            .any(|rule| rule.output == DEFAULT_TARGET);
+        if follows_default && mixer_default.borrow().has_routes() {
+            debug!("Default sink changed, moving the links once it settles");
+            reselect_timer_default.update_timer(Some(DEFAULT_SETTLE_DELAY), None);
+        }
+    });
+
+    // Streams come and go, and some players change their role while playing: rules matching on a
+    // role are re-resolved once the stream's ports have settled
+    let mixer_roles = mixer.clone();
+    let role_rules = active_rules.clone();
+    let role_changed: Rc<dyn Fn()> = Rc::new(move || {
+        let matches_roles = role_rules.borrow().iter().any(|rule| rule.role.is_some());
+        if matches_roles && mixer_roles.borrow().has_routes() {
+            debug!("Stream role appeared or changed, re-evaluating rules once it settles");
+            reselect_timer_role.update_timer(Some(PORT_SETTLE_DELAY), None);
+        }
+    });
+
+    // Once a held node has been left alone for its rule's settle_ms, the rules are re-evaluated
+    // if it is still there and still matches, picking up its ports as they are by then. The
+    // timer re-arms itself for the next node due, so it only holds itself weakly
+    let settle_timer: Rc<OnceCell<pipewire::loop_::TimerSource<'static>>> = Rc::default();
+    let settle_timer_rearm = Rc::downgrade(&settle_timer);
+    let settling_timer = settling.clone();
+    let settle_graph = discovered_nodes.clone();
+    let settle_rules = active_rules.clone();
+    let cli_settle = cli.clone();
+    let _ = settle_timer.set(pipewire_main_loop.loop_().add_timer(move |_| {
+        let now = Instant::now();
+        let settled = settling_timer.borrow_mut().take_settled(now);
+        let still_matching = settle_graph.lock().is_ok_and(|graph| {
+            settled.iter().any(|serial| {
+                graph.node(*serial).is_some_and(|node| {
+                    settle_delay(node, &settle_rules.borrow(), &cli_settle) > Duration::ZERO
+                })
+            })
+        });
+        if still_matching {
+            debug!("Appeared node settled, re-evaluating rules");
+            reselect_timer_settled.update_timer(Some(Duration::from_millis(1)), None);
+        }
+        let next = settling_timer.borrow().next_due(now);
+        if let (Some(delay), Some(timer)) = (next, settle_timer_rearm.upgrade()) {
+            if let Some(timer) = timer.get() {
+                timer.update_timer(Some(delay.max(Duration::from_millis(1))), None);
+            }
+        }
+    }));
+
+    // With --wait-forever, any node appearing while a rule lacks its nodes may complete it.
+    // Nodes announced by the initial discovery are not held back, they are selected right away
+    let mixer_waiting = mixer.clone();
+    let wait_forever = cli.wait_forever;
+    let discovered = Rc::new(Cell::new(false));
+    let discovered_added = discovered.clone();
+    let settle_hold_rules = active_rules.clone();
+    let cli_hold = cli.clone();
+    let node_added = move |node: &NodeInfo| {
+        let delay = settle_delay(node, &settle_hold_rules.borrow(), &cli_hold);
+        if discovered_added.get() && delay > Duration::ZERO {
+            let now = Instant::now();
+            let name = node
+                .properties
+                .get("node.name")
+                .unwrap_or(&node.description);
+            debug!(
+                "[Serial: {}, {}] appeared, linking it once it has settled for {} ms",
+                node.serial,
+                node.description,
+                delay.as_millis()
+            );
+            let mut settling = settling.borrow_mut();
+            settling.hold(name, node.serial, delay, now);
+            if let (Some(timer), Some(next)) = (settle_timer.get(), settling.next_due(now)) {
+                timer.update_timer(Some(next.max(Duration::from_millis(1))), None);
+            }
+        }
+        if wait_forever && mixer_waiting.borrow().awaiting_nodes() {
+            debug!("Node appeared while waiting for matches, re-evaluating rules once it settles");
+            reselect_timer_waiting.update_timer(Some(PORT_SETTLE_DELAY), None);
+        }
+    };
+
+    // Devices and nodes are bound to read the properties missing from their globals, like
+    // device.bus-path; the proxies and listeners are kept until the object is removed
+    let registry_bind = pipewire_registry.clone();
+    // Every node is bound to learn its state when outputs in use are to be left alone, or
+    // inputs are only linked while running. A reload may turn this on later
+    let bind_all_nodes = Rc::new(Cell::new(cli.only_if_idle || links_while_running(&rules)));
+    let bind_all_nodes_registry = bind_all_nodes.clone();
+    let discovered_nodes_binding = discovered_nodes.clone();
+    let bound_proxies: Rc<RefCell<HashMap<u32, BoundProxy>>> = Rc::default();
+    let bound_proxies_removal = bound_proxies.clone();
+
+    // Nodes announced before a reload asked for their state are bound through a second
+    // registry, which announces every global again. It is kept for as long as the mixer runs
+    let late_registry: Rc<
+        RefCell<Option<(pipewire::registry::RegistryRc, pipewire::registry::Listener)>>,
+    > = Rc::default();
+    let bind_known_nodes = {
+        let core = pipewire_core.clone();
+        let graph = discovered_nodes.clone();
+        let bound_proxies = bound_proxies.clone();
+        let monitor_changed = monitor_changed.clone();
+        let role_changed = role_changed.clone();
+        let node_updated = node_updated.clone();
+        move || {
+            let registry = match core.get_registry_rc() {
+                Ok(registry) => registry,
+                Err(error) => {
+                    warn!("Failed to bind the nodes known so far: {:?}", error);
+                    return;
+                }
+            };
+            let registry_bind = registry.clone();
+            let graph = graph.clone();
+            let bound_proxies = bound_proxies.clone();
+            let monitor_changed = monitor_changed.clone();
+            let role_changed = role_changed.clone();
+            let node_updated = node_updated.clone();
+            let listener = registry
+                .add_listener_local()
+                .global(move |global_object| {
+                    if global_object.type_ != pipewire::types::ObjectType::Node
+                        || bound_proxies.borrow().contains_key(&global_object.id)
+                    {
+                        return;
+                    }
+                    let Some(serial) = graph.lock().ok().and_then(|graph| {
+                        graph
+                            .nodes()
+                            .find(|node| node.global_id == global_object.id)
+                            .map(|node| node.serial)
+                    }) else {
+                        return;
+                    };
+                    match bind_node_info(
+                        &registry_bind,
+                        global_object,
+                        serial,
+                        &graph,
+                        monitor_changed.clone(),
+                        role_changed.clone(),
+                        node_updated.clone(),
+                    ) {
+                        Ok(bound) => {
+                            bound_proxies.borrow_mut().insert(global_object.id, bound);
+                        }
+                        Err(error) => warn!(
+                            "Failed to bind node [Serial: {}] to read its state: {:?}",
+                            serial, error
+                        ),
+                    }
+                })
+                .register();
+            *late_registry.borrow_mut() = Some((registry, listener));
+        }
+    };
+    // Rules moving streams through target.object write to the default metadata as well
+    let mixer_metadata = mixer.clone();
+
+    // With --watch, every global is logged as it comes and goes, remembered to log its removal
+    let watched: Option<Rc<RefCell<HashMap<u32, String>>>> = cli.watch.then(Rc::default);
+    let watched_removal = watched.clone();
+
+    // The metadata subcommand binds every Metadata global, printing changes once discovery is done
+    let dump_metadata = matches!(cli.command, Some(Command::Metadata { .. }));
+    let metadata_json = matches!(cli.command, Some(Command::Metadata { json: true, .. }));
+    let metadata_store: metadata::Store = Rc::default();
+    let metadata_store_binding = metadata_store.clone();
+    let metadata_store_removal = metadata_store.clone();
+    let metadata_live = Rc::new(Cell::new(false));
+    let metadata_live_listener = metadata_live.clone();
+    let metadata_proxies: Rc<RefCell<HashMap<u32, BoundProxy>>> = Rc::default();
+    let metadata_proxies_removal = metadata_proxies.clone();
 
     // Listener reacting to global events (i.e. nodes and ports) from the registry
     // Must be kept in scope to continue receiving callbacks
     let _registry_listener = pipewire_registry
         .add_listener_local()
         .global(move |global_object| {
-            if global_object.type_ == pipewire::types::ObjectType::Node {
-                if let Some(props) = global_object.props {
-                    let description = props
-                        .get(*pipewire::keys::NODE_DESCRIPTION)
-                        .or_else(|| props.get(*pipewire::keys::NODE_NAME))
-                        .unwrap_or("Unknown");
-                    let media_class = props.get(*pipewire::keys::MEDIA_CLASS).unwrap_or("Unknown");
-                    let input = media_class.contains("Source") || media_class.contains("Input");
-                    // Save the discovered node
-                    if let Ok(mut nodes) = discovered_nodes_collection.lock() {
-                        nodes.entry(global_object.id).or_insert(NodeInfo {
-                            global_id: global_object.id,
-                            description: description.to_string(),
-                            media_class: media_class.to_string(),
-                            input,
-                            ports: Vec::new(),
-                        });
+            if let Some(watched) = &watched {
+                let summary = describe_global(global_object);
+                info!("+ {}", summary);
+                watched.borrow_mut().insert(global_object.id, summary);
+            }
+            if global_object.type_ == pipewire::types::ObjectType::Device {
+                match bind_device(
+                    &registry_bind,
+                    global_object,
+                    &discovered_nodes_binding,
+                    monitor_changed.clone(),
+                ) {
+                    Ok(bound) => {
+                        bound_proxies.borrow_mut().insert(global_object.id, bound);
+                    }
+                    Err(error) => warn!(
+                        "Failed to bind device [ID: {}] to read its properties: {:?}",
+                        global_object.id, error
+                    ),
+                }
+            }
+            if global_object.type_ == pipewire::types::ObjectType::Client {
+                let name = global_object
+                    .props
+                    .and_then(|props| props.get(*pipewire::keys::APP_NAME));
+                if let Some(name) = name {
+                    if let Ok(mut graph) = discovered_nodes_collection.lock() {
+                        graph.add_client(global_object.id, name.to_string());
+                    }
+                }
+            }
+            if dump_metadata && global_object.type_ == pipewire::types::ObjectType::Metadata {
+                match metadata::bind(
+                    &registry_bind,
+                    global_object,
+                    &metadata_store_binding,
+                    metadata_live_listener.clone(),
+                    metadata_json,
+                ) {
+                    Ok(bound) => {
+                        metadata_proxies
+                            .borrow_mut()
+                            .insert(global_object.id, bound);
                     }
+                    Err(error) => warn!(
+                        "Failed to bind metadata [ID: {}]: {:?}",
+                        global_object.id, error
+                    ),
                 }
             }
-            if global_object.type_ == pipewire::types::ObjectType::Port {
-                if let Some(props) = global_object.props {
-                    if let Some(node_id) = props
-                        .get(*pipewire::keys::NODE_ID)
-                        .and_then(|s| s.parse::<u32>().ok())
+            let default_metadata = global_object.type_ == pipewire::types::ObjectType::Metadata
+                && global_object
+                    .props
+                    .is_some_and(|props| props.get("metadata.name") == Some("default"));
+            if default_metadata {
+                match bind_default_metadata(
+                    &registry_bind,
+                    global_object,
+                    &discovered_nodes_binding,
+                    default_changed.clone(),
+                ) {
+                    Ok(bound) => {
+                        bound_proxies.borrow_mut().insert(global_object.id, bound);
+                    }
+                    Err(error) => warn!(
+                        "Failed to bind the default metadata [ID: {}]: {:?}",
+                        global_object.id, error
+                    ),
+                }
+                match registry_bind.bind::<pipewire::metadata::Metadata, _>(global_object) {
+                    Ok(metadata) => mixer_metadata.borrow_mut().set_metadata(metadata),
+                    Err(error) => warn!(
+                        "Failed to bind the default metadata [ID: {}] for the mixer: {:?}",
+                        global_object.id, error
+                    ),
+                }
+            }
+            // Link globals only carry their endpoints, the stamp is in the bound link's info
+            if global_object.type_ == pipewire::types::ObjectType::Link {
+                if let Some(link) = global_object.props.and_then(LinkEntry::from_props) {
+                    if let Ok(mut graph) = discovered_nodes_binding.lock() {
+                        graph.add_link(global_object.id, link);
+                    }
+                }
+                match bind_link_info(&registry_bind, global_object, &discovered_nodes_binding) {
+                    Ok(bound) => {
+                        bound_proxies.borrow_mut().insert(global_object.id, bound);
+                    }
+                    Err(error) => warn!(
+                        "Failed to bind link [ID: {}] to read its properties: {:?}",
+                        global_object.id, error
+                    ),
+                }
+            }
+            // Save the discovered node or port
+            let collected = discovered_nodes_collection
+                .lock()
+                .ok()
+                .and_then(|mut nodes| discover::collect(&mut nodes, global_object));
+            match collected {
+                Some(Collected::Node(serial)) => {
+                    let node = discovered_nodes_collection
+                        .lock()
+                        .ok()
+                        .and_then(|nodes| nodes.node(serial).cloned());
+                    if let Some(node) = node {
+                        node_added(&node);
+                    }
+                    let Some(props) = global_object.props else {
+                        return;
+                    };
+                    let role = props.get(*pipewire::keys::MEDIA_ROLE).is_some();
+                    if role {
+                        role_changed();
+                    }
+                    // Only device nodes can be matched to a device route, and only streams with a
+                    // role may change it
+                    if props.get(*pipewire::keys::DEVICE_ID).is_some()
+                        || role
+                        || bind_all_nodes_registry.get()
                     {
-                        let channel = props
-                            .get(*pipewire::keys::AUDIO_CHANNEL)
-                            .or(props.get(*pipewire::keys::PORT_NAME))
-                            .unwrap_or("unknown")
-                            .to_string();
-                        let dir = props
-                            .get(*pipewire::keys::PORT_DIRECTION)
-                            .unwrap_or("unknown")
-                            .to_string();
-                        // Save the discovered port
-                        if let Ok(mut nodes) = discovered_nodes_collection.lock() {
-                            if let Some(node) = nodes.get_mut(&node_id) {
-                                node.ports.push((global_object.id, channel, dir));
+                        match bind_node_info(
+                            &registry_bind,
+                            global_object,
+                            serial,
+                            &discovered_nodes_binding,
+                            monitor_changed.clone(),
+                            role_changed.clone(),
+                            node_updated.clone(),
+                        ) {
+                            Ok(bound) => {
+                                bound_proxies.borrow_mut().insert(global_object.id, bound);
                             }
+                            Err(error) => warn!(
+                                "Failed to bind node [Serial: {}] to read its properties: {:?}",
+                                serial, error
+                            ),
+                        }
+                    }
+                }
+                Some(Collected::Port {
+                    node: node_serial,
+                    port,
+                    reclassified,
+                }) => {
+                    if let Some(node) = discovered_nodes_collection
+                        .lock()
+                        .ok()
+                        .and_then(|nodes| nodes.node(node_serial).cloned())
+                    {
+                        if reclassified {
+                            note_classification(&node);
                         }
+                        note_duplicate_channel(&node, &port);
+                    }
+                    // Ports added late (e.g. by JACK clients) on a routed node need linking too
+                    let delay = discovered_nodes_collection.lock().ok().and_then(|graph| {
+                        graph.port_relink_delay(
+                            node_serial,
+                            true,
+                            |node| mixer_ports.borrow().involves(node),
+                            PORT_SETTLE_DELAY,
+                            bluetooth_settle,
+                        )
+                    });
+                    if let Some(delay) = delay {
+                        debug!("Port added to routed node, relinking once ports settle");
+                        relink_timer.update_timer(Some(delay), None);
                     }
                 }
+                None => {}
             }
         })
         .global_remove(move |id| {
-            // Evict node from cache if destroyed in the PipeWire graph
-            if let Ok(mut nodes) = discovered_nodes_removal.lock() {
-                nodes.remove(&id);
+            if let Some(watched) = &watched_removal {
+                match watched.borrow_mut().remove(&id) {
+                    Some(summary) => info!("- {}", summary),
+                    None => info!("- [ID: {}]", id),
+                }
+            }
+            bound_proxies_removal.borrow_mut().remove(&id);
+            metadata_proxies_removal.borrow_mut().remove(&id);
+            // The mixer links the channel itself once a link it adopted is gone, and recreates
+            // its own links removed by others. Waiting lets a port removal that took the link
+            // along arrive first
+            if mixer_removal.borrow_mut().forget_link(id) {
+                debug!("Link [ID: {}] removed, relinking its channel", id);
+                relink_timer_removal.update_timer(Some(WATCHDOG_DELAY), None);
+            }
+            metadata_store_removal.borrow_mut().remove(&id);
+            // Evict node or port from cache if destroyed in the PipeWire graph
+            let removed = discovered_nodes_removal
+                .lock()
+                .ok()
+                .and_then(|mut nodes| nodes.remove_global(id));
+            // Links to or from a destroyed node or port are destroyed along with it
+            if let Some(removed) = removed {
+                mixer_removal.borrow_mut().forget(removed);
+                // Devices re-announce their ports on some format changes, relink the channels
+                // once the replacements have settled
+                if let Removed::Port { node, .. } = removed {
+                    let delay = discovered_nodes_removal.lock().ok().and_then(|graph| {
+                        graph.port_relink_delay(
+                            node,
+                            false,
+                            |node| mixer_removal.borrow().involves(node),
+                            PORT_SETTLE_DELAY,
+                            bluetooth_settle,
+                        )
+                    });
+                    if let Some(delay) = delay {
+                        debug!("Port removed from routed node, relinking once ports settle");
+                        relink_timer_removal.update_timer(Some(delay), None);
+                    }
+                }
             }
         })
         .register();
 
     // Set up a listener that only quits when our specific sync is finished
-    let main_loop_handle = pipewire_main_loop.clone();
+    let main_loop_handle = pipewire_main_loop;
     let pending_sync = Rc::new(RefCell::new(None));
     let pending_sync_check = pending_sync.clone();
     let _core_listener = pipewire_core
         .add_listener_local()
         .done(move |_object_id, seq| {
-            // Check if we have a sequence number to wait for
-            if let Some(target_seq) = *pending_sync_check.borrow() {
+            // Check if we have a sequence number to wait for, copied out so the next roundtrip
+            // can set it from wherever the loop was quit
+            let pending = *pending_sync_check.borrow();
+            if let Some(target_seq) = pending {
                 // seq and target_seq are both of the correct internal SPA type
                 if seq == target_seq {
                     main_loop_handle.quit();
@@ -131,148 +724,1787 @@ fn main() -> Result<()> {
         })
         .register();
 
-    // Trigger a sync event and store the sequence number
-    let sync_seq = pipewire_core
-        .sync(0)
-        .map_err(|error| anyhow!("PipeWire sync failed: {:?}", error))?;
-    *pending_sync.borrow_mut() = Some(sync_seq);
+    // Force discovery to end if the daemon never answers the sync
+    let discovery_timer = cli.discovery_timeout.map(|timeout_ms| {
+        let timer = pipewire_main_loop.loop_().add_timer(move |_| {
+            warn!(
+                "Discovery timed out after {} ms, the node list may be incomplete",
+                timeout_ms
+            );
+            pipewire_main_loop.quit();
+        });
+        timer.update_timer(Some(Duration::from_millis(timeout_ms)), None);
+        timer
+    });
 
-    // Run the loop until the 'done' event with the matching sequence number is received
-    pipewire_main_loop.run();
+    // Busy graphs take a while to list, show that discovery is progressing unless every global
+    // is printed anyway or the output is meant for another program
+    let spinner = if cli.watch || metadata_json {
+        None
+    } else {
+        Spinner::start(pipewire_main_loop, discovered_nodes.clone())
+    };
 
-    // Output the results in a readable format
+    // Run the loop until the initial burst of globals has been received, then until device
+    // properties have arrived in reply to the binds made meanwhile. Slow graphs may need more
+    // rounds to announce globals created while earlier ones were handled
+    for _ in 0..cli.discovery_rounds {
+        roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+    }
+    // Dropping the timer disarms it
+    drop(discovery_timer);
+    drop(spinner);
+    discovered.set(true);
 
-    // 1. Collect values for sorting
-    let nodes_lock = discovered_nodes.lock().unwrap();
-    let mut sorted_nodes: Vec<&NodeInfo> = nodes_lock.values().collect();
+    if let Some(Command::Metadata { watch, json }) = cli.command {
+        metadata::print_all(&metadata_store, json)?;
+        if watch {
+            info!("Watching metadata, press Ctrl+C to stop...");
+            metadata_live.set(true);
+            pipewire_main_loop.run();
+        }
+        return Ok(());
+    }
 
-    // 2. Sort by global_id in ascending order
-    sorted_nodes.sort_by_key(|n| n.global_id);
+    if let Some(Command::Clean {
+        rule,
+        instance,
+        older_than,
+    }) = &cli.command
+    {
+        let graph = discovered_nodes
+            .lock()
+            .map_err(|_| anyhow!("Discovered node cache is poisoned"))?;
+        let stale: Vec<(u32, String)> = graph
+            .stale_links(rule.as_deref(), instance.as_deref(), *older_than)
+            .into_iter()
+            .map(|(link_id, link)| (link_id, graph.describe_link(link)))
+            .collect();
+        drop(graph);
+        return clean_links(
+            pipewire_main_loop,
+            &pipewire_core,
+            &pipewire_registry,
+            &pending_sync,
+            stale,
+            cli.dry_run,
+        );
+    }
 
-    // 3. Determine the longest description for table-like alignment
+    // Only observe: the registry listener keeps logging until the process is stopped
+    if cli.watch {
+        info!("Watching the graph, press Ctrl+C to stop...");
+        pipewire_main_loop.run();
+        return Ok(());
+    }
+
+    // Output the results in a readable format
+
+    // 1. Collect values sorted by global_id in ascending order
+    let mut nodes_snapshot = snapshot(&discovered_nodes)?;
+    let sorted_nodes: Vec<&NodeInfo> = nodes_snapshot.iter().collect();
+
+    // 2. Determine the longest description for table-like alignment
     let max_desc_len = sorted_nodes
         .iter()
         .map(|n| n.description.len())
         .max()
         .unwrap_or(40);
 
-    // 4. Print table
+    // 3. Print table
     info!("PipeWire nodes found: {}", sorted_nodes.len());
     for node in &sorted_nodes {
         debug!(
-            "[ID: {:3}]  Description: {:<width$}  [Type: {}  Ports: {:?}",
+            "[ID: {:3} | Serial: {:4}]  Description: {:<width$}  [Type: {:>13}]  Ports: {}",
             node.global_id,
+            node.serial,
             node.description,
-            match node.media_class.as_str() {
-                "Audio/Sink" | "Stream/Input/Audio" => " Audio Output]",
-                "Audio/Source" | "Stream/Output/Audio" => "  Audio Input]",
-                "Video/Source" | "Stream/Output/Video" => "  Video Input]",
-                "Video/Sink" | "Stream/Input/Video" => " Video Output]",
-                _ => "Other/Virtual]",
-            },
-            node.ports,
+            node.kind().label(),
+            node.ports_summary(),
             width = max_desc_len
         );
     }
 
+    // 4. Optionally print an aligned, colorized table regardless of the log level
+    if cli.pretty {
+        table::print_nodes(&sorted_nodes, false);
+    }
+
+    if let Some(Command::List { verbose, by_device }) = cli.command {
+        if verbose {
+            let audio_nodes: Vec<&NodeInfo> = nodes_snapshot
+                .iter()
+                .filter(|node| node.media_class.contains("Audio"))
+                .collect();
+            let _bound = query_caps(&pipewire_registry, &discovered_nodes, &audio_nodes);
+            roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+            nodes_snapshot = snapshot(&discovered_nodes)?;
+        }
+        let sorted_nodes: Vec<&NodeInfo> = nodes_snapshot.iter().collect();
+        if by_device {
+            let sections = layout::by_device(
+                &sorted_nodes,
+                &*discovered_nodes
+                    .lock()
+                    .map_err(|_| anyhow!("Discovered node cache is poisoned"))?,
+            );
+            table::print_sections(&sections, verbose);
+        } else {
+            table::print_nodes(&sorted_nodes, verbose);
+        }
+        if cli.verbose_props {
+            print_verbose_props(&sorted_nodes, &rules, &cli);
+        }
+        return Ok(());
+    }
+
+    // Report how every rule matched every audio node, instead of linking
+    if let Some(only) = &cli.explain {
+        if let Some(expression) = cli.match_expression.as_ref().filter(|_| rules.is_empty()) {
+            explain_expression(expression, &sorted_nodes, cli.include_virtual);
+            return Ok(());
+        }
+        let explained: Vec<&Rule> = rules
+            .iter()
+            .filter(|rule| only.as_ref().is_none_or(|name| rule.to_string() == *name))
+            .collect();
+        if explained.is_empty() {
+            return Err(anyhow!("No rule to explain"));
+        }
+        for rule in explained.iter().flat_map(|rule| rule.legs()) {
+            explain_rule(&rule, &sorted_nodes, cli.match_options());
+        }
+        return Ok(());
+    }
+
+    if cli.verbose_props {
+        print_verbose_props(&sorted_nodes, &rules, &cli);
+    }
+
+    // Resolve the routes to maintain: an explicit matrix, or all matching inputs into one output
+    // Without any selection on a terminal, ask rather than give up on the built-in SPDIF match
+    let selections = match select(
+        &sorted_nodes,
+        &rules,
+        &cli,
+        &current_defaults(&discovered_nodes)?,
+    ) {
+        // A service started before its devices leaves the linking to the registry listener
+        Err(error) if cli.wait_forever && unmatched(&error) => {
+            warn!("{}, waiting for matching nodes to appear...", error);
+            Vec::new()
+        }
+        Err(error) if can_pick(&error, &rules, &cli) => {
+            warn!("{}, pick the nodes to mix instead", error);
+            let (inputs, output) = picker::pick(&sorted_nodes, cli.include_virtual)?;
+            vec![Selection {
+                inputs,
+                output,
+                rule: Rule::default(),
+            }]
+        }
+        result => result?,
+    };
+    let selections = if cli.only_if_idle && !selections.is_empty() {
+        skip_busy_outputs(selections)?
+    } else {
+        selections
+    };
+
+    // Find out what the endpoints can do, as mismatched rates resample or produce noise
+    let mut endpoints: Vec<&NodeInfo> = Vec::new();
+    for selection in &selections {
+        for node in selection.inputs.iter().chain([&selection.output]) {
+            if !endpoints.iter().any(|n| n.serial == node.serial) {
+                endpoints.push(node);
+            }
+        }
+    }
+    let bound = query_caps(&pipewire_registry, &discovered_nodes, &endpoints);
+    roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+    drop(bound);
+    let with_caps = |node: &NodeInfo| {
+        discovered_nodes
+            .lock()
+            .ok()
+            .and_then(|graph| graph.node(node.serial).cloned())
+            .unwrap_or_else(|| node.clone())
+    };
+    let mut selections = selections;
+    let selected = selections.len();
+    selections.retain(|selection| {
+        let output_node = with_caps(selection.output);
+        let mut compatible = true;
+        for input_node in &selection.inputs {
+            let input_node = with_caps(input_node);
+            let Some(mismatch) = compare_formats(&input_node, &output_node) else {
+                continue;
+            };
+            let message = format!(
+                "Input [Serial: {}, {}] and output [Serial: {}, {}] do not match: {}",
+                input_node.serial,
+                input_node.description,
+                output_node.serial,
+                output_node.description,
+                mismatch
+            );
+            if cli.strict_format {
+                error!("{}, leaving out rule {}", message, selection.rule);
+                compatible = false;
+            } else {
+                warn!("{}", message);
+            }
+        }
+        compatible
+    });
+    if selected > 0 && selections.is_empty() {
+        return Err(anyhow!("No rule links nodes of matching formats").context(Failure::Config));
+    }
+
+    if let Some(Command::Status) = cli.command {
+        for selection in &selections {
+            if let Some(leg) = &selection.rule.leg {
+                println!(
+                    "Rule {}, leg {} of {}{}:",
+                    leg.rule,
+                    leg.index + 1,
+                    leg.count,
+                    if leg.atomic { ", atomic" } else { "" }
+                );
+            }
+            println!("Mechanism: {}", selection.rule.mechanism);
+            println!("Inputs:");
+            for input_node in &selection.inputs {
+                let current = with_caps(input_node);
+                print_status_line(input_node, current.caps.clone());
+                if selection.rule.link_while == LinkWhile::Running && !current.is_busy() {
+                    println!("      waiting for input to run");
+                }
+            }
+            if selection.rule.output == DEFAULT_TARGET {
+                println!("Output (resolved from {}):", DEFAULT_TARGET);
+            } else {
+                println!("Output:");
+            }
+            print_status_line(selection.output, with_caps(selection.output).caps);
+        }
+        print_output_links(&selections, &discovered_nodes);
+        return Ok(());
+    }
+
+    let routes = build_routes(&selections, &cli);
+
+    // Show the links that would be created, using the same pairing as the mixer
+    if cli.dry_run {
+        for (selection, route) in selections.iter().zip(&routes) {
+            for input_node in &selection.inputs {
+                let plan = plan_links(input_node, selection.output, route, cli.pairing);
+                print_plan(input_node, selection.output, &plan);
+            }
+        }
+        print_evictions(&routes, &discovered_nodes);
+        return Ok(());
+    }
+
+    // Claim the pidfile before touching any links, so a second instance cannot fight the first
+    let _pidfile = cli.pidfile.as_deref().map(Pidfile::acquire).transpose()?;
+
+    // Links of a previous run are only replaced when asked to, as other tools may rely on them
+    if cli.mode == LinkMode::Exclusive {
+        let stale = discovered_nodes
+            .lock()
+            .map_err(|_| anyhow!("Discovered node cache is poisoned"))?
+            .managed_links();
+        if !stale.is_empty() {
+            info!("Removing {} link(s) left by a previous run", stale.len());
+            for link_id in stale {
+                debug!("Destroying link [ID: {}]", link_id);
+                if let Err(error) = pipewire_registry.destroy_global(link_id).into_result() {
+                    warn!("Failed to remove link [ID: {}]: {:?}", link_id, error);
+                }
+            }
+            roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+        }
+    }
+
+    info!("Configuring mixer...");
+    for selection in &selections {
+        debug!(
+            "Mapping {} input(s) to output [Serial: {}, {}]",
+            selection.inputs.len(),
+            selection.output.serial,
+            selection.output.description
+        );
+    }
+
+    // Link each input node to its output
+    // The routes are kept so ports appearing later are linked as well
+    mixer.borrow_mut().set_routes(routes);
+    let created = mixer.borrow_mut().link_all();
+    muter
+        .borrow_mut()
+        .sync(&discovered_nodes, &mixer.borrow().inputs());
+    // A request sent is not a link made: check the links once the daemon has answered
+    roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+    // Links go through negotiation before carrying audio, give them a moment to get there
+    wait_for_links(
+        pipewire_main_loop,
+        &mixer,
+        Duration::from_millis(cli.activation_timeout),
+    );
+    let verifications = mixer.borrow().verify();
+    let (requested, active) = report_verifications(&verifications, &discovered_nodes);
+    if active == requested {
+        info!("Mixer links established! Created {} link(s)", created);
+    } else {
+        warn!(
+            "Mixer links partly established: {} of {} link(s) active",
+            active, requested
+        );
+    }
+    #[cfg(feature = "notify")]
+    if cli.notify {
+        notify::linked(created, selections.len());
+    }
+    if cli.strict && active < requested {
+        let failure = if active == 0 {
+            Failure::NoLinks
+        } else {
+            Failure::PartialLinks
+        };
+        restore_volumes(&muter, pipewire_main_loop, &pipewire_core, &pending_sync)?;
+        return Err(anyhow!("{} of {} link(s) active", active, requested).context(failure));
+    }
+    let incomplete: Vec<&String> = verifications
+        .iter()
+        .flat_map(|verification| &verification.incomplete)
+        .collect();
+    if cli.require_complete && !incomplete.is_empty() {
+        restore_volumes(&muter, pipewire_main_loop, &pipewire_core, &pending_sync)?;
+        return Err(
+            anyhow!("{} stereo input(s) not fully linked", incomplete.len())
+                .context(Failure::Incomplete),
+        );
+    }
+
+    // Re-read the configuration on SIGHUP and rebuild every link from the updated rules
+    // The registry keeps the graph current, so there is nothing to re-discover
+    let graph_reload = discovered_nodes.clone();
+    let cli_reload = cli.clone();
+    let mixer_teardown = mixer.clone();
+    let muter_reload = muter.clone();
+    let _reload_signal =
+        pipewire_main_loop
+            .loop_()
+            .add_signal_local(pipewire::loop_::Signal::SIGHUP, move || {
+                info!("Reloading configuration...");
+                match load_rules(&cli_reload) {
+                    Ok(rules) => {
+                        if links_while_running(&rules) && !bind_all_nodes.replace(true) {
+                            debug!("A rule now links inputs while running, binding every node");
+                            bind_known_nodes();
+                        }
+                        *active_rules.borrow_mut() = rules;
+                    }
+                    Err(error) => {
+                        error!(
+                            "Failed to reload, keeping the current configuration: {:?}",
+                            error
+                        );
+                        return;
+                    }
+                }
+                mixer.borrow_mut().clear();
+                match reapply_rules(
+                    &graph_reload,
+                    &mixer,
+                    &active_rules.borrow(),
+                    &cli_reload,
+                    &settling_reload.borrow(),
+                ) {
+                    Ok(created) => info!("Configuration reloaded, created {} link(s)", created),
+                    Err(error) => warn!("Configuration reloaded, but nothing is routed: {}", error),
+                }
+                // Inputs the new rules route are muted, those they dropped get their volume back
+                muter_reload
+                    .borrow_mut()
+                    .sync(&graph_reload, &mixer.borrow().inputs());
+            });
+    // Stop on Ctrl+C or SIGTERM by leaving the loop, so the pidfile is removed on the way out
+    let _stop_signals = [
+        pipewire::loop_::Signal::SIGINT,
+        pipewire::loop_::Signal::SIGTERM,
+    ]
+    .map(|signal| {
+        pipewire_main_loop
+            .loop_()
+            .add_signal_local(signal, move || {
+                info!("Stopping the mixer...");
+                pipewire_main_loop.quit();
+            })
+    });
+    // A time-boxed session stops on its own, the same way as on Ctrl+C
+    let runtime_timer = cli.max_runtime.map(|max_runtime| {
+        info!("Stopping the mixer after {:?}", max_runtime);
+        let timer = pipewire_main_loop.loop_().add_timer(move |_| {
+            info!(
+                "Maximum runtime of {:?} reached, stopping the mixer...",
+                max_runtime
+            );
+            pipewire_main_loop.quit();
+        });
+        timer.update_timer(Some(max_runtime), None);
+        timer
+    });
+    // Run the main loop endlessly-ish
+    info!("Keep program active to maintain connections, or press Ctrl+C to stop the mixer...");
+    pipewire_main_loop.run();
+    drop(runtime_timer);
+    // The mixer's own links go with their proxies; adopted ones need an explicit destroy, and
+    // target.object entries outlive the mixer. A scheduled session waits for its links to be
+    // gone before exiting
+    let targeted = mixer_teardown.borrow().has_targets();
+    if cli.teardown_adopted || cli.max_runtime.is_some() || targeted {
+        mixer_teardown.borrow_mut().clear();
+        roundtrip(pipewire_main_loop, &pipewire_core, &pending_sync)?;
+    }
+    // Volumes outlive the mixer too, so muted inputs get theirs back
+    restore_volumes(&muter, pipewire_main_loop, &pipewire_core, &pending_sync)?;
+    Ok(())
+}
+
+/// Collect the rules from the config file, the plan, and any given with --route, in that order
+fn load_rules(cli: &Cli) -> Result<Vec<Rule>> {
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let plan = match &cli.plan {
+        Some(path) => Config::load_plan(path)?,
+        None => Config::default(),
+    };
+    let rules: Vec<Rule> = config
+        .rules
+        .into_iter()
+        .chain(plan.rules)
+        .chain(cli.routes.iter().cloned().map(Rule::from))
+        .map(|mut rule| {
+            match &mut rule.input {
+                RuleInput::Pattern(pattern) => cli.unalias(pattern),
+                RuleInput::Monitor { monitor_of } => cli.unalias(monitor_of),
+            }
+            cli.unalias(&mut rule.output);
+            for leg in &mut rule.outputs {
+                cli.unalias(&mut leg.pattern);
+            }
+            rule
+        })
+        .collect();
+    // Appending promises that links made by others survive, which these would break
+    if cli.output_append_to_existing {
+        if cli.foreign_links == ForeignLinks::Replace {
+            return Err(anyhow!(
+                "--output-append-to-existing cannot be combined with --foreign-links replace"
+            ));
+        }
+        if let Some(rule) = rules.iter().find(|rule| rule.exclusive) {
+            return Err(anyhow!(
+                "Rule {} is exclusive, which --output-append-to-existing does not allow",
+                rule
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+/// Resolve the routes to maintain: an explicit matrix, or all matching inputs into one output
+fn select<'a>(
+    nodes: &[&'a NodeInfo],
+    rules: &[Rule],
+    cli: &Cli,
+    defaults: &Defaults,
+) -> Result<Vec<Selection<'a>>> {
+    let mut selections = if rules.is_empty() {
+        select_default(nodes, cli)?
+    } else {
+        select_rules(
+            nodes,
+            rules,
+            cli.match_options(),
+            cli.allow_multiple,
+            defaults,
+        )?
+    };
+    if let Some(max_inputs) = cli.max_inputs {
+        for selection in &mut selections {
+            cap_inputs(selection, max_inputs as usize);
+        }
+    }
+    Ok(selections)
+}
+
+/// Keep the first `max_inputs` inputs of a selection, logging the ones left out
+fn cap_inputs(selection: &mut Selection, max_inputs: usize) {
+    if selection.inputs.len() <= max_inputs {
+        return;
+    }
+    let skipped = selection.inputs.split_off(max_inputs);
+    warn!(
+        "{} inputs matched for output [Serial: {}, {}], skipping {} beyond --max-inputs {}",
+        max_inputs + skipped.len(),
+        selection.output.serial,
+        selection.output.description,
+        skipped.len(),
+        max_inputs
+    );
+    for node in skipped {
+        debug!(
+            "Skipped input [ID: {:3} | Serial: {:4}] {}",
+            node.global_id, node.serial, node.description
+        );
+    }
+}
+
+/// Copy the defaults out of the graph, for selecting `@default@` outputs
+fn current_defaults(graph: &Mutex<Graph>) -> Result<Defaults> {
+    Ok(graph
+        .lock()
+        .map_err(|_| anyhow!("Discovered node cache is poisoned"))?
+        .defaults()
+        .clone())
+}
+
+/// Resolve the rules against the current graph and hand the resulting routes to the mixer
+///
+/// Returns the number of links created
+fn reapply_rules(
+    graph: &Mutex<Graph>,
+    mixer: &RefCell<Mixer>,
+    rules: &[Rule],
+    cli: &Cli,
+    settling: &Settling,
+) -> Result<usize> {
+    let nodes_snapshot = snapshot(graph)?;
+    let now = Instant::now();
+    let nodes: Vec<&NodeInfo> = nodes_snapshot
+        .iter()
+        .filter(|node| !settling.holds(node.serial, now))
+        .collect();
+    let selections = select(&nodes, rules, cli, &current_defaults(graph)?)?;
+    Ok(mixer
+        .borrow_mut()
+        .switch_routes(build_routes(&selections, cli)))
+}
+
+/// How long a node that just appeared must stay put before it is linked: the longest
+/// settle_ms of the rules matching it on either side
+fn settle_delay(node: &NodeInfo, rules: &[Rule], cli: &Cli) -> Duration {
+    let options = cli.match_options();
+    let settle_ms = rules
+        .iter()
+        .flat_map(Rule::legs)
+        .filter(|rule| rule.settle_ms > 0)
+        .filter(|rule| {
+            [Side::Input, Side::Output]
+                .into_iter()
+                .any(|side| match_node(rule, side, node, options).matched())
+        })
+        .map(|rule| rule.settle_ms)
+        .max()
+        .unwrap_or(0);
+    Duration::from_millis(settle_ms)
+}
+
+/// Whether a failed selection may fall back to asking interactively: nothing was selected
+/// explicitly, and someone is at the terminal
+fn can_pick(error: &anyhow::Error, rules: &[Rule], cli: &Cli) -> bool {
+    let unselected = rules.is_empty()
+        && cli.input_serials.is_empty()
+        && cli.output_serials.is_empty()
+        && cli.input_matches.is_empty()
+        && cli.input_apps.is_empty()
+        && cli.output_matches.is_empty()
+        && cli.match_expression.is_none();
+    unmatched(error) && unselected && picker::available()
+}
+
+/// Whether a selection failed for lack of matching nodes, rather than e.g. an ambiguous match
+fn unmatched(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<MixerError>(),
+        Some(MixerError::NoInputs | MixerError::NoOutputs)
+    )
+}
+
+/// Nodes that at least one rule matches on either side, or that the input and output selection
+/// picks when no rules are configured
+fn matched_nodes<'a>(nodes: &[&'a NodeInfo], rules: &[Rule], cli: &Cli) -> Vec<&'a NodeInfo> {
+    if !rules.is_empty() {
+        let options = cli.match_options();
+        return nodes
+            .iter()
+            .filter(|node| {
+                rules.iter().flat_map(Rule::legs).any(|rule| {
+                    [Side::Input, Side::Output]
+                        .into_iter()
+                        .any(|side| match_node(&rule, side, node, options).matched())
+                })
+            })
+            .cloned()
+            .collect();
+    }
+    let mut matched = select_inputs(nodes, cli);
+    for node in select_nodes(
+        nodes,
+        &cli.output_serials,
+        &cli.output_matches,
+        false,
+        None,
+        false,
+        cli.match_options(),
+    ) {
+        if !matched.iter().any(|n| n.serial == node.serial) {
+            matched.push(node);
+        }
+    }
+    matched.sort_by_key(|node| node.global_id);
+    matched
+}
+
+/// Print the properties of the matched nodes, or of every node with --all
+fn print_verbose_props(nodes: &[&NodeInfo], rules: &[Rule], cli: &Cli) {
+    let shown = if cli.all_nodes {
+        nodes.to_vec()
+    } else {
+        matched_nodes(nodes, rules, cli)
+    };
+    table::print_properties(&shown, !cli.no_truncate);
+}
+
+/// Inputs resolved against the discovered nodes, to be mixed into one output
+struct Selection<'a> {
+    inputs: Vec<&'a NodeInfo>,
+    output: &'a NodeInfo,
+    rule: Rule, // Pairing options; the default when no rules are configured
+}
+
+/// Select all matching inputs and the matching output, or every matching output with
+/// --all-outputs
+fn select_default<'a>(nodes: &[&'a NodeInfo], cli: &Cli) -> Result<Vec<Selection<'a>>> {
+    // Video is only linked by rules asking for it
+    let nodes: Vec<&NodeInfo> = nodes
+        .iter()
+        .filter(|node| node.media() == Media::Audio)
+        .copied()
+        .collect();
+    let nodes = nodes.as_slice();
     // Filter down separate lists for selected nodes
-    // TODO FIXME: this is hard-coded and should be more flexible
+    // Explicit serials take precedence over matching the description
+    // TODO FIXME: the description match is hard-coded and should be more flexible
     //   to support arbitrary inputs and outputs of any kind, like HDMI audio
-    let selected_inputs: Vec<&NodeInfo> = sorted_nodes
+    let mut selected_inputs = select_inputs(nodes, cli);
+    let excludes: Vec<String> = cli
+        .input_excludes
         .iter()
-        .filter(|node| node.description.to_uppercase().contains("SPDIF") && node.input)
-        .cloned()
+        .map(|p| p.to_uppercase())
         .collect();
+    selected_inputs.retain(|node| {
+        let text = cli.match_field.text(node).to_uppercase();
+        !excludes
+            .iter()
+            .any(|pattern| text.contains(pattern.as_str()))
+    });
     if !selected_inputs.is_empty() {
         info!("Matching inputs: {}", selected_inputs.len());
         for node in &selected_inputs {
-            debug!("[ID: {:3}] {}", node.global_id, node.description);
+            debug!(
+                "[ID: {:3} | Serial: {:4}] {}",
+                node.global_id, node.serial, node.description
+            );
         }
     }
-    let selected_outputs: Vec<&NodeInfo> = sorted_nodes
-        .iter()
-        .filter(|node| node.description.to_uppercase().contains("SPDIF") && !node.input)
-        .cloned()
-        .collect();
+    let selected_outputs = select_nodes(
+        nodes,
+        &cli.output_serials,
+        &cli.output_matches,
+        false,
+        None,
+        false,
+        cli.match_options(),
+    );
     if !selected_outputs.is_empty() {
         info!("Matching outputs: {}", selected_outputs.len());
         for node in &selected_outputs {
-            debug!("[ID: {:3}] {}", node.global_id, node.description);
+            debug!(
+                "[ID: {:3} | Serial: {:4}] {}",
+                node.global_id, node.serial, node.description
+            );
         }
     }
 
-    // Create mixer by mapping all matching inputs to the output(s)
     if selected_outputs.is_empty() {
-        Err(anyhow!("No matching output found"))
+        Err(MixerError::NoOutputs.into())
     } else if selected_inputs.is_empty() {
-        Err(anyhow!("No matching input(s) found"))
+        Err(MixerError::NoInputs.into())
     } else {
-        info!("Configuring mixer...");
+        // Mix into every matching output only when asked to, or into the first if any will do
+        let policy = if cli.all_outputs {
+            OnAmbiguous::All
+        } else if cli.allow_multiple {
+            OnAmbiguous::First
+        } else {
+            OnAmbiguous::Error
+        };
+        let outputs =
+            resolve_ambiguity(Side::Output, selected_outputs, policy).map_err(|ambiguous| {
+                anyhow!(
+                "{}\nSelect one with --output-serial, pass --allow-multiple to use the first, or --all-outputs to use all of them",
+                ambiguous
+            )
+            })?;
+        Ok(outputs
+            .into_iter()
+            .map(|output| Selection {
+                inputs: selected_inputs.clone(),
+                output,
+                rule: Rule::default(),
+            })
+            .collect())
+    }
+}
 
-        // Target the first discovered matching output
-        // TODO FIXME: this should support sending to multiple outputs simultaneously
-        let target_output_node = selected_outputs[0];
-        debug!(
-            "Mapping all matching inputs to output [ID: {}, {}]",
-            target_output_node.global_id, target_output_node.description
+/// The output of a rule targeting `@default@`: the node named by `default.audio.sink`
+fn default_outputs<'a>(
+    rule: &Rule,
+    nodes: &[&'a NodeInfo],
+    defaults: &Defaults,
+    options: MatchOptions,
+) -> Result<Vec<&'a NodeInfo>> {
+    if !defaults.available {
+        return Err(anyhow!(
+            "Rule {}: {} needs the \"default\" metadata published by a session manager such as WirePlumber, and there is none",
+            rule,
+            DEFAULT_TARGET
+        ));
+    }
+    let Some(name) = &defaults.audio_sink else {
+        warn!("Rule {}: no default sink is set", rule);
+        return Ok(Vec::new());
+    };
+    let outputs = default_sinks(nodes, defaults, options.include_virtual);
+    if let Some(output) = outputs.first() {
+        info!(
+            "Rule {}: {} resolved to {} [Serial: {}, {}]",
+            rule, DEFAULT_TARGET, name, output.serial, output.description
         );
+    }
+    Ok(outputs)
+}
 
-        // Keep the link proxies in-scope to retain them in the PipeWire graph
-        let mut links = Vec::new();
+/// Error for a rule side that matched several nodes, suggesting how to settle it
+fn ambiguous_error(rule: &Rule, ambiguous: Ambiguous) -> anyhow::Error {
+    anyhow!(
+        "Rule {}: {}\nTighten the {} pattern (e.g. with {}_properties or {}_match), or set on_ambiguous = \"first\" or \"all\" in the rule (--allow-multiple uses the first output)",
+        rule,
+        ambiguous,
+        ambiguous.side,
+        ambiguous.side,
+        ambiguous.side
+    )
+}
 
-        // Link each input node to the output
-        for input_node in selected_inputs {
-            debug!(
-                "Stereo linking: [ID: {}, {}]=>[ID: {}, {}]",
-                input_node.global_id,
-                input_node.description,
-                target_output_node.global_id,
-                target_output_node.description
+/// Resolve each rule independently, skipping rules that match nothing
+fn select_rules<'a>(
+    nodes: &[&'a NodeInfo],
+    rules: &[Rule],
+    options: MatchOptions,
+    allow_multiple: bool,
+    defaults: &Defaults,
+) -> Result<Vec<Selection<'a>>> {
+    let matching = |rule: &Rule, side: Side| -> Vec<&'a NodeInfo> {
+        nodes
+            .iter()
+            .filter(|node| match_node(rule, side, node, options).matched())
+            .cloned()
+            .collect()
+    };
+    let mut selections = Vec::new();
+    let mut missing_output = false; // Some rule found inputs but nowhere to send them
+    for parent in rules {
+        // A rule splitting its input across outputs selects each leg like a rule of its own
+        let first_leg = selections.len();
+        let mut broken = false;
+        for rule in &parent.legs() {
+            let mut inputs = matching(rule, Side::Input);
+            let mut outputs = if rule.output == DEFAULT_TARGET {
+                default_outputs(rule, nodes, defaults, options)?
+            } else {
+                matching(rule, Side::Output)
+            };
+            // A fuzzy pattern stands for the single closest node, so narrow down to it
+            for (side, pattern, candidates) in [
+                (Side::Input, rule.input.pattern(), &mut inputs),
+                (Side::Output, rule.output.as_str(), &mut outputs),
+            ] {
+                if let Some(fuzzy) = fuzzy_pattern(pattern) {
+                    *candidates = pick_fuzzy(rule, side, fuzzy, candidates, options);
+                }
+            }
+            rank_by_form_factor(&mut outputs, &rule.output_prefer_form_factor);
+            // Streams with a role come and go, so such a rule keeps its output until one shows up
+            if inputs.is_empty() && rule.role.is_none() {
+                warn!("Rule {}: no input matches {:?}", rule, rule.input.pattern());
+                broken = true;
+                continue;
+            }
+            let Some(&output) = outputs.first() else {
+                warn!("Rule {}: no output matches {:?}", rule, rule.output);
+                missing_output = true;
+                broken = true;
+                continue;
+            };
+            // Matching several inputs is how they get mixed, but an output is normally meant to be
+            // one device, unless form factors rank the candidates
+            let output_policy = rule.on_ambiguous.unwrap_or(
+                if allow_multiple || !rule.output_prefer_form_factor.is_empty() {
+                    OnAmbiguous::First
+                } else {
+                    OnAmbiguous::Error
+                },
+            );
+            let output_count = outputs.len();
+            let inputs = resolve_ambiguity(
+                Side::Input,
+                inputs,
+                rule.on_ambiguous.unwrap_or(OnAmbiguous::All),
+            )
+            .map_err(|ambiguous| ambiguous_error(rule, ambiguous))?;
+            let outputs = resolve_ambiguity(Side::Output, outputs, output_policy)
+                .map_err(|ambiguous| ambiguous_error(rule, ambiguous))?;
+            if !rule.output_prefer_form_factor.is_empty() {
+                info!(
+                    "Rule {}: output [Serial: {}, {}] preferred for its form factor ({})",
+                    rule,
+                    output.serial,
+                    output.description,
+                    output.form_factor.as_deref().unwrap_or("none")
+                );
+            } else if output_count > outputs.len() {
+                warn!(
+                    "Rule {}: {} outputs match {:?}, using [Serial: {}, {}]",
+                    rule, output_count, rule.output, output.serial, output.description
+                );
+            }
+            match &rule.role {
+                Some(role) if inputs.is_empty() => {
+                    info!("Rule {}: waiting for a stream with role {:?}", rule, role)
+                }
+                _ => info!("Rule {}: {} input(s)", rule, inputs.len()),
+            }
+            for node in &inputs {
+                debug!(
+                    "[ID: {:3} | Serial: {:4}] {}",
+                    node.global_id, node.serial, node.description
+                );
+            }
+            for output in outputs {
+                let (inputs, looped) = refuse_self_cascade(&inputs, output);
+                if !looped.is_empty() {
+                    error!(
+                        "Rule {}: refusing to cascade [Serial: {}, {}] into itself",
+                        rule, output.serial, output.description
+                    );
+                    if inputs.is_empty() && rule.role.is_none() {
+                        continue;
+                    }
+                }
+                selections.push(Selection {
+                    inputs,
+                    output,
+                    rule: rule.clone(),
+                });
+            }
+        }
+        if parent.atomic && broken && selections.len() > first_leg {
+            error!(
+                "Rule {}: not every leg found its nodes, linking none of them as the rule is atomic",
+                parent
             );
+            selections.truncate(first_leg);
+        }
+    }
+    if selections.is_empty() {
+        let failure = if missing_output {
+            MixerError::NoOutputs
+        } else {
+            MixerError::NoInputs
+        };
+        Err(anyhow!("No rule matched any nodes").context(failure))
+    } else {
+        Ok(selections)
+    }
+}
 
-            // Pair ports by direction: Outbound from Source to Inbound at Sink
-            let src_ports: Vec<_> = input_node
-                .ports
-                .iter()
-                .filter(|(_, _, dir)| dir == "out")
-                .collect();
-            let snk_ports: Vec<_> = target_output_node
-                .ports
-                .iter()
-                .filter(|(_, _, dir)| dir == "in")
-                .collect();
+/// Drop the selections whose output is already playing, for --only-if-idle
+///
+/// Only done at startup: once linked, the outputs are busy with the mixer's own links
+fn skip_busy_outputs(selections: Vec<Selection>) -> Result<Vec<Selection>> {
+    let (busy, idle): (Vec<_>, Vec<_>) = selections
+        .into_iter()
+        .partition(|selection| selection.output.is_busy());
+    for selection in &busy {
+        info!(
+            "Skipping output [Serial: {}, {}]: it is in use by another application",
+            selection.output.serial, selection.output.description
+        );
+    }
+    if idle.is_empty() {
+        return Err(anyhow!("Every matching output is in use").context(MixerError::NoOutputs));
+    }
+    Ok(idle)
+}
 
-            // Explicitly link matching pairs (FL->FL, FR->FR, etc)
-            for (out_id, out_chan, _) in src_ports {
-                // Find a destination port that matches the specific channel name
-                if let Some((in_id, _in_chan, _)) =
-                    snk_ports.iter().find(|(_, name, _)| name == out_chan)
-                {
-                    debug!("Linking channel {}: [{}]->[{}]", out_chan, out_id, in_id);
-                    let props = pipewire::__properties__! {
-                        *pipewire::keys::LINK_OUTPUT_NODE => input_node.global_id.to_string(),
-                        *pipewire::keys::LINK_OUTPUT_PORT => out_id.to_string(),
-                        *pipewire::keys::LINK_INPUT_NODE => target_output_node.global_id.to_string(),
-                        *pipewire::keys::LINK_INPUT_PORT => in_id.to_string(),
-                        *pipewire::keys::LINK_PASSIVE => "false", // Activate the link (wakes hardware)
-                        // "object.linger" => "true", // Persistent link FIXME TODO: first need to establish teardown process
-                    };
-                    // Request the core to create the link
-                    match pipewire_core
-                        .create_object::<pipewire::link::Link>("link-factory", &props)
-                    {
-                        Ok(link) => links.push(link),
-                        Err(e) => error!("Failed to create link: {:?}", e),
-                    }
+/// Turn the resolved selections into the routes maintained by the mixer
+fn build_routes(selections: &[Selection], cli: &Cli) -> Vec<Route> {
+    selections
+        .iter()
+        .map(|selection| Route {
+            rule: Some(selection.rule.to_string()),
+            inputs: selection.inputs.iter().map(|node| node.serial).collect(),
+            output: selection.output.serial,
+            aux_offset: selection.rule.aux_offset,
+            include_monitors: cli.include_monitors
+                || selection.rule.monitor
+                || selection.rule.input.is_monitor(),
+            channel_map: if selection.rule.channel_map.is_empty() {
+                cli.channel_map
+                    .iter()
+                    .fold(HashMap::new(), |mut map, (from, to)| {
+                        map.entry(from.clone())
+                            .or_insert_with(Vec::new)
+                            .push(to.clone());
+                        map
+                    })
+            } else {
+                selection
+                    .rule
+                    .channel_map
+                    .iter()
+                    .map(|(from, to)| {
+                        let targets = to.names().iter().map(|to| Channel::parse(to)).collect();
+                        (Channel::parse(from), targets)
+                    })
+                    .collect()
+            },
+            downmix: selection.rule.downmix,
+            upmix: selection.rule.upmix,
+            swap_lr: selection.rule.swap_lr,
+            links: selection.rule.links.clone(),
+            atomic_group: selection
+                .rule
+                .leg
+                .as_ref()
+                .filter(|leg| leg.atomic)
+                .map(|leg| leg.rule.clone()),
+            exclusive: selection.rule.exclusive,
+            on_channel_mismatch: selection.rule.on_channel_mismatch,
+            input_channels: if selection.rule.input_channels.is_empty() {
+                cli.input_channels.clone()
+            } else {
+                selection
+                    .rule
+                    .input_channels
+                    .iter()
+                    .map(|name| Channel::parse(name))
+                    .collect()
+            },
+            output_channels: cli.output_channels.clone(),
+            mono_to_stereo: selection.rule.mono_to_stereo,
+            stereo_to_mono: selection.rule.stereo_to_mono,
+            mechanism: selection.rule.mechanism,
+            media: selection.rule.media,
+            link_while: selection.rule.link_while,
+            idle_grace: selection
+                .rule
+                .idle_grace_ms
+                .map_or(DEFAULT_IDLE_GRACE, Duration::from_millis),
+        })
+        .collect()
+}
+
+/// Narrow the candidates of a `fuzzy:` pattern down to the one most similar to it
+///
+/// Returns no candidates (after logging why) if two of them are too close to tell apart
+fn pick_fuzzy<'a>(
+    rule: &Rule,
+    side: Side,
+    pattern: &str,
+    candidates: &[&'a NodeInfo],
+    options: MatchOptions,
+) -> Vec<&'a NodeInfo> {
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let descriptions: Vec<&str> = candidates
+        .iter()
+        .map(|node| options.match_field.text(node))
+        .collect();
+    match fuzzy::best_match(
+        pattern,
+        &descriptions,
+        options.fuzzy_threshold,
+        fuzzy::AMBIGUITY_MARGIN,
+    ) {
+        Ok(best) => {
+            let node = candidates[best.index];
+            info!(
+                "Rule {}: {} [Serial: {}, {}] is the closest to {:?} (score {:.2})",
+                rule, side, node.serial, node.description, pattern, best.score
+            );
+            vec![node]
+        }
+        Err(FuzzyError::Ambiguous { best, runner_up }) => {
+            warn!(
+                "Rule {}: refusing to pick an {} for {:?}, {:?} ({:.2}) and {:?} ({:.2}) are too close to tell apart",
+                rule,
+                side,
+                pattern,
+                descriptions[best.index],
+                best.score,
+                descriptions[runner_up.index],
+                runner_up.score
+            );
+            Vec::new()
+        }
+        Err(error) => {
+            warn!(
+                "Rule {}: refusing to pick an {} for {:?}: {}",
+                rule, side, pattern, error
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// The inputs selected by serial, pattern or expression, kept only if they belong to one of
+/// the --input-app applications. Given alone, the applications select every stream of theirs
+fn select_inputs<'a>(nodes: &[&'a NodeInfo], cli: &Cli) -> Vec<&'a NodeInfo> {
+    let by_app_only = !cli.input_apps.is_empty()
+        && cli.input_serials.is_empty()
+        && cli.input_matches.is_empty()
+        && cli.match_expression.is_none();
+    let mut selected = if by_app_only {
+        nodes
+            .iter()
+            .filter(|node| node.acts_as(true, cli.include_virtual))
+            .cloned()
+            .collect()
+    } else {
+        select_nodes(
+            nodes,
+            &cli.input_serials,
+            &cli.input_matches,
+            cli.input_match_all,
+            cli.match_expression.as_ref(),
+            true,
+            cli.match_options(),
+        )
+    };
+    if !cli.input_apps.is_empty() {
+        selected.retain(|node| node.matches_app(&cli.input_apps));
+    }
+    selected
+}
+
+/// Select the nodes of one direction, either by explicit serial, by expression or by description
+///
+/// A description must contain any of the patterns, or all of them with `match_all`,
+/// defaulting to "SPDIF" when no patterns are given
+fn select_nodes<'a>(
+    nodes: &[&'a NodeInfo],
+    serials: &[Serial],
+    patterns: &[String],
+    match_all: bool,
+    expression: Option<&Expression>,
+    input: bool,
+    options: MatchOptions,
+) -> Vec<&'a NodeInfo> {
+    let include_virtual = options.include_virtual;
+    if let Some(expression) = expression.filter(|_| serials.is_empty()) {
+        return nodes
+            .iter()
+            .filter(|node| match_expression(expression, node, input, include_virtual).matched())
+            .cloned()
+            .collect();
+    }
+    if serials.is_empty() {
+        let patterns: Vec<String> = if patterns.is_empty() {
+            vec!["SPDIF".to_string()]
+        } else {
+            patterns.iter().map(|p| p.to_uppercase()).collect()
+        };
+        return nodes
+            .iter()
+            .filter(|node| {
+                let text = options.match_field.text(node).to_uppercase();
+                let mut matches = patterns
+                    .iter()
+                    .map(|p| is_wildcard(p) || text.contains(p.as_str()));
+                let matched = if match_all {
+                    matches.all(|m| m)
                 } else {
-                    warn!("No matching input port found for channel {}", out_chan);
+                    matches.any(|m| m)
+                };
+                matched && node.acts_as(input, include_virtual)
+            })
+            .cloned()
+            .collect();
+    }
+    let direction = if input { "input" } else { "output" };
+    for serial in serials {
+        match nodes.iter().find(|node| node.serial == *serial) {
+            None => warn!("No node found with serial {}", serial),
+            Some(node) if !node.acts_as(input, include_virtual) => {
+                warn!(
+                    "Node with serial {} is not an {}: {}",
+                    serial, direction, node.description
+                )
+            }
+            Some(_) => {}
+        }
+    }
+    nodes
+        .iter()
+        .filter(|node| serials.contains(&node.serial) && node.acts_as(input, include_virtual))
+        .cloned()
+        .collect()
+}
+
+/// Work on a sorted copy of the cache, so the lock is not held while the main loop runs
+fn snapshot(graph: &Mutex<Graph>) -> Result<Vec<NodeInfo>> {
+    let mut nodes: Vec<NodeInfo> = graph
+        .lock()
+        .map_err(|_| anyhow!("Discovered node cache is poisoned"))?
+        .nodes()
+        .cloned()
+        .collect();
+    nodes.sort_by_key(|n| n.global_id);
+    Ok(nodes)
+}
+
+/// Trigger a sync event and run the loop until the 'done' event with the matching sequence
+/// number is received, so every reply to earlier requests has been processed
+fn roundtrip(
+    main_loop: &pipewire::main_loop::MainLoopRc,
+    core: &pipewire::core::CoreRc,
+    pending_sync: &RefCell<Option<AsyncSeq>>,
+) -> Result<()> {
+    let sync_seq = core.sync(0).map_err(MixerError::SyncFailed)?;
+    *pending_sync.borrow_mut() = Some(sync_seq);
+    main_loop.run();
+    Ok(())
+}
+
+/// Bind the nodes and request their `EnumFormat` params, merging the results into the graph
+///
+/// The replies arrive asynchronously: keep the returned proxies alive across a [`roundtrip`]
+fn query_caps(
+    registry: &pipewire::registry::RegistryRc,
+    graph: &Arc<Mutex<Graph>>,
+    nodes: &[&NodeInfo],
+) -> Vec<(pipewire::node::Node, pipewire::node::NodeListener)> {
+    let mut bound = Vec::new();
+    for node in nodes {
+        let global = pipewire::registry::GlobalObject::<&pipewire::spa::utils::dict::DictRef> {
+            id: node.global_id,
+            permissions: pipewire::permissions::PermissionFlags::empty(),
+            type_: pipewire::types::ObjectType::Node,
+            version: 0,
+            props: None,
+        };
+        let proxy = match registry.bind::<pipewire::node::Node, _>(&global) {
+            Ok(proxy) => proxy,
+            Err(error) => {
+                warn!(
+                    "Failed to bind node [Serial: {}] to query formats: {:?}",
+                    node.serial, error
+                );
+                continue;
+            }
+        };
+        let graph = graph.clone();
+        let serial = node.serial;
+        let listener = proxy
+            .add_listener_local()
+            .param(move |_seq, _id, _index, _next, param| {
+                if let Some(caps) = param.and_then(pod::parse_enum_format) {
+                    if let Ok(mut graph) = graph.lock() {
+                        graph.add_caps(serial, caps);
+                    }
                 }
+            })
+            .register();
+        proxy.enum_params(0, Some(ParamType::EnumFormat), 0, u32::MAX);
+        bound.push((proxy, listener));
+    }
+    bound
+}
+
+/// True if a rule only links its inputs while they run, which needs the state of every node
+fn links_while_running(rules: &[Rule]) -> bool {
+    rules
+        .iter()
+        .any(|rule| rule.link_while == LinkWhile::Running)
+}
+
+/// A bound proxy with its listener, which is dropped first as it is registered on the proxy
+type BoundProxy = (
+    Box<dyn pipewire::proxy::Listener>,
+    Box<dyn pipewire::proxy::ProxyT>,
+);
+
+/// Bind a Device global to read its full properties and follow its active routes, which carry
+/// the name of the monitor connected to each HDMI/DisplayPort output
+fn bind_device(
+    registry: &pipewire::registry::RegistryRc,
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+    graph: &Arc<Mutex<Graph>>,
+    monitor_changed: Rc<dyn Fn()>,
+) -> Result<BoundProxy> {
+    let device = registry
+        .bind::<pipewire::device::Device, _>(global)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    let device_id = global.id;
+    let graph_info = graph.clone();
+    let graph_routes = graph.clone();
+    let listener = device
+        .add_listener_local()
+        .info(move |info| {
+            if let Some(props) = info.props() {
+                if let Ok(mut graph) = graph_info.lock() {
+                    graph.add_device(
+                        device_id,
+                        props
+                            .get(*pipewire::keys::DEVICE_DESCRIPTION)
+                            .map(str::to_string),
+                        identity_properties(props),
+                    );
+                }
+            }
+        })
+        .param(move |_seq, _id, _index, _next, param| {
+            let Some(route) = param.and_then(pod::parse_route) else {
+                return;
+            };
+            let changed = graph_routes
+                .lock()
+                .is_ok_and(|mut graph| graph.set_route(device_id, &route));
+            if changed {
+                monitor_changed();
             }
+        })
+        .register();
+    // Routes are sent now and again whenever they change, e.g. when a monitor is plugged in
+    device.subscribe_params(&[ParamType::Route]);
+    Ok((Box::new(listener), Box::new(device)))
+}
+
+/// One line naming a global and listing all of its properties, for --watch
+fn describe_global(
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+) -> String {
+    // e.g. "PipeWire:Interface:Node" -> "Node"
+    let kind = global.type_.to_str();
+    let kind = kind.rsplit(':').next().unwrap_or(kind);
+    let mut properties: Vec<String> = global
+        .props
+        .map(|props| {
+            props
+                .iter()
+                .map(|(key, value)| format!("{}={:?}", key, value))
+                .collect()
+        })
+        .unwrap_or_default();
+    properties.sort();
+    format!("{} [ID: {}] {}", kind, global.id, properties.join(" "))
+}
+
+/// Bind the session manager's "default" metadata to follow the default nodes
+fn bind_default_metadata(
+    registry: &pipewire::registry::RegistryRc,
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+    graph: &Arc<Mutex<Graph>>,
+    default_changed: Rc<dyn Fn()>,
+) -> Result<BoundProxy> {
+    let metadata = registry
+        .bind::<pipewire::metadata::Metadata, _>(global)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    if let Ok(mut graph) = graph.lock() {
+        graph.add_default_metadata(global.id);
+    }
+    let graph = graph.clone();
+    let listener = metadata
+        .add_listener_local()
+        .property(move |subject, key, _type, value| {
+            // Defaults are properties of the core object
+            let changed = subject == pipewire::core::PW_ID_CORE
+                && graph
+                    .lock()
+                    .is_ok_and(|mut graph| graph.set_default(key, value));
+            if changed {
+                default_changed();
+            }
+            0
+        })
+        .register();
+    Ok((Box::new(listener), Box::new(metadata)))
+}
+
+/// Bind a link to find out whether a mixer created it
+fn bind_link_info(
+    registry: &pipewire::registry::RegistryRc,
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+    graph: &Arc<Mutex<Graph>>,
+) -> Result<BoundProxy> {
+    let link = registry
+        .bind::<pipewire::link::Link, _>(global)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    let link_id = global.id;
+    let graph = graph.clone();
+    let listener = link
+        .add_listener_local()
+        .info(move |info| {
+            let Ok(mut graph) = graph.lock() else {
+                return;
+            };
+            if let Some(stamp) = info.props().and_then(LinkStamp::from_props) {
+                graph.add_managed_link(link_id, stamp);
+            }
+            if info.change_mask().contains(LinkChangeMask::STATE) {
+                graph.set_link_state(link_id, LinkPhase::from(&info.state()));
+            }
+        })
+        .register();
+    Ok((Box::new(listener), Box::new(link)))
+}
+
+/// Run the loop until every link the mixer created is established or has failed, or until
+/// `timeout` has passed since the last staged link was requested
+fn wait_for_links(
+    main_loop: &'static pipewire::main_loop::MainLoopRc,
+    mixer: &Rc<RefCell<Mixer>>,
+    timeout: Duration,
+) {
+    if mixer.borrow().links_settled() {
+        return;
+    }
+    let deadline = Cell::new(Instant::now() + timeout);
+    let mixer = mixer.clone();
+    let timer = main_loop.loop_().add_timer(move |_| {
+        let mixer = mixer.borrow();
+        if mixer.staged_links() > 0 {
+            deadline.set(Instant::now() + timeout);
         }
-        info!("Mixer links established!");
-        // Run the main loop endlessly-ish
-        info!("Keep program active to maintain connections, or press Ctrl+C to stop the mixer...");
-        pipewire_main_loop.run();
-        Ok(())
+        if mixer.links_settled() || Instant::now() >= deadline.get() {
+            main_loop.quit();
+        }
+    });
+    timer.update_timer(Some(LINK_POLL_INTERVAL), Some(LINK_POLL_INTERVAL));
+    main_loop.run();
+}
+
+/// Log how the links of each route fared, returning the links requested and active in total
+fn report_verifications(
+    verifications: &[Verification],
+    graph: &Arc<Mutex<Graph>>,
+) -> (usize, usize) {
+    let describe = |verification: &Verification| match &verification.rule {
+        Some(rule) => format!("Rule {}", rule),
+        None => {
+            let description = graph.lock().ok().and_then(|graph| {
+                graph
+                    .node(verification.output)
+                    .map(|node| node.description.clone())
+            });
+            format!(
+                "Output [Serial: {}, {}]",
+                verification.output,
+                description.unwrap_or_default()
+            )
+        }
+    };
+    for verification in verifications {
+        if !verification.waiting.is_empty() {
+            info!(
+                "{}: waiting for input to run: {}",
+                describe(verification),
+                verification.waiting.join(", ")
+            );
+        }
+        if !verification.incomplete.is_empty() {
+            warn!(
+                "{}: stereo input(s) not fully linked, half the audio is missing: {}",
+                describe(verification),
+                verification.incomplete.join(", ")
+            );
+        }
+        if verification.failures.is_empty() {
+            info!(
+                "{}: {} of {} channel(s) active",
+                describe(verification),
+                verification.active,
+                verification.requested
+            );
+            continue;
+        }
+        let failures: Vec<String> = verification
+            .failures
+            .iter()
+            .map(|(channel, reason)| format!("{} ({})", channel, reason))
+            .collect();
+        warn!(
+            "{}: {} of {} channel(s) active, failed: {}",
+            describe(verification),
+            verification.active,
+            verification.requested,
+            failures.join(", ")
+        );
+    }
+    verifications
+        .iter()
+        .fold((0, 0), |(requested, active), verification| {
+            (
+                requested + verification.requested,
+                active + verification.active,
+            )
+        })
+}
+
+/// Give the inputs muted by `--mute` their volumes back, waiting until the daemon has them
+fn restore_volumes(
+    muter: &RefCell<Muter>,
+    main_loop: &pipewire::main_loop::MainLoopRc,
+    core: &pipewire::core::CoreRc,
+    pending_sync: &RefCell<Option<AsyncSeq>>,
+) -> Result<()> {
+    if muter.borrow_mut().restore_all() > 0 {
+        roundtrip(main_loop, core, pending_sync)?;
+    }
+    Ok(())
+}
+
+/// Destroy stamped links selected by `clean`, printing each, and wait for the daemon to
+/// remove them
+fn clean_links(
+    main_loop: &pipewire::main_loop::MainLoopRc,
+    core: &pipewire::core::CoreRc,
+    registry: &pipewire::registry::RegistryRc,
+    pending_sync: &RefCell<Option<AsyncSeq>>,
+    stale: Vec<(u32, String)>,
+    dry_run: bool,
+) -> Result<()> {
+    if stale.is_empty() {
+        println!("No stale links to remove");
+        return Ok(());
+    }
+    let mut removed = 0;
+    for (link_id, description) in &stale {
+        if dry_run {
+            println!("Would remove [Link ID: {:3}]  {}", link_id, description);
+            continue;
+        }
+        match registry.destroy_global(*link_id).into_result() {
+            Ok(_) => {
+                println!("Removed [Link ID: {:3}]  {}", link_id, description);
+                removed += 1;
+            }
+            Err(error) => warn!("Failed to remove link [ID: {}]: {:?}", link_id, error),
+        }
+    }
+    if !dry_run {
+        roundtrip(main_loop, core, pending_sync)?;
+        println!("Removed {} of {} stale link(s)", removed, stale.len());
+    }
+    Ok(())
+}
+
+/// Explain how the ports of a node without a media class decided its direction
+fn note_classification(node: &NodeInfo) {
+    let direction = match (node.classified, node.input) {
+        (false, _) => "cannot tell whether it is an input or an output",
+        (true, true) => "only has output ports, treating it as an input",
+        (true, false) => "only has input ports, treating it as an output",
+    };
+    debug!(
+        "Node [Serial: {}, {}] has no media class and {}",
+        node.serial, node.description, direction
+    );
+}
+
+/// Warn about a port carrying a channel the node already has a port for in that direction,
+/// as pairing by name can then pick either
+fn note_duplicate_channel(node: &NodeInfo, port: &PortInfo) {
+    if port.channel == "unknown" {
+        return;
+    }
+    let count = node.channel_count(port);
+    if count > 1 {
+        warn!(
+            "Node [Serial: {}, {}] has {} {} ports for channel {}, pairing them in port order",
+            node.serial, node.description, count, port.direction, port.channel
+        );
+    }
+}
+
+/// Bind a device node to read the properties tying it to its device's routes
+fn bind_node_info(
+    registry: &pipewire::registry::RegistryRc,
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+    serial: Serial,
+    graph: &Arc<Mutex<Graph>>,
+    monitor_changed: Rc<dyn Fn()>,
+    role_changed: Rc<dyn Fn()>,
+    node_updated: Rc<dyn Fn(Serial)>,
+) -> Result<BoundProxy> {
+    let node = registry
+        .bind::<pipewire::node::Node, _>(global)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    let graph = graph.clone();
+    let listener = node
+        .add_listener_local()
+        .info(move |info| {
+            if info.change_mask().contains(NodeChangeMask::STATE) {
+                let state = match info.state() {
+                    NodeState::Error(_) => "error",
+                    NodeState::Creating => "creating",
+                    NodeState::Suspended => "suspended",
+                    NodeState::Idle => "idle",
+                    NodeState::Running => "running",
+                };
+                if let Ok(mut graph) = graph.lock() {
+                    graph.set_node_state(serial, state);
+                }
+            }
+            let Some(props) = info.props() else {
+                // A state change alone still matters to inputs linked only while running
+                if info.change_mask().contains(NodeChangeMask::STATE) {
+                    node_updated(serial);
+                }
+                return;
+            };
+            let (changed, role_updated) = graph.lock().map_or((false, false), |mut graph| {
+                let role = |graph: &Graph| {
+                    graph
+                        .node(serial)
+                        .and_then(|node| node.properties.get(MEDIA_ROLE_KEY).cloned())
+                };
+                let before = role(&graph);
+                let changed = graph.add_node_info(serial, props);
+                (changed, role(&graph) != before)
+            });
+            if changed {
+                monitor_changed();
+            }
+            if role_updated {
+                role_changed();
+            }
+            node_updated(serial);
+        })
+        .register();
+    Ok((Box::new(listener), Box::new(node)))
+}
+
+fn print_status_line(node: &NodeInfo, caps: Option<AudioCaps>) {
+    let caps = caps.map_or_else(|| "formats unknown".to_string(), |caps| caps.to_string());
+    let profile = node
+        .bluetooth_profile
+        .as_ref()
+        .map_or_else(String::new, |profile| format!(", profile {}", profile));
+    println!(
+        "  [ID: {:3} | Serial: {:4}]  {}  ({}{})",
+        node.global_id, node.serial, node.description, caps, profile
+    );
+}
+
+/// List the links into the selected outputs: those a mixer made, by rule and instance, then
+/// those other clients made, which play alongside the mixer's
+fn print_output_links(selections: &[Selection], graph: &Arc<Mutex<Graph>>) {
+    let Ok(graph) = graph.lock() else {
+        return;
+    };
+    let outputs: HashSet<u32> = selections
+        .iter()
+        .map(|selection| selection.output.global_id)
+        .collect();
+    let mut printed_heading = false;
+    for (link_id, link) in graph.stamped_links() {
+        let Some(stamp) = link
+            .stamp
+            .as_ref()
+            .filter(|_| outputs.contains(&link.input_node))
+        else {
+            continue;
+        };
+        if !printed_heading {
+            println!("Mixer links:");
+            printed_heading = true;
+        }
+        // Recreated links were removed by another client, which may keep at it
+        let flapping = match stamp.recreated {
+            0 => String::new(),
+            recreated => format!(", flapping: recreated {} time(s)", recreated),
+        };
+        println!(
+            "  [Link ID: {:3}]  {}  (rule {}, instance {}, {}{})",
+            link_id,
+            graph.describe_link(link),
+            stamp.rule.as_deref().unwrap_or("unknown"),
+            stamp.instance.as_deref().unwrap_or("unknown"),
+            describe_link_state(link),
+            flapping
+        );
+    }
+    let mut printed_heading = false;
+    for (link_id, link) in graph.foreign_links() {
+        if !outputs.contains(&link.input_node) {
+            continue;
+        }
+        if !printed_heading {
+            println!("Foreign links:");
+            printed_heading = true;
+        }
+        println!(
+            "  [Link ID: {:3}]  {}  ({})",
+            link_id,
+            graph.describe_link(link),
+            describe_link_state(link)
+        );
+    }
+}
+
+/// State of a link for `status`, which only knows it once the link answered its bind
+fn describe_link_state(link: &LinkEntry) -> String {
+    link.state
+        .as_ref()
+        .map_or_else(|| "state unknown".to_string(), LinkPhase::to_string)
+}
+
+/// List the links that exclusive rules would destroy, once per output
+fn print_evictions(routes: &[Route], graph: &Arc<Mutex<Graph>>) {
+    let Ok(graph) = graph.lock() else {
+        return;
+    };
+    let mut outputs: Vec<Serial> = routes
+        .iter()
+        .filter(|route| route.exclusive)
+        .map(|route| route.output)
+        .collect();
+    outputs.sort_unstable();
+    outputs.dedup();
+    for output in outputs {
+        let Some(output_node) = graph.node(output) else {
+            continue;
+        };
+        let sources: HashSet<u32> = routes
+            .iter()
+            .filter(|route| route.output == output)
+            .flat_map(|route| &route.inputs)
+            .filter_map(|serial| Some(graph.node(*serial)?.global_id))
+            .collect();
+        let competing = graph.competing_links(output_node.global_id, &sources);
+        if competing.is_empty() {
+            continue;
+        }
+        println!(
+            "Would evict from [Serial: {}] {}:",
+            output_node.serial, output_node.description
+        );
+        for (link_id, link) in competing {
+            println!("  [Link ID: {:3}]  {}", link_id, graph.describe_link(link));
+        }
+    }
+}
+
+fn print_plan(input_node: &NodeInfo, output_node: &NodeInfo, plan: &LinkPlan) {
+    println!(
+        "[Serial: {}] {} => [Serial: {}] {}",
+        input_node.serial, input_node.description, output_node.serial, output_node.description
+    );
+    for planned in &plan.links {
+        println!(
+            "  {} -> {}  (by {})",
+            planned.source_channel, planned.sink_channel, planned.paired_by
+        );
+    }
+    if !plan.unmatched_sources.is_empty() {
+        println!(
+            "  unmatched source channels: {}",
+            plan.unmatched_sources.join(", ")
+        );
     }
+    if !plan.unused_sinks.is_empty() {
+        println!("  unused sink channels: {}", plan.unused_sinks.join(", "));
+    }
+    for failure in &plan.unresolved {
+        println!("  unresolved: {}", failure);
+    }
+}
+
+/// Print how each side of a rule matched each audio node
+fn explain_rule(rule: &Rule, nodes: &[&NodeInfo], options: MatchOptions) {
+    println!(
+        "Rule {} ({:?} => {:?})",
+        rule,
+        rule.input.to_string(),
+        rule.output
+    );
+    for node in nodes
+        .iter()
+        .filter(|node| node.media_class.contains("Audio"))
+    {
+        println!("  [Serial: {}] {}", node.serial, node.description);
+        for side in [Side::Input, Side::Output] {
+            let trace = match_node(rule, side, node, options);
+            println!("    {}: {}", side, describe_trace(&trace));
+        }
+    }
+}
+
+/// Like [`explain_rule`], for the input side selected by `--match`
+fn explain_expression(expression: &Expression, nodes: &[&NodeInfo], include_virtual: bool) {
+    println!("--match {}", expression);
+    for node in nodes
+        .iter()
+        .filter(|node| node.media_class.contains("Audio"))
+    {
+        println!("  [Serial: {}] {}", node.serial, node.description);
+        let trace = match_expression(expression, node, true, include_virtual);
+        println!("    {}: {}", Side::Input, describe_trace(&trace));
+    }
+}
+
+/// Render every check of a trace and the verdict, e.g.
+/// `direction is an input [ok], description contains "SPDIF" [FAILED] => not matched`
+fn describe_trace(trace: &MatchTrace) -> String {
+    fn describe(check: &Check) -> String {
+        let mark = if check.passed { "ok" } else { "FAILED" };
+        if check.details.is_empty() {
+            format!("{} [{}]", check.condition, mark)
+        } else {
+            let details: Vec<String> = check.details.iter().map(describe).collect();
+            format!(
+                "{} [{}] {{ {} }}",
+                check.condition,
+                mark,
+                details.join(", ")
+            )
+        }
+    }
+    let checks: Vec<String> = trace.checks.iter().map(describe).collect();
+    let verdict = if trace.matched() {
+        "matched"
+    } else {
+        "not matched"
+    };
+    format!("{} => {}", checks.join(", "), verdict)
 }
@@ -0,0 +1,593 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::config::Rule;
+use crate::expr::Expression;
+use crate::fuzzy::{fuzzy_pattern, similarity};
+use crate::graph::{Defaults, NodeInfo, Serial};
+
+/// Which end of a rule a node is matched against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Input,
+    Output,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Side::Input => "input",
+            Side::Output => "output",
+        })
+    }
+}
+
+/// Which nodes beyond the plain inputs and outputs may match
+#[derive(Clone, Copy, Debug)]
+pub struct MatchOptions {
+    pub include_monitors: bool, // Sinks can act as inputs through their monitor ports
+    pub include_virtual: bool,  // Nodes of unknown media class, by their port directions
+    pub fuzzy_threshold: f64,   // Minimum similarity for a `fuzzy:` pattern to match
+    pub match_field: MatchField, // What description patterns are compared with
+}
+
+/// The name of a node that description patterns are compared with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatchField {
+    /// node.description, e.g. "Scarlett 18i20 USB Pro Audio"
+    #[default]
+    Description,
+    /// node.nick, a shorter name some devices give, falling back to the description
+    Nick,
+}
+
+impl MatchField {
+    /// The text of a node a pattern is compared with
+    pub fn text<'a>(&self, node: &'a NodeInfo) -> &'a str {
+        match self {
+            MatchField::Description => &node.description,
+            MatchField::Nick => node.nick.as_deref().unwrap_or(&node.description),
+        }
+    }
+}
+
+impl fmt::Display for MatchField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchField::Description => "description",
+            MatchField::Nick => "nick",
+        })
+    }
+}
+
+/// Pattern matching every node of the right direction, like an empty one
+pub const WILDCARD: &str = "*";
+
+/// True for patterns that match any description
+pub fn is_wildcard(pattern: &str) -> bool {
+    pattern.is_empty() || pattern == WILDCARD
+}
+
+/// Property holding what a stream plays, e.g. "Music" or "Notification"
+pub const MEDIA_ROLE_KEY: &str = "media.role";
+
+/// Output pattern standing for whatever the session manager's default sink currently is
+pub const DEFAULT_TARGET: &str = "@default@";
+
+/// The sinks [`DEFAULT_TARGET`] stands for: the nodes named by `default.audio.sink`, none
+/// while no default sink is set
+///
+/// Called again whenever the default changes, so the routes move to whichever sink it is now
+pub fn default_sinks<'a>(
+    nodes: &[&'a NodeInfo],
+    defaults: &Defaults,
+    include_virtual: bool,
+) -> Vec<&'a NodeInfo> {
+    let Some(name) = &defaults.audio_sink else {
+        return Vec::new();
+    };
+    nodes
+        .iter()
+        .filter(|node| {
+            node.properties.get("node.name") == Some(name) && node.acts_as(false, include_virtual)
+        })
+        .copied()
+        .collect()
+}
+
+/// Split the inputs of a rule into those that may feed `output` and the output itself, which
+/// a rule tapping a sink's monitor can match: linked back into the sink, the monitor would
+/// feed its own output back forever
+///
+/// Returns the inputs left to link, then the ones refused
+pub fn refuse_self_cascade<'a>(
+    inputs: &[&'a NodeInfo],
+    output: &NodeInfo,
+) -> (Vec<&'a NodeInfo>, Vec<&'a NodeInfo>) {
+    inputs.iter().partition(|node| node.serial != output.serial)
+}
+
+/// What to do when one side of a rule matches more than one node
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnAmbiguous {
+    /// Use the first match, in ranking or discovery order
+    First,
+    /// Use every match
+    All,
+    /// Refuse to choose
+    Error,
+}
+
+/// Several nodes matched where one was expected
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ambiguous {
+    pub side: Side,
+    pub candidates: Vec<(u32, Serial, String)>, // Global id, serial and description
+}
+
+impl fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}s match:", self.candidates.len(), self.side)?;
+        for (global_id, serial, description) in &self.candidates {
+            write!(
+                f,
+                "\n  [ID: {} | Serial: {}] {}",
+                global_id, serial, description
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Narrow the nodes matched by one side of a rule according to the policy
+///
+/// A single match (or none) is never ambiguous
+pub fn resolve_ambiguity(
+    side: Side,
+    mut candidates: Vec<&NodeInfo>,
+    policy: OnAmbiguous,
+) -> Result<Vec<&NodeInfo>, Ambiguous> {
+    if candidates.len() <= 1 {
+        return Ok(candidates);
+    }
+    match policy {
+        OnAmbiguous::First => {
+            candidates.truncate(1);
+            Ok(candidates)
+        }
+        OnAmbiguous::All => Ok(candidates),
+        OnAmbiguous::Error => Err(Ambiguous {
+            side,
+            candidates: candidates
+                .iter()
+                .map(|node| (node.global_id, node.serial, node.description.clone()))
+                .collect(),
+        }),
+    }
+}
+
+/// One condition evaluated by the matcher
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub condition: String,
+    pub passed: bool,
+    /// Comparisons within an expression, for explaining; only the check itself decides the match
+    pub details: Vec<Check>,
+}
+
+/// Every condition evaluated while matching a node against one side of a rule
+///
+/// All conditions are evaluated, so a trace explains every reason a node was rejected
+#[derive(Clone, Debug, Default)]
+pub struct MatchTrace {
+    pub checks: Vec<Check>,
+}
+
+impl MatchTrace {
+    pub fn matched(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    fn check(&mut self, condition: String, passed: bool) {
+        self.checks.push(Check {
+            condition,
+            passed,
+            details: Vec::new(),
+        });
+    }
+}
+
+/// Match a node against the input or output side of a rule
+///
+/// With monitors included, a sink can also act as an input through its monitor ports
+pub fn match_node(rule: &Rule, side: Side, node: &NodeInfo, options: MatchOptions) -> MatchTrace {
+    let mut trace = MatchTrace::default();
+    let (pattern, properties, expression, direction_ok, direction) = match side {
+        // Only sinks have monitor ports to tap
+        Side::Input if rule.input.is_monitor() => (
+            rule.input.pattern(),
+            &rule.input_properties,
+            &rule.input_match,
+            node.acts_as(false, options.include_virtual) && node.has_monitor_ports(),
+            "is a sink with monitor ports",
+        ),
+        Side::Input if options.include_monitors || rule.monitor => (
+            rule.input.pattern(),
+            &rule.input_properties,
+            &rule.input_match,
+            node.acts_as(true, options.include_virtual) || node.has_monitor_ports(),
+            "is an input or has monitor ports",
+        ),
+        Side::Input => (
+            rule.input.pattern(),
+            &rule.input_properties,
+            &rule.input_match,
+            node.acts_as(true, options.include_virtual),
+            "is an input",
+        ),
+        Side::Output => (
+            rule.output.as_str(),
+            &rule.output_properties,
+            &rule.output_match,
+            node.acts_as(false, options.include_virtual),
+            "is an output",
+        ),
+    };
+    // Roles belong to the streams being mixed, never to the output
+    let role = match side {
+        Side::Input => rule.role.as_ref(),
+        Side::Output => None,
+    };
+    trace.check(format!("direction {}", direction), direction_ok);
+    trace.check(format!("carries {}", rule.media), node.carries(rule.media));
+    let field = options.match_field;
+    if let Some(fuzzy) = fuzzy_pattern(pattern) {
+        let score = similarity(fuzzy, field.text(node));
+        trace.check(
+            format!(
+                "{} resembles {:?} (score {:.2}, threshold {:.2})",
+                field, fuzzy, score, options.fuzzy_threshold
+            ),
+            score >= options.fuzzy_threshold,
+        );
+    } else if is_wildcard(pattern) {
+        // Matches everything, so it is only worth reporting on its own
+        if properties.is_empty() && expression.is_none() && role.is_none() {
+            trace.check(format!("any {}", field), true);
+        }
+    } else {
+        trace.check(
+            format!("{} contains {:?}", field, pattern),
+            field
+                .text(node)
+                .to_uppercase()
+                .contains(&pattern.to_uppercase()),
+        );
+    }
+    for (key, value) in properties {
+        trace.check(
+            format!("{} = {:?}", key, value),
+            node.properties.get(key) == Some(value),
+        );
+    }
+    if let Some(role) = role {
+        trace.check(
+            format!("{} is {:?}", MEDIA_ROLE_KEY, role),
+            node.properties
+                .get(MEDIA_ROLE_KEY)
+                .is_some_and(|value| value.eq_ignore_ascii_case(role)),
+        );
+    }
+    if let Some(expression) = expression {
+        trace.checks.push(expression.check(node));
+    }
+    trace
+}
+
+/// Match a node against a standalone expression, e.g. from `--match`, for one direction
+pub fn match_expression(
+    expression: &Expression,
+    node: &NodeInfo,
+    input: bool,
+    include_virtual: bool,
+) -> MatchTrace {
+    let mut trace = MatchTrace::default();
+    let direction = if input { "is an input" } else { "is an output" };
+    trace.check(
+        format!("direction {}", direction),
+        node.acts_as(input, include_virtual),
+    );
+    trace.checks.push(expression.check(node));
+    trace
+}
+
+/// Order candidate outputs by the position of their form factor in `preferences`
+///
+/// Nodes without a preferred form factor rank last. The sort is stable, so the existing
+/// order (by global id) breaks ties
+pub fn rank_by_form_factor(candidates: &mut [&NodeInfo], preferences: &[String]) {
+    if preferences.is_empty() {
+        return;
+    }
+    candidates.sort_by_key(|node| {
+        node.form_factor
+            .as_ref()
+            .and_then(|form_factor| {
+                preferences
+                    .iter()
+                    .position(|preferred| preferred.eq_ignore_ascii_case(form_factor))
+            })
+            .unwrap_or(preferences.len())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config::RuleInput;
+    use crate::fixtures::{port, sink, source};
+    use crate::graph::{ActiveLink, Graph, PortInfo, Serial, DEFAULT_AUDIO_SINK_KEY};
+    use crate::mixer::Route;
+    use crate::plan::{plan_links, Pairing};
+    use crate::routing::Routing;
+
+    #[test]
+    fn single_match_is_never_ambiguous() {
+        let only = sink(1, &["FL", "FR"]);
+        for policy in [OnAmbiguous::First, OnAmbiguous::All, OnAmbiguous::Error] {
+            let resolved = resolve_ambiguity(Side::Output, vec![&only], policy).unwrap();
+            assert_eq!(resolved.len(), 1, "{:?}", policy);
+            assert!(resolve_ambiguity(Side::Output, Vec::new(), policy)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn first_keeps_the_first_match() {
+        let (a, b) = (sink(1, &["FL"]), sink(2, &["FL"]));
+        let resolved = resolve_ambiguity(Side::Output, vec![&b, &a], OnAmbiguous::First).unwrap();
+        let serials: Vec<Serial> = resolved.iter().map(|node| node.serial).collect();
+        assert_eq!(serials, [2]);
+    }
+
+    #[test]
+    fn all_keeps_every_match() {
+        let (a, b) = (sink(1, &["FL"]), sink(2, &["FL"]));
+        let resolved = resolve_ambiguity(Side::Output, vec![&a, &b], OnAmbiguous::All).unwrap();
+        let serials: Vec<Serial> = resolved.iter().map(|node| node.serial).collect();
+        assert_eq!(serials, [1, 2]);
+    }
+
+    #[test]
+    fn error_lists_every_match() {
+        let (a, b) = (sink(1, &["FL"]), sink(2, &["FL"]));
+        let ambiguous =
+            resolve_ambiguity(Side::Output, vec![&a, &b], OnAmbiguous::Error).unwrap_err();
+        assert_eq!(
+            ambiguous.candidates,
+            [(1, 1, "Sink 1".to_string()), (2, 2, "Sink 2".to_string())]
+        );
+        assert_eq!(
+            ambiguous.to_string(),
+            "2 outputs match:\n  [ID: 1 | Serial: 1] Sink 1\n  [ID: 2 | Serial: 2] Sink 2"
+        );
+    }
+
+    fn with_form_factor(serial: Serial, form_factor: Option<&str>) -> NodeInfo {
+        NodeInfo {
+            form_factor: form_factor.map(str::to_string),
+            ..sink(serial, &["FL", "FR"])
+        }
+    }
+
+    #[test]
+    fn rank_by_form_factor_follows_the_preferences() {
+        let nodes = [
+            with_form_factor(1, None),
+            with_form_factor(2, Some("speaker")),
+            with_form_factor(3, Some("Headphone")),
+            with_form_factor(4, Some("hdmi")),
+            with_form_factor(5, Some("headphone")),
+            with_form_factor(6, None),
+        ];
+        let mut candidates: Vec<&NodeInfo> = nodes.iter().collect();
+        let preferences = ["headphone".to_string(), "speaker".to_string()];
+        rank_by_form_factor(&mut candidates, &preferences);
+        let serials: Vec<Serial> = candidates.iter().map(|node| node.serial).collect();
+        // Unlisted and missing form factors rank last, ties keep their order
+        assert_eq!(serials, [3, 5, 2, 1, 4, 6]);
+    }
+
+    #[test]
+    fn rank_by_form_factor_without_preferences_keeps_the_order() {
+        let nodes = [
+            with_form_factor(2, Some("speaker")),
+            with_form_factor(1, Some("headphone")),
+        ];
+        let mut candidates: Vec<&NodeInfo> = nodes.iter().collect();
+        rank_by_form_factor(&mut candidates, &[]);
+        let serials: Vec<Serial> = candidates.iter().map(|node| node.serial).collect();
+        assert_eq!(serials, [2, 1]);
+    }
+
+    fn named_sink(serial: Serial, name: &str) -> NodeInfo {
+        NodeInfo {
+            properties: BTreeMap::from([("node.name".to_string(), name.to_string())]),
+            ..sink(serial, &["FL", "FR"])
+        }
+    }
+
+    #[test]
+    fn default_sink_flips_move_the_route() {
+        let mut graph = Graph::default();
+        graph.add_default_metadata(0);
+        graph.add_node(named_sink(2, "speakers"));
+        graph.add_node(named_sink(3, "headphones"));
+        let into = |output: Serial| ActiveLink {
+            channel: "FL".to_string(),
+            output_node: 1,
+            output_port: 100,
+            input_node: output,
+            input_port: output * 100,
+        };
+        let mut routing = Routing::default();
+        let mut outputs = Vec::new();
+        for name in ["speakers", "headphones", "speakers"] {
+            let value = format!("{{ \"name\": \"{}\" }}", name);
+            assert!(graph.set_default(Some(DEFAULT_AUDIO_SINK_KEY), Some(&value)));
+            // Announcing the same default again is no change, so it does not relink
+            assert!(!graph.set_default(Some(DEFAULT_AUDIO_SINK_KEY), Some(&value)));
+            let nodes: Vec<&NodeInfo> = graph.nodes().collect();
+            let sinks = default_sinks(&nodes, graph.defaults(), false);
+            assert_eq!(sinks.len(), 1);
+            let output = sinks[0].serial;
+            routing.set(vec![Route {
+                inputs: vec![1],
+                output,
+                ..Route::default()
+            }]);
+            // The links into the previous default are no longer routed, and are pruned
+            let previous = outputs.last().copied().unwrap_or(output);
+            assert!(routing.route_of(&into(output)).is_some());
+            assert_eq!(
+                routing.route_of(&into(previous)).is_some(),
+                previous == output
+            );
+            outputs.push(output);
+        }
+        assert_eq!(outputs, [2, 3, 2]);
+
+        // A default naming no known sink resolves to nothing, and clearing it likewise
+        assert!(graph.set_default(Some(DEFAULT_AUDIO_SINK_KEY), Some("{ \"name\": \"hdmi\" }")));
+        let nodes: Vec<&NodeInfo> = graph.nodes().collect();
+        assert!(default_sinks(&nodes, graph.defaults(), false).is_empty());
+        assert!(graph.set_default(None, None));
+        let nodes: Vec<&NodeInfo> = graph.nodes().collect();
+        assert!(default_sinks(&nodes, graph.defaults(), false).is_empty());
+    }
+
+    const OPTIONS: MatchOptions = MatchOptions {
+        include_monitors: false,
+        include_virtual: false,
+        fuzzy_threshold: 0.6,
+        match_field: MatchField::Description,
+    };
+
+    fn stream(serial: Serial, role: &str) -> NodeInfo {
+        NodeInfo {
+            media_class: "Stream/Output/Audio".to_string(),
+            properties: BTreeMap::from([(MEDIA_ROLE_KEY.to_string(), role.to_string())]),
+            ..source(serial, &["FL", "FR"])
+        }
+    }
+
+    #[test]
+    fn only_streams_of_the_rule_role_are_linked() {
+        let music = stream(1, "Music");
+        let notification = stream(3, "Notification");
+        let kitchen = sink(2, &["FL", "FR"]);
+        let rule = Rule {
+            role: Some("music".to_string()),
+            output: "Sink 2".to_string(),
+            ..Rule::default()
+        };
+        let inputs: Vec<Serial> = [&music, &notification, &kitchen]
+            .into_iter()
+            .filter(|node| match_node(&rule, Side::Input, node, OPTIONS).matched())
+            .map(|node| node.serial)
+            .collect();
+        assert_eq!(inputs, [1]);
+        // The role says nothing about outputs, which have none
+        assert!(match_node(&rule, Side::Output, &kitchen, OPTIONS).matched());
+
+        let mut routing = Routing::default();
+        routing.set(vec![Route {
+            inputs,
+            output: kitchen.serial,
+            ..Route::default()
+        }]);
+        let linked = |node: &NodeInfo| {
+            plan_links(node, &kitchen, &routing.routes()[0], Pairing::default())
+                .links
+                .iter()
+                .filter(|planned| routing.route_of(&planned.link).is_some())
+                .count()
+        };
+        assert_eq!(linked(&music), 2);
+        assert_eq!(linked(&notification), 0);
+    }
+
+    /// A sink whose monitor ports, numbered from `serial * 100 + 50`, play back what it receives
+    fn monitored_sink(serial: Serial, description: &str) -> NodeInfo {
+        let mut node = NodeInfo {
+            description: description.to_string(),
+            ..sink(serial, &["FL", "FR"])
+        };
+        for (index, channel) in ["FL", "FR"].into_iter().enumerate() {
+            node.ports.push(PortInfo {
+                monitor: true,
+                ..port(serial * 100 + 50 + index as Serial, channel, "out")
+            });
+        }
+        node
+    }
+
+    #[test]
+    fn monitor_of_selects_sinks_with_monitor_ports() {
+        let desktop = monitored_sink(1, "Desktop");
+        let kitchen = monitored_sink(2, "Kitchen");
+        let bare = NodeInfo {
+            description: "Desktop Bare".to_string(),
+            ..sink(3, &["FL", "FR"])
+        };
+        let microphone = NodeInfo {
+            description: "Desktop Microphone".to_string(),
+            ..source(4, &["MONO"])
+        };
+        let rule = Rule {
+            input: RuleInput::Monitor {
+                monitor_of: "Desktop".to_string(),
+            },
+            output: "Kitchen".to_string(),
+            ..Rule::default()
+        };
+        let inputs: Vec<Serial> = [&desktop, &kitchen, &bare, &microphone]
+            .into_iter()
+            .filter(|node| match_node(&rule, Side::Input, node, OPTIONS).matched())
+            .map(|node| node.serial)
+            .collect();
+        assert_eq!(inputs, [1]);
+        // Without monitor_of, a sink is no input at all
+        let plain = Rule {
+            input: RuleInput::Pattern("Desktop".to_string()),
+            ..rule
+        };
+        assert!(!match_node(&plain, Side::Input, &desktop, OPTIONS).matched());
+    }
+
+    #[test]
+    fn a_sink_is_never_cascaded_into_itself() {
+        let desktop = monitored_sink(1, "Desktop");
+        let kitchen = monitored_sink(2, "Kitchen");
+        let serials = |nodes: Vec<&NodeInfo>| -> Vec<Serial> {
+            nodes.iter().map(|node| node.serial).collect()
+        };
+
+        let (inputs, refused) = refuse_self_cascade(&[&desktop], &desktop);
+        assert!(inputs.is_empty());
+        assert_eq!(serials(refused), [1]);
+
+        // A pattern matching both sinks cascades each into the other only
+        let (inputs, refused) = refuse_self_cascade(&[&desktop, &kitchen], &kitchen);
+        assert_eq!(serials(inputs), [1]);
+        assert_eq!(serials(refused), [2]);
+        let (inputs, refused) = refuse_self_cascade(&[&desktop, &kitchen], &desktop);
+        assert_eq!(serials(inputs), [2]);
+        assert_eq!(serials(refused), [1]);
+    }
+}
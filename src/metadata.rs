@@ -0,0 +1,155 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::BoundProxy;
+
+/// One key/value triple of a metadata object
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub subject: u32, // Global id the key applies to, 0 for the core (e.g. defaults)
+    pub key: String,
+    #[serde(rename = "type")]
+    pub type_: Option<String>, // e.g. Spa:String:JSON
+    pub value: Option<String>,
+}
+
+/// A Metadata global and the entries it announced
+#[derive(Debug, Serialize)]
+pub struct Object {
+    pub id: u32,
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+/// A change to an entry, printed while watching
+#[derive(Serialize)]
+struct Change<'a> {
+    metadata: &'a str,
+    #[serde(flatten)]
+    entry: &'a Entry,
+}
+
+/// Every Metadata global found so far, keyed on global id
+pub type Store = Rc<RefCell<BTreeMap<u32, Object>>>;
+
+/// Bind a Metadata global and record its entries as they are announced
+///
+/// Once `live` is set, every change is printed as well, as JSON lines with `json`
+pub fn bind(
+    registry: &pipewire::registry::RegistryRc,
+    global: &pipewire::registry::GlobalObject<&pipewire::spa::utils::dict::DictRef>,
+    store: &Store,
+    live: Rc<Cell<bool>>,
+    json: bool,
+) -> Result<BoundProxy> {
+    let metadata = registry
+        .bind::<pipewire::metadata::Metadata, _>(global)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    let id = global.id;
+    let name = global
+        .props
+        .and_then(|props| props.get("metadata.name"))
+        .unwrap_or("unnamed")
+        .to_string();
+    store.borrow_mut().insert(
+        id,
+        Object {
+            id,
+            name: name.clone(),
+            entries: Vec::new(),
+        },
+    );
+    let store = store.clone();
+    let listener = metadata
+        .add_listener_local()
+        .property(move |subject, key, type_, value| {
+            let mut store = store.borrow_mut();
+            let Some(object) = store.get_mut(&id) else {
+                return 0;
+            };
+            let Some(key) = key else {
+                // No key clears every entry of the subject
+                object.entries.retain(|entry| entry.subject != subject);
+                if live.get() {
+                    print_cleared(&name, subject, json);
+                }
+                return 0;
+            };
+            let entry = Entry {
+                subject,
+                key: key.to_string(),
+                type_: type_.map(str::to_string),
+                value: value.map(str::to_string),
+            };
+            if live.get() {
+                print_change(&name, &entry, json);
+            }
+            object
+                .entries
+                .retain(|existing| existing.subject != subject || existing.key != key);
+            // No value removes the key
+            if entry.value.is_some() {
+                object.entries.push(entry);
+            }
+            0
+        })
+        .register();
+    Ok((Box::new(listener), Box::new(metadata)))
+}
+
+/// Print every metadata object with its entries, as one JSON document with `json`
+pub fn print_all(store: &Store, json: bool) -> Result<()> {
+    let store = store.borrow();
+    if json {
+        let objects: Vec<&Object> = store.values().collect();
+        println!("{}", serde_json::to_string_pretty(&objects)?);
+        return Ok(());
+    }
+    for object in store.values() {
+        println!("Metadata [ID: {}] {}", object.id, object.name);
+        for entry in &object.entries {
+            println!("  {}", describe(entry));
+        }
+    }
+    Ok(())
+}
+
+fn describe(entry: &Entry) -> String {
+    let value = entry.value.as_deref().unwrap_or("(removed)");
+    match &entry.type_ {
+        Some(type_) => format!(
+            "subject {:3}  {} = {}  ({})",
+            entry.subject, entry.key, value, type_
+        ),
+        None => format!("subject {:3}  {} = {}", entry.subject, entry.key, value),
+    }
+}
+
+fn print_change(name: &str, entry: &Entry, json: bool) {
+    if json {
+        let change = Change {
+            metadata: name,
+            entry,
+        };
+        if let Ok(line) = serde_json::to_string(&change) {
+            println!("{}", line);
+        }
+    } else {
+        println!("{}: {}", name, describe(entry));
+    }
+}
+
+fn print_cleared(name: &str, subject: u32, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "metadata": name, "subject": subject, "cleared": true })
+        );
+    } else {
+        println!("{}: subject {:3}  (all keys removed)", name, subject);
+    }
+}
@@ -0,0 +1,1903 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pipewire::link::{Link, LinkChangeMask, LinkListener, LinkState};
+use pipewire::metadata::Metadata;
+use pipewire::properties::PropertiesBox;
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+use crate::channel::Channel;
+use crate::cycle::{find_cycle, Edge};
+use crate::error::MixerError;
+use crate::gate::{GateAction, LinkWhile, RunGate};
+use crate::graph::{ActiveLink, Graph, LinkPhase, Media, NodeInfo, PortInfo, Removed, Serial};
+use crate::links::{Forgotten, LinkBook, LinkStep, Recreation, Watchdog};
+use crate::plan::{
+    missing_map_targets, plan_links, Downmix, ExplicitLink, LinkPlan, OnChannelMismatch, PairedBy,
+    Pairing, Upmix,
+};
+use crate::pod;
+use crate::retry::{Backoff, RetryQueue, StaggerQueue};
+use crate::routing::Routing;
+use crate::stamp::{LinkStamp, RECREATED_KEY};
+
+/// Inputs mixed into one output, identified by serial so they survive global id reuse
+#[derive(Clone, Debug, Default)]
+pub struct Route {
+    pub rule: Option<String>, // Name of the rule the route came from, stamped on its links
+    pub inputs: Vec<Serial>,
+    pub output: Serial,
+    pub aux_offset: i32, // Input AUXn pairs with output AUX(n + aux_offset)
+    pub include_monitors: bool, // Monitor ports are only used as sources when opted in
+    pub channel_map: HashMap<Channel, Vec<Channel>>, // Explicit pairs, taking precedence over names
+    pub downmix: Option<Downmix>, // Folds channels the output lacks into the ones it has
+    pub upmix: Option<Upmix>, // Copies input channels onto output channels nothing feeds
+    pub swap_lr: bool,   // Cross FL and FR, for outputs wired the wrong way round
+    pub links: Vec<ExplicitLink>, // Exact port pairs replacing every automatic pairing
+    pub atomic_group: Option<String>, // Routes sharing a group are linked all or nothing
+    pub exclusive: bool, // Links into the output from nodes no route feeds it with are destroyed
+    pub on_channel_mismatch: OnChannelMismatch,
+    pub input_channels: Vec<Channel>, // Source channels taking part in pairing, all if empty
+    pub output_channels: Vec<Channel>, // Sink channels taking part in pairing, all if empty
+    pub mono_to_stereo: Option<bool>, // Single-port sources feed FL and FR; None does so by default
+    pub stereo_to_mono: Option<bool>, // Every source port feeds a single-port sink; None does so by default
+    pub mechanism: Mechanism,
+    pub media: Media, // Only ports carrying it are paired
+    pub link_while: LinkWhile,
+    pub idle_grace: Duration, // With LinkWhile::Running, how long an input may stop before it is unlinked
+}
+
+/// How links made by other clients (usually the session manager) between the same two nodes
+/// are treated, as linking over them plays the audio twice
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ForeignLinks {
+    /// Link regardless of them
+    #[default]
+    Ignore,
+    /// Leave node pairs that are already linked to them
+    Respect,
+    /// Destroy them before linking
+    Replace,
+}
+
+/// How a route moves its inputs to its output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mechanism {
+    /// Create a link per channel pair
+    #[default]
+    Links,
+    /// Set `target.object` on each application stream and let the session manager link it
+    Metadata,
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mechanism::Links => f.write_str("links"),
+            Mechanism::Metadata => f.write_str("metadata"),
+        }
+    }
+}
+
+/// Metadata key the session manager reads to pick the node a stream is linked to
+pub const TARGET_OBJECT_KEY: &str = "target.object";
+
+/// Recreations of one link the watchdog allows within [`WATCHDOG_WINDOW`], so it gives up
+/// rather than fight another policy daemon forever
+pub const WATCHDOG_LIMIT: usize = 3;
+
+pub const WATCHDOG_WINDOW: Duration = Duration::from_secs(60);
+
+/// Factory creating the links unless [`LinkOptions::link_factory`] names another
+pub const DEFAULT_LINK_FACTORY: &str = "link-factory";
+
+/// Properties applied to every link the mixer creates
+#[derive(Clone, Debug, Default)]
+pub struct LinkOptions {
+    pub passive: bool, // Passive links do not keep their nodes running
+    pub pairing: Pairing,
+    pub node_latency: Option<NodeLatency>,
+    pub foreign_links: ForeignLinks,
+    pub best_effort: bool, // Create the explicit links that resolve even when others do not
+    pub teardown_adopted: bool, // Destroy adopted links along with the mixer's own
+    pub allow_cycles: bool, // Link routes closing a feedback loop instead of refusing them
+    pub append_to_existing: bool, // Never destroy a link another client made, whatever the route
+    pub link_factory: Option<String>, // None uses DEFAULT_LINK_FACTORY
+    pub link_retries: u32, // Retries of a link the daemon refused to create, 0 never retries
+    pub watchdog: bool,    // Recreate links another client removes, see WATCHDOG_LIMIT
+    pub link_stagger: Duration, // Delay between two link requests, zero requests them at once
+}
+
+/// Requested `node.latency` as a quantum over a sample rate, e.g. `256/48000`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeLatency {
+    pub quantum: u32,
+    pub rate: u32,
+}
+
+impl FromStr for NodeLatency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected QUANTUM/RATE like 256/48000, got {:?}", s);
+        let (quantum, rate) = s.split_once('/').ok_or_else(invalid)?;
+        let quantum: u32 = quantum.trim().parse().map_err(|_| invalid())?;
+        let rate: u32 = rate.trim().parse().map_err(|_| invalid())?;
+        if quantum == 0 || rate == 0 {
+            return Err(invalid());
+        }
+        Ok(NodeLatency { quantum, rate })
+    }
+}
+
+impl fmt::Display for NodeLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.quantum, self.rate)
+    }
+}
+
+/// A link proxy the mixer created, with the listener reporting its state and negotiated format
+struct ManagedLink {
+    _listener: LinkListener, // Declared first so it is dropped before the proxy
+    _proxy: Link,
+    state: Rc<RefCell<Option<LinkPhase>>>, // Last state reported, None until the first info
+}
+
+/// Log how many ports of a node pairing skips for not facing `direction`, to diagnose nodes
+/// whose ports report an unexpected direction, or none. The monitor ports of a sink face the
+/// other way by design and are not counted
+fn log_direction_filter(node: &NodeInfo, direction: &str) {
+    let skipped: Vec<&PortInfo> = node
+        .ports
+        .iter()
+        .filter(|port| port.direction != direction && !port.monitor)
+        .collect();
+    if skipped.is_empty() {
+        return;
+    }
+    let unknown = skipped
+        .iter()
+        .filter(|port| port.direction == "unknown")
+        .count();
+    debug!(
+        "[Serial: {}, {}]: {} of {} port(s) skipped as not {:?}, {} with unknown direction",
+        node.serial,
+        node.description,
+        skipped.len(),
+        node.ports.len(),
+        direction,
+        unknown
+    );
+}
+
+/// Log the state transitions of a link and the format it settles on, warning when an endpoint
+/// cannot run at its rate, as PipeWire then silently resamples that endpoint. Links that fail
+/// to activate are reported with a hint where the cause is known, and counted in `failures`.
+/// The last state is kept in `state`
+fn watch_link(
+    proxy: &Link,
+    link: ActiveLink,
+    graph: Arc<Mutex<Graph>>,
+    state: Rc<RefCell<Option<LinkPhase>>>,
+    failures: Rc<Cell<usize>>,
+) -> LinkListener {
+    proxy
+        .add_listener_local()
+        .info(move |info| {
+            if info.change_mask().contains(LinkChangeMask::STATE) {
+                let current = LinkPhase::from(&info.state());
+                let (output, input) = describe_endpoints(&link, &graph);
+                let previous = state.replace(Some(current.clone()));
+                match previous {
+                    Some(previous) => debug!(
+                        "Link for channel {} from {} to {}: {} -> {}",
+                        link.channel, output, input, previous, current
+                    ),
+                    None => debug!(
+                        "Link for channel {} from {} to {}: {}",
+                        link.channel, output, input, current
+                    ),
+                }
+                if let LinkState::Error(message) = info.state() {
+                    report_link_error(&link, message, &output, &input);
+                    failures.set(failures.get() + 1);
+                }
+            }
+            if !info.change_mask().contains(LinkChangeMask::FORMAT) {
+                return;
+            }
+            let Some(format) = info.format().and_then(pod::parse_format) else {
+                return;
+            };
+            info!("Channel {} negotiated {}", link.channel, format);
+            let Some(rate) = format.rates.first().map(|range| range.min) else {
+                return;
+            };
+            let Ok(graph) = graph.lock() else {
+                return;
+            };
+            for serial in [link.output_node, link.input_node] {
+                let Some(node) = graph.node(serial) else {
+                    continue;
+                };
+                // Nodes without known rates (e.g. streams) adapt to the graph on their own
+                let Some(caps) = node.caps.as_ref().filter(|caps| !caps.rates.is_empty()) else {
+                    continue;
+                };
+                if !caps.rates.iter().any(|range| range.contains(rate)) {
+                    warn!(
+                        "Channel {} runs at {} Hz, which [Serial: {}, {}] does not support ({}): it is resampled",
+                        link.channel, rate, node.serial, node.description, caps
+                    );
+                }
+            }
+        })
+        .register()
+}
+
+/// Note output channels fed by several channels of one input, which PipeWire mixes together
+///
+/// Folding by stereo-to-mono and downmix is expected and logged on its own, so only other
+/// pairings are reported, like a channel map sending two channels to one port
+fn log_shared_sinks(input_node: &NodeInfo, output_node: &NodeInfo, plan: &LinkPlan) {
+    let mut sources: Vec<(&str, Vec<&str>)> = Vec::new();
+    for planned in &plan.links {
+        let sink = planned.sink_channel.as_str();
+        match sources.iter_mut().find(|(channel, _)| *channel == sink) {
+            Some((_, channels)) => channels.push(&planned.source_channel),
+            None => sources.push((sink, vec![&planned.source_channel])),
+        }
+    }
+    for (sink, channels) in sources.iter().filter(|(_, channels)| channels.len() > 1) {
+        let expected = plan.links.iter().all(|planned| {
+            planned.sink_channel != *sink
+                || matches!(
+                    planned.paired_by,
+                    PairedBy::StereoToMono | PairedBy::Downmix | PairedBy::Name
+                )
+        });
+        if !expected {
+            info!(
+                "Channels [{}] of [Serial: {}, {}] all feed {} of [Serial: {}], which mixes them",
+                channels.join(", "),
+                input_node.serial,
+                input_node.description,
+                sink,
+                output_node.serial
+            );
+        }
+    }
+}
+
+/// The nodes at either end of a link, as "[Serial: N, description]"
+fn describe_endpoints(link: &ActiveLink, graph: &Arc<Mutex<Graph>>) -> (String, String) {
+    let describe = |serial: Serial| {
+        graph
+            .lock()
+            .ok()
+            .and_then(|graph| graph.node(serial).map(|node| node.description.clone()))
+            .map_or_else(
+                || format!("[Serial: {}]", serial),
+                |description| format!("[Serial: {}, {}]", serial, description),
+            )
+    };
+    (describe(link.output_node), describe(link.input_node))
+}
+
+/// Log why a link failed, naming its endpoints and suggesting a fix for clock mismatches
+fn report_link_error(link: &ActiveLink, message: &str, output: &str, input: &str) {
+    error!(
+        "Link for channel {} from {} to {} failed: {}",
+        link.channel, output, input, message
+    );
+    let message = message.to_ascii_lowercase();
+    if message.contains("clock") || message.contains("driver") {
+        warn!(
+            "{} and {} follow different clocks and cannot be resampled between. Allow resampling on both (drop resample.disable from their config) or give them a common clock by setting the same node.group on each",
+            output, input
+        );
+    }
+}
+
+/// The inputs of a route with two source ports, i.e. stereo, that do not have both of them
+/// linked into the output, described along with the channels that are
+///
+/// Routes linking chosen channels or explicit ports are left out, as they may link one channel
+/// on purpose, and so are inputs `expected` says are not to be linked yet
+fn incomplete_stereo(
+    route: &Route,
+    graph: &Graph,
+    linked_ports: &HashSet<Serial>,
+    expected: impl Fn(Serial) -> bool,
+) -> Vec<String> {
+    if route.media != Media::Audio || !route.input_channels.is_empty() || !route.links.is_empty() {
+        return Vec::new();
+    }
+    route
+        .inputs
+        .iter()
+        .filter(|input| expected(**input))
+        .filter_map(|input| graph.node(*input))
+        .filter_map(|node| {
+            let sources: Vec<&PortInfo> = node
+                .ports
+                .iter()
+                .filter(|port| {
+                    port.direction == "out"
+                        && !port.midi
+                        && (route.include_monitors || !port.monitor)
+                })
+                .collect();
+            let linked: Vec<&str> = sources
+                .iter()
+                .filter(|port| linked_ports.contains(&port.serial))
+                .map(|port| port.channel.as_str())
+                .collect();
+            (sources.len() == 2 && linked.len() < 2).then(|| {
+                format!(
+                    "[Serial: {}, {}] ({})",
+                    node.serial,
+                    node.description,
+                    match linked.first() {
+                        Some(channel) => format!("only {} linked", channel),
+                        None => "no channel linked".to_string(),
+                    }
+                )
+            })
+        })
+        .collect()
+}
+
+/// The entries of [`Mixer::pair_properties`], built once per node pair rather than per link
+fn pair_entries(
+    input_node: &NodeInfo,
+    output_node: &NodeInfo,
+    route: &Route,
+    options: &LinkOptions,
+) -> Vec<(&'static str, String)> {
+    // The mixer input produces the audio, so it is the link's output node
+    let mut entries = vec![
+        (
+            *pipewire::keys::LINK_OUTPUT_NODE,
+            input_node.global_id.to_string(),
+        ),
+        (
+            *pipewire::keys::LINK_INPUT_NODE,
+            output_node.global_id.to_string(),
+        ),
+        // An active link wakes the hardware; a passive one lets it suspend when nothing else plays
+        (*pipewire::keys::LINK_PASSIVE, options.passive.to_string()),
+        // "object.linger" => "true", // Persistent link FIXME TODO: first need to establish teardown process
+    ];
+    entries.extend(LinkStamp::own(route.rule.as_deref()).properties());
+    if let Some(latency) = options.node_latency {
+        entries.push((*pipewire::keys::NODE_LATENCY, latency.to_string()));
+    }
+    entries
+}
+
+/// How links other clients made between a routed pair are treated: never replaced when
+/// appending to what exists
+fn foreign_policy(options: &LinkOptions) -> ForeignLinks {
+    match options.foreign_links {
+        ForeignLinks::Replace if options.append_to_existing => ForeignLinks::Respect,
+        policy => policy,
+    }
+}
+
+/// The route of a link requested earlier and its input and output nodes, unless it is no
+/// longer routed, its nodes are gone, or a link between its ports exists by now
+fn wanted_link<P>(
+    routing: &Routing,
+    graph: &Graph,
+    links: &LinkBook<P>,
+    link: &ActiveLink,
+) -> Option<(Route, NodeInfo, NodeInfo)> {
+    let route = routing.route_of(link)?.clone();
+    if links.step(link, graph) != LinkStep::Create {
+        return None;
+    }
+    Some((
+        route,
+        graph.node(link.output_node)?.clone(),
+        graph.node(link.input_node)?.clone(),
+    ))
+}
+
+/// Remove the links `unwanted` selects from the shared list, holding its lock for that alone
+fn take_links(
+    active_links: &Mutex<Vec<ActiveLink>>,
+    unwanted: impl Fn(&ActiveLink) -> bool,
+) -> Vec<ActiveLink> {
+    let Ok(mut active) = active_links.lock() else {
+        return Vec::new();
+    };
+    let (dropped, kept) = active.drain(..).partition(|link| unwanted(link));
+    *active = kept;
+    dropped
+}
+
+/// How the links of one route fared once the daemon answered the requests for them
+#[derive(Clone, Debug)]
+pub struct Verification {
+    pub rule: Option<String>,
+    pub output: Serial,
+    pub requested: usize, // Links created or adopted for the route
+    pub active: usize,
+    pub failures: Vec<(String, String)>, // Channel, and why it is not established
+    pub waiting: Vec<String>, // Inputs left unlinked until they run, see LinkWhile::Running
+    pub incomplete: Vec<String>, // Stereo inputs with fewer than both channels linked, and why
+}
+
+/// Owns the link proxies created for the active route and keeps them in sync with the graph
+pub struct Mixer {
+    core: pipewire::core::CoreRc,
+    registry: pipewire::registry::RegistryRc, // Destroys the links replaced by the mixer's own
+    graph: Arc<Mutex<Graph>>,
+    active_links: Arc<Mutex<Vec<ActiveLink>>>, // Shared with the HTTP server
+    routing: Routing,
+    options: LinkOptions,
+    // The link proxies, kept alive to retain the links in the PipeWire graph, and the links
+    // another client or run already made between routed ports, used instead of a duplicate
+    links: LinkBook<ManagedLink>,
+    link_failures: Rc<Cell<usize>>, // Links that entered the error state, counted by their listeners
+    // Links the daemon refused to create, retried with backoff as devices may still be settling
+    retries: RetryQueue<(Serial, Serial), ActiveLink>, // (Output port, Input port) -> link
+    retry_scheduler: Option<Box<dyn Fn(Duration)>>,
+    // Links waiting to be created one at a time, as requesting many at once can cause xruns
+    staged: StaggerQueue<(Serial, Serial), ActiveLink>, // (Output port, Input port) -> link
+    stagger_scheduler: Option<Box<dyn Fn(Duration)>>,
+    // Recreations of links another client removed, limited per link and counted in total
+    watchdog: Watchdog,
+    // The default metadata, and the streams pointed at an output through it
+    metadata: Option<Metadata>,
+    targets: HashMap<Serial, (u32, Serial)>, // Stream -> (stream global id, output)
+    // Inputs of LinkWhile::Running routes, linked only while their gate is open
+    gates: HashMap<Serial, RunGate>,
+    gate_scheduler: Option<Box<dyn Fn(Duration)>>,
+}
+
+impl Mixer {
+    pub fn new(
+        core: pipewire::core::CoreRc,
+        registry: pipewire::registry::RegistryRc,
+        graph: Arc<Mutex<Graph>>,
+        active_links: Arc<Mutex<Vec<ActiveLink>>>,
+        options: LinkOptions,
+    ) -> Self {
+        let retries = RetryQueue::new(Backoff::new(options.link_retries));
+        let watchdog = Watchdog::new(options.watchdog, WATCHDOG_LIMIT, WATCHDOG_WINDOW);
+        let staged = StaggerQueue::new(options.link_stagger);
+        Mixer {
+            core,
+            registry,
+            graph,
+            active_links,
+            routing: Routing::default(),
+            options,
+            links: LinkBook::default(),
+            link_failures: Rc::new(Cell::new(0)),
+            retries,
+            retry_scheduler: None,
+            staged,
+            stagger_scheduler: None,
+            watchdog,
+            metadata: None,
+            targets: HashMap::new(),
+            gates: HashMap::new(),
+            gate_scheduler: None,
+        }
+    }
+
+    /// Hand over a proxy of the default metadata, needed by routes using [`Mechanism::Metadata`]
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = Some(metadata);
+    }
+
+    /// True if a stream has been pointed at an output through `target.object`, which outlives
+    /// the mixer unless [`Mixer::clear`] removes it
+    pub fn has_targets(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    /// Have `schedule` called with the delay until the next retry of a failed link whenever
+    /// one is scheduled, to call [`Mixer::retry_links`] once it has passed
+    ///
+    /// Without a scheduler failed links are only retried by the next [`Mixer::link_all`]
+    pub fn set_retry_scheduler(&mut self, schedule: impl Fn(Duration) + 'static) {
+        self.retry_scheduler = Some(Box::new(schedule));
+    }
+
+    /// Retry the failed links that are due, dropping those no longer routed, whose ports are
+    /// gone, or that exist by now. Returns the number of links created
+    pub fn retry_links(&mut self) -> usize {
+        let mut created = 0;
+        for (ports, link) in self.retries.take_due(Instant::now()) {
+            let retry = link.clone();
+            match self.create_if_wanted(link) {
+                None => {
+                    self.retries.resolve(&ports);
+                }
+                Some(Ok(())) => {
+                    info!("Channel {}: linked on retry", retry.channel);
+                    created += 1;
+                }
+                Some(Err(MixerError::LinkFailed { channel, source })) => {
+                    warn!("Retry for channel {} failed: {:?}", channel, source);
+                    self.schedule_retry(retry);
+                }
+                Some(Err(error)) => {
+                    warn!("{}", error);
+                    self.retries.resolve(&ports);
+                }
+            }
+        }
+        self.arm_retry();
+        created
+    }
+
+    /// Have `schedule` called with the delay until the next staged link is due whenever one
+    /// is, to call [`Mixer::create_staged`] once it has passed
+    ///
+    /// Links are only staged with a scheduler set and [`LinkOptions::link_stagger`] above zero,
+    /// otherwise they are all requested at once
+    pub fn set_stagger_scheduler(&mut self, schedule: impl Fn(Duration) + 'static) {
+        self.stagger_scheduler = Some(Box::new(schedule));
+    }
+
+    /// Create the next staged link that is still wanted, then schedule the one after it.
+    /// Returns true if a link was created
+    pub fn create_staged(&mut self) -> bool {
+        let mut created = false;
+        let next = match self.graph.lock() {
+            Ok(graph) => {
+                let (routing, links) = (&self.routing, &self.links);
+                self.staged
+                    .pop_wanted(|link| wanted_link(routing, &graph, links, link))
+            }
+            Err(_) => None,
+        };
+        if let Some((link, wanted)) = next {
+            let retry = link.clone();
+            match self.create_wanted(link, wanted) {
+                Ok(()) => created = true,
+                Err(MixerError::LinkFailed { channel, source }) => {
+                    error!(
+                        "Failed to create link for channel {}: {:?}",
+                        channel, source
+                    );
+                    self.schedule_retry(retry);
+                }
+                Err(error) => warn!("{}", error),
+            }
+        }
+        if let (Some(schedule), Some(delay)) = (&self.stagger_scheduler, self.staged.next_turn()) {
+            schedule(delay);
+        }
+        created
+    }
+
+    /// Links waiting for their turn to be created
+    pub fn staged_links(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Queue a link to be created once the links staged before it are, starting the schedule
+    /// if nothing was waiting
+    fn stage_link(&mut self, link: ActiveLink) {
+        let ports = (link.output_port, link.input_port);
+        if let (Some(schedule), Some(delay)) =
+            (&self.stagger_scheduler, self.staged.push(ports, link))
+        {
+            schedule(delay);
+        }
+    }
+
+    /// True if links are spaced out rather than requested at once
+    fn staggered(&self) -> bool {
+        !self.options.link_stagger.is_zero() && self.stagger_scheduler.is_some()
+    }
+
+    /// Create a link requested earlier, unless it is no longer routed, its nodes are gone, or
+    /// a link between its ports exists by now, which the next link_all adopts if it is not ours
+    fn create_if_wanted(&mut self, link: ActiveLink) -> Option<Result<(), MixerError>> {
+        let wanted = {
+            let graph = self.graph.lock().ok()?;
+            wanted_link(&self.routing, &graph, &self.links, &link)?
+        };
+        Some(self.create_wanted(link, wanted))
+    }
+
+    /// Create a link [`wanted_link`] found still wanted
+    fn create_wanted(
+        &mut self,
+        link: ActiveLink,
+        (route, input_node, output_node): (Route, NodeInfo, NodeInfo),
+    ) -> Result<(), MixerError> {
+        let mut props = self.pair_properties(&input_node, &output_node, &route);
+        self.create_link(link, &mut props)
+    }
+
+    /// Queue a link the daemon refused for another attempt, unless it has used up its retries
+    fn schedule_retry(&mut self, link: ActiveLink) {
+        let ports = (link.output_port, link.input_port);
+        let channel = link.channel.clone();
+        match self.retries.failed(ports, link, Instant::now()) {
+            Some((attempt, delay)) => info!(
+                "Retrying channel {} in {} ms (attempt {} of {})",
+                channel,
+                delay.as_millis(),
+                attempt,
+                self.options.link_retries
+            ),
+            None if self.options.link_retries > 0 => error!(
+                "Giving up on channel {} after {} retries",
+                channel, self.options.link_retries
+            ),
+            None => {}
+        }
+        self.arm_retry();
+    }
+
+    /// Have the scheduler wake us for the earliest retry due
+    fn arm_retry(&self) {
+        let delay = self.retries.next_due(Instant::now());
+        if let (Some(schedule), Some(delay)) = (&self.retry_scheduler, delay) {
+            schedule(delay);
+        }
+    }
+
+    /// Have `schedule` called with the delay until the grace period of an input that stopped
+    /// running runs out, to call [`Mixer::close_idle_inputs`] once it has passed
+    ///
+    /// Without a scheduler such inputs stay linked until they run again
+    pub fn set_gate_scheduler(&mut self, schedule: impl Fn(Duration) + 'static) {
+        self.gate_scheduler = Some(Box::new(schedule));
+    }
+
+    /// Follow the state of a node, returning true if it is an input of a
+    /// [`LinkWhile::Running`] route that started running and should be linked now
+    pub fn input_state_changed(&mut self, node: Serial, running: bool) -> bool {
+        let Some(route) =
+            self.routing.routes().iter().find(|route| {
+                route.link_while == LinkWhile::Running && route.inputs.contains(&node)
+            })
+        else {
+            return false;
+        };
+        let grace = route.idle_grace;
+        let gate = self
+            .gates
+            .entry(node)
+            .or_insert_with(|| RunGate::new(grace));
+        let was_open = gate.is_open();
+        let action = gate.observe(running, Instant::now());
+        match (was_open, gate.is_open()) {
+            (false, true) => info!("Input [Serial: {}] started running, linking it", node),
+            (true, true) if !running => debug!(
+                "Input [Serial: {}] stopped running, unlinking it in {} ms unless it runs again",
+                node,
+                grace.as_millis()
+            ),
+            _ => {}
+        }
+        self.arm_gate();
+        action == Some(GateAction::Link)
+    }
+
+    /// Unlink the inputs that have not run again within their grace period, returning the
+    /// number of links destroyed
+    pub fn close_idle_inputs(&mut self) -> usize {
+        let now = Instant::now();
+        let closed: Vec<Serial> = self
+            .gates
+            .iter_mut()
+            .filter_map(|(node, gate)| {
+                (gate.expire(now) == Some(GateAction::Unlink)).then_some(*node)
+            })
+            .collect();
+        let mut dropped = 0;
+        for node in closed {
+            let unlinked =
+                self.drop_links("input stopped running", |link| link.output_node == node);
+            info!(
+                "Input [Serial: {}] stayed idle, removed {} link(s) until it runs again",
+                node, unlinked
+            );
+            dropped += unlinked;
+        }
+        self.arm_gate();
+        dropped
+    }
+
+    /// Have the scheduler wake us for the earliest grace period to run out
+    fn arm_gate(&self) {
+        let now = Instant::now();
+        let delay = self
+            .gates
+            .values()
+            .filter_map(|gate| gate.deadline())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now));
+        if let (Some(schedule), Some(delay)) = (&self.gate_scheduler, delay) {
+            schedule(delay);
+        }
+    }
+
+    /// True if an input of the route may be linked: always, unless the route waits for its
+    /// inputs to run and this one has not yet
+    fn gate_open(&mut self, route: &Route, input_node: &NodeInfo) -> bool {
+        if route.link_while == LinkWhile::Always {
+            return true;
+        }
+        // The state may be known before the route is, e.g. for an input running at startup
+        let gate = self
+            .gates
+            .entry(input_node.serial)
+            .or_insert_with(|| RunGate::new(route.idle_grace));
+        if !gate.is_open() && input_node.is_busy() {
+            gate.observe(true, Instant::now());
+        }
+        gate.is_open()
+    }
+
+    /// True once no link is staged and every link this mixer created is established or has
+    /// failed, so [`Mixer::verify`] has the final word on them
+    pub fn links_settled(&self) -> bool {
+        let moved = self.graph.lock().is_ok_and(|graph| {
+            let edges = graph.link_edges();
+            self.targets
+                .iter()
+                .all(|(stream, (_, output))| edges.contains(&(*stream, *output)))
+        });
+        moved
+            && self.staged.is_empty()
+            && self.links.proxies().all(|managed| {
+                managed
+                    .state
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(LinkPhase::is_settled)
+            })
+    }
+
+    /// How many of the links this mixer created have entered the error state so far
+    pub fn link_failures(&self) -> usize {
+        self.link_failures.get()
+    }
+
+    /// Set the routes to maintain; links are created by [`Mixer::link_all`]
+    ///
+    /// Links between nodes that are no longer routed to each other are destroyed
+    pub fn set_routes(&mut self, routes: Vec<Route>) {
+        self.routing.set(routes);
+        self.prune();
+    }
+
+    /// Switch to new routes, creating their links before destroying those no longer routed,
+    /// so audio moving to another output is not interrupted
+    ///
+    /// Returns the number of links created
+    pub fn switch_routes(&mut self, routes: Vec<Route>) -> usize {
+        self.routing.set(routes);
+        let created = self.link_all();
+        self.prune();
+        created
+    }
+
+    /// Destroy the links between nodes that are no longer routed to each other
+    fn prune(&mut self) {
+        let routing = self.routing.clone();
+        self.drop_links("no longer routed", |link| routing.route_of(link).is_none());
+        let unrouted: Vec<(Serial, Serial)> = self
+            .links
+            .adopted()
+            .filter(|(_, (_, link))| routing.route_of(link).is_none())
+            .map(|(ports, _)| *ports)
+            .collect();
+        for ports in unrouted {
+            self.release(ports);
+        }
+        let untargeted: Vec<Serial> = self
+            .targets
+            .iter()
+            .filter(|(stream, (_, output))| {
+                !routing.routes().iter().any(|route| {
+                    route.mechanism == Mechanism::Metadata
+                        && route.output == *output
+                        && route.inputs.contains(stream)
+                })
+            })
+            .map(|(stream, _)| *stream)
+            .collect();
+        for stream in untargeted {
+            self.clear_target(stream);
+        }
+        self.gates.retain(|input, _| {
+            routing
+                .routes()
+                .iter()
+                .any(|route| route.link_while == LinkWhile::Running && route.inputs.contains(input))
+        });
+    }
+
+    /// Remove the `target.object` the mixer set on a stream, so the session manager routes it
+    /// by its own policy again
+    fn clear_target(&mut self, stream: Serial) {
+        let Some((global_id, output)) = self.targets.remove(&stream) else {
+            return;
+        };
+        debug!(
+            "Clearing {} of [Serial: {}] (was [Serial: {}])",
+            TARGET_OBJECT_KEY, stream, output
+        );
+        if let Some(metadata) = &self.metadata {
+            metadata.set_property(global_id, TARGET_OBJECT_KEY, None, None);
+        }
+    }
+
+    /// Stop relying on an adopted link, destroying it only with `teardown_adopted`, as it
+    /// belongs to whoever made it
+    fn release(&mut self, ports: (Serial, Serial)) {
+        let Some((link_id, _)) = self.links.release(&ports) else {
+            return;
+        };
+        if !self.options.teardown_adopted {
+            debug!("Leaving adopted link [ID: {}] in place", link_id);
+            return;
+        }
+        debug!("Destroying adopted link [ID: {}]", link_id);
+        if let Err(error) = self.registry.destroy_global(link_id).into_result() {
+            warn!(
+                "Failed to remove adopted link [ID: {}]: {:?}",
+                link_id, error
+            );
+        }
+    }
+
+    /// Forget a link global that was destroyed, returning true if the mixer relied on it and
+    /// should link its channel itself: it was adopted, or it was one of the mixer's own links
+    /// removed by another client and the watchdog may recreate it
+    ///
+    /// Must be called before the link is removed from the graph
+    pub fn forget_link(&mut self, link_id: u32) -> bool {
+        // The ports may be on their way out too, which the relink finds out
+        let ports = self
+            .graph
+            .lock()
+            .ok()
+            .and_then(|graph| graph.link_ports(link_id));
+        let Ok(mut active) = self.active_links.lock() else {
+            return false;
+        };
+        let forgotten = self.links.forget(link_id, ports, &mut active);
+        drop(active);
+        let link = match forgotten {
+            Forgotten::Adopted => return true,
+            Forgotten::Untracked => return false,
+            Forgotten::Own(link) => link,
+        };
+        match self.watchdog.recreate(&link, Instant::now()) {
+            Recreation::Off => {
+                warn!(
+                    "Link for channel {} [ID: {}] was removed by another client",
+                    link.channel, link_id
+                );
+                false
+            }
+            Recreation::RateLimited => {
+                warn!(
+                    "Link for channel {} [ID: {}] was removed again, not recreating it as it was already recreated {} times within {} s",
+                    link.channel,
+                    link_id,
+                    WATCHDOG_LIMIT,
+                    WATCHDOG_WINDOW.as_secs()
+                );
+                false
+            }
+            Recreation::Recreate(recreated) => {
+                info!(
+                    "Link for channel {} [ID: {}] was removed by another client, recreating it (recreation {})",
+                    link.channel, link_id, recreated
+                );
+                true
+            }
+        }
+    }
+
+    /// Forget every route, destroy every link the mixer created and clear every `target.object`
+    /// it set
+    pub fn clear(&mut self) {
+        self.routing.clear();
+        self.links.clear_own();
+        self.retries.clear();
+        self.staged.clear();
+        let targeted: Vec<Serial> = self.targets.keys().copied().collect();
+        for stream in targeted {
+            self.clear_target(stream);
+        }
+        let adopted: Vec<(Serial, Serial)> =
+            self.links.adopted().map(|(ports, _)| *ports).collect();
+        for ports in adopted {
+            self.release(ports);
+        }
+        if let Ok(mut active) = self.active_links.lock() {
+            active.clear();
+        }
+        debug!("Dropped all managed links");
+    }
+
+    /// Route a node in place of a vanished node of the same device and direction, see
+    /// [`Routing::adopt_replacement`]
+    ///
+    /// Returns true if the node took over a route endpoint; its links are created by the next
+    /// [`Mixer::link_all`]
+    pub fn adopt_replacement(&mut self, node: &NodeInfo) -> bool {
+        let Ok(graph) = self.graph.lock() else {
+            return false;
+        };
+        self.routing.adopt_replacement(node, &graph)
+    }
+
+    /// True once routes have been set, i.e. the mixer is maintaining links
+    pub fn has_routes(&self) -> bool {
+        !self.routing.is_empty()
+    }
+
+    /// True if there is no route yet, or a node of one is gone, so a node appearing may be the
+    /// one the rules are waiting for
+    pub fn awaiting_nodes(&self) -> bool {
+        self.graph
+            .lock()
+            .is_ok_and(|graph| self.routing.awaiting_nodes(&graph))
+    }
+
+    /// Check the links of every route against the graph: a link is active once its global
+    /// exists and the daemon has not reported it failed
+    ///
+    /// Only meaningful after a sync following [`Mixer::link_all`], as link globals are announced
+    /// asynchronously
+    pub fn verify(&self) -> Vec<Verification> {
+        let (Ok(active), Ok(graph)) = (self.active_links.lock(), self.graph.lock()) else {
+            return Vec::new();
+        };
+        self.routing
+            .routes()
+            .iter()
+            .map(|route| {
+                if route.mechanism == Mechanism::Metadata {
+                    return self.verify_targets(route, &graph);
+                }
+                let routed = |link: &&ActiveLink| {
+                    route.output == link.input_node && route.inputs.contains(&link.output_node)
+                };
+                let mut verification = Verification {
+                    rule: route.rule.clone(),
+                    output: route.output,
+                    requested: 0,
+                    active: 0,
+                    failures: Vec::new(),
+                    waiting: route
+                        .inputs
+                        .iter()
+                        .filter(|input| {
+                            route.link_while == LinkWhile::Running
+                                && !self.gates.get(input).is_some_and(RunGate::is_open)
+                        })
+                        .filter_map(|input| graph.node(*input))
+                        .map(|node| format!("[Serial: {}, {}]", node.serial, node.description))
+                        .collect(),
+                    incomplete: Vec::new(),
+                };
+                let mut linked_ports: HashSet<Serial> = HashSet::new(); // Source ports with an active link
+                let adopted = self.links.adopted().map(|(_, (_, link))| link);
+                for link in active.iter().filter(routed).chain(adopted.filter(routed)) {
+                    verification.requested += 1;
+                    let ports = (link.output_port, link.input_port);
+                    // Own links report their state to the proxy, adopted ones to the bound global
+                    let state = match self.links.proxy(&ports) {
+                        Some(managed) => managed.state.borrow().clone(),
+                        None => self
+                            .links
+                            .adopted()
+                            .find(|(adopted, _)| **adopted == ports)
+                            .and_then(|(_, (link_id, _))| graph.link(*link_id)?.state.clone()),
+                    };
+                    let exists = graph
+                        .link_ids(link)
+                        .and_then(|(_, output_port, _, input_port)| {
+                            graph.find_link(output_port, input_port)
+                        })
+                        .is_some();
+                    let failure = match state {
+                        Some(state) if state.is_established() => None,
+                        Some(LinkPhase::Error(message)) => Some(message),
+                        Some(state) => Some(format!("still {}", state)),
+                        None if exists => None,
+                        None => Some("no link appeared".to_string()),
+                    };
+                    match failure {
+                        Some(reason) => verification.failures.push((link.channel.clone(), reason)),
+                        None => {
+                            verification.active += 1;
+                            linked_ports.insert(link.output_port);
+                        }
+                    }
+                }
+                verification.incomplete =
+                    incomplete_stereo(route, &graph, &linked_ports, |input| {
+                        route.link_while == LinkWhile::Always
+                            || self.gates.get(&input).is_some_and(RunGate::is_open)
+                    });
+                verification
+            })
+            .collect()
+    }
+
+    /// Check the streams a route pointed at its output: a stream counts as active once the
+    /// session manager has linked it there
+    fn verify_targets(&self, route: &Route, graph: &Graph) -> Verification {
+        let edges = graph.link_edges();
+        let mut verification = Verification {
+            rule: route.rule.clone(),
+            output: route.output,
+            requested: 0,
+            active: 0,
+            failures: Vec::new(),
+            waiting: Vec::new(),
+            incomplete: Vec::new(),
+        };
+        for stream in &route.inputs {
+            if self.targets.get(stream).map(|(_, output)| *output) != Some(route.output) {
+                continue;
+            }
+            verification.requested += 1;
+            if edges.contains(&(*stream, route.output)) {
+                verification.active += 1;
+            } else {
+                let description = graph
+                    .node(*stream)
+                    .map(|node| node.description.clone())
+                    .unwrap_or_else(|| format!("[Serial: {}]", stream));
+                verification
+                    .failures
+                    .push((description, "not moved by the session manager".to_string()));
+            }
+        }
+        verification
+    }
+
+    /// True if the node is one of the endpoints of an active route
+    pub fn involves(&self, node: Serial) -> bool {
+        self.routing.involves(node)
+    }
+
+    /// Serials of the inputs of every route, each listed once
+    pub fn inputs(&self) -> Vec<Serial> {
+        self.routing.inputs()
+    }
+
+    /// Pair the ports of every input with its route's output and create any links that are missing
+    ///
+    /// Safe to call repeatedly: existing links are left alone. Returns the number of links created
+    pub fn link_all(&mut self) -> usize {
+        let routes = self.routing.routes().to_vec();
+        let mut created = 0;
+        let mut failed_groups: Vec<&str> = Vec::new();
+        let mut evicted = 0;
+        let mut edges = self
+            .graph
+            .lock()
+            .map(|graph| graph.link_edges())
+            .unwrap_or_default();
+        for route in &routes {
+            if let Some(reason) = self.refuse_route(route, &mut edges) {
+                error!("{}", reason);
+                continue;
+            }
+            let (linked, failed) = match route.mechanism {
+                Mechanism::Links => {
+                    if route.exclusive && !self.options.append_to_existing {
+                        evicted += self.evict_competitors(route);
+                    }
+                    self.link_route(route)
+                }
+                Mechanism::Metadata => self.target_route(route),
+            };
+            created += linked;
+            match &route.atomic_group {
+                Some(group) if failed > 0 && !failed_groups.contains(&group.as_str()) => {
+                    failed_groups.push(group)
+                }
+                _ => {}
+            }
+        }
+        // A leg failing takes down the links of every other leg of its rule
+        for group in failed_groups {
+            error!(
+                "Rule {}: a leg failed to link, removing the links of every leg as the rule is atomic",
+                group
+            );
+            for route in routes
+                .iter()
+                .filter(|route| route.atomic_group.as_deref() == Some(group))
+            {
+                created = created.saturating_sub(self.unlink_route(route));
+            }
+        }
+        if evicted > 0 {
+            info!(
+                "Evicted {} competing link(s) from exclusive outputs",
+                evicted
+            );
+        }
+        created
+    }
+
+    /// Why a route must not be linked: it links a node to itself, or closes a feedback loop
+    /// with the links in `edges`, which gains the route's edges if it is accepted
+    fn refuse_route(&self, route: &Route, edges: &mut Vec<Edge>) -> Option<String> {
+        let Ok(graph) = self.graph.lock() else {
+            return None;
+        };
+        let describe = |serial: Serial| match graph.node(serial) {
+            Some(node) => format!("[Serial: {}, {}]", serial, node.description),
+            None => format!("[Serial: {}]", serial),
+        };
+        let planned: Vec<Edge> = route
+            .inputs
+            .iter()
+            .map(|input| (*input, route.output))
+            .collect();
+        if route.inputs.contains(&route.output) {
+            return Some(format!(
+                "Refusing to link {} to itself",
+                describe(route.output)
+            ));
+        }
+        if let Some(node) = route.inputs.iter().chain([&route.output]).find(|serial| {
+            graph
+                .node(**serial)
+                .is_some_and(|node| !node.carries(route.media))
+        }) {
+            return Some(format!(
+                "Refusing to link into {}: {} carries no {}, and a route cannot mix media",
+                describe(route.output),
+                describe(*node),
+                route.media
+            ));
+        }
+        if !self.options.allow_cycles {
+            if let Some(cycle) = find_cycle(edges, &planned) {
+                let path: Vec<String> = cycle.into_iter().map(describe).collect();
+                return Some(format!(
+                    "Refusing to link into {}: it would close the feedback loop {} (pass --allow-cycles to link anyway)",
+                    describe(route.output),
+                    path.join(" => ")
+                ));
+            }
+        }
+        edges.extend(planned);
+        None
+    }
+
+    /// Destroy the links into an exclusive route's output that come from nodes no route feeds
+    /// it with, returning how many were destroyed
+    fn evict_competitors(&self, route: &Route) -> usize {
+        let competing: Vec<(u32, String)> = {
+            let Ok(graph) = self.graph.lock() else {
+                return 0;
+            };
+            // Every route into the output is ours to keep, not only this one
+            let sources: Vec<Serial> = self
+                .routing
+                .routes()
+                .iter()
+                .filter(|other| other.output == route.output)
+                .flat_map(|other| other.inputs.iter().copied())
+                .collect();
+            graph
+                .competitors(route.output, &sources, &self.links.port_pairs())
+                .into_iter()
+                .map(|(link_id, link)| (link_id, graph.describe_link(link)))
+                .collect()
+        };
+        let mut evicted = 0;
+        for (link_id, description) in competing {
+            info!("Evicting link [ID: {}] {}", link_id, description);
+            if let Err(error) = self.registry.destroy_global(link_id).into_result() {
+                warn!("Failed to remove link [ID: {}]: {:?}", link_id, error);
+                continue;
+            }
+            if let Ok(mut graph) = self.graph.lock() {
+                graph.remove_global(link_id);
+            }
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Destroy the links of one route, or clear the targets it set, returning how many there were
+    fn unlink_route(&mut self, route: &Route) -> usize {
+        if route.mechanism == Mechanism::Metadata {
+            let targeted: Vec<Serial> = route
+                .inputs
+                .iter()
+                .filter(|stream| {
+                    self.targets.get(stream).map(|(_, output)| *output) == Some(route.output)
+                })
+                .copied()
+                .collect();
+            for stream in &targeted {
+                self.clear_target(*stream);
+            }
+            return targeted.len();
+        }
+        self.drop_links("atomic rule failed", |link| {
+            route.output == link.input_node && route.inputs.contains(&link.output_node)
+        })
+    }
+
+    /// Forget the links `unwanted` selects and destroy their proxies, returning how many there
+    /// were
+    ///
+    /// The shared list is unlocked before any proxy goes, so nothing a proxy does on its way
+    /// out can find it locked
+    fn drop_links(&mut self, reason: &str, unwanted: impl Fn(&ActiveLink) -> bool) -> usize {
+        let dropped = take_links(&self.active_links, unwanted);
+        for link in &dropped {
+            debug!(
+                "Dropping link for channel {}: [{}]->[{}] ({})",
+                link.channel, link.output_port, link.input_port, reason
+            );
+            self.links.remove(&(link.output_port, link.input_port));
+        }
+        dropped.len()
+    }
+
+    /// Point the streams of a route at its output through `target.object`, leaving the linking
+    /// to the session manager, returning the streams pointed and failed
+    fn target_route(&mut self, route: &Route) -> (usize, usize) {
+        let rule = route.rule.as_deref().unwrap_or("--route");
+        let Some(metadata) = &self.metadata else {
+            error!(
+                "Rule {}: no default metadata to set {} in, is a session manager running?",
+                rule, TARGET_OBJECT_KEY
+            );
+            return (0, 1);
+        };
+        let input_nodes: Vec<NodeInfo> = {
+            let Ok(graph) = self.graph.lock() else {
+                return (0, 0);
+            };
+            if graph.node(route.output).is_none() {
+                warn!("Output [Serial: {}] no longer exists", route.output);
+                return (0, 1);
+            }
+            route
+                .inputs
+                .iter()
+                .filter_map(|serial| graph.node(*serial).cloned())
+                .collect()
+        };
+        let mut targeted = 0;
+        let mut failed = 0;
+        for node in &input_nodes {
+            if !node.is_stream() {
+                error!(
+                    "Rule {}: [Serial: {}, {}] is not an application stream, and mechanism = \"metadata\" can only move streams. Use mechanism = \"links\" to route devices",
+                    rule, node.serial, node.description
+                );
+                failed += 1;
+                continue;
+            }
+            if self.targets.get(&node.serial) == Some(&(node.global_id, route.output)) {
+                continue;
+            }
+            debug!(
+                "Setting {} of [Serial: {}, {}] to [Serial: {}]",
+                TARGET_OBJECT_KEY, node.serial, node.description, route.output
+            );
+            metadata.set_property(
+                node.global_id,
+                TARGET_OBJECT_KEY,
+                None,
+                Some(&route.output.to_string()),
+            );
+            self.targets
+                .insert(node.serial, (node.global_id, route.output));
+            targeted += 1;
+        }
+        (targeted, failed)
+    }
+
+    /// Link the inputs of a route to its output, returning the links created and failed
+    fn link_route(&mut self, route: &Route) -> (usize, usize) {
+        // Copy the endpoints out so the graph is not locked while links are requested
+        let (output_node, input_nodes) = {
+            let Ok(graph) = self.graph.lock() else {
+                return (0, 0);
+            };
+            let Some(output_node) = graph.node(route.output).cloned() else {
+                warn!("Output [Serial: {}] no longer exists", route.output);
+                return (0, 1);
+            };
+            let input_nodes: Vec<NodeInfo> = route
+                .inputs
+                .iter()
+                .filter_map(|serial| graph.node(*serial).cloned())
+                .collect();
+            (output_node, input_nodes)
+        };
+        let input_nodes: Vec<NodeInfo> = input_nodes
+            .into_iter()
+            .filter(|node| self.gate_open(route, node))
+            .collect();
+
+        self.routing
+            .note_endpoints(input_nodes.iter().chain([&output_node]));
+
+        let mut created = 0;
+        let mut adopted = 0;
+        let mut failed = 0;
+        let mut fed_sinks: HashSet<Serial> = HashSet::new(); // Sink ports with a link from any input
+        log_direction_filter(&output_node, "in");
+        let missing = missing_map_targets(route, &output_node);
+        if !missing.is_empty() {
+            warn!(
+                "Channel map sends {} into [Serial: {}, {}], which has no port for it: {}",
+                if missing.len() == 1 {
+                    "a channel"
+                } else {
+                    "channels"
+                },
+                output_node.serial,
+                output_node.description,
+                missing.join(", ")
+            );
+        }
+        if route.on_channel_mismatch == OnChannelMismatch::Error {
+            for input_node in &input_nodes {
+                let plan = plan_links(input_node, &output_node, route, self.options.pairing);
+                if let Some(summary) = plan.mismatch_summary() {
+                    error!(
+                        "Not linking into [Serial: {}, {}]: channels of [Serial: {}, {}] do not match ({})",
+                        output_node.serial,
+                        output_node.description,
+                        input_node.serial,
+                        input_node.description,
+                        summary
+                    );
+                    return (0, 1);
+                }
+            }
+        }
+        for input_node in &input_nodes {
+            debug!(
+                "Linking: [Serial: {}, {}]=>[Serial: {}, {}]",
+                input_node.serial,
+                input_node.description,
+                output_node.serial,
+                output_node.description
+            );
+            log_direction_filter(input_node, "out");
+            let plan = plan_links(input_node, &output_node, route, self.options.pairing);
+            if self.options.pairing == Pairing::NameThenPosition
+                && plan
+                    .links
+                    .first()
+                    .is_some_and(|planned| planned.paired_by == PairedBy::Position)
+            {
+                info!(
+                    "No channel names line up between [Serial: {}] and [Serial: {}], pairing ports by position",
+                    input_node.serial, output_node.serial
+                );
+            }
+            if let Some(summary) = plan.mismatch_summary() {
+                let pair = format!(
+                    "[Serial: {}, {}]=>[Serial: {}, {}]",
+                    input_node.serial,
+                    input_node.description,
+                    output_node.serial,
+                    output_node.description
+                );
+                match route.on_channel_mismatch {
+                    OnChannelMismatch::Truncate => debug!("{}: {}", pair, summary),
+                    _ => warn!("{}: {}", pair, summary),
+                }
+            }
+            // With nothing paired every channel is left over, so these are the full channel sets
+            if plan.links.is_empty() && !plan.unmatched_sources.is_empty() {
+                error!(
+                    "No links possible from [Serial: {}, {}] to [Serial: {}, {}]: channels [{}] share nothing with [{}]",
+                    input_node.serial,
+                    input_node.description,
+                    output_node.serial,
+                    output_node.description,
+                    plan.unmatched_sources.join(", "),
+                    plan.unused_sinks.join(", ")
+                );
+            }
+            if let Some(mono) = plan
+                .links
+                .iter()
+                .find(|planned| planned.paired_by == PairedBy::MonoToStereo)
+            {
+                info!(
+                    "[Serial: {}, {}] has a single port ({}), linking it to both FL and FR of [Serial: {}]",
+                    input_node.serial, input_node.description, mono.source_channel, output_node.serial
+                );
+            }
+            if let Some(mono) = plan
+                .links
+                .iter()
+                .find(|planned| planned.paired_by == PairedBy::StereoToMono)
+            {
+                info!(
+                    "[Serial: {}] has a single port ({}), mixing all {} channels of [Serial: {}, {}] into it; PipeWire sums them, so correlated content such as centered vocals gets up to {:.0} dB louder",
+                    output_node.serial,
+                    mono.sink_channel,
+                    plan.links.len(),
+                    input_node.serial,
+                    input_node.description,
+                    20.0 * (plan.links.len() as f64).log10()
+                );
+            }
+            let folded: Vec<&str> = plan
+                .links
+                .iter()
+                .filter(|planned| planned.paired_by == PairedBy::Downmix)
+                .map(|planned| planned.source_channel.as_str())
+                .fold(Vec::new(), |mut channels, channel| {
+                    if !channels.contains(&channel) {
+                        channels.push(channel);
+                    }
+                    channels
+                });
+            if !folded.is_empty() {
+                info!(
+                    "Downmixing channels [{}] of [Serial: {}, {}] into [Serial: {}]",
+                    folded.join(", "),
+                    input_node.serial,
+                    input_node.description,
+                    output_node.serial
+                );
+            }
+            log_shared_sinks(input_node, &output_node, &plan);
+            if !plan.unresolved.is_empty() {
+                for failure in &plan.unresolved {
+                    if self.options.best_effort {
+                        warn!(
+                            "Skipping explicit link {} from [Serial: {}]",
+                            failure, input_node.serial
+                        );
+                    } else {
+                        error!(
+                            "Explicit link {} from [Serial: {}, {}]",
+                            failure, input_node.serial, input_node.description
+                        );
+                    }
+                }
+                if !self.options.best_effort {
+                    error!(
+                        "Not linking [Serial: {}] to [Serial: {}] until every explicit link resolves, or pass --best-effort",
+                        input_node.serial, output_node.serial
+                    );
+                    continue;
+                }
+            }
+            if plan.links.is_empty() {
+                continue;
+            }
+            if !self.handle_foreign_links(input_node, &output_node) {
+                fed_sinks.extend(plan.links.iter().map(|planned| planned.link.input_port));
+                continue;
+            }
+            let mut props = self.pair_properties(input_node, &output_node, route);
+            for planned in plan.links {
+                debug!(
+                    "Paired {:?} -> {:?} by {}",
+                    planned.source_channel, planned.sink_channel, planned.paired_by
+                );
+                let link = planned.link;
+                let ports = (link.output_port, link.input_port);
+                let step = match self.graph.lock() {
+                    Ok(graph) => self.links.step(&link, &graph),
+                    Err(_) => LinkStep::Create,
+                };
+                if step == LinkStep::Kept {
+                    fed_sinks.insert(link.input_port);
+                    continue;
+                }
+                // Recreated as often as the watchdog allows: another client wants it gone
+                if self.watchdog.exhausted(&ports, Instant::now()) {
+                    debug!(
+                        "Channel {}: removed too often by another client, leaving it out for now",
+                        link.channel
+                    );
+                    continue;
+                }
+                // Another client, or an earlier run, already linked these ports: a second link
+                // would double the signal
+                if let LinkStep::Adopt(link_id) = step {
+                    info!(
+                        "Channel {}: link already present, adopting [ID: {}]",
+                        link.channel, link_id
+                    );
+                    fed_sinks.insert(link.input_port);
+                    self.links.adopt(link_id, link);
+                    adopted += 1;
+                    continue;
+                }
+                let input_port = link.input_port;
+                if self.staggered() {
+                    fed_sinks.insert(input_port);
+                    if !self.staged.contains(&ports) {
+                        self.stage_link(link);
+                        created += 1;
+                    }
+                    continue;
+                }
+                let retry = link.clone();
+                match self.create_link(link, &mut props) {
+                    Ok(()) => {
+                        fed_sinks.insert(input_port);
+                        created += 1;
+                    }
+                    Err(MixerError::LinkFailed { channel, source }) => {
+                        error!(
+                            "Failed to create link for channel {}: {:?}",
+                            channel, source
+                        );
+                        self.schedule_retry(retry);
+                        failed += 1;
+                    }
+                    Err(error) => {
+                        warn!("{}", error);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+
+        // Every output of a rule is linked on its own, so a failure here leaves the others alone
+        let adopted_note = if adopted > 0 {
+            format!(", {} adopted", adopted)
+        } else {
+            String::new()
+        };
+        if failed > 0 {
+            warn!(
+                "Output [Serial: {}, {}]: {} channel(s) linked{}, {} failed",
+                output_node.serial, output_node.description, created, adopted_note, failed
+            );
+        } else if created > 0 || adopted > 0 {
+            info!(
+                "Output [Serial: {}, {}]: {} channel(s) linked{}",
+                output_node.serial, output_node.description, created, adopted_note
+            );
+        }
+
+        // Per-input plans only see their own links, so report the output as a whole
+        let silent: Vec<&str> = output_node
+            .ports
+            .iter()
+            .filter(|port| port.direction == "in" && !fed_sinks.contains(&port.serial))
+            .filter(|port| {
+                route.output_channels.is_empty() || route.output_channels.contains(&port.position())
+            })
+            .map(|port| port.channel.as_str())
+            .collect();
+        if !input_nodes.is_empty()
+            && !silent.is_empty()
+            && route.on_channel_mismatch != OnChannelMismatch::Truncate
+        {
+            info!(
+                "Output [Serial: {}, {}] left unconnected: channels [{}]",
+                output_node.serial,
+                output_node.description,
+                silent.join(", ")
+            );
+        }
+        (created, failed)
+    }
+
+    /// Apply the foreign link policy to the links already made from an input to the output
+    ///
+    /// Returns false if the pair is left to those links
+    fn handle_foreign_links(&self, input_node: &NodeInfo, output_node: &NodeInfo) -> bool {
+        let policy = foreign_policy(&self.options);
+        if policy == ForeignLinks::Ignore {
+            return true;
+        }
+        let foreign: Vec<(u32, String)> = {
+            let Ok(graph) = self.graph.lock() else {
+                return true;
+            };
+            let own = self.links.own_port_pairs();
+            graph
+                .foreign_links_between(input_node.serial, output_node.serial, &own)
+                .into_iter()
+                .map(|(id, link)| (id, graph.link_client(link)))
+                .collect()
+        };
+        if foreign.is_empty() {
+            return true;
+        }
+        if policy == ForeignLinks::Respect {
+            info!(
+                "[Serial: {}, {}]=>[Serial: {}, {}] is already linked by {}, leaving it alone",
+                input_node.serial,
+                input_node.description,
+                output_node.serial,
+                output_node.description,
+                foreign[0].1
+            );
+            return false;
+        }
+        for (link_id, client) in foreign {
+            info!("Replacing link [ID: {}] made by {}", link_id, client);
+            if let Err(error) = self.registry.destroy_global(link_id).into_result() {
+                warn!("Failed to remove link [ID: {}]: {:?}", link_id, error);
+            } else if let Ok(mut graph) = self.graph.lock() {
+                // Not there to adopt, even before the daemon confirms the removal
+                graph.remove_global(link_id);
+            }
+        }
+        true
+    }
+
+    /// Drop the bookkeeping for links that went away along with a node or port
+    pub fn forget(&mut self, removed: Removed) {
+        self.drop_links("endpoint removed", |link| link.involves(removed.serial()));
+        self.links
+            .retain_adopted(|link| !link.involves(removed.serial()));
+        self.retries
+            .retain(|_, link| !link.involves(removed.serial()));
+        self.staged
+            .retain(|_, link| !link.involves(removed.serial()));
+        let serial = removed.serial();
+        self.gates.remove(&serial);
+        // A stream takes its metadata along, one left pointing at a vanished output is reset
+        self.targets.remove(&serial);
+        let orphaned: Vec<Serial> = self
+            .targets
+            .iter()
+            .filter(|(_, (_, output))| *output == serial)
+            .map(|(stream, _)| *stream)
+            .collect();
+        for stream in orphaned {
+            self.clear_target(stream);
+        }
+        self.watchdog.forget(serial);
+    }
+
+    /// Properties shared by every link from a mixer input to its output, so that only the port
+    /// ids are filled in per channel
+    fn pair_properties(
+        &self,
+        input_node: &NodeInfo,
+        output_node: &NodeInfo,
+        route: &Route,
+    ) -> PropertiesBox {
+        let mut props = PropertiesBox::new();
+        for (key, value) in pair_entries(input_node, output_node, route, &self.options) {
+            props.insert(key, value);
+        }
+        props
+    }
+
+    /// Request one link, setting its port ids on the properties of its node pair
+    fn create_link(
+        &mut self,
+        link: ActiveLink,
+        props: &mut PropertiesBox,
+    ) -> Result<(), MixerError> {
+        // Translate serials to global ids only now, as ids may have been recycled since discovery.
+        // The node ids were resolved from the same graph state when `props` was built
+        let Some((_, output_port_id, _, input_port_id)) = self
+            .graph
+            .lock()
+            .ok()
+            .and_then(|graph| graph.link_ids(&link))
+        else {
+            return Err(MixerError::PortVanished {
+                channel: link.channel,
+            });
+        };
+        debug!(
+            "Linking channel {}: [{}]->[{}] (serials [{}]->[{}])",
+            link.channel, output_port_id, input_port_id, link.output_port, link.input_port
+        );
+        props.insert(
+            *pipewire::keys::LINK_OUTPUT_PORT,
+            output_port_id.to_string(),
+        );
+        props.insert(*pipewire::keys::LINK_INPUT_PORT, input_port_id.to_string());
+        match self
+            .watchdog
+            .recreated(&(link.output_port, link.input_port))
+        {
+            Some(recreated) => props.insert(RECREATED_KEY, recreated.to_string()),
+            None => props.remove(RECREATED_KEY),
+        }
+        let factory = self
+            .options
+            .link_factory
+            .as_deref()
+            .unwrap_or(DEFAULT_LINK_FACTORY);
+        match self
+            .core
+            .create_object::<pipewire::link::Link>(factory, &*props)
+        {
+            Ok(proxy) => {
+                self.retries.resolve(&(link.output_port, link.input_port));
+                let state = Rc::new(RefCell::new(None));
+                let listener = watch_link(
+                    &proxy,
+                    link.clone(),
+                    self.graph.clone(),
+                    state.clone(),
+                    self.link_failures.clone(),
+                );
+                self.links.insert(
+                    (link.output_port, link.input_port),
+                    ManagedLink {
+                        _listener: listener,
+                        _proxy: proxy,
+                        state,
+                    },
+                );
+                if let Ok(mut active) = self.active_links.lock() {
+                    active.push(link);
+                }
+                Ok(())
+            }
+            Err(source) => Err(MixerError::LinkFailed {
+                channel: link.channel,
+                source,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{sink, source};
+
+    #[test]
+    fn pair_entries_make_the_input_the_link_output_node() {
+        let input_node = source(31, &["FL", "FR"]);
+        let output_node = sink(57, &["FL", "FR"]);
+        let entries = pair_entries(
+            &input_node,
+            &output_node,
+            &Route::default(),
+            &LinkOptions::default(),
+        );
+        let value = |key: &str| {
+            entries
+                .iter()
+                .find(|(entry, _)| *entry == key)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value(*pipewire::keys::LINK_OUTPUT_NODE), Some("31"));
+        assert_eq!(value(*pipewire::keys::LINK_INPUT_NODE), Some("57"));
+        assert_eq!(value(*pipewire::keys::LINK_PASSIVE), Some("false"));
+    }
+
+    #[test]
+    fn appending_respects_foreign_links_it_would_replace() {
+        let options = |foreign_links, append_to_existing| LinkOptions {
+            foreign_links,
+            append_to_existing,
+            ..LinkOptions::default()
+        };
+        for policy in [
+            ForeignLinks::Ignore,
+            ForeignLinks::Respect,
+            ForeignLinks::Replace,
+        ] {
+            assert_eq!(foreign_policy(&options(policy, false)), policy);
+        }
+        assert_eq!(
+            foreign_policy(&options(ForeignLinks::Replace, true)),
+            ForeignLinks::Respect
+        );
+        assert_eq!(
+            foreign_policy(&options(ForeignLinks::Ignore, true)),
+            ForeignLinks::Ignore
+        );
+    }
+
+    fn stereo_graph() -> Graph {
+        let mut graph = Graph::default();
+        graph.add_node(source(1, &["FL", "FR"]));
+        graph.add_node(source(3, &["MONO"]));
+        graph.add_node(source(4, &["FL", "FR", "FC", "LFE", "RL", "RR"]));
+        graph.add_node(sink(2, &["FL", "FR"]));
+        graph
+    }
+
+    fn routed(inputs: Vec<Serial>) -> Route {
+        Route {
+            inputs,
+            output: 2,
+            ..Route::default()
+        }
+    }
+
+    #[test]
+    fn half_linked_stereo_inputs_are_incomplete() {
+        let graph = stereo_graph();
+        let route = routed(vec![1]);
+        let check = |linked: &[Serial]| {
+            let linked: HashSet<Serial> = linked.iter().copied().collect();
+            incomplete_stereo(&route, &graph, &linked, |_| true)
+        };
+        assert_eq!(check(&[100]), ["[Serial: 1, Source 1] (only FL linked)"]);
+        assert_eq!(check(&[101]), ["[Serial: 1, Source 1] (only FR linked)"]);
+        assert_eq!(check(&[]), ["[Serial: 1, Source 1] (no channel linked)"]);
+        assert!(check(&[100, 101]).is_empty());
+    }
+
+    #[test]
+    fn only_stereo_inputs_are_checked() {
+        let graph = stereo_graph();
+        let route = routed(vec![3, 4]);
+        assert!(incomplete_stereo(&route, &graph, &HashSet::from([300, 400]), |_| true).is_empty());
+        // Inputs not expected to be linked yet are left out
+        let route = routed(vec![1]);
+        assert!(incomplete_stereo(&route, &graph, &HashSet::new(), |_| false).is_empty());
+    }
+
+    #[test]
+    fn routes_choosing_their_channels_are_not_checked() {
+        let graph = stereo_graph();
+        let linked = HashSet::from([100]);
+        let chosen = Route {
+            input_channels: vec![Channel::FrontLeft],
+            ..routed(vec![1])
+        };
+        assert!(incomplete_stereo(&chosen, &graph, &linked, |_| true).is_empty());
+        let explicit = Route {
+            links: vec![ExplicitLink::default()],
+            ..routed(vec![1])
+        };
+        assert!(incomplete_stereo(&explicit, &graph, &linked, |_| true).is_empty());
+    }
+
+    fn link(output_node: Serial, input_node: Serial, channel: &str) -> ActiveLink {
+        ActiveLink {
+            channel: channel.to_string(),
+            output_node,
+            output_port: output_node * 100 + channel.len() as Serial,
+            input_node,
+            input_port: input_node * 100 + channel.len() as Serial,
+        }
+    }
+
+    #[test]
+    fn links_are_taken_with_the_list_unlocked_afterwards() {
+        let active_links = Mutex::new(vec![link(1, 3, "FL"), link(1, 3, "MONO"), link(2, 3, "FL")]);
+        let dropped = take_links(&active_links, |link| link.output_node == 1);
+        assert_eq!(dropped.len(), 2);
+        // A node removed while the dropped proxies go (e.g. from a registry callback the drop
+        // lets run) forgets its links from the same list, which must not be locked
+        let mut forgotten = Vec::new();
+        for _ in &dropped {
+            assert!(active_links.try_lock().is_ok());
+            forgotten.extend(take_links(&active_links, |link| link.involves(2)));
+        }
+        assert_eq!(forgotten.len(), 1);
+        assert!(active_links.lock().unwrap().is_empty());
+    }
+}
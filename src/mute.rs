@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use pipewire::spa::param::ParamType;
+use tracing::{debug, info, warn};
+
+use pie_mixer::config::{Rule, RuleInput};
+use pie_mixer::graph::{Graph, NodeInfo, Serial};
+use pie_mixer::matcher::{match_node, MatchOptions, Side};
+use pie_mixer::pod;
+
+/// An input set to zero volume, with the volumes it had before
+struct Muted {
+    description: String,
+    _listener: pipewire::node::NodeListener, // Registered on the proxy, so dropped first
+    proxy: pipewire::node::Node,
+    previous: Rc<RefCell<Option<Vec<f32>>>>, // Filled in once the node answers
+}
+
+/// Routed inputs matching a `--mute` pattern, linked at zero volume
+///
+/// Each input's volumes are read before it is silenced and given back once it is no longer
+/// routed, or by [`Muter::restore_all`] when the mixer stops
+pub struct Muter {
+    registry: pipewire::registry::RegistryRc,
+    patterns: Vec<String>,
+    options: MatchOptions,
+    muted: HashMap<Serial, Muted>,
+    released: Vec<Muted>, // Restored, kept until their request has been sent
+}
+
+impl Muter {
+    pub fn new(
+        registry: pipewire::registry::RegistryRc,
+        patterns: Vec<String>,
+        options: MatchOptions,
+    ) -> Self {
+        Muter {
+            registry,
+            patterns,
+            options,
+            muted: HashMap::new(),
+            released: Vec::new(),
+        }
+    }
+
+    /// Mute the routed inputs matching a pattern that are not muted yet, and give the inputs
+    /// no longer routed their volumes back
+    ///
+    /// Called whenever the routes change, so inputs that appear later or are routed by a reload
+    /// are muted as well. Returns the number of inputs muted
+    pub fn sync(&mut self, graph: &Mutex<Graph>, routed: &[Serial]) -> usize {
+        if self.patterns.is_empty() {
+            return 0;
+        }
+        // Restored inputs have had a loop iteration to send their request by now
+        self.released.clear();
+        let Ok(graph) = graph.lock() else {
+            return 0;
+        };
+        let unrouted: Vec<Serial> = self
+            .muted
+            .keys()
+            .filter(|serial| !routed.contains(serial))
+            .copied()
+            .collect();
+        for serial in unrouted {
+            if let Some(muted) = self.muted.remove(&serial) {
+                // A node that is gone took its volume along
+                if graph.node(serial).is_some() {
+                    restore(serial, &muted);
+                    self.released.push(muted);
+                }
+            }
+        }
+        let mut count = 0;
+        for serial in routed {
+            if self.muted.contains_key(serial) {
+                continue;
+            }
+            let Some(node) = graph.node(*serial) else {
+                continue;
+            };
+            if !self.matches(node) {
+                continue;
+            }
+            if let Some(muted) = self.mute(node) {
+                self.muted.insert(*serial, muted);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Give every muted input its volumes back
+    ///
+    /// The requests are only sent once the loop runs again, e.g. through a roundtrip. Returns
+    /// the number of inputs restored
+    pub fn restore_all(&mut self) -> usize {
+        let muted: Vec<(Serial, Muted)> = self.muted.drain().collect();
+        for (serial, muted) in &muted {
+            restore(*serial, muted);
+        }
+        let count = muted.len();
+        self.released
+            .extend(muted.into_iter().map(|(_, muted)| muted));
+        count
+    }
+
+    fn matches(&self, node: &NodeInfo) -> bool {
+        self.patterns.iter().any(|pattern| {
+            let rule = Rule {
+                input: RuleInput::Pattern(pattern.clone()),
+                ..Rule::default()
+            };
+            match_node(&rule, Side::Input, node, self.options).matched()
+        })
+    }
+
+    /// Ask for the node's volumes, then set every channel to zero
+    ///
+    /// The daemon handles requests in order, so the volumes reported are those from before
+    fn mute(&self, node: &NodeInfo) -> Option<Muted> {
+        let channels = node
+            .ports
+            .iter()
+            .filter(|port| port.direction == "out" && !port.monitor)
+            .count()
+            .max(1);
+        let Some(bytes) = pod::channel_volumes(&vec![0.0; channels]) else {
+            warn!(
+                "Failed to build the volume param for [Serial: {}]",
+                node.serial
+            );
+            return None;
+        };
+        let global = pipewire::registry::GlobalObject::<&pipewire::spa::utils::dict::DictRef> {
+            id: node.global_id,
+            permissions: pipewire::permissions::PermissionFlags::empty(),
+            type_: pipewire::types::ObjectType::Node,
+            version: 0,
+            props: None,
+        };
+        let proxy = match self.registry.bind::<pipewire::node::Node, _>(&global) {
+            Ok(proxy) => proxy,
+            Err(error) => {
+                warn!(
+                    "Failed to bind node [Serial: {}] to mute it: {:?}",
+                    node.serial, error
+                );
+                return None;
+            }
+        };
+        let previous: Rc<RefCell<Option<Vec<f32>>>> = Rc::default();
+        let previous_read = previous.clone();
+        let listener = proxy
+            .add_listener_local()
+            .param(move |_seq, _id, _index, _next, param| {
+                let mut previous = previous_read.borrow_mut();
+                if previous.is_none() {
+                    *previous = param.and_then(pod::parse_channel_volumes);
+                }
+            })
+            .register();
+        proxy.enum_params(0, Some(ParamType::Props), 0, u32::MAX);
+        let param = pipewire::spa::pod::Pod::from_bytes(&bytes)?;
+        proxy.set_param(ParamType::Props, 0, param);
+        info!(
+            "Muted input [Serial: {}, {}]: linked, with its {} channel(s) at zero volume",
+            node.serial, node.description, channels
+        );
+        Some(Muted {
+            description: node.description.clone(),
+            _listener: listener,
+            proxy,
+            previous,
+        })
+    }
+}
+
+/// Send the volumes an input had before it was muted
+fn restore(serial: Serial, muted: &Muted) {
+    let Some(volumes) = muted.previous.borrow().clone() else {
+        warn!(
+            "Volume of [Serial: {}, {}] was never reported, leaving it muted",
+            serial, muted.description
+        );
+        return;
+    };
+    let Some(bytes) = pod::channel_volumes(&volumes) else {
+        return;
+    };
+    if let Some(param) = pipewire::spa::pod::Pod::from_bytes(&bytes) {
+        muted.proxy.set_param(ParamType::Props, 0, param);
+        debug!(
+            "Restored the volume of [Serial: {}, {}] to {:?}",
+            serial, muted.description, volumes
+        );
+    }
+}
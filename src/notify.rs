@@ -0,0 +1,29 @@
+use std::process::Command;
+
+use tracing::warn;
+
+/// Show a desktop notification through `notify-send`, which talks to the notification daemon
+/// over D-Bus
+fn send(summary: &str, body: &str) {
+    let result = Command::new("notify-send")
+        .args(["--app-name=pie_mixer", summary, body])
+        .status();
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("notify-send failed: {}", status),
+        Err(error) => warn!("Failed to run notify-send: {:?}", error),
+    }
+}
+
+/// Report the links created for the selected outputs
+pub fn linked(created: usize, outputs: usize) {
+    send(
+        "pie_mixer: mixing",
+        &format!("Created {} link(s) to {} output(s)", created, outputs),
+    );
+}
+
+/// Report why the mixer gave up
+pub fn failed(error: &anyhow::Error) {
+    send("pie_mixer: failed", &format!("{:#}", error));
+}
@@ -0,0 +1,176 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{anyhow, Result};
+
+use pie_mixer::graph::NodeInfo;
+
+/// Whether someone is at a terminal to answer the prompts
+pub fn available() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Ask which inputs to mix into which output, listing the candidates with numbers
+///
+/// Returns the chosen inputs and output, after printing the flags and config rules that
+/// would select them without asking
+pub fn pick<'a>(
+    nodes: &[&'a NodeInfo],
+    include_virtual: bool,
+) -> Result<(Vec<&'a NodeInfo>, &'a NodeInfo)> {
+    let inputs: Vec<&NodeInfo> = nodes
+        .iter()
+        .filter(|node| node.acts_as(true, include_virtual))
+        .cloned()
+        .collect();
+    let outputs: Vec<&NodeInfo> = nodes
+        .iter()
+        .filter(|node| node.acts_as(false, include_virtual))
+        .cloned()
+        .collect();
+    if inputs.is_empty() || outputs.is_empty() {
+        return Err(anyhow!(
+            "Nothing to pick from: no inputs or no outputs were discovered"
+        ));
+    }
+
+    let mut lines = io::stdin().lock().lines();
+    println!("Inputs:");
+    list(&inputs);
+    let chosen = loop {
+        let answer = prompt(&mut lines, "Inputs to mix (numbers separated by spaces): ")?;
+        match parse_choices(&answer, inputs.len()) {
+            Ok(choices) if !choices.is_empty() => break choices,
+            Ok(_) => println!("Pick at least one input"),
+            Err(error) => println!("{}", error),
+        }
+    };
+    let chosen_inputs: Vec<&NodeInfo> = chosen.into_iter().map(|index| inputs[index]).collect();
+
+    println!("Outputs:");
+    list(&outputs);
+    let output = loop {
+        let answer = prompt(&mut lines, "Output: ")?;
+        match parse_choices(&answer, outputs.len()) {
+            Ok(choices) if choices.len() == 1 => break outputs[choices[0]],
+            Ok(_) => println!("Pick exactly one output"),
+            Err(error) => println!("{}", error),
+        }
+    };
+
+    print_equivalents(&chosen_inputs, output);
+    Ok((chosen_inputs, output))
+}
+
+fn list(nodes: &[&NodeInfo]) {
+    for (number, node) in nodes.iter().enumerate() {
+        println!(
+            "  {:2}) [Serial: {:4}] {}",
+            number + 1,
+            node.serial,
+            node.description
+        );
+    }
+}
+
+fn prompt(lines: &mut impl Iterator<Item = io::Result<String>>, question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    match lines.next() {
+        Some(line) => Ok(line?),
+        None => Err(anyhow!("No selection made")),
+    }
+}
+
+/// Parse 1-based choices like "1 3" or "1,3" into distinct 0-based indices, in order given
+fn parse_choices(answer: &str, count: usize) -> Result<Vec<usize>, String> {
+    let mut choices = Vec::new();
+    for part in answer
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+    {
+        let index = part
+            .parse::<usize>()
+            .ok()
+            .filter(|number| (1..=count).contains(number))
+            .ok_or_else(|| format!("Expected numbers from 1 to {}, got {:?}", count, part))?
+            - 1;
+        if !choices.contains(&index) {
+            choices.push(index);
+        }
+    }
+    Ok(choices)
+}
+
+/// Print the flags selecting the same nodes in this session, and config rules matching them by
+/// description, which survive restarts
+fn print_equivalents(inputs: &[&NodeInfo], output: &NodeInfo) {
+    let flags: Vec<String> = inputs
+        .iter()
+        .map(|node| format!("--input-serial {}", node.serial))
+        .chain([format!("--output-serial {}", output.serial)])
+        .collect();
+    println!();
+    println!(
+        "Same selection while these nodes exist: {}",
+        flags.join(" ")
+    );
+    println!("To keep it, add to the config file:");
+    println!();
+    let descriptions: Vec<&str> = inputs
+        .iter()
+        .map(|node| node.description.as_str())
+        .collect();
+    println!("{}", config_rules(&descriptions, &output.description));
+}
+
+/// One `[[rule]]` per input, serialized by `toml` so any description comes out as a valid
+/// TOML string
+fn config_rules(inputs: &[&str], output: &str) -> String {
+    let rules: Vec<toml::Value> = inputs
+        .iter()
+        .map(|input| {
+            toml::Value::Table(toml::Table::from_iter([
+                ("input".to_string(), toml::Value::from(*input)),
+                ("output".to_string(), toml::Value::from(output)),
+            ]))
+        })
+        .collect();
+    let config = toml::Table::from_iter([("rule".to_string(), toml::Value::Array(rules))]);
+    toml::to_string(&config).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use pie_mixer::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn choices_are_distinct_and_in_range() {
+        assert_eq!(parse_choices("1 3", 3), Ok(vec![0, 2]));
+        assert_eq!(parse_choices("1,3", 3), Ok(vec![0, 2]));
+        assert_eq!(parse_choices(" 3, 1 ", 3), Ok(vec![2, 0]));
+        assert_eq!(parse_choices("2 2,2", 3), Ok(vec![1]));
+        assert_eq!(parse_choices("", 3), Ok(Vec::new()));
+        for answer in ["0", "4", "1 4", "-1", "one"] {
+            assert!(
+                parse_choices(answer, 3).is_err(),
+                "{:?} was accepted",
+                answer
+            );
+        }
+    }
+
+    #[test]
+    fn suggested_rules_parse_as_config() {
+        let inputs = ["Büro \"Desk\" Mic", "C:\\Line\tIn", "Zero\u{200b}Width"];
+        let output = "Speakers [HDMI]";
+        let text = config_rules(&inputs, output);
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(config.rules.len(), inputs.len());
+        for (rule, input) in config.rules.iter().zip(inputs) {
+            assert_eq!(rule.input.pattern(), input);
+            assert_eq!(rule.output, output);
+        }
+    }
+}
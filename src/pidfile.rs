@@ -0,0 +1,72 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+use crate::failure::Failure;
+
+/// A locked pidfile holding the process id, removed again when dropped
+///
+/// The lock, not the file's existence, marks a running instance: a pidfile left behind by a
+/// crash is unlocked and simply taken over
+pub struct Pidfile {
+    path: PathBuf,
+    _file: File, // Keeps the lock until the file is closed
+}
+
+impl Pidfile {
+    /// Lock the pidfile and write our process id, failing if another instance holds the lock
+    pub fn acquire(path: &Path) -> Result<Pidfile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|error| anyhow!("Failed to open pidfile {}: {:?}", path.display(), error))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                let holder = match pid.trim() {
+                    "" => "Another process".to_string(),
+                    pid => format!("PID {}", pid),
+                };
+                return Err(anyhow!("{} holds the lock on {}", holder, path.display())
+                    .context(Failure::AlreadyRunning));
+            }
+            Err(TryLockError::Error(error)) => {
+                return Err(anyhow!(
+                    "Failed to lock pidfile {}: {:?}",
+                    path.display(),
+                    error
+                ));
+            }
+        }
+        // Only truncate once locked, so a running instance's pid is never wiped
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Pidfile {
+            path: path.to_path_buf(),
+            _file: file,
+        })
+    }
+}
+
+impl Drop for Pidfile {
+    /// Remove the file on shutdown; the lock goes with it when the file is closed
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove pidfile {}: {:?}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
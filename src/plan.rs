@@ -0,0 +1,1526 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::channel::{channel_from_port_name, Channel};
+use crate::graph::{ActiveLink, Media, NodeInfo, PortInfo, Serial};
+use crate::mixer::Route;
+use crate::pod::RateRange;
+
+/// How the ports of an input are matched up with the ports of an output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Pairing {
+    /// Only pair ports carrying the same channel (FL->FL, FR->FR, etc)
+    Name,
+    /// Pair the ports in order, ignoring their channel names
+    Position,
+    /// Pair by channel name, falling back to position when no names line up
+    #[default]
+    NameThenPosition,
+    /// Pair by channel name, then pair the ports left over in the order the node lists them,
+    /// for filter nodes with ports like `input_1` that carry no channel names
+    Order,
+}
+
+/// Which rule paired two ports, so surprising links can be traced back
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PairedBy {
+    Name,
+    NormalizedName,
+    ChannelMap,
+    AuxOffset,
+    Position,
+    MonoToStereo,
+    StereoToMono,
+    Downmix,
+    Upmix,
+    SwapLr,
+    Explicit,
+    Order,
+}
+
+impl fmt::Display for PairedBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PairedBy::Name => "name",
+            PairedBy::NormalizedName => "normalized name",
+            PairedBy::ChannelMap => "channel map",
+            PairedBy::AuxOffset => "AUX offset",
+            PairedBy::Position => "position",
+            PairedBy::MonoToStereo => "mono to stereo",
+            PairedBy::StereoToMono => "stereo to mono",
+            PairedBy::Downmix => "downmix",
+            PairedBy::Upmix => "upmix",
+            PairedBy::SwapLr => "swapped left/right",
+            PairedBy::Explicit => "explicit link",
+            PairedBy::Order => "port order",
+        })
+    }
+}
+
+/// Built-in map folding channels the output lacks into the ones it has
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Downmix {
+    /// FC and RC to both FL and FR, rear and side channels to their side; LFE is dropped
+    Stereo,
+    /// Like `stereo`, with LFE sent to both FL and FR as well
+    StereoLfe,
+}
+
+impl Downmix {
+    /// The output channels a source channel folds into, empty if it is dropped
+    fn targets(self, channel: &Channel) -> Vec<Channel> {
+        match channel {
+            Channel::FrontCenter | Channel::RearCenter | Channel::Mono => {
+                vec![Channel::FrontLeft, Channel::FrontRight]
+            }
+            Channel::Lfe if self == Downmix::StereoLfe => {
+                vec![Channel::FrontLeft, Channel::FrontRight]
+            }
+            Channel::RearLeft | Channel::SideLeft => vec![Channel::FrontLeft],
+            Channel::RearRight | Channel::SideRight => vec![Channel::FrontRight],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Built-in map copying channels of the input onto output channels it has no source for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Upmix {
+    /// FL also to RL and FR also to RR, so the rear speakers play the front pair
+    CopyRears,
+}
+
+impl Upmix {
+    /// The extra output channels a source channel is copied to
+    fn targets(self, channel: &Channel) -> Vec<Channel> {
+        match channel {
+            Channel::FrontLeft => vec![Channel::RearLeft],
+            Channel::FrontRight => vec![Channel::RearRight],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// What to do when an input and its output do not have the same channels, leaving source
+/// channels unrouted or destination channels silent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnChannelMismatch {
+    /// Link what pairs and say nothing about the rest
+    Truncate,
+    /// Link what pairs and log one summary of the rest
+    #[default]
+    Warn,
+    /// Link nothing for the rule
+    Error,
+}
+
+/// One entry of a rule's `links`, naming both ports outright
+///
+/// Each side is a channel or port name (`from`/`to`, normalized like `playback_AUX3` to `AUX3`)
+/// or a port's global id (`from_port`/`to_port`)
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExplicitLink {
+    pub from: Option<String>,
+    pub from_port: Option<u32>,
+    pub to: Option<String>,
+    pub to_port: Option<u32>,
+}
+
+/// A link that would be created, and why its ports were paired
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedLink {
+    pub link: ActiveLink,
+    pub source_channel: String,
+    pub sink_channel: String,
+    pub paired_by: PairedBy,
+}
+
+/// Outcome of pairing the ports of one input with the ports of one output
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LinkPlan {
+    pub links: Vec<PlannedLink>,
+    pub unmatched_sources: Vec<String>, // Source channels without a partner
+    pub unused_sinks: Vec<String>,      // Sink channels nothing would be linked to
+    pub unresolved: Vec<String>,        // Explicit links naming ports that do not exist
+}
+
+impl LinkPlan {
+    /// True if every source channel found a partner and every sink channel is fed
+    pub fn is_complete(&self) -> bool {
+        !self.links.is_empty()
+            && self.unmatched_sources.is_empty()
+            && self.unused_sinks.is_empty()
+            && self.unresolved.is_empty()
+    }
+
+    /// The channels left out on either side, e.g. "6 source channel(s) unrouted: FC, LFE, RL,
+    /// RR, SL, SR", or None if every channel is paired
+    pub fn mismatch_summary(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.unmatched_sources.is_empty() {
+            parts.push(format!(
+                "{} source channel(s) unrouted: {}",
+                self.unmatched_sources.len(),
+                self.unmatched_sources.join(", ")
+            ));
+        }
+        if !self.unused_sinks.is_empty() {
+            parts.push(format!(
+                "{} destination channel(s) silent: {}",
+                self.unused_sinks.len(),
+                self.unused_sinks.join(", ")
+            ));
+        }
+        (!parts.is_empty()).then(|| parts.join("; "))
+    }
+}
+
+/// Which channels of `input` would pair with `output` under the default pairing, without
+/// creating anything
+pub fn compatibility(input: &NodeInfo, output: &NodeInfo) -> LinkPlan {
+    plan_links(input, output, &Route::default(), Pairing::default())
+}
+
+/// Pair ports by direction: Outbound from Source to Inbound at Sink
+///
+/// Pure function of the node snapshots, shared by dry runs and actual linking
+pub fn plan_links(
+    input_node: &NodeInfo,
+    output_node: &NodeInfo,
+    route: &Route,
+    pairing: Pairing,
+) -> LinkPlan {
+    let src_ports: Vec<&PortInfo> = input_node
+        .ports
+        .iter()
+        .filter(|port| port.direction == "out" && (route.include_monitors || !port.monitor))
+        .filter(|port| port.midi == (route.media == Media::Midi))
+        .filter(|port| selected(&route.input_channels, port))
+        .collect();
+    let snk_ports: Vec<&PortInfo> = output_node
+        .ports
+        .iter()
+        .filter(|port| port.direction == "in")
+        .filter(|port| port.midi == (route.media == Media::Midi))
+        .filter(|port| selected(&route.output_channels, port))
+        .collect();
+
+    let mut unresolved = Vec::new();
+    let pairs = if !route.links.is_empty() {
+        let (pairs, failures) = pair_explicit(&src_ports, &snk_ports, &route.links);
+        unresolved = failures;
+        pairs
+    } else if route.media != Media::Audio {
+        // Video and MIDI ports carry no channels: pair the port names that line up, then the
+        // rest in order, which links the single port most such nodes have on each side
+        let mut pairs = pair_by_name(&src_ports, &snk_ports, route);
+        let remaining = pair_by_order(&src_ports, &snk_ports, &pairs);
+        pairs.extend(remaining);
+        pairs
+    } else if let Some(pairs) = pair_mono_to_stereo(&src_ports, &snk_ports, route) {
+        pairs
+    } else if let Some(pairs) = pair_stereo_to_mono(&src_ports, &snk_ports, route) {
+        pairs
+    } else if pairing == Pairing::Order {
+        let mut pairs = pair_by_name(&src_ports, &snk_ports, route);
+        let remaining = pair_by_order(&src_ports, &snk_ports, &pairs);
+        pairs.extend(remaining);
+        pairs
+    } else if pairing == Pairing::Position {
+        pair_by_position(&src_ports, &snk_ports, route)
+    } else {
+        let pairs = pair_by_name(&src_ports, &snk_ports, route);
+        if pairing == Pairing::NameThenPosition && pairs.is_empty() {
+            pair_by_position(&src_ports, &snk_ports, route)
+        } else {
+            pairs
+        }
+    };
+
+    let is_paired = |port: &PortInfo, source: bool| {
+        pairs.iter().any(|(src_port, snk_port, _)| {
+            let paired = if source { src_port } else { snk_port };
+            paired.serial == port.serial
+        })
+    };
+    // Explicit links leave out what they do not name on purpose
+    let unmatched_sources = src_ports
+        .iter()
+        .filter(|port| route.links.is_empty() && !is_paired(port, true))
+        .map(|port| port.channel.clone())
+        .collect();
+    let unused_sinks = snk_ports
+        .iter()
+        .filter(|port| !is_paired(port, false))
+        .map(|port| port.channel.clone())
+        .collect();
+
+    let links = pairs
+        .iter()
+        .map(|(src_port, snk_port, paired_by)| PlannedLink {
+            link: ActiveLink {
+                channel: src_port.position().to_string(),
+                output_node: input_node.serial,
+                output_port: src_port.serial,
+                input_node: output_node.serial,
+                input_port: snk_port.serial,
+            },
+            source_channel: src_port.channel.clone(),
+            sink_channel: snk_port.channel.clone(),
+            paired_by: *paired_by,
+        })
+        .collect();
+
+    LinkPlan {
+        links,
+        unmatched_sources,
+        unused_sinks,
+        unresolved,
+    }
+}
+
+/// The `channel_map` entries of a route sending a channel to one the output has no port for,
+/// as `FROM:TO`
+pub fn missing_map_targets(route: &Route, output_node: &NodeInfo) -> Vec<String> {
+    let mut missing: Vec<String> = route
+        .channel_map
+        .iter()
+        .flat_map(|(from, targets)| targets.iter().map(move |to| (from, to)))
+        .filter(|(_, to)| {
+            !output_node
+                .ports
+                .iter()
+                .any(|port| port.direction == "in" && port.position() == **to)
+        })
+        .map(|(from, to)| format!("{}:{}", from, to))
+        .collect();
+    missing.sort();
+    missing
+}
+
+/// Formats an input and an output do not agree on, found before linking them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatMismatch {
+    pub rates: Option<(Vec<RateRange>, Vec<RateRange>)>, // Input and output rates with none in common
+    pub fixed_rate: bool, // The output is pinned to its rate through `audio.rate`
+    pub formats: Option<(Vec<String>, Vec<String>)>, // Input and output sample formats with none in common
+    pub resampled: bool,                             // False if either side sets `resample.disable`
+}
+
+impl fmt::Display for FormatMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |rates: &[RateRange]| {
+            rates
+                .iter()
+                .map(|rate| rate.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let mut parts = Vec::new();
+        if let Some((input, output)) = &self.rates {
+            parts.push(format!(
+                "{} Hz vs {}{} Hz, {}",
+                join(input),
+                if self.fixed_rate { "fixed " } else { "" },
+                join(output),
+                if self.resampled {
+                    "PipeWire will resample"
+                } else {
+                    "resampling is disabled, expect silence or noise"
+                }
+            ));
+        }
+        if let Some((input, output)) = &self.formats {
+            parts.push(format!(
+                "sample formats {} vs {}, PipeWire will convert",
+                input.join(","),
+                output.join(",")
+            ));
+        }
+        f.write_str(&parts.join("; "))
+    }
+}
+
+/// Compare the rates and sample formats of an input and an output, None if they can be linked
+/// as is
+///
+/// A node's `audio.rate` property pins it to that rate and takes precedence over the rates of
+/// its `EnumFormat` caps. Sides that advertise nothing are assumed to adapt.
+pub fn compare_formats(input_node: &NodeInfo, output_node: &NodeInfo) -> Option<FormatMismatch> {
+    let input_rates = node_rates(input_node);
+    let output_rates = node_rates(output_node);
+    let rates_disjoint = !input_rates.is_empty()
+        && !output_rates.is_empty()
+        && !input_rates
+            .iter()
+            .any(|rate| output_rates.iter().any(|o| rate.overlaps(o)));
+
+    let formats = |node: &NodeInfo| {
+        node.caps
+            .as_ref()
+            .map(|caps| caps.formats.clone())
+            .unwrap_or_default()
+    };
+    let (input_formats, output_formats) = (formats(input_node), formats(output_node));
+    let formats_disjoint = !input_formats.is_empty()
+        && !output_formats.is_empty()
+        && !input_formats.iter().any(|f| output_formats.contains(f));
+
+    if !rates_disjoint && !formats_disjoint {
+        return None;
+    }
+    let resample_disabled = |node: &NodeInfo| {
+        node.properties.get("resample.disable").map(String::as_str) == Some("true")
+    };
+    Some(FormatMismatch {
+        fixed_rate: rates_disjoint && fixed_rate(output_node).is_some(),
+        rates: rates_disjoint.then_some((input_rates, output_rates)),
+        formats: formats_disjoint.then_some((input_formats, output_formats)),
+        resampled: !resample_disabled(input_node) && !resample_disabled(output_node),
+    })
+}
+
+/// The rate set through the node's `audio.rate` property
+fn fixed_rate(node: &NodeInfo) -> Option<u32> {
+    node.properties.get("audio.rate")?.parse().ok()
+}
+
+/// The rates a node runs at: its fixed rate if any, otherwise those of its caps
+fn node_rates(node: &NodeInfo) -> Vec<RateRange> {
+    match fixed_rate(node) {
+        Some(rate) => vec![RateRange {
+            min: rate,
+            max: rate,
+        }],
+        None => node
+            .caps
+            .as_ref()
+            .map(|caps| caps.rates.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// True if the port carries one of `channels`, or `channels` does not restrict them
+fn selected(channels: &[Channel], port: &PortInfo) -> bool {
+    channels.is_empty() || channels.contains(&port.position())
+}
+
+/// Source port, sink port, and the strategy that paired them
+type PortPair<'a> = (&'a PortInfo, &'a PortInfo, PairedBy);
+
+/// Pair each source port with the sink port of the same channel position
+///
+/// Channel names are normalized first, so aliases like "FL" and "front-left" pair up.
+/// The route's channel map takes precedence and may send a channel to several sink ports, then
+/// AUX channels are shifted by its offset. Channels still without a partner are folded in by
+/// the route's downmix, if any, while the upmix copies paired channels onto output channels no
+/// source channel has. With `swap_lr`, FL and FR are crossed unless mapped explicitly
+fn pair_by_name<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    route: &Route,
+) -> Vec<PortPair<'a>> {
+    let src_channels: Vec<Channel> = src_ports.iter().map(|port| port.position()).collect();
+    let mut pairs = Vec::new();
+    for src_port in src_ports {
+        let channel = src_port.position();
+        // Buggy drivers may announce a channel twice: the nth such source port pairs with the
+        // nth sink port of its target channel, in port order, or the first if there are fewer
+        let occurrence = src_ports
+            .iter()
+            .filter(|other| other.position() == channel && port_order(other) < port_order(src_port))
+            .count();
+        let find = |target: &Channel| {
+            let mut matching: Vec<&'a PortInfo> = snk_ports
+                .iter()
+                .filter(|p| p.position() == *target)
+                .copied()
+                .collect();
+            matching.sort_by_key(|port| port_order(port));
+            matching.get(occurrence).or(matching.first()).copied()
+        };
+        let named = if route.swap_lr {
+            swap_lr(&channel)
+        } else {
+            channel.clone()
+        };
+        let (targets, paired_by) = match route.channel_map.get(&channel) {
+            Some(mapped) => (mapped.clone(), PairedBy::ChannelMap),
+            None if route.aux_offset != 0 && matches!(channel, Channel::Aux(_)) => (
+                channel.offset(route.aux_offset).into_iter().collect(),
+                PairedBy::AuxOffset,
+            ),
+            None if named != channel => (vec![named.clone()], PairedBy::SwapLr),
+            None => (vec![channel.clone()], PairedBy::Name),
+        };
+        // Find the destination ports that match the specific channel positions
+        let paired = pairs.len();
+        for snk_port in targets.iter().filter_map(find) {
+            let paired_by = if paired_by == PairedBy::Name && src_port.channel != snk_port.channel {
+                PairedBy::NormalizedName
+            } else {
+                paired_by
+            };
+            pairs.push((*src_port, snk_port, paired_by));
+        }
+        if !matches!(paired_by, PairedBy::Name | PairedBy::SwapLr) {
+            continue;
+        }
+        if pairs.len() > paired {
+            if let Some(upmix) = route.upmix {
+                let targets = upmix.targets(&named);
+                let free = targets
+                    .iter()
+                    .filter(|target| !src_channels.contains(target));
+                for snk_port in free.filter_map(find) {
+                    pairs.push((*src_port, snk_port, PairedBy::Upmix));
+                }
+            }
+        } else if let Some(downmix) = route.downmix {
+            for snk_port in downmix.targets(&channel).iter().filter_map(find) {
+                pairs.push((*src_port, snk_port, PairedBy::Downmix));
+            }
+        }
+    }
+    pairs
+}
+
+/// Pair exactly the ports a rule's `links` name, bypassing every automatic strategy
+///
+/// Returns the pairs, and a message naming the entry for each one that did not resolve
+fn pair_explicit<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    links: &[ExplicitLink],
+) -> (Vec<PortPair<'a>>, Vec<String>) {
+    let mut pairs = Vec::new();
+    let mut failures = Vec::new();
+    for (index, link) in links.iter().enumerate() {
+        let resolved = resolve_port(src_ports, link.from.as_deref(), link.from_port, "from")
+            .and_then(|src_port| {
+                let snk_port = resolve_port(snk_ports, link.to.as_deref(), link.to_port, "to")?;
+                Ok((src_port, snk_port))
+            });
+        match resolved {
+            Ok((src_port, snk_port)) => pairs.push((src_port, snk_port, PairedBy::Explicit)),
+            Err(error) => failures.push(format!("links[{}]: {}", index, error)),
+        }
+    }
+    (pairs, failures)
+}
+
+/// Find the port one side of an explicit link names, by channel or port name or by global id
+fn resolve_port<'a>(
+    ports: &[&'a PortInfo],
+    name: Option<&str>,
+    id: Option<u32>,
+    side: &str,
+) -> Result<&'a PortInfo, String> {
+    match (name, id) {
+        (Some(name), None) => {
+            let channel = Channel::parse(&channel_from_port_name(name));
+            ports
+                .iter()
+                .find(|port| port.position() == channel)
+                .copied()
+                .ok_or_else(|| format!("no port for channel {:?}", name))
+        }
+        (None, Some(id)) => ports
+            .iter()
+            .find(|port| port.global_id == id)
+            .copied()
+            .ok_or_else(|| format!("no port with id {}", id)),
+        (Some(_), Some(_)) => Err(format!("give either {} or {}_port, not both", side, side)),
+        (None, None) => Err(format!("missing {} or {}_port", side, side)),
+    }
+}
+
+/// Link a single source port to both front channels of the sink, so a mono source is heard
+/// on both speakers
+///
+/// Done for any source with exactly one port unless the route turns it off or maps that port
+/// explicitly. Returns None when it does not apply, e.g. the sink has no FL/FR pair
+fn pair_mono_to_stereo<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    route: &Route,
+) -> Option<Vec<PortPair<'a>>> {
+    let [src_port] = src_ports else {
+        return None;
+    };
+    // A port left over by a channel filter is one channel of a larger input, not a mono source
+    if route.mono_to_stereo == Some(false)
+        || route.channel_map.contains_key(&src_port.position())
+        || !route.input_channels.is_empty()
+    {
+        return None;
+    }
+    let find = |channel: Channel| {
+        snk_ports
+            .iter()
+            .find(|port| port.position() == channel)
+            .copied()
+    };
+    let left = find(Channel::FrontLeft)?;
+    let right = find(Channel::FrontRight)?;
+    Some(vec![
+        (*src_port, left, PairedBy::MonoToStereo),
+        (*src_port, right, PairedBy::MonoToStereo),
+    ])
+}
+
+/// Link every source port to the only port of a single-channel sink, which sums them
+///
+/// Done for any sink with exactly one port and a source with several, unless the route turns it
+/// off or maps channels explicitly. The sink port then carries several links
+fn pair_stereo_to_mono<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    route: &Route,
+) -> Option<Vec<PortPair<'a>>> {
+    let [snk_port] = snk_ports else {
+        return None;
+    };
+    if src_ports.len() < 2
+        || route.stereo_to_mono == Some(false)
+        || !route.channel_map.is_empty()
+        || !route.output_channels.is_empty()
+    {
+        return None;
+    }
+    Some(
+        src_ports
+            .iter()
+            .map(|src_port| (*src_port, *snk_port, PairedBy::StereoToMono))
+            .collect(),
+    )
+}
+
+/// Zip the ports in a deterministic order (channel name, then port id), ignoring their channels
+///
+/// Ports beyond the length of the shorter side are left unlinked. With `swap_lr`, sources zipped
+/// with FL or FR go to the other one instead
+fn pair_by_position<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    route: &Route,
+) -> Vec<PortPair<'a>> {
+    let sorted = |ports: &[&'a PortInfo]| {
+        let mut ports = ports.to_vec();
+        ports.sort_by(|a, b| {
+            a.channel
+                .cmp(&b.channel)
+                .then(a.global_id.cmp(&b.global_id))
+        });
+        ports
+    };
+    sorted(src_ports)
+        .into_iter()
+        .zip(sorted(snk_ports))
+        .map(|(src_port, snk_port)| {
+            let swapped = swap_lr(&snk_port.position());
+            let crossed = snk_ports
+                .iter()
+                .find(|port| port.position() == swapped)
+                .filter(|_| route.swap_lr && swapped != snk_port.position());
+            match crossed {
+                Some(crossed) => (src_port, *crossed, PairedBy::SwapLr),
+                None => (src_port, snk_port, PairedBy::Position),
+            }
+        })
+        .collect()
+}
+
+/// Zip the ports no other pair uses in the order their nodes list them (`port.id`, then
+/// discovery order), ignoring their channels
+fn pair_by_order<'a>(
+    src_ports: &[&'a PortInfo],
+    snk_ports: &[&'a PortInfo],
+    pairs: &[PortPair<'a>],
+) -> Vec<PortPair<'a>> {
+    let remaining = |ports: &[&'a PortInfo], source: bool| {
+        let mut ports: Vec<&'a PortInfo> = ports
+            .iter()
+            .filter(|port| {
+                !pairs.iter().any(|(src_port, snk_port, _)| {
+                    let paired = if source { src_port } else { snk_port };
+                    paired.serial == port.serial
+                })
+            })
+            .copied()
+            .collect();
+        ports.sort_by_key(|port| port_order(port));
+        ports
+    };
+    remaining(src_ports, true)
+        .into_iter()
+        .zip(remaining(snk_ports, false))
+        .map(|(src_port, snk_port)| (src_port, snk_port, PairedBy::Order))
+        .collect()
+}
+
+/// Sort key putting ports in the order their node lists them, those without `port.id` last
+fn port_order(port: &PortInfo) -> (u32, Serial) {
+    (port.index.unwrap_or(u32::MAX), port.serial)
+}
+
+/// The channel on the other side for FL and FR, any other channel unchanged
+fn swap_lr(channel: &Channel) -> Channel {
+    match channel {
+        Channel::FrontLeft => Channel::FrontRight,
+        Channel::FrontRight => Channel::FrontLeft,
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::fixtures::{node, port, sink, source};
+    use crate::graph::Serial;
+    use crate::pod::AudioCaps;
+
+    /// Source channel, sink channel and strategy of each planned link, in plan order
+    fn pairs(plan: &LinkPlan) -> Vec<(&str, &str, PairedBy)> {
+        plan.links
+            .iter()
+            .map(|link| {
+                (
+                    link.source_channel.as_str(),
+                    link.sink_channel.as_str(),
+                    link.paired_by,
+                )
+            })
+            .collect()
+    }
+
+    fn plan(input: &NodeInfo, output: &NodeInfo, route: &Route) -> LinkPlan {
+        plan_links(input, output, route, Pairing::default())
+    }
+
+    #[test]
+    fn stereo_source_sums_into_a_mono_sink() {
+        let plan = plan(
+            &source(1, &["FL", "FR"]),
+            &sink(2, &["MONO"]),
+            &Route::default(),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "MONO", PairedBy::StereoToMono),
+                ("FR", "MONO", PairedBy::StereoToMono),
+            ]
+        );
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn eight_channels_sum_into_a_mono_sink() {
+        let channels = ["FL", "FR", "FC", "LFE", "RL", "RR", "SL", "SR"];
+        let plan = plan(
+            &source(1, &channels),
+            &sink(2, &["MONO"]),
+            &Route::default(),
+        );
+        assert_eq!(plan.links.len(), 8);
+        assert!(plan
+            .links
+            .iter()
+            .all(|link| link.link.input_port == 200 && link.paired_by == PairedBy::StereoToMono));
+        let sources: Vec<&str> = plan
+            .links
+            .iter()
+            .map(|link| link.source_channel.as_str())
+            .collect();
+        assert_eq!(sources, channels);
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn stereo_to_mono_can_be_turned_off() {
+        let route = Route {
+            stereo_to_mono: Some(false),
+            ..Route::default()
+        };
+        let plan = plan(&source(1, &["FL", "FR"]), &sink(2, &["MONO"]), &route);
+        // Nothing pairs by name, so the position fallback links only the first source port
+        assert_eq!(pairs(&plan), [("FL", "MONO", PairedBy::Position)]);
+        assert_eq!(plan.unmatched_sources, ["FR"]);
+    }
+
+    const SURROUND_51: [&str; 6] = ["FL", "FR", "FC", "LFE", "RL", "RR"];
+
+    const SURROUND_71: [&str; 8] = ["FL", "FR", "FC", "LFE", "RL", "RR", "SL", "SR"];
+
+    fn downmix(downmix: Downmix) -> Route {
+        Route {
+            downmix: Some(downmix),
+            ..Route::default()
+        }
+    }
+
+    #[test]
+    fn downmix_51_to_stereo_drops_lfe() {
+        let plan = plan(
+            &source(1, &SURROUND_51),
+            &sink(2, &["FL", "FR"]),
+            &downmix(Downmix::Stereo),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FR", "FR", PairedBy::Name),
+                ("FC", "FL", PairedBy::Downmix),
+                ("FC", "FR", PairedBy::Downmix),
+                ("RL", "FL", PairedBy::Downmix),
+                ("RR", "FR", PairedBy::Downmix),
+            ]
+        );
+        assert_eq!(plan.unmatched_sources, ["LFE"]);
+    }
+
+    #[test]
+    fn downmix_51_to_stereo_lfe_keeps_lfe() {
+        let plan = plan(
+            &source(1, &SURROUND_51),
+            &sink(2, &["FL", "FR"]),
+            &downmix(Downmix::StereoLfe),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FR", "FR", PairedBy::Name),
+                ("FC", "FL", PairedBy::Downmix),
+                ("FC", "FR", PairedBy::Downmix),
+                ("LFE", "FL", PairedBy::Downmix),
+                ("LFE", "FR", PairedBy::Downmix),
+                ("RL", "FL", PairedBy::Downmix),
+                ("RR", "FR", PairedBy::Downmix),
+            ]
+        );
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn downmix_71_to_stereo_folds_sides_and_rears() {
+        let plan = plan(
+            &source(1, &SURROUND_71),
+            &sink(2, &["FL", "FR"]),
+            &downmix(Downmix::Stereo),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FR", "FR", PairedBy::Name),
+                ("FC", "FL", PairedBy::Downmix),
+                ("FC", "FR", PairedBy::Downmix),
+                ("RL", "FL", PairedBy::Downmix),
+                ("RR", "FR", PairedBy::Downmix),
+                ("SL", "FL", PairedBy::Downmix),
+                ("SR", "FR", PairedBy::Downmix),
+            ]
+        );
+        assert_eq!(plan.unmatched_sources, ["LFE"]);
+    }
+
+    #[test]
+    fn downmix_71_to_stereo_lfe_routes_every_channel() {
+        let plan = plan(
+            &source(1, &SURROUND_71),
+            &sink(2, &["FL", "FR"]),
+            &downmix(Downmix::StereoLfe),
+        );
+        assert_eq!(plan.links.len(), 10);
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn channel_map_sends_one_channel_to_several() {
+        let route = Route {
+            channel_map: HashMap::from([(
+                Channel::FrontCenter,
+                vec![Channel::FrontLeft, Channel::FrontRight],
+            )]),
+            ..Route::default()
+        };
+        let plan = plan(&source(1, &SURROUND_51), &sink(2, &["FL", "FR"]), &route);
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FR", "FR", PairedBy::Name),
+                ("FC", "FL", PairedBy::ChannelMap),
+                ("FC", "FR", PairedBy::ChannelMap),
+            ]
+        );
+        assert_eq!(plan.unmatched_sources, ["LFE", "RL", "RR"]);
+    }
+
+    #[test]
+    fn copy_rears_feeds_the_rear_pair_from_the_front() {
+        let route = Route {
+            upmix: Some(Upmix::CopyRears),
+            ..Route::default()
+        };
+        let plan = plan(&source(1, &["FL", "FR"]), &sink(2, &SURROUND_51), &route);
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FL", "RL", PairedBy::Upmix),
+                ("FR", "FR", PairedBy::Name),
+                ("FR", "RR", PairedBy::Upmix),
+            ]
+        );
+        let ports: Vec<(Serial, Serial)> = plan
+            .links
+            .iter()
+            .map(|link| (link.link.output_port, link.link.input_port))
+            .collect();
+        assert_eq!(ports, [(100, 200), (100, 204), (101, 201), (101, 205)]);
+        assert!(plan.unmatched_sources.is_empty());
+        assert_eq!(plan.unused_sinks, ["FC", "LFE"]);
+    }
+
+    #[test]
+    fn swap_lr_crosses_the_front_pair_under_either_pairing() {
+        let cases = [
+            (Pairing::Name, false, ("FL", "FR"), PairedBy::Name),
+            (Pairing::Name, true, ("FR", "FL"), PairedBy::SwapLr),
+            (Pairing::Position, false, ("FL", "FR"), PairedBy::Position),
+            (Pairing::Position, true, ("FR", "FL"), PairedBy::SwapLr),
+        ];
+        for (pairing, swap_lr, (left_to, right_to), paired_by) in cases {
+            let route = Route {
+                swap_lr,
+                ..Route::default()
+            };
+            let plan = plan_links(
+                &source(1, &["FL", "FR"]),
+                &sink(2, &["FL", "FR"]),
+                &route,
+                pairing,
+            );
+            assert_eq!(
+                pairs(&plan),
+                [("FL", left_to, paired_by), ("FR", right_to, paired_by)],
+                "{:?} with swap_lr = {}",
+                pairing,
+                swap_lr
+            );
+        }
+    }
+
+    #[test]
+    fn channel_map_wins_over_swap_lr() {
+        let route = Route {
+            swap_lr: true,
+            channel_map: HashMap::from([(Channel::FrontLeft, vec![Channel::FrontLeft])]),
+            ..Route::default()
+        };
+        let plan = plan(&source(1, &["FL", "FR"]), &sink(2, &["FL", "FR"]), &route);
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::ChannelMap),
+                ("FR", "FL", PairedBy::SwapLr),
+            ]
+        );
+        assert_eq!(plan.unused_sinks, ["FR"]);
+    }
+
+    /// Every subset of `pool`, in a fixed order
+    fn subsets<'a>(pool: &[&'a str]) -> Vec<Vec<&'a str>> {
+        (0..1u32 << pool.len())
+            .map(|mask| {
+                pool.iter()
+                    .enumerate()
+                    .filter(|(bit, _)| mask & (1 << bit) != 0)
+                    .map(|(_, channel)| *channel)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn position_is_only_a_fallback_for_names_that_never_line_up() {
+        let pool = ["FL", "FR", "FC", "LFE", "AUX0"];
+        let route = Route {
+            mono_to_stereo: Some(false),
+            stereo_to_mono: Some(false),
+            ..Route::default()
+        };
+        for src in subsets(&pool) {
+            for snk in subsets(&pool) {
+                let plan = plan(&source(1, &src), &sink(2, &snk), &route);
+                let shared: Vec<&str> = src.iter().filter(|c| snk.contains(c)).copied().collect();
+                let case = format!("{:?} -> {:?}", src, snk);
+                if shared.is_empty() {
+                    assert_eq!(plan.links.len(), src.len().min(snk.len()), "{}", case);
+                    assert!(
+                        plan.links.iter().all(|l| l.paired_by == PairedBy::Position),
+                        "{}",
+                        case
+                    );
+                } else {
+                    let expected: Vec<(&str, &str, PairedBy)> =
+                        shared.iter().map(|c| (*c, *c, PairedBy::Name)).collect();
+                    assert_eq!(pairs(&plan), expected, "{}", case);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn aliases_pair_by_name_before_any_fallback() {
+        let plan = plan(
+            &source(1, &["front-left", "front-right", "AUX5"]),
+            &sink(2, &["AUX9", "FR", "FL"]),
+            &Route::default(),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("front-left", "FL", PairedBy::NormalizedName),
+                ("front-right", "FR", PairedBy::NormalizedName),
+            ]
+        );
+        assert_eq!(plan.unmatched_sources, ["AUX5"]);
+        assert_eq!(plan.unused_sinks, ["AUX9"]);
+    }
+
+    fn aux_offset(aux_offset: i32) -> Route {
+        Route {
+            aux_offset,
+            ..Route::default()
+        }
+    }
+
+    #[test]
+    fn aux_offset_shifts_aux_channels_up() {
+        let plan = plan(
+            &source(1, &["AUX0", "AUX1"]),
+            &sink(2, &["AUX0", "AUX1", "AUX2", "AUX3"]),
+            &aux_offset(2),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("AUX0", "AUX2", PairedBy::AuxOffset),
+                ("AUX1", "AUX3", PairedBy::AuxOffset),
+            ]
+        );
+        assert_eq!(plan.unused_sinks, ["AUX0", "AUX1"]);
+    }
+
+    #[test]
+    fn negative_aux_offset_below_zero_leaves_the_channel_unrouted() {
+        let plan = plan(
+            &source(1, &["AUX0", "AUX1", "AUX2"]),
+            &sink(2, &["AUX0", "AUX1"]),
+            &aux_offset(-1),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("AUX1", "AUX0", PairedBy::AuxOffset),
+                ("AUX2", "AUX1", PairedBy::AuxOffset),
+            ]
+        );
+        assert_eq!(plan.unmatched_sources, ["AUX0"]);
+    }
+
+    #[test]
+    fn aux_offset_leaves_named_channels_alone() {
+        let plan = plan(
+            &source(1, &["FL", "FR", "AUX0", "AUX1"]),
+            &sink(2, &["FL", "FR", "AUX0", "AUX1", "AUX2", "AUX3"]),
+            &aux_offset(2),
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FL", "FL", PairedBy::Name),
+                ("FR", "FR", PairedBy::Name),
+                ("AUX0", "AUX2", PairedBy::AuxOffset),
+                ("AUX1", "AUX3", PairedBy::AuxOffset),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_port_feeds_both_front_channels() {
+        for channel in ["MONO", "", "capture_1"] {
+            let plan = plan(
+                &source(1, &[channel]),
+                &sink(2, &["FL", "FR"]),
+                &Route::default(),
+            );
+            assert_eq!(
+                pairs(&plan),
+                [
+                    (channel, "FL", PairedBy::MonoToStereo),
+                    (channel, "FR", PairedBy::MonoToStereo),
+                ],
+                "{:?}",
+                channel
+            );
+            assert!(plan.is_complete());
+        }
+    }
+
+    #[test]
+    fn mono_to_stereo_can_be_turned_off() {
+        let route = Route {
+            mono_to_stereo: Some(false),
+            ..Route::default()
+        };
+        let plan = plan(&source(1, &["MONO"]), &sink(2, &["FL", "FR"]), &route);
+        assert_eq!(pairs(&plan), [("MONO", "FL", PairedBy::Position)]);
+        assert_eq!(plan.unused_sinks, ["FR"]);
+    }
+
+    #[test]
+    fn mono_to_stereo_needs_a_front_pair() {
+        let plan = plan(
+            &source(1, &["MONO"]),
+            &sink(2, &["MONO"]),
+            &Route::default(),
+        );
+        assert_eq!(pairs(&plan), [("MONO", "MONO", PairedBy::Name)]);
+    }
+
+    #[test]
+    fn every_output_is_paired_on_its_own() {
+        // What --all-outputs does with one input and two matching sinks: a route per output
+        let input = source(1, &["FL", "FR"]);
+        let stereo = sink(2, &["FL", "FR"]);
+        let surround = sink(3, &SURROUND_51);
+        let plans: Vec<LinkPlan> = [&stereo, &surround]
+            .into_iter()
+            .map(|output| {
+                let route = Route {
+                    inputs: vec![input.serial],
+                    output: output.serial,
+                    ..Route::default()
+                };
+                plan(&input, output, &route)
+            })
+            .collect();
+        for (plan, output) in plans.iter().zip([2, 3]) {
+            assert_eq!(
+                pairs(plan),
+                [("FL", "FL", PairedBy::Name), ("FR", "FR", PairedBy::Name)]
+            );
+            assert!(plan
+                .links
+                .iter()
+                .all(|planned| planned.link.output_node == 1 && planned.link.input_node == output));
+        }
+        // The surround sink leaves channels silent, which says nothing about the stereo one
+        assert!(plans[0].mismatch_summary().is_none());
+        assert_eq!(
+            plans[1].mismatch_summary().as_deref(),
+            Some("4 destination channel(s) silent: FC, LFE, RL, RR")
+        );
+        let sink_ports: Vec<Serial> = plans
+            .iter()
+            .flat_map(|plan| plan.links.iter().map(|planned| planned.link.input_port))
+            .collect();
+        assert_eq!(sink_ports, [200, 201, 300, 301]);
+    }
+
+    #[test]
+    fn mismatch_summary_lists_unrouted_sources() {
+        let plan = plan(
+            &source(1, &SURROUND_71),
+            &sink(2, &["FL", "FR"]),
+            &Route::default(),
+        );
+        assert_eq!(
+            plan.mismatch_summary().as_deref(),
+            Some("6 source channel(s) unrouted: FC, LFE, RL, RR, SL, SR")
+        );
+    }
+
+    #[test]
+    fn mismatch_summary_lists_silent_destinations() {
+        let plan = plan(
+            &source(1, &["FL", "FR"]),
+            &sink(2, &SURROUND_51),
+            &Route::default(),
+        );
+        assert_eq!(
+            plan.mismatch_summary().as_deref(),
+            Some("4 destination channel(s) silent: FC, LFE, RL, RR")
+        );
+    }
+
+    #[test]
+    fn mismatch_summary_joins_both_sides() {
+        let plan = plan(
+            &source(1, &["FL", "FR", "AUX0"]),
+            &sink(2, &["FL", "FR", "FC"]),
+            &Route::default(),
+        );
+        assert_eq!(
+            plan.mismatch_summary().as_deref(),
+            Some("1 source channel(s) unrouted: AUX0; 1 destination channel(s) silent: FC")
+        );
+    }
+
+    #[test]
+    fn mismatch_summary_is_none_when_every_channel_pairs() {
+        let plan = plan(
+            &source(1, &["FL", "FR"]),
+            &sink(2, &["FL", "FR"]),
+            &Route::default(),
+        );
+        assert_eq!(plan.mismatch_summary(), None);
+    }
+
+    /// A sink whose monitor ports, numbered from `serial * 100 + 50`, play back what it receives
+    fn monitored_sink(serial: Serial, channels: &[&str]) -> NodeInfo {
+        let mut node = sink(serial, channels);
+        let monitors = channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| PortInfo {
+                monitor: true,
+                ..port(serial * 100 + 50 + index as Serial, channel, "out")
+            });
+        node.ports.extend(monitors);
+        node
+    }
+
+    #[test]
+    fn monitor_ports_are_skipped_as_sources() {
+        let skipped = plan(
+            &monitored_sink(1, &["FL", "FR"]),
+            &sink(2, &["FL", "FR"]),
+            &Route::default(),
+        );
+        assert!(skipped.links.is_empty());
+    }
+
+    #[test]
+    fn include_monitors_links_monitor_ports() {
+        let route = Route {
+            include_monitors: true,
+            ..Route::default()
+        };
+        let included = plan(
+            &monitored_sink(1, &["FL", "FR"]),
+            &sink(2, &["FL", "FR"]),
+            &route,
+        );
+        assert_eq!(
+            pairs(&included),
+            [("FL", "FL", PairedBy::Name), ("FR", "FR", PairedBy::Name)]
+        );
+        let sources: Vec<Serial> = included
+            .links
+            .iter()
+            .map(|link| link.link.output_port)
+            .collect();
+        assert_eq!(sources, [150, 151]);
+    }
+
+    fn explicit(links: Vec<ExplicitLink>) -> Route {
+        Route {
+            links,
+            ..Route::default()
+        }
+    }
+
+    fn by_name(from: &str, to: &str) -> ExplicitLink {
+        ExplicitLink {
+            from: Some(from.to_string()),
+            to: Some(to.to_string()),
+            ..ExplicitLink::default()
+        }
+    }
+
+    #[test]
+    fn explicit_links_resolve_channel_and_port_names() {
+        let route = explicit(vec![by_name("FR", "playback_AUX3"), by_name("FL", "AUX0")]);
+        let plan = plan(
+            &source(1, &["FL", "FR"]),
+            &sink(2, &["AUX0", "AUX1", "AUX2", "AUX3"]),
+            &route,
+        );
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("FR", "AUX3", PairedBy::Explicit),
+                ("FL", "AUX0", PairedBy::Explicit),
+            ]
+        );
+        // Only sinks are reported, as links leave out sources on purpose
+        assert!(plan.unmatched_sources.is_empty());
+        assert_eq!(plan.unused_sinks, ["AUX1", "AUX2"]);
+        assert!(plan.unresolved.is_empty());
+    }
+
+    #[test]
+    fn explicit_links_resolve_port_ids() {
+        let route = explicit(vec![ExplicitLink {
+            from_port: Some(101),
+            to_port: Some(200),
+            ..ExplicitLink::default()
+        }]);
+        let plan = plan(&source(1, &["FL", "FR"]), &sink(2, &["FL", "FR"]), &route);
+        assert_eq!(pairs(&plan), [("FR", "FL", PairedBy::Explicit)]);
+    }
+
+    #[test]
+    fn explicit_link_failures_name_the_entry() {
+        let route = explicit(vec![
+            by_name("FL", "FL"),
+            ExplicitLink {
+                from: Some("FR".to_string()),
+                from_port: Some(101),
+                to: Some("FR".to_string()),
+                ..ExplicitLink::default()
+            },
+            ExplicitLink {
+                from: Some("FR".to_string()),
+                ..ExplicitLink::default()
+            },
+            by_name("SL", "FR"),
+            ExplicitLink {
+                from_port: Some(999),
+                to: Some("FR".to_string()),
+                ..ExplicitLink::default()
+            },
+        ]);
+        let plan = plan(&source(1, &["FL", "FR"]), &sink(2, &["FL", "FR"]), &route);
+        assert_eq!(pairs(&plan), [("FL", "FL", PairedBy::Explicit)]);
+        assert_eq!(
+            plan.unresolved,
+            [
+                "links[1]: give either from or from_port, not both",
+                "links[2]: missing to or to_port",
+                "links[3]: no port for channel \"SL\"",
+                "links[4]: no port with id 999",
+            ]
+        );
+        assert!(!plan.is_complete());
+    }
+
+    fn media(media: Media) -> Route {
+        Route {
+            media,
+            ..Route::default()
+        }
+    }
+
+    #[test]
+    fn single_video_ports_pair_whatever_their_names() {
+        let camera = node(
+            1,
+            "Camera",
+            "Video/Source",
+            vec![port(10, "capture_1", "out")],
+        );
+        let viewer = node(2, "Viewer", "Video/Sink", vec![port(20, "input_0", "in")]);
+        let plan = plan(&camera, &viewer, &media(Media::Video));
+        assert_eq!(pairs(&plan), [("capture_1", "input_0", PairedBy::Order)]);
+        assert_eq!(plan.links[0].link.output_port, 10);
+        assert_eq!(plan.links[0].link.input_port, 20);
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn video_ports_with_the_same_name_pair_by_it() {
+        let camera = node(1, "Camera", "Video/Source", vec![port(10, "video", "out")]);
+        let viewer = node(
+            2,
+            "Viewer",
+            "Video/Sink",
+            vec![port(20, "preview", "in"), port(21, "video", "in")],
+        );
+        let plan = plan(&camera, &viewer, &media(Media::Video));
+        assert_eq!(pairs(&plan), [("video", "video", PairedBy::Name)]);
+        assert_eq!(plan.unused_sinks, ["preview"]);
+    }
+
+    /// A MIDI port at position `index` of its node
+    fn midi_port(serial: Serial, name: &str, direction: &str, index: u32) -> PortInfo {
+        PortInfo {
+            midi: true,
+            index: Some(index),
+            ..port(serial, name, direction)
+        }
+    }
+
+    #[test]
+    fn keyboard_midi_ports_pair_in_node_order() {
+        let keyboard = node(
+            1,
+            "Keyboard",
+            "Midi/Bridge",
+            vec![
+                midi_port(11, "capture_1", "out", 1),
+                midi_port(10, "capture_0", "out", 0),
+                port(12, "FL", "out"),
+            ],
+        );
+        let synth = node(
+            2,
+            "Synth",
+            "Midi/Bridge",
+            vec![
+                port(22, "FL", "in"),
+                midi_port(21, "midi_in_b", "in", 1),
+                midi_port(20, "midi_in_a", "in", 0),
+            ],
+        );
+        let plan = plan(&keyboard, &synth, &media(Media::Midi));
+        assert_eq!(
+            pairs(&plan),
+            [
+                ("capture_0", "midi_in_a", PairedBy::Order),
+                ("capture_1", "midi_in_b", PairedBy::Order),
+            ]
+        );
+        // The audio ports are left to audio routes
+        assert!(plan.is_complete());
+
+        let audio = plan_links(&keyboard, &synth, &Route::default(), Pairing::default());
+        assert_eq!(pairs(&audio), [("FL", "FL", PairedBy::Name)]);
+    }
+
+    fn rates(ranges: &[(u32, u32)]) -> Vec<RateRange> {
+        ranges
+            .iter()
+            .map(|&(min, max)| RateRange { min, max })
+            .collect()
+    }
+
+    /// A node advertising `formats` and `ranges` of rates in its caps
+    fn with_caps(mut node: NodeInfo, formats: &[&str], ranges: &[(u32, u32)]) -> NodeInfo {
+        node.caps = Some(AudioCaps {
+            formats: formats.iter().map(|format| format.to_string()).collect(),
+            rates: rates(ranges),
+            channels: vec![2],
+        });
+        node
+    }
+
+    fn with_property(mut node: NodeInfo, key: &str, value: &str) -> NodeInfo {
+        node.properties.insert(key.to_string(), value.to_string());
+        node
+    }
+
+    #[test]
+    fn disjoint_rates_are_resampled() {
+        let input = with_caps(source(1, &["FL", "FR"]), &[], &[(44100, 44100)]);
+        let output = with_caps(
+            sink(2, &["FL", "FR"]),
+            &[],
+            &[(48000, 48000), (96000, 96000)],
+        );
+        let mismatch = compare_formats(&input, &output).unwrap();
+        assert_eq!(
+            mismatch,
+            FormatMismatch {
+                rates: Some((
+                    rates(&[(44100, 44100)]),
+                    rates(&[(48000, 48000), (96000, 96000)])
+                )),
+                fixed_rate: false,
+                formats: None,
+                resampled: true,
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "44100 Hz vs 48000,96000 Hz, PipeWire will resample"
+        );
+    }
+
+    #[test]
+    fn overlapping_rate_ranges_are_compatible() {
+        let input = with_caps(source(1, &["FL", "FR"]), &["S16LE"], &[(8000, 48000)]);
+        let output = with_caps(sink(2, &["FL", "FR"]), &["S16LE"], &[(44100, 192000)]);
+        assert_eq!(compare_formats(&input, &output), None);
+    }
+
+    #[test]
+    fn audio_rate_pins_the_output_over_its_caps() {
+        let input = with_caps(source(1, &["FL", "FR"]), &[], &[(44100, 44100)]);
+        let output = with_caps(sink(2, &["FL", "FR"]), &[], &[(44100, 192000)]);
+        assert_eq!(compare_formats(&input, &output), None);
+
+        let output = with_property(output, "audio.rate", "48000");
+        let mismatch = compare_formats(&input, &output).unwrap();
+        assert!(mismatch.fixed_rate);
+        assert_eq!(
+            mismatch.rates,
+            Some((rates(&[(44100, 44100)]), rates(&[(48000, 48000)])))
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "44100 Hz vs fixed 48000 Hz, PipeWire will resample"
+        );
+    }
+
+    #[test]
+    fn resample_disable_on_either_side_is_reported() {
+        let input = with_caps(source(1, &["FL", "FR"]), &[], &[(44100, 44100)]);
+        let output = with_caps(sink(2, &["FL", "FR"]), &[], &[(48000, 48000)]);
+        for (input, output) in [
+            (
+                with_property(input.clone(), "resample.disable", "true"),
+                output.clone(),
+            ),
+            (input, with_property(output, "resample.disable", "true")),
+        ] {
+            let mismatch = compare_formats(&input, &output).unwrap();
+            assert!(!mismatch.resampled);
+            assert_eq!(
+                mismatch.to_string(),
+                "44100 Hz vs 48000 Hz, resampling is disabled, expect silence or noise"
+            );
+        }
+    }
+
+    #[test]
+    fn disjoint_sample_formats_are_converted() {
+        let input = with_caps(
+            source(1, &["FL", "FR"]),
+            &["S16LE", "S24LE"],
+            &[(48000, 48000)],
+        );
+        let output = with_caps(sink(2, &["FL", "FR"]), &["F32LE"], &[(48000, 48000)]);
+        let mismatch = compare_formats(&input, &output).unwrap();
+        assert_eq!(mismatch.rates, None);
+        assert_eq!(
+            mismatch.to_string(),
+            "sample formats S16LE,S24LE vs F32LE, PipeWire will convert"
+        );
+    }
+
+    #[test]
+    fn sides_without_caps_are_assumed_to_adapt() {
+        let stream = source(1, &["FL", "FR"]);
+        let output = with_caps(sink(2, &["FL", "FR"]), &["S16LE"], &[(48000, 48000)]);
+        assert_eq!(compare_formats(&stream, &output), None);
+        let empty = with_caps(source(1, &["FL", "FR"]), &[], &[]);
+        assert_eq!(compare_formats(&empty, &output), None);
+        assert_eq!(compare_formats(&output, &empty), None);
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use pipewire::spa::param::audio::AudioFormat;
+use pipewire::spa::param::format::{FormatProperties, MediaType};
+use pipewire::spa::pod::deserialize::PodDeserializer;
+use pipewire::spa::pod::serialize::PodSerializer;
+use pipewire::spa::pod::{
+    CanonicalFixedSizedPod, ChoiceValue, Object, Pod, Property, PropertyFlags, Value, ValueArray,
+};
+use pipewire::spa::utils::{Choice, ChoiceEnum, Id};
+use serde::Serialize;
+
+/// Inclusive range of sample rates; a single supported rate has `min == max`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct RateRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl RateRange {
+    pub fn contains(&self, rate: u32) -> bool {
+        (self.min..=self.max).contains(&rate)
+    }
+
+    pub fn overlaps(&self, other: &RateRange) -> bool {
+        self.min <= other.max && other.min <= self.max
+    }
+}
+
+impl fmt::Display for RateRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// Audio capabilities advertised by a node through its `EnumFormat` params
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AudioCaps {
+    pub formats: Vec<String>, // Sample formats, e.g. "S16LE"
+    pub rates: Vec<RateRange>,
+    pub channels: Vec<u32>,
+}
+
+impl AudioCaps {
+    /// Fold in the values from another `EnumFormat` param, skipping duplicates
+    pub fn merge(&mut self, other: AudioCaps) {
+        extend_unique(&mut self.formats, other.formats);
+        extend_unique(&mut self.rates, other.rates);
+        extend_unique(&mut self.channels, other.channels);
+    }
+
+    /// True if both sides advertise rates but have none in common
+    ///
+    /// Unknown rates are assumed compatible, as nodes without an `EnumFormat` (e.g. most
+    /// streams) adapt to whatever the graph runs at
+    pub fn rates_disjoint(&self, other: &AudioCaps) -> bool {
+        !self.rates.is_empty()
+            && !other.rates.is_empty()
+            && !self
+                .rates
+                .iter()
+                .any(|rate| other.rates.iter().any(|o| rate.overlaps(o)))
+    }
+}
+
+impl fmt::Display for AudioCaps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rates: Vec<String> = self.rates.iter().map(|r| r.to_string()).collect();
+        let channels: Vec<String> = self.channels.iter().map(|c| c.to_string()).collect();
+        write!(
+            f,
+            "formats: {}  rates: {}  channels: {}",
+            or_unknown(&self.formats.join(",")),
+            or_unknown(&rates.join(",")),
+            or_unknown(&channels.join(","))
+        )
+    }
+}
+
+/// Extract the formats, rates and channel counts from one `EnumFormat` param
+///
+/// Returns None if the pod cannot be parsed or does not describe audio
+pub fn parse_enum_format(pod: &Pod) -> Option<AudioCaps> {
+    let (_, value) = PodDeserializer::deserialize_any_from(pod.as_bytes()).ok()?;
+    let Value::Object(object) = value else {
+        return None;
+    };
+    parse_format_object(&object)
+}
+
+/// Extract the format a link or port settled on, with a single format, rate and channel count
+///
+/// Returns None if the pod cannot be parsed or does not describe audio
+pub fn parse_format(pod: &Pod) -> Option<AudioCaps> {
+    // A negotiated format is an EnumFormat object with fixed values instead of choices
+    parse_enum_format(pod)
+}
+
+fn parse_format_object(object: &Object) -> Option<AudioCaps> {
+    let mut caps = AudioCaps::default();
+    let mut audio = false;
+    for property in &object.properties {
+        match FormatProperties::from_raw(property.key) {
+            FormatProperties::MediaType => {
+                audio = ids(&property.value).contains(&MediaType::Audio.as_raw());
+            }
+            FormatProperties::AudioFormat => {
+                caps.formats = ids(&property.value)
+                    .into_iter()
+                    .map(|raw| format_name(AudioFormat::from_raw(raw)))
+                    .collect();
+            }
+            FormatProperties::AudioRate => {
+                caps.rates = ints(&property.value)
+                    .into_iter()
+                    .map(|(min, max)| RateRange { min, max })
+                    .collect();
+            }
+            FormatProperties::AudioChannels => {
+                caps.channels = ints(&property.value)
+                    .into_iter()
+                    .flat_map(|(min, max)| min..=max)
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    audio.then_some(caps)
+}
+
+/// `SPA_TYPE_OBJECT_Props` and `SPA_PARAM_Props`, from `spa/param/param-types.h`
+const TYPE_OBJECT_PROPS: u32 = 0x40002;
+const PARAM_PROPS: u32 = 2;
+/// `SPA_PROP_channelVolumes`, from `spa/param/props.h`
+const PROP_CHANNEL_VOLUMES: u32 = 0x10008;
+
+/// Serialize a `Props` param setting the volume of each channel, e.g. all zero to silence a
+/// node without unlinking it
+///
+/// Returns the bytes of the pod, to be read with [`Pod::from_bytes`]
+pub fn channel_volumes(volumes: &[f32]) -> Option<Vec<u8>> {
+    let props = Value::Object(Object {
+        type_: TYPE_OBJECT_PROPS,
+        id: PARAM_PROPS,
+        properties: vec![Property {
+            key: PROP_CHANNEL_VOLUMES,
+            flags: PropertyFlags::empty(),
+            value: Value::ValueArray(ValueArray::Float(volumes.to_vec())),
+        }],
+    });
+    let (cursor, _) = PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &props).ok()?;
+    Some(cursor.into_inner())
+}
+
+/// Read the volume of each channel from a `Props` param, to give it back after
+/// [`channel_volumes`] silenced the node
+///
+/// Returns None if the pod cannot be parsed or has no channel volumes
+pub fn parse_channel_volumes(pod: &Pod) -> Option<Vec<f32>> {
+    let (_, value) = PodDeserializer::deserialize_any_from(pod.as_bytes()).ok()?;
+    let Value::Object(object) = value else {
+        return None;
+    };
+    if object.type_ != TYPE_OBJECT_PROPS {
+        return None;
+    }
+    object
+        .properties
+        .into_iter()
+        .find(|property| property.key == PROP_CHANNEL_VOLUMES)
+        .and_then(|property| match property.value {
+            Value::ValueArray(ValueArray::Float(volumes)) => Some(volumes),
+            _ => None,
+        })
+}
+
+/// Keys of the `Route` param object, from `spa/param/route.h`
+const ROUTE_DIRECTION: u32 = 2;
+const ROUTE_DEVICE: u32 = 3;
+const ROUTE_INFO: u32 = 8;
+const ROUTE_DEVICES: u32 = 11;
+/// `SPA_DIRECTION_OUTPUT`: the route plays back, e.g. an HDMI or headphone jack
+const DIRECTION_OUTPUT: u32 = 1;
+
+/// Route info key ALSA fills from the ELD of the connected HDMI/DisplayPort monitor
+const ROUTE_MONITOR_NAME: &str = "device.product.name";
+
+/// One route (jack or connector) of a device, from its `Route` params
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceRoute {
+    pub output: bool,
+    pub devices: Vec<i32>, // card.profile.device of the nodes the route belongs to
+    pub info: BTreeMap<String, String>,
+}
+
+impl DeviceRoute {
+    /// Name of the monitor or receiver connected to a playback route, if it reported one
+    pub fn monitor_name(&self) -> Option<&str> {
+        if !self.output {
+            return None;
+        }
+        self.info
+            .get(ROUTE_MONITOR_NAME)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+}
+
+/// Extract the direction, profile devices and info of one `Route` param
+///
+/// Returns None if the pod cannot be parsed
+pub fn parse_route(pod: &Pod) -> Option<DeviceRoute> {
+    let (_, value) = PodDeserializer::deserialize_any_from(pod.as_bytes()).ok()?;
+    let Value::Object(object) = value else {
+        return None;
+    };
+    let mut route = DeviceRoute::default();
+    for property in &object.properties {
+        match (property.key, &property.value) {
+            (ROUTE_DIRECTION, Value::Id(Id(direction))) => {
+                route.output = *direction == DIRECTION_OUTPUT;
+            }
+            // Active routes name one device, enumerated routes list all they apply to
+            (ROUTE_DEVICE, Value::Int(device)) => extend_unique(&mut route.devices, [*device]),
+            (ROUTE_DEVICES, Value::ValueArray(ValueArray::Int(devices))) => {
+                extend_unique(&mut route.devices, devices.iter().copied());
+            }
+            (ROUTE_INFO, Value::Struct(fields)) => route.info = parse_info(fields),
+            _ => {}
+        }
+    }
+    Some(route)
+}
+
+/// The info struct is a count followed by that many key/value string pairs
+fn parse_info(fields: &[Value]) -> BTreeMap<String, String> {
+    fields
+        .get(1..)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [Value::String(key), Value::String(value)] => Some((key.clone(), value.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Short name of a sample format, e.g. "S16LE" rather than "AudioFormat::S16LE"
+fn format_name(format: AudioFormat) -> String {
+    let name = format!("{:?}", format);
+    name.trim_start_matches("AudioFormat::").to_string()
+}
+
+/// All values offered for an id property, whether fixed or a choice
+fn ids(value: &Value) -> Vec<u32> {
+    match value {
+        Value::Id(Id(id)) => vec![*id],
+        Value::Choice(ChoiceValue::Id(Choice(_, choice))) => {
+            offered(choice).into_iter().map(|(Id(id), _)| id).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// All values offered for a non-negative integer property, as inclusive ranges
+fn ints(value: &Value) -> Vec<(u32, u32)> {
+    let ranges = match value {
+        Value::Int(int) => vec![(*int, *int)],
+        Value::Choice(ChoiceValue::Int(Choice(_, choice))) => offered(choice),
+        _ => Vec::new(),
+    };
+    ranges
+        .into_iter()
+        .filter_map(|(min, max)| Some((u32::try_from(min).ok()?, u32::try_from(max).ok()?)))
+        .collect()
+}
+
+/// Flatten a choice into inclusive `(min, max)` ranges, discrete values having `min == max`
+fn offered<T: CanonicalFixedSizedPod + Copy + PartialEq>(choice: &ChoiceEnum<T>) -> Vec<(T, T)> {
+    match choice {
+        ChoiceEnum::None(value) => vec![(*value, *value)],
+        ChoiceEnum::Range { min, max, .. } | ChoiceEnum::Step { min, max, .. } => {
+            vec![(*min, *max)]
+        }
+        ChoiceEnum::Enum {
+            default,
+            alternatives,
+        } => {
+            // The default is usually repeated among the alternatives
+            let mut values = vec![*default];
+            extend_unique(&mut values, alternatives.iter().copied());
+            values.into_iter().map(|value| (value, value)).collect()
+        }
+        ChoiceEnum::Flags { .. } => Vec::new(),
+    }
+}
+
+fn extend_unique<T: PartialEq>(values: &mut Vec<T>, others: impl IntoIterator<Item = T>) {
+    for other in others {
+        if !values.contains(&other) {
+            values.push(other);
+        }
+    }
+}
+
+fn or_unknown(joined: &str) -> &str {
+    if joined.is_empty() {
+        "?"
+    } else {
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pipewire::spa::param::ParamType;
+    use pipewire::spa::utils::{ChoiceFlags, SpaTypes};
+
+    use super::*;
+
+    /// Serialize an `EnumFormat` object with the given properties
+    fn enum_format(properties: Vec<(FormatProperties, Value)>) -> Vec<u8> {
+        let object = Value::Object(Object {
+            type_: SpaTypes::ObjectParamFormat.as_raw(),
+            id: ParamType::EnumFormat.as_raw(),
+            properties: properties
+                .into_iter()
+                .map(|(key, value)| Property {
+                    key: key.as_raw(),
+                    flags: PropertyFlags::empty(),
+                    value,
+                })
+                .collect(),
+        });
+        let (cursor, _) =
+            PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &object).unwrap();
+        cursor.into_inner()
+    }
+
+    fn audio_format(rate: Value, channels: Value) -> Vec<u8> {
+        enum_format(vec![
+            (
+                FormatProperties::MediaType,
+                Value::Id(Id(MediaType::Audio.as_raw())),
+            ),
+            (FormatProperties::AudioRate, rate),
+            (FormatProperties::AudioChannels, channels),
+        ])
+    }
+
+    fn int_choice(choice: ChoiceEnum<i32>) -> Value {
+        Value::Choice(ChoiceValue::Int(Choice(ChoiceFlags::empty(), choice)))
+    }
+
+    fn parse(bytes: &[u8]) -> Option<AudioCaps> {
+        parse_enum_format(Pod::from_bytes(bytes).unwrap())
+    }
+
+    fn rates(ranges: &[(u32, u32)]) -> Vec<RateRange> {
+        ranges
+            .iter()
+            .map(|&(min, max)| RateRange { min, max })
+            .collect()
+    }
+
+    #[test]
+    fn enum_of_rates() {
+        let rate = int_choice(ChoiceEnum::Enum {
+            default: 48000,
+            alternatives: vec![44100, 48000, 96000],
+        });
+        let caps = parse(&audio_format(rate, Value::Int(2))).unwrap();
+        // The default comes first and is not repeated
+        assert_eq!(
+            caps.rates,
+            rates(&[(48000, 48000), (44100, 44100), (96000, 96000)])
+        );
+        assert_eq!(caps.channels, [2]);
+        assert!(caps.formats.is_empty());
+    }
+
+    #[test]
+    fn range_of_rates_and_channels() {
+        let rate = int_choice(ChoiceEnum::Range {
+            default: 48000,
+            min: 8000,
+            max: 192000,
+        });
+        let channels = int_choice(ChoiceEnum::Range {
+            default: 2,
+            min: 1,
+            max: 4,
+        });
+        let caps = parse(&audio_format(rate, channels)).unwrap();
+        assert_eq!(caps.rates, rates(&[(8000, 192000)]));
+        assert_eq!(caps.channels, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fixed_rate() {
+        let bytes = audio_format(Value::Int(44100), Value::Int(6));
+        let caps = parse(&bytes).unwrap();
+        assert_eq!(caps.rates, rates(&[(44100, 44100)]));
+        assert_eq!(caps.channels, [6]);
+        // A negotiated format reads the same way
+        assert_eq!(parse_format(Pod::from_bytes(&bytes).unwrap()), Some(caps));
+    }
+
+    #[test]
+    fn negative_values_are_dropped() {
+        let rate = int_choice(ChoiceEnum::Enum {
+            default: -1,
+            alternatives: vec![48000],
+        });
+        let caps = parse(&audio_format(rate, Value::Int(2))).unwrap();
+        assert_eq!(caps.rates, rates(&[(48000, 48000)]));
+    }
+
+    #[test]
+    fn non_audio_formats_are_skipped() {
+        let video = enum_format(vec![
+            (
+                FormatProperties::MediaType,
+                Value::Id(Id(MediaType::Video.as_raw())),
+            ),
+            (FormatProperties::AudioRate, Value::Int(48000)),
+        ]);
+        assert_eq!(parse(&video), None);
+        assert_eq!(parse(&enum_format(Vec::new())), None);
+    }
+
+    #[test]
+    fn channel_volumes_round_trip() {
+        let bytes = channel_volumes(&[0.5, 0.25]).unwrap();
+        assert_eq!(
+            parse_channel_volumes(Pod::from_bytes(&bytes).unwrap()),
+            Some(vec![0.5, 0.25])
+        );
+    }
+
+    #[test]
+    fn props_without_channel_volumes() {
+        // SPA_PROP_volume, the node-wide volume, is not a per-channel one
+        let object = Value::Object(Object {
+            type_: TYPE_OBJECT_PROPS,
+            id: PARAM_PROPS,
+            properties: vec![Property {
+                key: 0x10003,
+                flags: PropertyFlags::empty(),
+                value: Value::Float(1.0),
+            }],
+        });
+        let (cursor, _) =
+            PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &object).unwrap();
+        let bytes = cursor.into_inner();
+        assert_eq!(
+            parse_channel_volumes(Pod::from_bytes(&bytes).unwrap()),
+            None
+        );
+        // Nor is a format, whatever its keys
+        let format = audio_format(Value::Int(48000), Value::Int(2));
+        assert_eq!(
+            parse_channel_volumes(Pod::from_bytes(&format).unwrap()),
+            None
+        );
+    }
+
+    /// Serialize a `Route` param object with the given properties
+    fn route(properties: Vec<(u32, Value)>) -> Option<DeviceRoute> {
+        let object = Value::Object(Object {
+            type_: SpaTypes::ObjectParamRoute.as_raw(),
+            id: ParamType::Route.as_raw(),
+            properties: properties
+                .into_iter()
+                .map(|(key, value)| Property {
+                    key,
+                    flags: PropertyFlags::empty(),
+                    value,
+                })
+                .collect(),
+        });
+        let (cursor, _) =
+            PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &object).unwrap();
+        parse_route(Pod::from_bytes(&cursor.into_inner()).unwrap())
+    }
+
+    /// The info struct of a route: a count, then key/value pairs
+    fn info(pairs: &[(&str, &str)]) -> Value {
+        let mut fields = vec![Value::Int(pairs.len() as i32)];
+        for (key, value) in pairs {
+            fields.push(Value::String(key.to_string()));
+            fields.push(Value::String(value.to_string()));
+        }
+        Value::Struct(fields)
+    }
+
+    #[test]
+    fn enumerated_hdmi_route_names_its_monitor() {
+        let parsed = route(vec![
+            (ROUTE_DIRECTION, Value::Id(Id(DIRECTION_OUTPUT))),
+            (
+                ROUTE_DEVICES,
+                Value::ValueArray(ValueArray::Int(vec![3, 7, 3])),
+            ),
+            (
+                ROUTE_INFO,
+                info(&[("port.type", "hdmi"), (ROUTE_MONITOR_NAME, "DELL U2415")]),
+            ),
+        ])
+        .unwrap();
+        assert!(parsed.output);
+        assert_eq!(parsed.devices, [3, 7]);
+        assert_eq!(
+            parsed.info.get("port.type").map(String::as_str),
+            Some("hdmi")
+        );
+        assert_eq!(parsed.monitor_name(), Some("DELL U2415"));
+    }
+
+    #[test]
+    fn active_route_names_one_device() {
+        let parsed = route(vec![
+            (ROUTE_DIRECTION, Value::Id(Id(DIRECTION_OUTPUT))),
+            (ROUTE_DEVICE, Value::Int(3)),
+            (ROUTE_INFO, info(&[(ROUTE_MONITOR_NAME, "")])),
+        ])
+        .unwrap();
+        assert_eq!(parsed.devices, [3]);
+        // An unplugged connector reports an empty name
+        assert_eq!(parsed.monitor_name(), None);
+    }
+
+    #[test]
+    fn capture_routes_have_no_monitor() {
+        let parsed = route(vec![
+            (ROUTE_DIRECTION, Value::Id(Id(0))),
+            (ROUTE_DEVICE, Value::Int(0)),
+            (ROUTE_INFO, info(&[(ROUTE_MONITOR_NAME, "Webcam")])),
+        ])
+        .unwrap();
+        assert!(!parsed.output);
+        assert_eq!(parsed.monitor_name(), None);
+    }
+}
@@ -0,0 +1,54 @@
+use std::cell::Cell;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pie_mixer::graph::Graph;
+
+/// How often the spinner is redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Discovery progress drawn on stderr, counting the nodes found so far, erased when dropped
+///
+/// Drawn from a timer on the main loop, so it only moves while the loop runs and never holds
+/// it up
+pub struct Spinner {
+    _timer: pipewire::loop_::TimerSource<'static>,
+}
+
+impl Spinner {
+    /// Start drawing after the first tick, or None if stderr is not a terminal
+    pub fn start(
+        main_loop: &'static pipewire::main_loop::MainLoopRc,
+        graph: Arc<Mutex<Graph>>,
+    ) -> Option<Spinner> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let frame = Cell::new(0);
+        let timer = main_loop.loop_().add_timer(move |_| {
+            let nodes = graph.lock().map_or(0, |graph| graph.nodes().count());
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(
+                stderr,
+                "\r\x1b[2K{} Discovering… {} node(s) found",
+                FRAMES[frame.get() % FRAMES.len()],
+                nodes
+            );
+            let _ = stderr.flush();
+            frame.set(frame.get() + 1);
+        });
+        timer.update_timer(Some(TICK), Some(TICK));
+        Some(Spinner { _timer: timer })
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry, doubled for every further attempt
+pub const INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between two attempts, however many came before
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff: `initial`, twice that, four times that... capped at `max`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: u32, // Retries before giving up, 0 never retries
+}
+
+impl Backoff {
+    /// Backoff with the default delays, giving up after `attempts` retries
+    pub fn new(attempts: u32) -> Self {
+        Backoff {
+            initial: INITIAL_DELAY,
+            max: MAX_DELAY,
+            attempts,
+        }
+    }
+
+    /// Delay before retry number `attempt`, counting from 1, or None once the attempts are used up
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.attempts {
+            return None;
+        }
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// An item waiting for its next attempt
+#[derive(Debug)]
+struct Pending<T> {
+    item: T,
+    attempt: u32,         // Number of the retry scheduled or under way
+    due: Option<Instant>, // None while the retry is under way
+}
+
+/// Items whose last attempt failed, each retried on its own [`Backoff`] schedule
+///
+/// Knows nothing of what is retried: the caller takes the items that are due, attempts them,
+/// then reports each as [`RetryQueue::failed`] again or [`RetryQueue::resolve`]d
+#[derive(Debug)]
+pub struct RetryQueue<K, T> {
+    backoff: Backoff,
+    pending: HashMap<K, Pending<T>>,
+}
+
+impl<K: Clone + Eq + Hash, T: Clone> RetryQueue<K, T> {
+    pub fn new(backoff: Backoff) -> Self {
+        RetryQueue {
+            backoff,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Record a failed attempt at `now`, returning the number of the retry scheduled and its
+    /// delay, or None if the item has used up its attempts and is dropped
+    pub fn failed(&mut self, key: K, item: T, now: Instant) -> Option<(u32, Duration)> {
+        let attempt = self
+            .pending
+            .get(&key)
+            .map_or(1, |pending| pending.attempt + 1);
+        let Some(delay) = self.backoff.delay(attempt) else {
+            self.pending.remove(&key);
+            return None;
+        };
+        self.pending.insert(
+            key,
+            Pending {
+                item,
+                attempt,
+                due: Some(now + delay),
+            },
+        );
+        Some((attempt, delay))
+    }
+
+    /// Stop retrying an item, as it succeeded or is no longer wanted. Returns false if it was
+    /// not queued
+    pub fn resolve(&mut self, key: &K) -> bool {
+        self.pending.remove(key).is_some()
+    }
+
+    /// The items due for a retry at `now`, marked as under way until resolved or failed again
+    pub fn take_due(&mut self, now: Instant) -> Vec<(K, T)> {
+        self.pending
+            .iter_mut()
+            .filter(|(_, pending)| pending.due.is_some_and(|due| due <= now))
+            .map(|(key, pending)| {
+                pending.due = None;
+                (key.clone(), pending.item.clone())
+            })
+            .collect()
+    }
+
+    /// Time from `now` until the earliest scheduled retry, zero if one is overdue
+    pub fn next_due(&self, now: Instant) -> Option<Duration> {
+        self.pending
+            .values()
+            .filter_map(|pending| pending.due)
+            .min()
+            .map(|due| due.saturating_duration_since(now))
+    }
+
+    /// Drop the items `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &T) -> bool) {
+        self.pending.retain(|key, pending| keep(key, &pending.item));
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Items handed out one at a time, `stagger` apart, e.g. link requests spaced out as
+/// requesting many at once can cause xruns
+///
+/// Knows nothing of timers: the caller takes a turn once the delay returned by
+/// [`StaggerQueue::push`] or [`StaggerQueue::next_turn`] has passed
+#[derive(Debug)]
+pub struct StaggerQueue<K, T> {
+    stagger: Duration,
+    queue: VecDeque<(K, T)>,
+}
+
+impl<K: Eq, T> StaggerQueue<K, T> {
+    pub fn new(stagger: Duration) -> Self {
+        StaggerQueue {
+            stagger,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queue an item behind those already waiting, returning the delay until the first turn
+    /// if the queue was idle, so the caller has a turn to schedule
+    pub fn push(&mut self, key: K, item: T) -> Option<Duration> {
+        self.queue.push_back((key, item));
+        (self.queue.len() == 1).then_some(Duration::ZERO)
+    }
+
+    /// Take the turn: the first item `want` accepts, along with what it found out about it.
+    /// Items it rejects, e.g. of a route removed while they waited, are dropped without
+    /// taking a turn
+    pub fn pop_wanted<R>(&mut self, mut want: impl FnMut(&T) -> Option<R>) -> Option<(T, R)> {
+        while let Some((_, item)) = self.queue.pop_front() {
+            if let Some(wanted) = want(&item) {
+                return Some((item, wanted));
+            }
+        }
+        None
+    }
+
+    /// Delay until the next turn, None once nothing is waiting
+    pub fn next_turn(&self) -> Option<Duration> {
+        (!self.queue.is_empty()).then_some(self.stagger)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.queue.iter().any(|(queued, _)| queued == key)
+    }
+
+    /// Drop the items `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &T) -> bool) {
+        self.queue.retain(|(key, item)| keep(key, item));
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Caps how often something may happen for each key within a sliding window, e.g. recreating
+/// a link another client keeps destroying
+#[derive(Debug)]
+pub struct RateLimit<K> {
+    limit: usize,
+    window: Duration,
+    events: HashMap<K, VecDeque<Instant>>,
+}
+
+impl<K: Eq + Hash> RateLimit<K> {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        RateLimit {
+            limit,
+            window,
+            events: HashMap::new(),
+        }
+    }
+
+    /// Record an event at `now` unless `limit` events already happened within the window,
+    /// returning whether it was allowed
+    pub fn allow(&mut self, key: K, now: Instant) -> bool {
+        let window = self.window;
+        let events = self.events.entry(key).or_default();
+        while events
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) >= window)
+        {
+            events.pop_front();
+        }
+        if events.len() >= self.limit {
+            return false;
+        }
+        events.push_back(now);
+        true
+    }
+
+    /// True if another event at `now` would not be allowed
+    pub fn exhausted(&self, key: &K, now: Instant) -> bool {
+        self.events.get(key).is_some_and(|events| {
+            events
+                .iter()
+                .filter(|at| now.saturating_duration_since(**at) < self.window)
+                .count()
+                >= self.limit
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{ActiveLink, Serial};
+    use crate::mixer::Route;
+    use crate::routing::Routing;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let backoff = Backoff::new(10);
+        let delays: Vec<Duration> = (1..=10).filter_map(|n| backoff.delay(n)).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000, 30000].map(|ms| MS * ms)
+        );
+        assert_eq!(backoff.delay(0), None);
+        assert_eq!(backoff.delay(11), None);
+        assert_eq!(Backoff::new(u32::MAX).delay(40), Some(MAX_DELAY));
+    }
+
+    #[test]
+    fn no_attempts_never_retries() {
+        let backoff = Backoff::new(0);
+        assert_eq!(backoff.delay(1), None);
+        let mut queue = RetryQueue::new(backoff);
+        assert_eq!(queue.failed("FL", (), Instant::now()), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn failed_gives_up_after_the_attempts() {
+        let now = Instant::now();
+        let mut queue = RetryQueue::new(Backoff::new(3));
+        assert_eq!(queue.failed("FL", (), now), Some((1, MS * 500)));
+        assert_eq!(queue.failed("FL", (), now), Some((2, MS * 1000)));
+        assert_eq!(queue.failed("FL", (), now), Some((3, MS * 2000)));
+        assert_eq!(queue.failed("FL", (), now), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn take_due_marks_items_under_way() {
+        let now = Instant::now();
+        let mut queue = RetryQueue::new(Backoff::new(3));
+        queue.failed("FL", 1, now);
+        queue.failed("FR", 2, now + MS * 100);
+        assert!(queue.take_due(now + MS * 499).is_empty());
+        assert_eq!(queue.take_due(now + MS * 500), vec![("FL", 1)]);
+        // Under way, so not handed out again nor waited for
+        assert!(queue.take_due(now + MS * 550).is_empty());
+        assert_eq!(queue.next_due(now + MS * 500), Some(MS * 100));
+        // Failing again schedules the next attempt
+        assert_eq!(queue.failed("FL", 1, now + MS * 600), Some((2, MS * 1000)));
+        assert_eq!(queue.next_due(now + MS * 600), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn resolve_drops_the_item() {
+        let now = Instant::now();
+        let mut queue = RetryQueue::new(Backoff::new(3));
+        queue.failed("FL", (), now);
+        assert!(queue.resolve(&"FL"));
+        assert!(!queue.resolve(&"FL"));
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_due(now), None);
+        assert!(queue.take_due(now + MAX_DELAY).is_empty());
+        // Starting over after a resolve counts from the first attempt again
+        assert_eq!(queue.failed("FL", (), now), Some((1, MS * 500)));
+    }
+
+    #[test]
+    fn next_due_is_zero_once_overdue() {
+        let now = Instant::now();
+        let mut queue = RetryQueue::new(Backoff::new(3));
+        assert_eq!(queue.next_due(now), None);
+        queue.failed("FL", (), now);
+        assert_eq!(queue.next_due(now), Some(MS * 500));
+        assert_eq!(queue.next_due(now + MS * 200), Some(MS * 300));
+        assert_eq!(queue.next_due(now + MS * 900), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn staged_items_take_turns_in_order_stagger_apart() {
+        let mut queue = StaggerQueue::new(MS * 50);
+        assert_eq!(queue.next_turn(), None);
+        // Only the first item starts the schedule, the rest wait their turn
+        assert_eq!(queue.push("FL", 1), Some(Duration::ZERO));
+        assert_eq!(queue.push("FR", 2), None);
+        assert_eq!(queue.push("LFE", 3), None);
+        assert!(queue.contains(&"FR"));
+        let mut turns = Vec::new();
+        while let Some((item, ())) = queue.pop_wanted(|_| Some(())) {
+            turns.push((item, queue.next_turn()));
+        }
+        assert_eq!(turns, [(1, Some(MS * 50)), (2, Some(MS * 50)), (3, None)]);
+        // Idle again, so the next item starts a new schedule
+        assert_eq!(queue.push("FL", 1), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn links_of_a_route_removed_mid_stagger_are_cancelled() {
+        let link = |input: Serial, channel: &str, port: Serial| ActiveLink {
+            channel: channel.to_string(),
+            output_node: input,
+            output_port: input * 100 + port,
+            input_node: 9,
+            input_port: 900 + port,
+        };
+        let route = |input: Serial| Route {
+            inputs: vec![input],
+            output: 9,
+            ..Route::default()
+        };
+        let mut routing = Routing::default();
+        routing.set(vec![route(1), route(2)]);
+        let mut queue = StaggerQueue::new(MS * 50);
+        for link in [
+            link(1, "FL", 0),
+            link(1, "FR", 1),
+            link(2, "FL", 0),
+            link(2, "FR", 1),
+        ] {
+            queue.push((link.output_port, link.input_port), link);
+        }
+        let mut take = |routing: &Routing| {
+            queue
+                .pop_wanted(|link| routing.route_of(link).map(|route| route.inputs[0]))
+                .map(|(link, input)| (input, link.channel))
+        };
+        assert_eq!(take(&routing), Some((1, "FL".to_string())));
+
+        // The first rule goes: its other channel is skipped without taking a turn
+        routing.set(vec![route(2)]);
+        assert_eq!(take(&routing), Some((2, "FL".to_string())));
+        routing.set(Vec::new());
+        assert_eq!(take(&routing), None);
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_turn(), None);
+    }
+
+    #[test]
+    fn rate_limit_allows_again_once_the_window_slides() {
+        let now = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut limit = RateLimit::new(2, window);
+        assert!(limit.allow("FL", now));
+        assert!(limit.allow("FL", now + MS));
+        assert!(limit.exhausted(&"FL", now + MS * 2));
+        assert!(!limit.allow("FL", now + MS * 2));
+        assert!(limit.allow("FR", now + MS * 2));
+        assert!(!limit.exhausted(&"FL", now + window));
+        assert!(limit.allow("FL", now + window));
+    }
+}
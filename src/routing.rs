@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use tracing::info;
+
+use crate::graph::{ActiveLink, Graph, NodeInfo, Serial};
+use crate::mixer::Route;
+
+/// The routes a mixer maintains, and the devices behind their endpoints
+///
+/// Knows nothing of proxies: the mixer asks which routes a link or node belongs to, and
+/// follows the endpoints a device recreates, like a Bluetooth headset switching profile
+#[derive(Clone, Debug, Default)]
+pub struct Routing {
+    routes: Vec<Route>,
+    // Routed node -> (device.string, is input), to recognize the node a device recreates
+    endpoint_devices: HashMap<Serial, (String, bool)>,
+}
+
+impl Routing {
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Replace the routes, keeping the devices already seen behind their endpoints
+    pub fn set(&mut self, routes: Vec<Route>) {
+        self.routes = routes;
+    }
+
+    /// Forget every route and endpoint device
+    pub fn clear(&mut self) {
+        self.routes.clear();
+        self.endpoint_devices.clear();
+    }
+
+    /// True before routes have been set
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The route a link belongs to, from one of its inputs into its output
+    pub fn route_of(&self, link: &ActiveLink) -> Option<&Route> {
+        self.routes.iter().find(|route| {
+            route.output == link.input_node && route.inputs.contains(&link.output_node)
+        })
+    }
+
+    /// True if the node is one of the endpoints of a route
+    pub fn involves(&self, node: Serial) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.output == node || route.inputs.contains(&node))
+    }
+
+    /// Serials of the inputs of every route, each listed once
+    pub fn inputs(&self) -> Vec<Serial> {
+        let mut inputs: Vec<Serial> = Vec::new();
+        for input in self.routes.iter().flat_map(|route| &route.inputs) {
+            if !inputs.contains(input) {
+                inputs.push(*input);
+            }
+        }
+        inputs
+    }
+
+    /// True if there is no route yet, or a node of one is gone, so a node appearing may be the
+    /// one the rules are waiting for
+    pub fn awaiting_nodes(&self, graph: &Graph) -> bool {
+        self.routes.is_empty()
+            || self.routes.iter().any(|route| {
+                route
+                    .inputs
+                    .iter()
+                    .chain([&route.output])
+                    .any(|serial| graph.node(*serial).is_none())
+            })
+    }
+
+    /// Remember the device behind each routed node that has one, to follow it when the
+    /// device recreates the node
+    pub fn note_endpoints<'a>(&mut self, nodes: impl IntoIterator<Item = &'a NodeInfo>) {
+        for node in nodes {
+            if let Some(device_string) = &node.device_string {
+                self.endpoint_devices
+                    .insert(node.serial, (device_string.clone(), node.input));
+            }
+        }
+    }
+
+    /// Route a node in place of a vanished node of the same device and direction, as Bluetooth
+    /// devices recreate their nodes (with a new serial and port set) on every profile switch
+    ///
+    /// Returns true if the node took over a route endpoint
+    pub fn adopt_replacement(&mut self, node: &NodeInfo, graph: &Graph) -> bool {
+        let Some(device_string) = &node.device_string else {
+            return false;
+        };
+        let replaced: Vec<Serial> = self
+            .endpoint_devices
+            .iter()
+            .filter(|(serial, (device, input))| {
+                **serial != node.serial
+                    && device == device_string
+                    && *input == node.input
+                    && graph.node(**serial).is_none()
+            })
+            .map(|(serial, _)| *serial)
+            .collect();
+        for old in &replaced {
+            info!(
+                "[Serial: {}, {}] replaces [Serial: {}] (profile {}), relinking once it settles",
+                node.serial,
+                node.description,
+                old,
+                node.bluetooth_profile.as_deref().unwrap_or("unknown")
+            );
+            for route in &mut self.routes {
+                if route.output == *old {
+                    route.output = node.serial;
+                }
+                for input in &mut route.inputs {
+                    if *input == *old {
+                        *input = node.serial;
+                    }
+                }
+            }
+            self.endpoint_devices.remove(old);
+        }
+        if replaced.is_empty() {
+            return false;
+        }
+        self.endpoint_devices
+            .insert(node.serial, (device_string.clone(), node.input));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{sink, source};
+    use crate::plan::{plan_links, PairedBy, Pairing};
+
+    const HEADSET: &str = "bluez_card.00_1B_66_AA_BB_CC";
+
+    fn headset(serial: Serial, profile: &str, channels: &[&str]) -> NodeInfo {
+        NodeInfo {
+            device_string: Some(HEADSET.to_string()),
+            bluetooth_profile: Some(profile.to_string()),
+            ..sink(serial, channels)
+        }
+    }
+
+    /// The node of one profile goes away and the next one is announced, as the daemon
+    /// reports a switch
+    fn switch_profile(graph: &mut Graph, old: Serial, new: NodeInfo) -> NodeInfo {
+        graph.remove_global(old as u32);
+        graph.add_node(new.clone());
+        new
+    }
+
+    #[test]
+    fn bluetooth_profile_switches_keep_the_route() {
+        let mut graph = Graph::default();
+        let music = source(1, &["FL", "FR"]);
+        graph.add_node(music.clone());
+        let a2dp = headset(10, "a2dp-sink", &["FL", "FR"]);
+        graph.add_node(a2dp.clone());
+        let mut routing = Routing::default();
+        routing.set(vec![Route {
+            inputs: vec![1],
+            output: 10,
+            ..Route::default()
+        }]);
+        routing.note_endpoints([&music, &a2dp]);
+
+        // A2DP -> HFP: the headset comes back as a mono node
+        let hfp = switch_profile(&mut graph, 10, headset(11, "headset-head-unit", &["MONO"]));
+        assert!(routing.adopt_replacement(&hfp, &graph));
+        assert_eq!(routing.routes()[0].output, 11);
+        assert!(!routing.involves(10));
+        let plan = plan_links(&music, &hfp, &routing.routes()[0], Pairing::default());
+        assert_eq!(plan.links.len(), 2);
+        assert!(plan
+            .links
+            .iter()
+            .all(|planned| planned.paired_by == PairedBy::StereoToMono));
+
+        // HFP -> A2DP: back to stereo, under yet another serial
+        let a2dp = switch_profile(&mut graph, 11, headset(12, "a2dp-sink", &["FL", "FR"]));
+        assert!(routing.adopt_replacement(&a2dp, &graph));
+        assert_eq!(routing.routes()[0].output, 12);
+        let plan = plan_links(&music, &a2dp, &routing.routes()[0], Pairing::default());
+        assert_eq!(plan.links.len(), 2);
+        assert!(plan
+            .links
+            .iter()
+            .all(|planned| planned.paired_by == PairedBy::Name));
+        assert!(!routing.awaiting_nodes(&graph));
+    }
+
+    #[test]
+    fn only_vanished_nodes_of_the_same_device_and_direction_are_replaced() {
+        let mut graph = Graph::default();
+        let a2dp = headset(10, "a2dp-sink", &["FL", "FR"]);
+        graph.add_node(a2dp.clone());
+        let mut routing = Routing::default();
+        routing.set(vec![Route {
+            inputs: vec![1],
+            output: 10,
+            ..Route::default()
+        }]);
+        routing.note_endpoints([&a2dp]);
+
+        // Both profiles announced at once: the old node still exists
+        let hfp = headset(11, "headset-head-unit", &["MONO"]);
+        graph.add_node(hfp.clone());
+        assert!(!routing.adopt_replacement(&hfp, &graph));
+
+        graph.remove_global(10);
+        let microphone = NodeInfo {
+            device_string: Some(HEADSET.to_string()),
+            ..source(12, &["MONO"])
+        };
+        graph.add_node(microphone.clone());
+        assert!(!routing.adopt_replacement(&microphone, &graph));
+        let other = NodeInfo {
+            device_string: Some("bluez_card.00_1B_66_DD_EE_FF".to_string()),
+            ..sink(13, &["FL", "FR"])
+        };
+        graph.add_node(other.clone());
+        assert!(!routing.adopt_replacement(&other, &graph));
+        assert_eq!(routing.routes()[0].output, 10);
+        assert!(routing.awaiting_nodes(&graph));
+    }
+}
@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::Serialize;
+use tiny_http::{Header, Method, Response, Server};
+use tracing::{debug, info, warn};
+
+use crate::error::MixerError;
+use crate::graph::{ActiveLink, Graph, LinkEntry, NodeInfo};
+
+/// JSON document describing the current state of the graph
+#[derive(Serialize)]
+struct GraphSnapshot<'a> {
+    nodes: Vec<&'a NodeInfo>,
+    links: &'a [ActiveLink],
+    foreign_links: &'a [ForeignLink],
+}
+
+/// A link some other client made, labeled with that client
+#[derive(Serialize)]
+struct ForeignLink {
+    id: u32,
+    #[serde(flatten)]
+    link: LinkEntry,
+    client: String,
+}
+
+/// Serve the discovered nodes and active links as JSON from a background thread
+///
+/// Routes: `/` (nodes and links), `/nodes`, `/links`, `/foreign-links`
+pub fn spawn(
+    addr: SocketAddr,
+    nodes: Arc<Mutex<Graph>>,
+    links: Arc<Mutex<Vec<ActiveLink>>>,
+) -> Result<(), MixerError> {
+    let server = Server::http(addr).map_err(|source| MixerError::ServeFailed { addr, source })?;
+    info!("Serving graph as JSON on http://{}", addr);
+
+    thread::Builder::new()
+        .name("pie_mixer-http".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                debug!("HTTP {} {}", request.method(), request.url());
+                let body = if *request.method() != Method::Get {
+                    None
+                } else {
+                    render(request.url(), &nodes, &links)
+                };
+                let response = match body {
+                    Some(json) => Response::from_string(json).with_header(
+                        Header::from_bytes("Content-Type", "application/json")
+                            .expect("static header is valid"),
+                    ),
+                    None => Response::from_string("Not Found").with_status_code(404),
+                };
+                if let Err(error) = request.respond(response) {
+                    warn!("Failed to send HTTP response: {:?}", error);
+                }
+            }
+        })
+        .map_err(|error| MixerError::ServeFailed {
+            addr,
+            source: Box::new(error),
+        })?;
+    Ok(())
+}
+
+/// Render the JSON body for a request path, or None if the path is unknown
+fn render(url: &str, nodes: &Mutex<Graph>, links: &Mutex<Vec<ActiveLink>>) -> Option<String> {
+    // Ignore any query string
+    let path = url.split('?').next().unwrap_or(url).trim_end_matches('/');
+
+    // Copy out of the shared state quickly so the PipeWire thread is never held up by slow clients
+    let (nodes, foreign_links) = {
+        let graph = nodes.lock().ok()?;
+        let foreign_links: Vec<ForeignLink> = graph
+            .foreign_links()
+            .into_iter()
+            .map(|(id, link)| ForeignLink {
+                id,
+                link: link.clone(),
+                client: graph.link_client(link),
+            })
+            .collect();
+        (
+            graph.nodes().cloned().collect::<Vec<NodeInfo>>(),
+            foreign_links,
+        )
+    };
+    let links = links.lock().ok()?.clone();
+    let mut sorted_nodes: Vec<&NodeInfo> = nodes.iter().collect();
+    sorted_nodes.sort_by_key(|n| n.global_id);
+
+    let json = match path {
+        "" => serde_json::to_string_pretty(&GraphSnapshot {
+            nodes: sorted_nodes,
+            links: &links,
+            foreign_links: &foreign_links,
+        }),
+        "/nodes" => serde_json::to_string_pretty(&sorted_nodes),
+        "/links" => serde_json::to_string_pretty(&links),
+        "/foreign-links" => serde_json::to_string_pretty(&foreign_links),
+        _ => return None,
+    };
+    json.ok()
+}
@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use tracing::{debug, warn};
+
+use crate::discover::{self, Connection};
+use crate::error::MixerError;
+use crate::graph::{ActiveLink, Graph, NodeInfo};
+use crate::mixer::{LinkOptions, Mixer, Route};
+
+/// State shared between a [`Reply`] and the loop thread answering it
+struct Slot<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    closed: bool, // The answer was dropped unsent, e.g. because the loop thread stopped
+}
+
+/// Future resolving to the answer of the PipeWire loop thread
+///
+/// Runtime-agnostic: it can be awaited from tokio, async-std or any other executor
+pub struct Reply<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+/// Sending half of a [`Reply`], moved to the loop thread
+struct Answer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+fn reply<T>() -> (Answer<T>, Reply<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        waker: None,
+        closed: false,
+    }));
+    (Answer { slot: slot.clone() }, Reply { slot })
+}
+
+impl<T> Answer<T> {
+    fn send(self, value: T) {
+        if let Ok(mut slot) = self.slot.lock() {
+            slot.value = Some(value);
+        }
+    }
+}
+
+impl<T> Drop for Answer<T> {
+    fn drop(&mut self) {
+        if let Ok(mut slot) = self.slot.lock() {
+            slot.closed = true;
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T, MixerError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // A poisoned slot means the loop thread panicked while answering
+        let Ok(mut slot) = self.slot.lock() else {
+            return Poll::Ready(Err(MixerError::Stopped));
+        };
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if slot.closed {
+            return Poll::Ready(Err(MixerError::Stopped));
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Requests handled on the loop thread
+enum Command {
+    Nodes(Answer<Vec<NodeInfo>>),
+    SetRoutes(Vec<Route>, Answer<usize>),
+    Shutdown,
+}
+
+/// The mixer running on a dedicated thread with its own PipeWire main loop, for use from async
+/// code that must not block on the loop
+///
+/// Discovery happens before [`MixerService::spawn`] resolves; nodes and ports added later are
+/// tracked, but routes are only relinked when set again
+pub struct MixerService {
+    commands: pipewire::channel::Sender<Command>,
+    graph: Arc<Mutex<Graph>>,
+    active_links: Arc<Mutex<Vec<ActiveLink>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MixerService {
+    /// Connect to PipeWire on a new thread, resolving once the initial nodes are discovered
+    pub async fn spawn(options: LinkOptions) -> Result<MixerService, MixerError> {
+        let (commands, receiver) = pipewire::channel::channel();
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        let active_links = Arc::new(Mutex::new(Vec::new()));
+        let thread_graph = graph.clone();
+        let thread_links = active_links.clone();
+        let (started, connected) = reply();
+        let thread = thread::Builder::new()
+            .name("pie_mixer-pipewire".to_string())
+            .spawn(move || {
+                if let Err(error) = run(options, thread_graph, thread_links, receiver, started) {
+                    warn!("Mixer thread stopped: {:?}", error);
+                }
+            })
+            .map_err(MixerError::SpawnFailed)?;
+        let service = MixerService {
+            commands,
+            graph,
+            active_links,
+            thread: Some(thread),
+        };
+        // Dropping the service on failure joins the thread, which has stopped by then
+        connected.await??;
+        Ok(service)
+    }
+
+    /// The nodes and ports known to the loop thread, in global id order
+    pub fn nodes(&self) -> Reply<Vec<NodeInfo>> {
+        let (answer, nodes) = reply();
+        // A failed send drops the answer, resolving the reply to an error
+        let _ = self.commands.send(Command::Nodes(answer));
+        nodes
+    }
+
+    /// Replace the routes the mixer maintains, resolving to the number of links created
+    ///
+    /// Links no longer routed are destroyed
+    pub fn set_routes(&self, routes: Vec<Route>) -> Reply<usize> {
+        let (answer, created) = reply();
+        let _ = self.commands.send(Command::SetRoutes(routes, answer));
+        created
+    }
+
+    /// The links the mixer currently maintains
+    pub fn active_links(&self) -> Vec<ActiveLink> {
+        self.active_links
+            .lock()
+            .map(|links| links.clone())
+            .unwrap_or_default()
+    }
+
+    /// The graph as discovered so far, shared with the loop thread
+    pub fn graph(&self) -> Arc<Mutex<Graph>> {
+        self.graph.clone()
+    }
+}
+
+impl Drop for MixerService {
+    /// Stop the loop thread, destroying the links it created
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Body of the loop thread: discover, report readiness, then serve commands until shut down
+fn run(
+    options: LinkOptions,
+    graph: Arc<Mutex<Graph>>,
+    active_links: Arc<Mutex<Vec<ActiveLink>>>,
+    commands: pipewire::channel::Receiver<Command>,
+    started: Answer<Result<(), MixerError>>,
+) -> Result<(), MixerError> {
+    let Connection {
+        main_loop,
+        context: _context,
+        core,
+        registry,
+    } = match discover::connect() {
+        Ok(connected) => connected,
+        Err(error) => {
+            // Handed to the caller of spawn, who reports it
+            started.send(Err(error));
+            return Ok(());
+        }
+    };
+
+    let mixer = Rc::new(RefCell::new(Mixer::new(
+        core.clone(),
+        registry.clone(),
+        graph.clone(),
+        active_links,
+        options,
+    )));
+
+    let mixer_removal = mixer.clone();
+    let _registry_listener = discover::watch(&registry, graph.clone(), move |removed| {
+        mixer_removal.borrow_mut().forget(removed);
+    });
+
+    // Discovery is complete once the daemon answers a sync sent after the registry was requested
+    let pending = core.sync(0).map_err(MixerError::SyncFailed)?;
+    let started = RefCell::new(Some(started));
+    let _core_listener = core
+        .add_listener_local()
+        .done(move |_id, seq| {
+            if seq == pending {
+                if let Some(started) = started.borrow_mut().take() {
+                    debug!("Discovery complete, serving requests");
+                    started.send(Ok(()));
+                }
+            }
+        })
+        .register();
+
+    let main_loop_quit = main_loop.clone();
+    let _receiver = commands.attach(main_loop.loop_(), move |command| match command {
+        Command::Nodes(answer) => {
+            let mut nodes: Vec<NodeInfo> = graph
+                .lock()
+                .map(|graph| graph.nodes().cloned().collect())
+                .unwrap_or_default();
+            nodes.sort_by_key(|node| node.global_id);
+            answer.send(nodes);
+        }
+        Command::SetRoutes(routes, answer) => {
+            let mut mixer = mixer.borrow_mut();
+            mixer.set_routes(routes);
+            answer.send(mixer.link_all());
+        }
+        Command::Shutdown => main_loop_quit.quit(),
+    });
+    main_loop.run();
+    Ok(())
+}
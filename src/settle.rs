@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::graph::Serial;
+
+/// Nodes that appeared recently and are held back from linking until they stop changing
+///
+/// Keyed on node.name, which a device keeps when it recreates a node under a new serial, so a
+/// node reappearing within its window starts the wait over
+#[derive(Debug, Default)]
+pub struct Settling {
+    nodes: HashMap<String, (Serial, Instant)>, // node.name -> (latest serial, end of the wait)
+}
+
+impl Settling {
+    /// Hold a node back for `delay` from `now`, restarting the wait of a node of the same name
+    pub fn hold(&mut self, name: &str, serial: Serial, delay: Duration, now: Instant) {
+        self.nodes.insert(name.to_string(), (serial, now + delay));
+    }
+
+    /// True if the node is still held back at `now`
+    pub fn holds(&self, serial: Serial, now: Instant) -> bool {
+        self.nodes
+            .values()
+            .any(|(held, until)| *held == serial && *until > now)
+    }
+
+    /// The nodes whose wait is over at `now`, no longer held
+    pub fn take_settled(&mut self, now: Instant) -> Vec<Serial> {
+        let mut settled = Vec::new();
+        self.nodes.retain(|_, (serial, until)| {
+            let done = *until <= now;
+            if done {
+                settled.push(*serial);
+            }
+            !done
+        });
+        settled
+    }
+
+    /// Time from `now` until the next wait is over
+    pub fn next_due(&self, now: Instant) -> Option<Duration> {
+        self.nodes
+            .values()
+            .map(|(_, until)| until.saturating_duration_since(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn node_settles_once_its_wait_is_over() {
+        let start = Instant::now();
+        let mut settling = Settling::default();
+        settling.hold("usb-dac", 5, DELAY, start);
+        assert!(settling.holds(5, start));
+        assert_eq!(settling.next_due(start), Some(DELAY));
+
+        let before = start + DELAY - Duration::from_millis(1);
+        assert!(settling.holds(5, before));
+        assert!(settling.take_settled(before).is_empty());
+
+        // The wait ends exactly at the deadline
+        let end = start + DELAY;
+        assert!(!settling.holds(5, end));
+        assert_eq!(settling.next_due(end), Some(Duration::ZERO));
+        assert_eq!(settling.take_settled(end), [5]);
+        assert!(settling.take_settled(end).is_empty());
+        assert_eq!(settling.next_due(end), None);
+    }
+
+    #[test]
+    fn flapping_node_restarts_its_wait_under_the_new_serial() {
+        let start = Instant::now();
+        let mut settling = Settling::default();
+        settling.hold("usb-dac", 5, DELAY, start);
+
+        // The device recreates the node under a new serial one second in
+        let flap = start + Duration::from_secs(1);
+        settling.hold("usb-dac", 6, DELAY, flap);
+        assert!(!settling.holds(5, flap));
+        assert!(settling.holds(6, flap));
+
+        // Past the first deadline the node is still held, counted from the flap
+        let first_end = start + DELAY;
+        assert!(settling.holds(6, first_end));
+        assert!(settling.take_settled(first_end).is_empty());
+        assert_eq!(settling.next_due(first_end), Some(Duration::from_secs(1)));
+
+        assert_eq!(settling.take_settled(flap + DELAY), [6]);
+    }
+
+    #[test]
+    fn nodes_of_other_names_wait_independently() {
+        let start = Instant::now();
+        let mut settling = Settling::default();
+        settling.hold("usb-dac", 5, DELAY, start);
+        settling.hold("headset", 7, DELAY * 2, start);
+        assert_eq!(settling.next_due(start), Some(DELAY));
+        assert_eq!(settling.take_settled(start + DELAY), [5]);
+        assert!(settling.holds(7, start + DELAY));
+        assert_eq!(settling.next_due(start + DELAY), Some(DELAY));
+    }
+}
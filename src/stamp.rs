@@ -0,0 +1,146 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pipewire::spa::utils::dict::DictRef;
+use serde::Serialize;
+
+/// Property stamped on every link the mixer creates, so a later run can tell them apart from
+/// links made by other clients
+pub const MANAGED_KEY: &str = "pie_mixer.managed";
+
+/// Property naming the rule a link was created for
+pub const RULE_KEY: &str = "pie_mixer.rule";
+
+/// Property holding the process id of the mixer that created a link
+pub const INSTANCE_KEY: &str = "pie_mixer.instance";
+
+/// Property holding when a link was created, in seconds since the Unix epoch
+pub const CREATED_KEY: &str = "pie_mixer.created";
+
+/// Property counting how often the watchdog recreated a link after another client removed it
+pub const RECREATED_KEY: &str = "pie_mixer.recreated";
+
+/// What a mixer stamps on the links it creates, as read back from a bound link
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LinkStamp {
+    pub rule: Option<String>,
+    pub instance: Option<String>, // Missing on links made by versions before the instance stamp
+    pub created: Option<u64>,     // Seconds since the Unix epoch
+    pub recreated: u32,           // Times the link was recreated after being removed
+}
+
+impl LinkStamp {
+    /// Stamp for a link this process creates for a rule
+    pub fn own(rule: Option<&str>) -> Self {
+        LinkStamp {
+            rule: rule.map(str::to_string),
+            instance: Some(instance_id()),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since| since.as_secs()),
+            recreated: 0,
+        }
+    }
+
+    /// The properties to create a link with, read back by [`LinkStamp::from_props`]
+    pub fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = vec![(MANAGED_KEY, "true".to_string())];
+        if let Some(rule) = &self.rule {
+            properties.push((RULE_KEY, rule.clone()));
+        }
+        if let Some(instance) = &self.instance {
+            properties.push((INSTANCE_KEY, instance.clone()));
+        }
+        if let Some(created) = self.created {
+            properties.push((CREATED_KEY, created.to_string()));
+        }
+        if self.recreated > 0 {
+            properties.push((RECREATED_KEY, self.recreated.to_string()));
+        }
+        properties
+    }
+
+    /// Read the stamp from a link's properties, None if no mixer made it
+    pub fn from_props(props: &DictRef) -> Option<Self> {
+        Self::from_lookup(|key| props.get(key))
+    }
+
+    /// Read the stamp through `get`, which looks up one property of a link
+    fn from_lookup<'a>(get: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        if get(MANAGED_KEY) != Some("true") {
+            return None;
+        }
+        Some(LinkStamp {
+            rule: get(RULE_KEY).map(str::to_string),
+            instance: get(INSTANCE_KEY).map(str::to_string),
+            created: get(CREATED_KEY).and_then(|created| created.parse().ok()),
+            recreated: get(RECREATED_KEY)
+                .and_then(|recreated| recreated.parse().ok())
+                .unwrap_or(0),
+        })
+    }
+
+    /// How long ago the link was created, None if unknown
+    pub fn age(&self) -> Option<Duration> {
+        let created = UNIX_EPOCH + Duration::from_secs(self.created?);
+        SystemTime::now().duration_since(created).ok()
+    }
+}
+
+/// Identifies this mixer process among the instances that may have stamped links
+pub fn instance_id() -> String {
+    std::process::id().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Read a stamp back from the properties it creates a link with
+    fn round_trip(stamp: &LinkStamp) -> Option<LinkStamp> {
+        let props: HashMap<&str, String> = stamp.properties().into_iter().collect();
+        LinkStamp::from_lookup(|key| props.get(key).map(String::as_str))
+    }
+
+    #[test]
+    fn stamp_survives_a_round_trip() {
+        let stamp = LinkStamp {
+            rule: Some("desk".to_string()),
+            instance: Some("4242".to_string()),
+            created: Some(1_700_000_000),
+            recreated: 3,
+        };
+        assert_eq!(round_trip(&stamp), Some(stamp));
+        let own = LinkStamp::own(Some("desk"));
+        assert_eq!(round_trip(&own), Some(own));
+    }
+
+    #[test]
+    fn missing_fields_read_back_empty() {
+        let stamp = LinkStamp::default();
+        assert_eq!(stamp.properties(), [(MANAGED_KEY, "true".to_string())]);
+        assert_eq!(round_trip(&stamp), Some(stamp));
+    }
+
+    #[test]
+    fn unparseable_numbers_are_ignored() {
+        let props = HashMap::from([
+            (MANAGED_KEY, "true"),
+            (CREATED_KEY, "yesterday"),
+            (RECREATED_KEY, "-1"),
+        ]);
+        let stamp = LinkStamp::from_lookup(|key| props.get(key).copied()).unwrap();
+        assert_eq!(stamp.created, None);
+        assert_eq!(stamp.recreated, 0);
+    }
+
+    #[test]
+    fn links_without_the_managed_stamp_are_not_ours() {
+        let props = HashMap::from([(RULE_KEY, "desk"), (CREATED_KEY, "1700000000")]);
+        assert_eq!(LinkStamp::from_lookup(|key| props.get(key).copied()), None);
+        let props = HashMap::from([(MANAGED_KEY, "false")]);
+        assert_eq!(LinkStamp::from_lookup(|key| props.get(key).copied()), None);
+    }
+}
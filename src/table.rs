@@ -0,0 +1,151 @@
+use std::io::IsTerminal;
+
+use owo_colors::{OwoColorize, Style};
+
+use pie_mixer::graph::{NodeInfo, NodeKind, IDENTITY_KEYS, MONITOR_NAME_KEY};
+
+use crate::layout::{Section, MIDI_HEADING};
+
+/// Print the nodes as an aligned table with a header row
+///
+/// Rows are colored by kind (inputs green, outputs blue, everything else gray)
+/// when stdout is a terminal, so piping the output yields plain text.
+/// `verbose` adds columns with the queried formats, rates and channel counts, and with the
+/// device properties rules can match on. MIDI nodes follow the others under a heading of their own
+pub fn print_nodes(nodes: &[&NodeInfo], verbose: bool) {
+    let (midi, nodes): (Vec<&NodeInfo>, Vec<&NodeInfo>) =
+        nodes.iter().partition(|node| node.kind() == NodeKind::Midi);
+    let mut sections = vec![Section {
+        heading: None,
+        nodes,
+    }];
+    if !midi.is_empty() {
+        sections.push(Section {
+            heading: Some(MIDI_HEADING.to_string()),
+            nodes: midi,
+        });
+    }
+    print_sections(&sections, verbose);
+}
+
+/// Print the nodes of every section under its heading, as a single table so that the columns
+/// line up across sections
+pub fn print_sections(sections: &[Section], verbose: bool) {
+    let color = std::io::stdout().is_terminal();
+    let mut header = vec!["ID", "SERIAL", "TYPE", "DESCRIPTION", "NICK"];
+    if verbose {
+        header.push("CAPS");
+        header.push("DEVICE");
+    }
+    header.push("PORTS");
+    let header: Vec<String> = header.into_iter().map(String::from).collect();
+
+    let rows: Vec<Vec<String>> = sections
+        .iter()
+        .flat_map(|section| &section.nodes)
+        .map(|node| {
+            let mut row = vec![
+                node.global_id.to_string(),
+                node.serial.to_string(),
+                node.kind().label().to_string(),
+                node.description.clone(),
+                node.nick.clone().unwrap_or_default(),
+            ];
+            if verbose {
+                row.push(
+                    node.caps
+                        .as_ref()
+                        .map_or_else(String::new, |c| c.to_string()),
+                );
+                let properties: Vec<String> = node
+                    .properties
+                    .iter()
+                    .filter(|(key, _)| {
+                        IDENTITY_KEYS.contains(&key.as_str()) || *key == MONITOR_NAME_KEY
+                    })
+                    .map(|(key, value)| format!("{}={:?}", key, value))
+                    .collect();
+                row.push(properties.join(" "));
+            }
+            row.push(node.ports_summary());
+            row
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(String::len).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header_style = if color {
+        Style::new().bold()
+    } else {
+        Style::new()
+    };
+    println!("{}", format_row(&header, &widths).style(header_style));
+    let mut rows = rows.iter();
+    for section in sections {
+        if let Some(heading) = &section.heading {
+            println!();
+            println!("{}", heading.style(header_style));
+        }
+        for (node, row) in section.nodes.iter().zip(rows.by_ref()) {
+            // Pad before styling, as escape codes would otherwise count towards the width
+            let line = format_row(row, &widths);
+            if color {
+                println!("{}", line.style(kind_style(node.kind())));
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+/// Values longer than this many characters are cut short when truncating
+const MAX_VALUE_WIDTH: usize = 80;
+
+/// Print every known property of each node, sorted by key, under a line naming the node
+///
+/// With `truncate`, long values (e.g. icons or long card names) end in an ellipsis
+pub fn print_properties(nodes: &[&NodeInfo], truncate: bool) {
+    for node in nodes {
+        println!(
+            "[ID: {} | Serial: {}] {}",
+            node.global_id, node.serial, node.description
+        );
+        for (key, value) in &node.properties {
+            let value = if truncate && value.chars().count() > MAX_VALUE_WIDTH {
+                let cut: String = value.chars().take(MAX_VALUE_WIDTH - 1).collect();
+                format!("{}…", cut)
+            } else {
+                value.clone()
+            };
+            println!("  {} = {:?}", key, value);
+        }
+        println!();
+    }
+}
+
+fn kind_style(kind: NodeKind) -> Style {
+    match kind {
+        NodeKind::AudioInput | NodeKind::VideoInput => Style::new().green(),
+        NodeKind::AudioOutput | NodeKind::VideoOutput => Style::new().blue(),
+        NodeKind::Midi => Style::new().magenta(),
+        NodeKind::Other => Style::new().bright_black(),
+    }
+}
+
+/// Left-align each cell to its column width; the last column is not padded
+fn format_row(cells: &[String], widths: &[usize]) -> String {
+    let Some((last, rest)) = cells.split_last() else {
+        return String::new();
+    };
+    let mut line = String::new();
+    for (cell, width) in rest.iter().zip(widths) {
+        line.push_str(&format!("{:<width$}  ", cell, width = width));
+    }
+    line.push_str(last);
+    line
+}